@@ -43,6 +43,10 @@ enum ManualEvent {
     Pong,
 }
 
+impl tokio_fsm::FsmState for ManualState {
+    type Event = ManualEvent;
+}
+
 struct ManualFsm {
     context: Context,
 }
@@ -1,15 +1,15 @@
-use std::{collections::HashMap, sync::Arc};
-
 use axum::{
-    extract::{Path, State},
+    extract::State,
     http::StatusCode,
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-use tokio_fsm::{fsm, Transition};
+use tokio_fsm::{
+    axum::{FsmManager, Instance, ManagerError},
+    fsm, Transition,
+};
 
 // --- DOMAIN TYPES ---
 
@@ -27,7 +27,7 @@ pub struct OrderContext {
 
 // --- FSM DEFINITION ---
 
-#[fsm(initial = Created)]
+#[fsm(initial = Created, serde)]
 impl OrderFsm {
     type Context = OrderContext;
     type Error = std::convert::Infallible;
@@ -74,12 +74,10 @@ impl OrderFsm {
 
 // --- API STATE ---
 
-struct AppState {
-    // Map of OrderID -> FSM Handle
-    // In a real app, you might use a DB and reconstruct FSMs, or use an actor registry.
-    // For this demo, we keep handles in memory.
-    orders: Mutex<HashMap<String, OrderFsmHandle>>,
-}
+// Every live order FSM, by order id. `FsmManager` replaces a hand-rolled
+// `Mutex<HashMap<String, OrderFsmHandle>>`; in a real app you might also
+// persist snapshots and rebuild FSMs on demand.
+type Orders = FsmManager<OrderFsmHandle>;
 
 // --- AXUM HANDLERS ---
 
@@ -91,73 +89,41 @@ struct CreateOrderRequest {
 }
 
 async fn create_order(
-    State(state): State<Arc<AppState>>,
+    State(orders): State<Orders>,
     Json(payload): Json<CreateOrderRequest>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, ManagerError<OrderFsmEvent>> {
     let order = Order {
         id: payload.id.clone(),
         items: payload.items,
         total: payload.total,
     };
 
-    let context = OrderContext { order };
-    let (handle, _) = OrderFsm::spawn(context);
-
-    state.orders.lock().await.insert(payload.id.clone(), handle);
-
-    (StatusCode::CREATED, Json("Order created"))
+    orders.create(payload.id, || OrderFsm::spawn(OrderContext { order }))?;
+    Ok((StatusCode::CREATED, Json("Order created")))
 }
 
+// Each step waits for its handler and responds with the resulting state,
+// which `#[fsm(serde)]` serializes as a plain string such as "Charged".
 async fn validate_order(
-    Path(id): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let mut orders = state.orders.lock().await;
-    if let Some(handle) = orders.get_mut(&id) {
-        if handle.send(OrderFsmEvent::Validate).await.is_ok() {
-            return (StatusCode::OK, Json("Validation started"));
-        }
-    }
-    (StatusCode::NOT_FOUND, Json("Order not found or closed"))
+    Instance(order): Instance<OrderFsmHandle>,
+) -> Result<Json<OrderFsmState>, ManagerError<OrderFsmEvent>> {
+    Ok(Json(order.call(OrderFsmEvent::Validate).await?))
 }
 
 async fn charge_order(
-    Path(id): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let mut orders = state.orders.lock().await;
-    if let Some(handle) = orders.get_mut(&id) {
-        if handle.send(OrderFsmEvent::Charge).await.is_ok() {
-            return (StatusCode::OK, Json("Charging started"));
-        }
-    }
-    (StatusCode::NOT_FOUND, Json("Order not found or closed"))
+    Instance(order): Instance<OrderFsmHandle>,
+) -> Result<Json<OrderFsmState>, ManagerError<OrderFsmEvent>> {
+    Ok(Json(order.call(OrderFsmEvent::Charge).await?))
 }
 
 async fn ship_order(
-    Path(id): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let mut orders = state.orders.lock().await;
-    if let Some(handle) = orders.get_mut(&id) {
-        if handle.send(OrderFsmEvent::Ship).await.is_ok() {
-            return (StatusCode::OK, Json("Shipping started"));
-        }
-    }
-    (StatusCode::NOT_FOUND, Json("Order not found or closed"))
+    Instance(order): Instance<OrderFsmHandle>,
+) -> Result<Json<OrderFsmState>, ManagerError<OrderFsmEvent>> {
+    Ok(Json(order.call(OrderFsmEvent::Ship).await?))
 }
 
-async fn get_order_status(
-    Path(id): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let orders = state.orders.lock().await;
-    if let Some(handle) = orders.get(&id) {
-        // tokio-fsm handles expose current_state() synchronously if it's available
-        let state = handle.current_state();
-        return (StatusCode::OK, Json(format!("{:?}", state)));
-    }
-    (StatusCode::NOT_FOUND, Json("Order not found".to_string()))
+async fn get_order_status(Instance(order): Instance<OrderFsmHandle>) -> impl IntoResponse {
+    Json(serde_json::json!({ "state": order.current_state() }))
 }
 
 // --- MAIN ---
@@ -180,17 +146,15 @@ async fn main() {
 
     tracing::info!("Starting Axum FSM Server...");
 
-    let app_state = Arc::new(AppState {
-        orders: Mutex::new(HashMap::new()),
-    });
+    let orders = Orders::new();
 
     let app = Router::new()
         .route("/orders", post(create_order))
-        .route("/orders/:id/validate", post(validate_order))
-        .route("/orders/:id/charge", post(charge_order))
-        .route("/orders/:id/ship", post(ship_order))
-        .route("/orders/:id", get(get_order_status))
-        .with_state(app_state);
+        .route("/orders/{id}/validate", post(validate_order))
+        .route("/orders/{id}/charge", post(charge_order))
+        .route("/orders/{id}/ship", post(ship_order))
+        .route("/orders/{id}", get(get_order_status))
+        .with_state(orders);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     tracing::info!("listening on {}", listener.local_addr().unwrap());
@@ -52,14 +52,11 @@ impl ManualFsm {
                 tokio::select! {
                     event = rx.recv() => {
                         let Some(event) = event else { break };
-                        match (fsm.state, event) {
-                            (ManualState::Idle, ManualEvent::Start(job)) => {
-                                println!("Manual: Starting job {}", job.id);
-                                fsm.context.count += 1;
-                                fsm.state = ManualState::Processing;
-                                let _ = state_tx.send(fsm.state);
-                            }
-                            _ => {}
+                        if let (ManualState::Idle, ManualEvent::Start(job)) = (fsm.state, event) {
+                            println!("Manual: Starting job {}", job.id);
+                            fsm.context.count += 1;
+                            fsm.state = ManualState::Processing;
+                            let _ = state_tx.send(fsm.state);
                         }
                     }
                 }
@@ -66,7 +66,7 @@ async fn main() {
         id: 1,
         data: "test".to_string(),
     };
-    handle.send(WorkerFsmEvent::Job(job)).await.unwrap();
+    handle.send_into(job).await.unwrap();
 
     // Wait a bit
     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -0,0 +1,77 @@
+//! Serving FSM instances from an axum app.
+//!
+//! An [`FsmManager`] holds the handles of every live instance by id, in place
+//! of a hand-rolled `Mutex<HashMap<String, Handle>>` in the app state, and
+//! [`Instance`] extracts the one named by the route's `{id}`:
+//!
+//! ```rust,ignore
+//! async fn create(State(orders): State<FsmManager<OrderFsmHandle>>, Json(order): Json<Order>) -> Result<StatusCode, ManagerError<OrderFsmEvent>> {
+//!     orders.create(order.id.clone(), || OrderFsm::spawn(order))?;
+//!     Ok(StatusCode::CREATED)
+//! }
+//!
+//! async fn ship(Instance(order): Instance<OrderFsmHandle>) -> Result<Json<OrderFsmState>, ManagerError<OrderFsmEvent>> {
+//!     Ok(Json(order.call(OrderFsmEvent::Ship).await?))
+//! }
+//!
+//! let app = Router::new()
+//!     .route("/orders", post(create))
+//!     .route("/orders/{id}/ship", post(ship))
+//!     .with_state(FsmManager::<OrderFsmHandle>::new());
+//! ```
+//!
+//! Apps with more state implement `FromRef` for the manager.
+
+use ::axum::{
+    Json,
+    extract::{FromRef, FromRequestParts, Path},
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+
+use crate::FsmHandle;
+pub use crate::manager::{FsmManager, ManagerError};
+
+/// Extracts the handle of the instance named by the route's only path
+/// parameter from the app's [`FsmManager`], rejecting with
+/// [`ManagerError::NotFound`] if there is none.
+#[derive(Debug, Clone)]
+pub struct Instance<H>(pub H);
+
+impl<S, H> FromRequestParts<S> for Instance<H>
+where
+    H: FsmHandle,
+    FsmManager<H>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        FsmManager::<H>::from_ref(state)
+            .get(&id)
+            .map(Instance)
+            .ok_or_else(|| ManagerError::<H::Event>::NotFound.into_response())
+    }
+}
+
+impl<E> IntoResponse for ManagerError<E> {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Exists | Self::Dropped | Self::Discarded(_) => StatusCode::CONFLICT,
+            Self::Closed(_) => StatusCode::GONE,
+        };
+        let body = Json(ErrorBody {
+            error: self.to_string(),
+        });
+        (status, body).into_response()
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    error: String,
+}
@@ -0,0 +1,166 @@
+//! Consuming a message broker into FSM instances.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+
+use std::future::Future;
+
+#[cfg(feature = "kafka")]
+pub use self::kafka::KafkaSource;
+#[cfg(feature = "nats")]
+pub use self::nats::NatsSource;
+use crate::{CallError, FsmCall, FsmHandle, FsmManager};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type Spawn<H> = Box<dyn FnMut(&str) -> H + Send>;
+
+/// A topic or subject consumed by a [`Bridge`], whose messages are committed
+/// one at a time.
+///
+/// `KafkaSource` and `NatsSource` implement it with the `kafka` and `nats`
+/// features.
+pub trait EventSource: Send {
+    /// A received message, with whatever is needed to commit it.
+    type Message: Send + Sync;
+    /// Why receiving or committing failed.
+    type Error: Into<BoxError>;
+
+    /// Receives the next message, or `None` once the source has ended.
+    fn next(&mut self) -> impl Future<Output = Option<Result<Self::Message, Self::Error>>> + Send;
+
+    /// Marks `message` as processed, so it isn't redelivered. Brokers that
+    /// track offsets also treat every earlier message as processed.
+    fn commit(
+        &mut self,
+        message: &Self::Message,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// Consumes an [`EventSource`] into the instances of an [`FsmManager`]:
+///
+/// ```rust,ignore
+/// let bridge = Bridge::new(source, orders.clone(), |message: &OwnedMessage| {
+///     let key = String::from_utf8(message.key().unwrap_or_default().to_vec())?;
+///     let event = serde_json::from_slice(message.payload().unwrap_or_default())?;
+///     Ok::<_, Box<dyn Error + Send + Sync>>(Some((key, event)))
+/// })
+/// .spawn_missing(|id| OrderFsm::spawn(Order::new(id)).0);
+/// tokio::spawn(bridge.run());
+/// ```
+///
+/// `decode` maps each message to the id of the instance it is for and its
+/// event, or `None` to skip it. The event is sent with `call`, and the
+/// message is committed only once it has been handled, so a crash or a
+/// dropped [`run`](Self::run) redelivers it rather than losing it. Delivery
+/// is therefore at least once: the message being handled when the bridge
+/// stopped may be handled again after a restart.
+///
+/// Messages are handled one at a time, in order. For more throughput, run a
+/// bridge per partition or consumer, so ordering holds where the broker
+/// keeps it.
+pub struct Bridge<S, H: FsmHandle, D> {
+    source: S,
+    manager: FsmManager<H>,
+    decode: D,
+    spawn: Option<Spawn<H>>,
+}
+
+impl<S, H, D, E> Bridge<S, H, D>
+where
+    S: EventSource,
+    H: FsmCall,
+    D: FnMut(&S::Message) -> Result<Option<(String, H::Event)>, E> + Send,
+    E: Into<BoxError>,
+{
+    /// Creates a bridge that routes the messages of `source` to the
+    /// instances in `manager`.
+    pub fn new(source: S, manager: FsmManager<H>, decode: D) -> Self {
+        Self {
+            source,
+            manager,
+            decode,
+            spawn: None,
+        }
+    }
+
+    /// Spawns an instance with `spawn` for messages whose id isn't
+    /// registered, instead of stopping with [`BridgeError::NotFound`].
+    pub fn spawn_missing(mut self, spawn: impl FnMut(&str) -> H + Send + 'static) -> Self {
+        self.spawn = Some(Box::new(spawn));
+        self
+    }
+
+    /// Consumes messages until the source ends or one can't be handled.
+    ///
+    /// On error, the failing message is left uncommitted.
+    pub async fn run(mut self) -> Result<(), BridgeError<H::Event>> {
+        while let Some(message) = self.source.next().await {
+            let message = message.map_err(|err| BridgeError::Source(err.into()))?;
+            let routed = (self.decode)(&message).map_err(|err| BridgeError::Decode(err.into()))?;
+            if let Some((id, event)) = routed {
+                let handle = self.instance(id.clone())?;
+                match handle.call(event).await {
+                    // Events the FSM's queue policy discarded on purpose are
+                    // as handled as they will get.
+                    Ok(_) | Err(CallError::Discarded(_)) => {}
+                    Err(CallError::Dropped) => return Err(BridgeError::Dropped { id }),
+                    Err(CallError::Closed(event)) => {
+                        return Err(BridgeError::Closed { id, event });
+                    }
+                }
+            }
+            self.source
+                .commit(&message)
+                .await
+                .map_err(|err| BridgeError::Source(err.into()))?;
+        }
+        Ok(())
+    }
+
+    fn instance(&mut self, id: String) -> Result<H, BridgeError<H::Event>> {
+        if let Some(handle) = self.manager.get(&id) {
+            return Ok(handle);
+        }
+        let Some(spawn) = &mut self.spawn else {
+            return Err(BridgeError::NotFound(id));
+        };
+        // `create` only fails if the id was registered meanwhile, which is
+        // just as good.
+        let _ = self.manager.create(id.clone(), || (spawn(&id), ()));
+        self.manager.get(&id).ok_or(BridgeError::NotFound(id))
+    }
+}
+
+impl<S: std::fmt::Debug, H: FsmHandle, D> std::fmt::Debug for Bridge<S, H, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bridge")
+            .field("source", &self.source)
+            .field("manager", &self.manager)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error returned by [`Bridge::run`].
+#[derive(Debug, thiserror::Error)]
+pub enum BridgeError<E> {
+    /// Receiving or committing a message failed.
+    #[error("event source failed: {0}")]
+    Source(BoxError),
+    /// `decode` rejected a message.
+    #[error("failed to decode message: {0}")]
+    Decode(BoxError),
+    /// A message was for an instance that isn't registered, and the bridge
+    /// has no [`spawn_missing`](Bridge::spawn_missing).
+    #[error("instance `{0}` not found")]
+    NotFound(String),
+    /// The instance a message was for has stopped; its event is returned.
+    #[error("instance `{id}` has stopped")]
+    Closed { id: String, event: E },
+    /// A message's event was enqueued but never handled, e.g. because the
+    /// instance stopped first.
+    #[error("event for instance `{id}` was dropped before it was handled")]
+    Dropped { id: String },
+}
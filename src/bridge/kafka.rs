@@ -0,0 +1,51 @@
+use rdkafka::{
+    Offset, TopicPartitionList,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    error::KafkaError,
+    message::{Message as _, OwnedMessage},
+};
+
+use super::EventSource;
+
+/// A Kafka consumer, as an [`EventSource`].
+///
+/// Each message's offset is committed once it has been handled, which also
+/// commits every earlier offset of its partition. Create the consumer with
+/// `enable.auto.commit=false`, or it commits messages before they are
+/// handled.
+pub struct KafkaSource {
+    consumer: StreamConsumer,
+}
+
+impl KafkaSource {
+    /// Consumes the topics `consumer` is subscribed to.
+    pub fn new(consumer: StreamConsumer) -> Self {
+        Self { consumer }
+    }
+}
+
+impl EventSource for KafkaSource {
+    type Message = OwnedMessage;
+    type Error = KafkaError;
+
+    async fn next(&mut self) -> Option<Result<OwnedMessage, KafkaError>> {
+        Some(self.consumer.recv().await.map(|message| message.detach()))
+    }
+
+    async fn commit(&mut self, message: &OwnedMessage) -> Result<(), KafkaError> {
+        // Kafka commits the offset of the next message to read.
+        let mut offsets = TopicPartitionList::new();
+        offsets.add_partition_offset(
+            message.topic(),
+            message.partition(),
+            Offset::Offset(message.offset() + 1),
+        )?;
+        self.consumer.commit(&offsets, CommitMode::Async)
+    }
+}
+
+impl std::fmt::Debug for KafkaSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSource").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,44 @@
+use std::{future::poll_fn, pin::Pin};
+
+use async_nats::jetstream::{Message, consumer::pull};
+use futures_core::Stream;
+
+use super::EventSource;
+
+/// A NATS JetStream pull consumer, as an [`EventSource`].
+///
+/// Messages are committed with a double ack, which waits for the server to
+/// confirm it, so an ack lost in transit doesn't redeliver a handled
+/// message. Give the consumer an `ack_wait` longer than the slowest handler.
+pub struct NatsSource {
+    messages: Pin<Box<pull::Stream>>,
+}
+
+impl NatsSource {
+    /// Consumes `messages`, from `consumer.messages().await?`.
+    pub fn new(messages: pull::Stream) -> Self {
+        Self {
+            messages: Box::pin(messages),
+        }
+    }
+}
+
+impl EventSource for NatsSource {
+    type Message = Message;
+    type Error = async_nats::Error;
+
+    async fn next(&mut self) -> Option<Result<Message, async_nats::Error>> {
+        let message = poll_fn(|cx| self.messages.as_mut().poll_next(cx)).await?;
+        Some(message.map_err(Into::into))
+    }
+
+    async fn commit(&mut self, message: &Message) -> Result<(), async_nats::Error> {
+        message.double_ack().await
+    }
+}
+
+impl std::fmt::Debug for NatsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NatsSource").finish_non_exhaustive()
+    }
+}
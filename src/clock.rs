@@ -0,0 +1,248 @@
+//! Time sources for state timeouts.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use crate::rt::{Instant, Sleep, watch};
+
+/// A boxed sleep future, as returned by [`Clock::sleep_until`].
+pub type ClockSleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The time source state timeouts are measured against.
+///
+/// FSMs use tokio's timer unless a clock is set with the builder's `clock`,
+/// e.g. a [`ManualClock`] to drive timeouts from a test or simulation
+/// without real sleeps or a paused runtime.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once the clock reaches `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep;
+}
+
+/// Tokio's timer, the clock FSMs use by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        crate::rt::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        Box::pin(crate::rt::sleep_until(deadline))
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one and hand another to
+/// the builder:
+///
+/// ```rust,ignore
+/// let clock = ManualClock::new();
+/// let (handle, task) = MyFsm::builder(ctx).clock(clock.clone()).spawn();
+/// handle.send(MyFsmEvent::Start).await?;
+/// clock.advance(Duration::from_secs(30)); // fires a 30s state timeout
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Arc<watch::Sender<Duration>>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at the current time.
+    pub fn new() -> Self {
+        Self {
+            start: crate::rt::now(),
+            elapsed: Arc::new(watch::Sender::new(Duration::ZERO)),
+        }
+    }
+
+    /// Moves the clock forward by `duration`, waking every sleep whose
+    /// deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += duration);
+    }
+
+    /// Returns how far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        let start = self.start;
+        let mut elapsed = self.elapsed.subscribe();
+        Box::pin(async move {
+            // The sender lives as long as any clone of the clock; once they
+            // are all gone, time stops and the sleep never completes.
+            if elapsed
+                .wait_for(|elapsed| start + *elapsed >= deadline)
+                .await
+                .is_err()
+            {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+/// Reads `clock`, or tokio's clock if the builder set none.
+///
+/// Internal-only: This is called by generated code.
+#[doc(hidden)]
+pub fn now_on(clock: &Option<Arc<dyn Clock>>) -> Instant {
+    match clock {
+        Some(clock) => clock.now(),
+        None => crate::rt::now(),
+    }
+}
+
+/// Sleeps for `duration` on `clock`, or on tokio's clock if the builder set
+/// none.
+///
+/// Internal-only: This is called by generated code.
+#[doc(hidden)]
+pub fn sleep_on(
+    clock: &Option<Arc<dyn Clock>>,
+    duration: Duration,
+) -> impl Future<Output = ()> + Send + 'static {
+    let custom = clock
+        .as_ref()
+        .map(|clock| clock.sleep_until(clock.now() + duration));
+    let tokio = custom.is_none().then(|| crate::rt::sleep(duration));
+    async move {
+        match (custom, tokio) {
+            (Some(sleep), _) => sleep.await,
+            (None, Some(sleep)) => sleep.await,
+            (None, None) => {}
+        }
+    }
+}
+
+/// The state timeout of a running FSM.
+///
+/// Without a custom clock, a single tokio `Sleep` is allocated at spawn and
+/// reused for every timeout. Custom clocks allocate a sleep per armed timeout,
+/// and need no Tokio time driver. A [`manual`](Self::manual) timer allocates
+/// neither and never fires on its own; its owner checks
+/// [`is_due`](Self::is_due) instead.
+///
+/// Internal-only: This is driven by generated code.
+#[doc(hidden)]
+pub struct Timer {
+    clock: Option<Arc<dyn Clock>>,
+    tokio: Option<Pin<Box<Sleep>>>,
+    custom: Option<ClockSleep>,
+    deadline: Option<Instant>,
+    manual: bool,
+}
+
+impl Timer {
+    pub fn new(clock: Option<Arc<dyn Clock>>) -> Self {
+        Self {
+            tokio: clock
+                .is_none()
+                .then(|| Box::pin(crate::rt::sleep(Duration::ZERO))),
+            clock,
+            custom: None,
+            deadline: None,
+            manual: false,
+        }
+    }
+
+    /// Creates a timer that only tracks its deadline, usable outside a
+    /// Tokio runtime.
+    pub fn manual(clock: Option<Arc<dyn Clock>>) -> Self {
+        Self {
+            clock,
+            tokio: None,
+            custom: None,
+            deadline: None,
+            manual: true,
+        }
+    }
+
+    /// Fires the timer once `duration` has elapsed on the clock.
+    pub fn reset_after(&mut self, duration: Duration) {
+        self.reset_at(self.now() + duration);
+    }
+
+    /// Fires the timer once the clock reaches `deadline`.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        if !self.manual {
+            match (&self.clock, &mut self.tokio) {
+                (Some(clock), _) => self.custom = Some(clock.sleep_until(deadline)),
+                (None, Some(tokio)) => tokio.as_mut().reset(deadline),
+                (None, None) => {}
+            }
+        }
+        self.deadline = Some(deadline);
+    }
+
+    /// Stops the timer from firing until the next `reset_after`.
+    pub fn disarm(&mut self) {
+        self.custom = None;
+        self.deadline = None;
+    }
+
+    /// The time the timer fires at, if armed.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the timer is armed and its deadline has passed.
+    pub fn is_due(&self) -> bool {
+        self.deadline.is_some_and(|deadline| self.now() >= deadline)
+    }
+
+    /// Sleeps for `duration` on the timer's clock, independently of its
+    /// deadline, e.g. to time a handler against the builder's `watchdog`.
+    pub fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        sleep_on(&self.clock, duration)
+    }
+
+    /// Reads the timer's clock.
+    pub fn now(&self) -> Instant {
+        now_on(&self.clock)
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.deadline.is_none() {
+            return Poll::Pending;
+        }
+        let this = &mut *self;
+        let fired = match (&mut this.custom, &mut this.tokio) {
+            (Some(sleep), _) => sleep.as_mut().poll(cx),
+            (None, Some(sleep)) => sleep.as_mut().poll(cx),
+            (None, None) => Poll::Pending,
+        };
+        if fired.is_ready() {
+            this.disarm();
+        }
+        fired
+    }
+}
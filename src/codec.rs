@@ -0,0 +1,151 @@
+use std::{future::poll_fn, pin::pin};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::{FsmHandle, TransitionRecord};
+
+/// Converts an FSM's events from, and its states and transitions to, the
+/// bytes of a wire format, so it can be driven over a socket.
+///
+/// [`JsonCodec`] uses the FSM's serde derives, from `#[fsm(serde)]`.
+pub trait EventCodec<E, S> {
+    /// Why a frame couldn't be decoded or a value encoded.
+    type Error: std::error::Error;
+
+    /// Decodes an event from one frame.
+    fn decode_event(&mut self, frame: &[u8]) -> Result<E, Self::Error>;
+
+    /// Encodes a state, such as the result of a `call`.
+    fn encode_state(&mut self, state: &S) -> Result<Vec<u8>, Self::Error>;
+
+    /// Encodes a state change, as forwarded by [`drive_framed`].
+    fn encode_transition(&mut self, record: &TransitionRecord) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// An [`EventCodec`] for JSON, in the shape of the FSM's serde derives:
+/// `"Submit"` or `{"Pay":5}` for events and `"Paid"` for states.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl<E, S> EventCodec<E, S> for JsonCodec
+where
+    E: serde::de::DeserializeOwned,
+    S: serde::Serialize,
+{
+    type Error = serde_json::Error;
+
+    fn decode_event(&mut self, frame: &[u8]) -> Result<E, Self::Error> {
+        serde_json::from_slice(frame)
+    }
+
+    fn encode_state(&mut self, state: &S) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(state)
+    }
+
+    fn encode_transition(&mut self, record: &TransitionRecord) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(record)
+    }
+}
+
+/// Drives the FSM behind `handle` from a framed transport, such as a
+/// websocket or a `tokio_util::codec::Framed` TCP stream:
+///
+/// ```rust,ignore
+/// let transport = Framed::new(socket, LengthDelimitedCodec::new());
+/// tokio_fsm::drive_framed(&handle, JsonCodec, transport).await?;
+/// ```
+///
+/// Each frame read is decoded into an event and sent to the FSM. The FSM's
+/// current state, then each change after it, is written back as an encoded
+/// [`TransitionRecord`]. Changes are observed like `subscribe` does, so a
+/// burst of transitions may be written as just its latest.
+///
+/// Returns once the transport has no more frames, or once the FSM has
+/// stopped and its final change has been written. Events read after the
+/// FSM stopped are dropped.
+pub async fn drive_framed<H, C, T, B, F, E>(
+    handle: &H,
+    mut codec: C,
+    transport: T,
+) -> Result<(), FramedError<C::Error, E>>
+where
+    H: FsmHandle,
+    C: EventCodec<H::Event, H::State>,
+    T: Stream<Item = Result<B, E>> + Sink<F, Error = E>,
+    B: AsRef<[u8]>,
+    Vec<u8>: Into<F>,
+{
+    let inspector = handle.inspector();
+    let mut changes = handle.subscribe();
+    changes.borrow_and_update();
+    let mut transport = pin!(transport);
+    let mut running = true;
+
+    let record = (inspector.last_change)();
+    let frame = codec
+        .encode_transition(&record)
+        .map_err(FramedError::Encode)?;
+    write(transport.as_mut(), frame.into()).await?;
+
+    loop {
+        tokio::select! {
+            frame = poll_fn(|cx| transport.as_mut().poll_next(cx)) => {
+                let Some(frame) = frame else {
+                    return Ok(());
+                };
+                let frame = frame.map_err(FramedError::Transport)?;
+                if running {
+                    let event = codec
+                        .decode_event(frame.as_ref())
+                        .map_err(FramedError::Decode)?;
+                    running = handle.send(event).await.is_ok();
+                }
+            }
+            changed = changes.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                changes.borrow_and_update();
+                let record = (inspector.last_change)();
+                let frame = codec
+                    .encode_transition(&record)
+                    .map_err(FramedError::Encode)?;
+                write(transport.as_mut(), frame.into()).await?;
+            }
+        }
+    }
+}
+
+async fn write<T, F, C, E>(
+    mut transport: std::pin::Pin<&mut T>,
+    frame: F,
+) -> Result<(), FramedError<C, E>>
+where
+    T: Sink<F, Error = E>,
+{
+    poll_fn(|cx| transport.as_mut().poll_ready(cx))
+        .await
+        .map_err(FramedError::Transport)?;
+    transport
+        .as_mut()
+        .start_send(frame)
+        .map_err(FramedError::Transport)?;
+    poll_fn(|cx| transport.as_mut().poll_flush(cx))
+        .await
+        .map_err(FramedError::Transport)
+}
+
+/// Error returned by [`drive_framed`].
+#[derive(Debug, thiserror::Error)]
+pub enum FramedError<C, T> {
+    /// A frame wasn't a valid event.
+    #[error("failed to decode event: {0}")]
+    Decode(C),
+    /// A transition couldn't be encoded.
+    #[error("failed to encode transition: {0}")]
+    Encode(C),
+    /// Reading or writing a frame failed.
+    #[error("transport error: {0}")]
+    Transport(T),
+}
@@ -1,5 +1,23 @@
 //! Core runtime types for tokio-fsm.
 
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+
+use crate::rt::Instant;
+
 /// Represents a state transition in the FSM.
 ///
 /// This type is returned by FSM handlers to indicate which state the machine
@@ -9,35 +27,732 @@
 /// # Example
 ///
 /// ```rust
-/// # use tokio_fsm::Transition;
+/// # use tokio_fsm::{FsmState, Transition};
 /// # struct Running;
+/// # impl FsmState for Running { type Event = (); }
 /// async fn my_handler() -> Transition<Running> {
 ///     // Perform some async logic...
 ///     Transition::to(Running)
 /// }
 /// ```
 #[derive(Debug)]
-pub enum Transition<T> {
-    /// Transition to the specified target state.
-    To(T),
+#[must_use]
+pub struct Transition<T: FsmState> {
+    state: T,
+    effects: Vec<Effect>,
+    follow_ups: Vec<T::Event>,
+    halt: bool,
+    data: Option<Box<dyn Any + Send>>,
 }
 
-impl<T> Transition<T> {
+impl<T: FsmState> Transition<T> {
     /// Creates a new transition to the specified target state.
     ///
     /// The target state must be a valid state defined within the FSM.
-    #[must_use]
     pub fn to(state: T) -> Self {
-        Self::To(state)
+        Self {
+            state,
+            effects: Vec::new(),
+            follow_ups: Vec::new(),
+            halt: false,
+            data: None,
+        }
     }
 
-    /// Extracts the target state from the transition.
+    /// Creates a transition to `state` carrying `data` for the state's
+    /// `#[on_entry]` hook, which receives it once the transition is committed.
+    ///
+    /// Only states whose entry hook takes a value implement [`EntryData`], so
+    /// the value's type is checked at compile time.
     ///
-    /// Internal-only: This is typically used by the generated event loop.
+    /// ```rust
+    /// # use tokio_fsm::{EntryData, FsmState, Transition};
+    /// # struct Quoted;
+    /// # impl FsmState for Quoted { type Event = (); }
+    /// # impl EntryData for Quoted { type Data = u64; }
+    /// fn quoted(price: u64) -> Transition<Quoted> {
+    ///     Transition::to_with(Quoted, price)
+    /// }
+    /// ```
+    pub fn to_with(state: T, data: T::Data) -> Self
+    where
+        T: EntryData,
+    {
+        let mut transition = Self::to(state);
+        transition.data = Some(Box::new(data));
+        transition
+    }
+
+    /// Attaches side effects to this transition.
+    ///
+    /// Effects are executed by the run loop *after* the new state has been
+    /// committed, so a handler never awaits another FSM's queue while holding
+    /// its own loop.
+    ///
+    /// ```rust
+    /// # use tokio_fsm::{Effect, FsmHandle, FsmState, Transition};
+    /// # struct Running;
+    /// # impl FsmState for Running { type Event = (); }
+    /// fn notify<H: FsmHandle>(peer: &H, event: H::Event) -> Transition<Running> {
+    ///     Transition::to(Running).with_effects(vec![Effect::send(peer, event)])
+    /// }
+    /// ```
+    pub fn with_effects(mut self, effects: impl IntoIterator<Item = Effect>) -> Self {
+        self.effects.extend(effects);
+        self
+    }
+
+    /// Schedules `event` to be processed by this FSM right after the
+    /// transition.
+    ///
+    /// Follow-up events are handled before anything else in the queue, which
+    /// lets a handler drive a multi-step pipeline on its own. `event` is the
+    /// FSM's generated `[FsmName]Event`, as tied to the target state by
+    /// [`FsmState`], so another FSM's events don't compile.
+    ///
+    /// ```rust
+    /// # use tokio_fsm::{FsmState, Transition};
+    /// # struct Validated;
+    /// # enum OrderFsmEvent { Charge }
+    /// # impl FsmState for Validated { type Event = OrderFsmEvent; }
+    /// fn validated() -> Transition<Validated> {
+    ///     Transition::to(Validated).then(OrderFsmEvent::Charge)
+    /// }
+    /// ```
+    pub fn then(mut self, event: T::Event) -> Self {
+        self.follow_ups.push(event);
+        self
+    }
+
+    /// Stops the FSM once it has entered the target state, for workflows
+    /// that know when they are finished.
+    ///
+    /// The transition's effects still run, but follow-up and queued events
+    /// are dropped, as with an immediate shutdown. The task then resolves to
+    /// the context.
+    ///
+    /// ```rust
+    /// # use tokio_fsm::{FsmState, Transition};
+    /// # struct Completed;
+    /// # impl FsmState for Completed { type Event = (); }
+    /// fn completed() -> Transition<Completed> {
+    ///     Transition::to(Completed).halt()
+    /// }
+    /// ```
+    pub fn halt(mut self) -> Self {
+        self.halt = true;
+        self
+    }
+
+    /// Returns the state this transition moves to.
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Extracts the target state from the transition, discarding any effects,
+    /// follow-up events and [`to_with`](Self::to_with) data.
+    ///
+    /// The generated event loop keeps all of them; this is for code driving a
+    /// handler by hand, e.g. in unit tests.
     #[must_use]
     pub fn into_state(self) -> T {
+        self.state
+    }
+
+    /// Splits the transition into its target state and attached effects.
+    ///
+    /// Internal-only: This is used by the generated event loop.
+    #[doc(hidden)]
+    pub fn into_parts(self) -> TransitionParts<T> {
+        TransitionParts {
+            state: self.state,
+            effects: self.effects,
+            follow_ups: self.follow_ups,
+            halt: self.halt,
+            data: self.data,
+        }
+    }
+}
+
+/// A bare state marker is a transition to it, so handlers returning
+/// `impl Into<Transition<Running>>` can just return `Running`.
+impl<T: FsmState> From<T> for Transition<T> {
+    fn from(state: T) -> Self {
+        Self::to(state)
+    }
+}
+
+/// The decomposed form of a [`Transition`], consumed by the generated loop.
+#[doc(hidden)]
+pub struct TransitionParts<T: FsmState> {
+    pub state: T,
+    pub effects: Vec<Effect>,
+    pub follow_ups: Vec<T::Event>,
+    pub halt: bool,
+    pub data: Option<Box<dyn Any + Send>>,
+}
+
+/// A state of a generated FSM, which a [`Transition`] can target.
+///
+/// Implemented by `#[fsm]` on every state's marker type and on the
+/// `[FsmName]State` enum, tying them to the FSM's events.
+pub trait FsmState {
+    /// The generated `[FsmName]Event` enum.
+    type Event: Send + 'static;
+}
+
+/// The value a state's `#[on_entry]` hook takes, passed along with
+/// [`Transition::to_with`].
+///
+/// Implemented by `#[fsm]` on the marker type of every state whose entry
+/// hook takes a value.
+pub trait EntryData {
+    /// The hook's argument type.
+    type Data: Send + 'static;
+}
+
+/// A side effect requested by a handler.
+///
+/// Effects are attached to a [`Transition`] via [`Transition::with_effects`]
+/// and executed by the run loop once the transition is committed.
+pub struct Effect {
+    kind: EffectKind,
+}
+
+enum EffectKind {
+    Send(Box<dyn Deliver>),
+}
+
+impl Effect {
+    /// Sends `event` to the FSM behind `target`.
+    ///
+    /// Delivery never blocks the emitting FSM: if the peer's queue is full the
+    /// event is handed to a background task that waits for capacity. Events
+    /// sent to a closed FSM are dropped.
+    pub fn send<H: FsmHandle>(target: &H, event: H::Event) -> Self {
+        Self {
+            kind: EffectKind::Send(Box::new(Delivery {
+                target: target.clone(),
+                event,
+            })),
+        }
+    }
+
+    /// Executes the effect.
+    ///
+    /// Internal-only: This is called by the generated event loop.
+    #[doc(hidden)]
+    pub fn execute(self) {
+        match self.kind {
+            EffectKind::Send(delivery) => delivery.deliver(),
+        }
+    }
+}
+
+impl fmt::Debug for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            EffectKind::Send(_) => f.write_str("Effect::Send"),
+        }
+    }
+}
+
+trait Deliver: Send {
+    fn deliver(self: Box<Self>);
+}
+
+struct Delivery<H: FsmHandle> {
+    target: H,
+    event: H::Event,
+}
+
+impl<H: FsmHandle> Deliver for Delivery<H> {
+    fn deliver(self: Box<Self>) {
+        let Delivery { target, event } = *self;
+        if let Err(TrySendError::Full(event)) = target.try_send(event) {
+            crate::rt::spawn_detached(async move {
+                let _ = target.send(event).await;
+            });
+        }
+    }
+}
+
+/// Common interface implemented by every generated `[FsmName]Handle`.
+///
+/// This allows generic code (such as [`Effect::send`]) to address any FSM
+/// without knowing its concrete type.
+pub trait FsmHandle: Clone + Send + Sync + 'static {
+    /// The generated `[FsmName]State` enum.
+    type State: Copy + Send + Sync + 'static;
+    /// The generated `[FsmName]Event` enum.
+    type Event: Send + 'static;
+    /// The FSM's `type Context`.
+    type Context: Send + 'static;
+
+    /// Sends an event to the FSM, waiting for queue capacity.
+    fn send(
+        &self,
+        event: Self::Event,
+    ) -> impl Future<Output = Result<(), SendError<Self::Event>>> + Send;
+
+    /// Attempts to send an event without awaiting capacity.
+    fn try_send(&self, event: Self::Event) -> Result<(), TrySendError<Self::Event>>;
+
+    /// Sends anything convertible into an event, such as a payload type
+    /// carried by exactly one event, so producers can be generic over
+    /// `Into<H::Event>`.
+    fn send_into<T: Into<Self::Event>>(
+        &self,
+        value: T,
+    ) -> impl Future<Output = Result<(), SendError<Self::Event>>> + Send {
+        self.send(value.into())
+    }
+
+    /// Returns the current state of the FSM.
+    fn current_state(&self) -> Self::State;
+
+    /// Returns the number of events waiting in the queue.
+    fn queue_len(&self) -> usize;
+
+    /// Runs `f` on the FSM's context between handlers, like the handle's
+    /// `inspect`.
+    fn inspect<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Self::Context) -> R + Send + 'static,
+    ) -> impl Future<Output = Result<R, PingError>> + Send;
+
+    /// Subscribes to state change notifications, like the handle's
+    /// `subscribe`.
+    fn subscribe(&self) -> tokio::sync::watch::Receiver<StateChange<Self::State>>;
+
+    /// Subscribes to every state change, like the handle's
+    /// `subscribe_transitions`.
+    fn subscribe_transitions(
+        &self,
+    ) -> tokio::sync::mpsc::UnboundedReceiver<StateChange<Self::State>>;
+
+    /// Returns the identity of the FSM instance behind this handle.
+    fn id(&self) -> InstanceId;
+
+    /// Returns a type-erased view of the instance for introspection tools.
+    ///
+    /// Internal-only: This is implemented by generated handles.
+    #[doc(hidden)]
+    fn inspector(&self) -> crate::Inspector;
+}
+
+/// Request/response on top of [`FsmHandle`], implemented by every generated
+/// `[FsmName]Handle`.
+///
+/// It is a trait of its own so that handles implemented by hand needn't
+/// provide it.
+pub trait FsmCall: FsmHandle {
+    /// Sends an event and waits until it has been handled, like the
+    /// handle's `call`.
+    fn call(
+        &self,
+        event: Self::Event,
+    ) -> impl Future<Output = Result<Self::State, CallError<Self::Event>>> + Send;
+
+    /// Waits for room in the queue and holds it for
+    /// [`call_reserved`](Self::call_reserved), so backpressure applies
+    /// before there is an event to send, as in `FsmService::poll_ready`.
+    ///
+    /// Resolves to an empty reservation if the FSM has stopped; the call
+    /// then fails with `CallError::Closed`.
+    fn reserve(&self) -> impl Future<Output = Reservation<Self::Event>> + Send {
+        async { Reservation::none() }
+    }
+
+    /// Like [`call`](Self::call), sending into the room `reservation` holds
+    /// instead of waiting for capacity.
+    fn call_reserved(
+        &self,
+        reservation: Reservation<Self::Event>,
+        event: Self::Event,
+    ) -> impl Future<Output = Result<Self::State, CallError<Self::Event>>> + Send {
+        drop(reservation);
+        self.call(event)
+    }
+}
+
+/// Common interface implemented by every generated `[FsmName]Task`.
+///
+/// This allows a parent FSM's `spawn_child` to take ownership of any child
+/// FSM's task.
+pub trait FsmTask: Future + Unpin + Send + 'static {
+    /// Stops the FSM at its next await point, dropping unprocessed events.
+    fn abort(&self);
+
+    /// Returns the identity of the FSM instance this task runs.
+    fn id(&self) -> InstanceId;
+
+    /// Returns where the FSM is in its graph, or was when it stopped.
+    fn site(&self) -> FailureSite;
+
+    /// Returns a way to shut the FSM down that doesn't keep it alive.
+    ///
+    /// Internal-only: This is used by `FsmGroup`.
+    #[doc(hidden)]
+    fn shutdown_signal(&self) -> ShutdownSignal;
+}
+
+/// Aborts the wrapped FSM task when dropped, tying the child FSM to the
+/// lifetime of its owner.
+///
+/// Internal-only: This is created by the generated `spawn_child`.
+#[doc(hidden)]
+pub struct ChildTask<T: FsmTask>(pub T);
+
+impl<T: FsmTask> Future for ChildTask<T> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T: FsmTask> Drop for ChildTask<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// A state change notification published by the run loop.
+///
+/// Every committed transition produces a new `StateChange`, observable through
+/// the handle's `subscribe()` and `last_change()` methods. The sequence number
+/// increases by one per notification, so observers can detect round trips
+/// (`A -> B -> A`) and missed updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StateChange<S> {
+    /// Monotonic notification counter, starting at `0` for the initial state.
+    pub seq: u64,
+    /// The state the FSM was in before the change.
+    pub from: S,
+    /// The state the FSM is in after the change.
+    pub to: S,
+    /// What caused the change.
+    pub cause: TransitionCause,
+    /// When the FSM entered `to`. Transitions back into the same state keep
+    /// it, so retries don't hide how long the FSM has been stuck.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub entered: Instant,
+}
+
+impl<S: Copy + PartialEq> StateChange<S> {
+    /// The notification published when an FSM is spawned in `state` at
+    /// `now`, read from the FSM's clock.
+    #[must_use]
+    pub fn initial(state: S, now: Instant) -> Self {
+        Self {
+            seq: 0,
+            from: state,
+            to: state,
+            cause: TransitionCause::Initial,
+            entered: now,
+        }
+    }
+
+    /// Builds the notification that follows `self`, for a change made at
+    /// `now` on the FSM's clock.
+    #[must_use]
+    pub fn next(&self, to: S, cause: TransitionCause, now: Instant) -> Self {
+        Self {
+            seq: self.seq + 1,
+            from: self.to,
+            to,
+            cause,
+            entered: if to == self.to { self.entered } else { now },
+        }
+    }
+
+    /// How long the FSM has been in `to`, on tokio's clock. For an FSM
+    /// built with a custom `clock`, use the handle's `time_in_current_state`.
+    pub fn time_in_state(&self) -> Duration {
+        self.entered.elapsed()
+    }
+}
+
+type TransitionSubscribers<S> =
+    std::sync::Mutex<Vec<tokio::sync::mpsc::UnboundedSender<StateChange<S>>>>;
+
+/// Lossless subscribers to an FSM's state changes, fed by its run loop.
+///
+/// Unlike `subscribe`, which only holds the latest change, every change is
+/// buffered for each receiver until it is read. The FSM owns the feed, so
+/// receivers close once it is dropped.
+///
+/// Internal-only: This is owned by generated FSMs.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct TransitionFeed<S> {
+    subscribers: Arc<TransitionSubscribers<S>>,
+}
+
+impl<S> Default for TransitionFeed<S> {
+    fn default() -> Self {
+        Self {
+            subscribers: Arc::default(),
+        }
+    }
+}
+
+impl<S: Copy> TransitionFeed<S> {
+    /// Returns the view handles subscribe through.
+    pub fn watcher(&self) -> TransitionWatcher<S> {
+        TransitionWatcher {
+            subscribers: Arc::downgrade(&self.subscribers),
+        }
+    }
+
+    /// Hands `change` to every subscriber, forgetting those that are gone.
+    pub fn publish(&self, change: StateChange<S>) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|tx| tx.send(change).is_ok());
+    }
+}
+
+/// Subscribes to a [`TransitionFeed`] without keeping it alive.
+///
+/// Internal-only: This is shared by generated handles.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct TransitionWatcher<S> {
+    subscribers: Weak<TransitionSubscribers<S>>,
+}
+
+impl<S> Clone for TransitionWatcher<S> {
+    fn clone(&self) -> Self {
+        Self {
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+impl<S> TransitionWatcher<S> {
+    /// Returns a receiver of every change published from now on, already
+    /// closed if the FSM is gone.
+    pub fn subscribe(&self) -> tokio::sync::mpsc::UnboundedReceiver<StateChange<S>> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(tx);
+        }
+        rx
+    }
+}
+
+/// The reason behind a [`StateChange`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TransitionCause {
+    /// The FSM was just spawned.
+    Initial,
+    /// A handler for the named event ran.
+    Event(&'static str),
+    /// A state timeout fired.
+    Timeout,
+    /// An `#[auto]` handler ran upon entering the previous state.
+    Auto,
+    /// The `#[on_start]` handler ran before the first event.
+    Start,
+    /// The `#[on_orphaned]` handler ran after every handle was dropped.
+    Orphaned,
+    /// The `#[persist]` hook failed after a transition, diverting the FSM to
+    /// the hook's `on_error` state.
+    PersistFailed,
+    /// A handler panicked, diverting the FSM to the `#[fsm(on_panic)]` state.
+    Panicked,
+    /// The run loop failed and was restarted by the `#[fsm(restart)]`
+    /// policy.
+    Restarted,
+    /// The run loop stopped. `from` and `to` are both the final state.
+    Shutdown,
+}
+
+impl TransitionCause {
+    /// The kind of cause in snake case, such as `"event"` or
+    /// `"persist_failed"`, for reporting outside Rust.
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Self::To(state) => state,
+            Self::Initial => "initial",
+            Self::Event(_) => "event",
+            Self::Timeout => "timeout",
+            Self::Auto => "auto",
+            Self::Start => "start",
+            Self::Orphaned => "orphaned",
+            Self::PersistFailed => "persist_failed",
+            Self::Panicked => "panicked",
+            Self::Restarted => "restarted",
+            Self::Shutdown => "shutdown",
+        }
+    }
+
+    /// The event whose handler caused the change, if any.
+    pub fn event(&self) -> Option<&'static str> {
+        match self {
+            Self::Event(event) => Some(event),
+            _ => None,
+        }
+    }
+}
+
+/// A process-unique identifier for a spawned FSM instance.
+///
+/// Every call to a generated `spawn` allocates a fresh id, shared by all
+/// clones of the returned handle. Handles compare and hash by this id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstanceId(u64);
+
+impl InstanceId {
+    /// Allocates the next unused id.
+    ///
+    /// Internal-only: This is called by the generated `spawn`.
+    #[doc(hidden)]
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the raw numeric value of the id.
+    #[must_use]
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Error returned when parsing a generated State or Event enum from a string
+/// fails.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("'{name}' is not a valid {target}")]
+pub struct ParseNameError {
+    name: String,
+    target: &'static str,
+}
+
+impl ParseNameError {
+    /// Creates an error for `name`, which failed to parse into `target`.
+    ///
+    /// Internal-only: This is called by generated `FromStr` impls.
+    #[doc(hidden)]
+    pub fn new(name: &str, target: &'static str) -> Self {
+        Self {
+            name: name.to_owned(),
+            target,
+        }
+    }
+
+    /// Returns the input that failed to parse.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Error returned when converting an integer into a generated State enum
+/// declared with `#[fsm(state_repr = ...)]` fails.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{value} is not the discriminant of any {target}")]
+pub struct InvalidDiscriminant {
+    value: i128,
+    target: &'static str,
+}
+
+impl InvalidDiscriminant {
+    /// Creates an error for `value`, which is no discriminant of `target`.
+    ///
+    /// Internal-only: This is called by generated `TryFrom` impls.
+    #[doc(hidden)]
+    pub fn new(value: i128, target: &'static str) -> Self {
+        Self { value, target }
+    }
+
+    /// Returns the value that failed to convert.
+    #[must_use]
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+}
+
+/// Error returned by an event validator registered with the generated
+/// builder's `validator`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid event: {message}")]
+pub struct ValidationError {
+    message: std::borrow::Cow<'static, str>,
+}
+
+impl ValidationError {
+    /// Creates an error explaining why the event was rejected.
+    pub fn new(message: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    /// Returns the reason the event was rejected.
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Error returned by the generated handle's `submit` and `try_submit`.
+///
+/// Every variant hands the event back to the caller.
+#[derive(Debug, thiserror::Error)]
+pub enum SubmitError<E> {
+    /// The builder's validator rejected the event; it was never enqueued.
+    #[error("{error}")]
+    Invalid { event: E, error: ValidationError },
+    /// The queue is full (`try_submit` only).
+    #[error("no available capacity")]
+    Full(E),
+    /// The queue was at or above the builder's `shed_above` watermark; the
+    /// event was rejected to keep latency bounded.
+    #[error("load shed")]
+    Shed(E),
+    /// The FSM has stopped.
+    #[error("channel closed")]
+    Closed(E),
+}
+
+impl<E> SubmitError<E> {
+    /// Recovers the rejected event.
+    pub fn into_event(self) -> E {
+        match self {
+            Self::Invalid { event, .. }
+            | Self::Full(event)
+            | Self::Shed(event)
+            | Self::Closed(event) => event,
+        }
+    }
+}
+
+impl<E> From<SendError<E>> for SubmitError<E> {
+    fn from(SendError(event): SendError<E>) -> Self {
+        Self::Closed(event)
+    }
+}
+
+impl<E> From<TrySendError<E>> for SubmitError<E> {
+    fn from(err: TrySendError<E>) -> Self {
+        match err {
+            TrySendError::Full(event) => Self::Full(event),
+            TrySendError::Closed(event) => Self::Closed(event),
         }
     }
 }
@@ -51,14 +766,820 @@ pub enum ShutdownMode {
     Graceful,
     /// Immediate shutdown: The event loop terminates immediately, dropping any
     /// unprocessed events in the queue, and returns the current context.
+    /// Call the handle's `drain_pending()` first to keep those events.
     Immediate,
 }
 
+/// Requests a shutdown of an FSM without keeping it alive, as held by its
+/// task.
+///
+/// Internal-only: This is created by generated `spawn` functions.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal(pub Weak<crate::rt::watch::Sender<Option<ShutdownMode>>>);
+
+impl ShutdownSignal {
+    /// Asks the FSM to stop in `mode`. Does nothing once every handle is
+    /// dropped, as the FSM is then stopping anyway.
+    pub fn send(&self, mode: ShutdownMode) {
+        if let Some(shutdown_tx) = self.0.upgrade() {
+            let _ = shutdown_tx.send(Some(mode));
+        }
+    }
+}
+
+/// Out-of-band requests from a handle to the run loop, delivered on a
+/// channel separate from events so they never queue behind them.
+///
+/// Internal-only: This is constructed by generated handle methods.
+#[doc(hidden)]
+pub enum Control<E> {
+    /// Remove every queued event and send them back in order.
+    Drain(tokio::sync::oneshot::Sender<Vec<E>>),
+    /// Remove the queued events matching the predicate and send them back in
+    /// order.
+    Purge(
+        Box<dyn FnMut(&E) -> bool + Send>,
+        tokio::sync::oneshot::Sender<Vec<E>>,
+    ),
+    /// Reply as soon as the run loop gets to it.
+    Ping(tokio::sync::oneshot::Sender<()>),
+    /// Call with the FSM's context, which the closure downcasts.
+    Inspect(InspectFn),
+    /// Start handling the events the stream yields.
+    Attach(Source<E>),
+}
+
+/// A closure run on the context by [`Control::Inspect`].
+type InspectFn = Box<dyn FnOnce(&dyn Any) + Send>;
+
+impl<E> fmt::Debug for Control<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drain(_) => f.write_str("Control::Drain"),
+            Self::Purge(..) => f.write_str("Control::Purge"),
+            Self::Ping(_) => f.write_str("Control::Ping"),
+            Self::Inspect(_) => f.write_str("Control::Inspect"),
+            Self::Attach(_) => f.write_str("Control::Attach"),
+        }
+    }
+}
+
+/// An event in the queue, with when it was sent and the deadline it has to
+/// be handled by.
+///
+/// Internal-only: This is what generated senders enqueue.
+#[doc(hidden)]
+pub struct Envelope<E> {
+    pub event: E,
+    pub enqueued_at: Instant,
+    pub deadline: Option<Instant>,
+    /// Set by the handle's `call`, to hear which state the event left the FSM
+    /// in.
+    pub ack: Option<Ack>,
+}
+
+/// A closure the run loop calls with the FSM's state, which it downcasts,
+/// once the event carrying it has been handled, or with the reason it was
+/// discarded instead.
+#[doc(hidden)]
+pub type Ack = Box<dyn FnOnce(Result<&dyn Any, DropReason>) + Send + Sync>;
+
+/// An event on the priority lane or among the follow-ups, with the [`Ack`]
+/// of a `call` waiting on it, if any.
+#[doc(hidden)]
+pub type Acked<E> = (E, Option<Ack>);
+
+impl<E> Envelope<E> {
+    /// Wraps `event`, stamped as enqueued at `now`, read from the FSM's
+    /// clock.
+    pub fn new(event: E, deadline: Option<Instant>, now: Instant) -> Self {
+        Self {
+            event,
+            enqueued_at: now,
+            deadline,
+            ack: None,
+        }
+    }
+
+    /// Whether the deadline has passed by `now`, read from the FSM's clock,
+    /// so the event should be dropped.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+
+    /// Takes the event out, stamping it as dequeued at `now`.
+    pub fn open(self, now: Instant) -> (E, EventMeta, Option<Ack>) {
+        let meta = EventMeta {
+            enqueued_at: self.enqueued_at,
+            dequeued_at: now,
+            deadline: self.deadline,
+        };
+        (self.event, meta, self.ack)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Envelope<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Envelope")
+            .field("event", &self.event)
+            .field("enqueued_at", &self.enqueued_at)
+            .field("deadline", &self.deadline)
+            .field("ack", &self.ack.is_some())
+            .finish()
+    }
+}
+
+/// When an event went through the queue, as returned by `self.event_meta()`
+/// inside its handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventMeta {
+    enqueued_at: Instant,
+    dequeued_at: Instant,
+    deadline: Option<Instant>,
+}
+
+impl EventMeta {
+    /// When the event was sent.
+    pub fn enqueued_at(&self) -> Instant {
+        self.enqueued_at
+    }
+
+    /// When the run loop took the event off the queue.
+    pub fn dequeued_at(&self) -> Instant {
+        self.dequeued_at
+    }
+
+    /// How long the event waited in the queue.
+    pub fn queue_delay(&self) -> Duration {
+        self.dequeued_at - self.enqueued_at
+    }
+
+    /// How long ago the event was sent, by tokio's clock. Under a custom
+    /// `clock`, subtract [`enqueued_at`](Self::enqueued_at) from its `now()`
+    /// instead.
+    pub fn age(&self) -> Duration {
+        self.enqueued_at.elapsed()
+    }
+
+    /// The deadline given to `send_with_ttl`, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+/// A boxed event stream attached with the generated handle's `attach_source`.
+pub type Source<E> = Pin<Box<dyn Stream<Item = E> + Send>>;
+
+/// The event streams attached to a running FSM.
+///
+/// Internal-only: This is polled by generated run loops.
+#[doc(hidden)]
+pub struct Sources<E> {
+    /// Streams that ended are set to `None` and swept after each poll.
+    ///
+    /// Only ever accessed through `&mut self`; the mutex just makes the FSM
+    /// `Sync` for handlers holding `&self` across an await.
+    streams: std::sync::Mutex<Vec<Option<Source<E>>>>,
+    /// Index polled first next time, so one busy stream can't starve the rest.
+    next: usize,
+}
+
+impl<E> Sources<E> {
+    pub fn new() -> Self {
+        Self {
+            streams: std::sync::Mutex::default(),
+            next: 0,
+        }
+    }
+
+    fn streams_mut(&mut self) -> &mut Vec<Option<Source<E>>> {
+        self.streams
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn attach(&mut self, source: Source<E>) {
+        self.streams_mut().push(Some(source));
+    }
+
+    /// Waits for the next event from any stream, dropping streams as they
+    /// end. Never completes while no stream is attached.
+    pub fn recv(&mut self) -> impl Future<Output = E> + '_ {
+        std::future::poll_fn(move |cx| {
+            let start = self.next;
+            let streams = self.streams_mut();
+            let len = streams.len();
+            let mut ready = None;
+            let mut ended = false;
+            for offset in 0..len {
+                let index = (start + offset) % len;
+                let Some(stream) = &mut streams[index] else {
+                    continue;
+                };
+                match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(event)) => {
+                        ready = Some((index, event));
+                        break;
+                    }
+                    Poll::Ready(None) => {
+                        streams[index] = None;
+                        ended = true;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+            if ended {
+                streams.retain(Option::is_some);
+            }
+            let ready = ready.map(|(index, event)| {
+                self.next = index + 1;
+                event
+            });
+            match ready {
+                Some(event) => Poll::Ready(event),
+                None => Poll::Pending,
+            }
+        })
+    }
+}
+
+impl<E> Default for Sources<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> fmt::Debug for Sources<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sources")
+            .field(
+                "streams",
+                &self.streams.lock().map_or(0, |streams| streams.len()),
+            )
+            .finish()
+    }
+}
+
+/// The send started by the last `Sink::start_send` on a handle, driven to
+/// completion by the next `poll_ready` or `poll_flush`. Empty without the
+/// `sink` feature.
+///
+/// Clones start out empty, so each handle clone is a separate sink.
+///
+/// Internal-only: This backs the generated handle's `Sink` impl.
+#[doc(hidden)]
+pub struct SinkSlot<E> {
+    /// Only ever accessed through `&mut self`; the mutex keeps the handle
+    /// `Sync`.
+    #[cfg(feature = "sink")]
+    send: std::sync::Mutex<Option<SinkSend<E>>>,
+    _event: std::marker::PhantomData<fn(E)>,
+}
+
+#[cfg(feature = "sink")]
+type SinkSend<E> = Pin<Box<dyn Future<Output = Result<(), SendError<E>>> + Send>>;
+
+impl<E> SinkSlot<E> {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "sink")]
+            send: std::sync::Mutex::new(None),
+            _event: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "sink")]
+impl<E> SinkSlot<E> {
+    /// Stores the send of the next item. The previous one must have been
+    /// flushed.
+    pub fn start(&mut self, send: impl Future<Output = Result<(), SendError<E>>> + Send + 'static) {
+        *self.send_mut() = Some(Box::pin(send));
+    }
+
+    /// Drives the stored send, if any, to completion.
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SendError<E>>> {
+        let slot = self.send_mut();
+        let Some(send) = slot else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = std::task::ready!(send.as_mut().poll(cx));
+        *slot = None;
+        Poll::Ready(result)
+    }
+
+    fn send_mut(&mut self) -> &mut Option<SinkSend<E>> {
+        self.send
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<E> Default for SinkSlot<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Clone for SinkSlot<E> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+/// Implements `futures_sink::Sink` for a generated handle with the `sink`
+/// feature, and nothing without it. Each item is sent like `send`, so it
+/// waits for queue capacity while flushing; closing the sink doesn't stop the
+/// FSM.
+///
+/// Internal-only: This is invoked by generated code, which can't see which
+/// features of this crate are enabled.
+#[cfg(feature = "sink")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_sink {
+    ($handle:ty, $event:ty) => {
+        impl $crate::__private::futures_sink::Sink<$event> for $handle {
+            type Error = ::tokio::sync::mpsc::error::SendError<$event>;
+
+            fn poll_ready(
+                self: ::std::pin::Pin<&mut Self>,
+                cx: &mut ::std::task::Context<'_>,
+            ) -> ::std::task::Poll<Result<(), Self::Error>> {
+                self.get_mut().sink.poll_flush(cx)
+            }
+
+            fn start_send(
+                self: ::std::pin::Pin<&mut Self>,
+                event: $event,
+            ) -> Result<(), Self::Error> {
+                let this = self.get_mut();
+                let sender = this.sender.clone();
+                this.sink.start(async move { sender.send(event).await });
+                Ok(())
+            }
+
+            fn poll_flush(
+                self: ::std::pin::Pin<&mut Self>,
+                cx: &mut ::std::task::Context<'_>,
+            ) -> ::std::task::Poll<Result<(), Self::Error>> {
+                self.get_mut().sink.poll_flush(cx)
+            }
+
+            fn poll_close(
+                self: ::std::pin::Pin<&mut Self>,
+                cx: &mut ::std::task::Context<'_>,
+            ) -> ::std::task::Poll<Result<(), Self::Error>> {
+                self.get_mut().sink.poll_flush(cx)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "sink"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_sink {
+    ($handle:ty, $event:ty) => {};
+}
+
+/// Implements `arbitrary::Arbitrary` for a generated event enum with the
+/// `arbitrary` feature, and nothing without it. The impl is bounded on every
+/// payload type implementing `Arbitrary`, so it simply doesn't apply to enums
+/// whose payloads can't be generated.
+///
+/// Internal-only: This is invoked by generated code, which can't see which
+/// features of this crate are enabled.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_arbitrary {
+    ($event:ident { $($variant:ident $(($payload:ty))?),* $(,)? }) => {
+        impl<'a> $crate::__private::arbitrary::Arbitrary<'a> for $event
+        where
+            $($($payload: $crate::__private::arbitrary::Arbitrary<'a>,)?)*
+        {
+            #[allow(unused_assignments)]
+            fn arbitrary(
+                u: &mut $crate::__private::arbitrary::Unstructured<'a>,
+            ) -> $crate::__private::arbitrary::Result<Self> {
+                let mut index = u.choose_index($event::NAMES.len())?;
+                $(
+                    if index == 0 {
+                        return Ok(Self::$variant $((
+                            <$payload as $crate::__private::arbitrary::Arbitrary<'a>>::arbitrary(u)?
+                        ))?);
+                    }
+                    index -= 1;
+                )*
+                unreachable!()
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "arbitrary"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_arbitrary {
+    ($($tt:tt)*) => {};
+}
+
+/// Implements `arbitrary::Arbitrary` for a generated payload struct with the
+/// `arbitrary` feature, bounded like [`__impl_arbitrary`].
+///
+/// Internal-only: This is invoked by generated code.
+#[cfg(feature = "arbitrary")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_arbitrary_payload {
+    ($payload:ident { $($field:ident: $ty:ty),* $(,)? }) => {
+        impl<'a> $crate::__private::arbitrary::Arbitrary<'a> for $payload
+        where
+            $($ty: $crate::__private::arbitrary::Arbitrary<'a>,)*
+        {
+            fn arbitrary(
+                u: &mut $crate::__private::arbitrary::Unstructured<'a>,
+            ) -> $crate::__private::arbitrary::Result<Self> {
+                Ok(Self {
+                    $($field: <$ty as $crate::__private::arbitrary::Arbitrary<'a>>::arbitrary(u)?,)*
+                })
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "arbitrary"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_arbitrary_payload {
+    ($($tt:tt)*) => {};
+}
+
+/// Error returned by the generated handle's `call`.
+#[derive(Debug, thiserror::Error)]
+pub enum CallError<E> {
+    /// The FSM has stopped; the event was never enqueued.
+    #[error("channel closed")]
+    Closed(E),
+    /// The event was enqueued but never handled: the FSM stopped first.
+    #[error("event dropped before it was handled")]
+    Dropped,
+    /// The FSM discarded the event on purpose: the builder's validator
+    /// rejected it, it was shed, superseded by a coalesced event, expired or
+    /// purged, or a `#[preempt]` event cancelled its handler.
+    #[error("event discarded: {0}")]
+    Discarded(DropReason),
+}
+
+/// Room in an FSM's queue, held from [`FsmCall::reserve`] until an event is
+/// sent into it with [`FsmCall::call_reserved`]. Dropping it gives the room
+/// back.
+///
+/// Only the default tokio channel can hold room ahead of a send; with the
+/// `flume` and `kanal` backends a reservation is empty and the send waits
+/// for capacity itself.
+pub struct Reservation<E> {
+    permit: Option<tokio::sync::mpsc::OwnedPermit<Envelope<E>>>,
+}
+
+impl<E> Reservation<E> {
+    /// A reservation holding no room, for channels that can't reserve or
+    /// FSMs that have stopped.
+    pub fn none() -> Self {
+        Self { permit: None }
+    }
+
+    /// Wraps a slot of the tokio queue.
+    ///
+    /// Internal-only: This is called by generated senders.
+    #[doc(hidden)]
+    pub fn permit(permit: tokio::sync::mpsc::OwnedPermit<Envelope<E>>) -> Self {
+        Self {
+            permit: Some(permit),
+        }
+    }
+
+    /// Takes the slot out, if there is one.
+    ///
+    /// Internal-only: This is called by generated senders.
+    #[doc(hidden)]
+    pub fn into_permit(self) -> Option<tokio::sync::mpsc::OwnedPermit<Envelope<E>>> {
+        self.permit
+    }
+}
+
+impl<E> fmt::Debug for Reservation<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reservation")
+            .field("reserved", &self.permit.is_some())
+            .finish()
+    }
+}
+
+/// Error returned by the generated handle's `ping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PingError {
+    /// The FSM's task has stopped, so nothing answered.
+    #[error("FSM has stopped")]
+    Stopped,
+}
+
+/// Tracks how many events per `coalesce_by` key are queued, so the run loop
+/// can skip all but the latest.
+///
+/// Internal-only: This is shared by generated handles and run loops.
+#[doc(hidden)]
+#[derive(Debug, Clone, Default)]
+pub struct Coalescer {
+    queued: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<CoalesceKey, usize>>>,
+}
+
+impl Coalescer {
+    fn counts(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<CoalesceKey, usize>> {
+        self.queued
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Called before an event with `key` is sent. The count is taken back
+    /// when the returned guard drops, unless the send went through and it
+    /// was [`commit`](CoalesceGuard::commit)ted: a send that fails, or is
+    /// cancelled while waiting for capacity, must not leave the count
+    /// behind, or every later event with `key` would be superseded.
+    pub fn enqueue(&self, key: CoalesceKey) -> CoalesceGuard<'_> {
+        *self.counts().entry(key.clone()).or_insert(0) += 1;
+        CoalesceGuard {
+            coalescer: self,
+            key: Some(key),
+        }
+    }
+
+    /// Called when an event taken off the queue has `key`. Returns `true` if
+    /// a newer event with the same key is still queued, making it stale.
+    pub fn dequeue(&self, key: CoalesceKey) -> bool {
+        let mut counts = self.counts();
+        match counts.get_mut(&key) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                true
+            }
+            Some(_) => {
+                counts.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// A [`Coalescer::enqueue`] not yet known to have been sent.
+///
+/// Internal-only: This is held by generated handles while sending.
+#[doc(hidden)]
+#[must_use]
+#[derive(Debug)]
+pub struct CoalesceGuard<'a> {
+    coalescer: &'a Coalescer,
+    key: Option<CoalesceKey>,
+}
+
+impl CoalesceGuard<'_> {
+    /// Keeps the count: the event is in the queue.
+    pub fn commit(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for CoalesceGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.coalescer.dequeue(key);
+        }
+    }
+}
+
+/// Whether an FSM's queue is congested, as published by the handle's
+/// `queue_pressure()`.
+///
+/// The queue turns [`High`](Self::High) once it holds at least the high
+/// watermark set with the builder's `watermarks(low, high)`, and goes back to
+/// [`Normal`](Self::Normal) only once it has drained to the low watermark.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum QueuePressure {
+    /// Below the high watermark, or back at the low watermark since.
+    #[default]
+    Normal,
+    /// At or above the high watermark.
+    High,
+}
+
+/// Compares queue occupancy against the builder's watermarks and publishes
+/// crossings as [`QueuePressure`].
+///
+/// Internal-only: This is shared by generated handles and run loops.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct Watermarks {
+    inner: std::sync::Arc<WatermarksInner>,
+}
+
+#[derive(Debug)]
+struct WatermarksInner {
+    /// `(low, high)`, or `None` if the queue isn't watched.
+    thresholds: Option<(usize, usize)>,
+    pressure: tokio::sync::watch::Sender<QueuePressure>,
+}
+
+impl Watermarks {
+    pub fn new(thresholds: Option<(usize, usize)>) -> Self {
+        Self {
+            inner: std::sync::Arc::new(WatermarksInner {
+                thresholds,
+                pressure: tokio::sync::watch::Sender::new(QueuePressure::Normal),
+            }),
+        }
+    }
+
+    /// Called with the queue length after an event is enqueued or dequeued.
+    #[inline]
+    pub fn observe(&self, len: usize) {
+        let Some((low, high)) = self.inner.thresholds else {
+            return;
+        };
+        self.inner.pressure.send_if_modified(|pressure| {
+            let next = match *pressure {
+                QueuePressure::Normal if len >= high => QueuePressure::High,
+                QueuePressure::High if len <= low => QueuePressure::Normal,
+                _ => return false,
+            };
+            *pressure = next;
+            true
+        });
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<QueuePressure> {
+        self.inner.pressure.subscribe()
+    }
+}
+
+/// An event name paired with a type-erased `coalesce_by` key.
+///
+/// Internal-only: This is built by generated code.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct CoalesceKey {
+    event: &'static str,
+    key: Arc<dyn DynKey>,
+}
+
+impl CoalesceKey {
+    pub fn new<K: Eq + std::hash::Hash + Send + Sync + 'static>(
+        event: &'static str,
+        key: K,
+    ) -> Self {
+        Self {
+            event,
+            key: Arc::new(key),
+        }
+    }
+}
+
+impl fmt::Debug for CoalesceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoalesceKey")
+            .field("event", &self.event)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for CoalesceKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.event == other.event && self.key.eq_dyn(&*other.key)
+    }
+}
+
+impl Eq for CoalesceKey {}
+
+impl std::hash::Hash for CoalesceKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.event.hash(state);
+        self.key.hash_dyn(state);
+    }
+}
+
+trait DynKey: Any + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn eq_dyn(&self, other: &dyn DynKey) -> bool;
+    fn hash_dyn(&self, state: &mut dyn std::hash::Hasher);
+}
+
+impl<K: Eq + std::hash::Hash + Send + Sync + 'static> DynKey for K {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn eq_dyn(&self, other: &dyn DynKey) -> bool {
+        other.as_any().downcast_ref::<K>() == Some(self)
+    }
+
+    fn hash_dyn(&self, mut state: &mut dyn std::hash::Hasher) {
+        self.hash(&mut state);
+    }
+}
+
+/// Why an event was dropped without running a handler.
+///
+/// With the `tracing` feature, every drop is reported as a structured event
+/// on the `tokio_fsm` target carrying the FSM name, state, event name and
+/// this reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DropReason {
+    /// The current state has no handler for the event.
+    NoHandler,
+    /// The event queue was full when `try_send` was called.
+    QueueFull,
+    /// The FSM was shutting down or had already stopped.
+    ShuttingDown,
+    /// A newer event with the same `coalesce_by` key was queued behind it.
+    Superseded,
+    /// The queue was above the builder's `shed_above` watermark when the
+    /// event was sent.
+    Shed,
+    /// The builder's `validator` rejected the event.
+    Invalid,
+    /// The event was sent with `send_with_ttl` and its deadline passed
+    /// while it was queued.
+    Expired,
+    /// A `#[preempt]` event cancelled its handler before it finished.
+    Preempted,
+    /// The handle's `purge` or `drain_pending` removed it from the queue.
+    Purged,
+}
+
+impl DropReason {
+    /// Returns the reason as a `snake_case` label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoHandler => "no_handler",
+            Self::QueueFull => "queue_full",
+            Self::ShuttingDown => "shutting_down",
+            Self::Superseded => "superseded",
+            Self::Shed => "shed",
+            Self::Invalid => "invalid",
+            Self::Expired => "expired",
+            Self::Preempted => "preempted",
+            Self::Purged => "purged",
+        }
+    }
+
+    /// Reports a dropped event. A no-op without the `tracing` feature.
+    ///
+    /// Internal-only: This is called by generated code.
+    #[doc(hidden)]
+    #[inline]
+    pub fn trace(self, fsm: &'static str, state: &'static str, event: &'static str) {
+        #[cfg(feature = "tracing")]
+        match self {
+            Self::NoHandler | Self::Superseded | Self::Preempted | Self::Purged => tracing::debug!(
+                target: "tokio_fsm",
+                fsm,
+                state,
+                event,
+                reason = self.as_str(),
+                "event dropped"
+            ),
+            Self::QueueFull | Self::Shed | Self::Invalid | Self::ShuttingDown | Self::Expired => {
+                tracing::warn!(
+                    target: "tokio_fsm",
+                    fsm,
+                    state,
+                    event,
+                    reason = self.as_str(),
+                    "event dropped"
+                )
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = (fsm, state, event);
+    }
+}
+
+impl std::fmt::Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Error type returned by the FSM background task.
 ///
 /// This enum distinguishes between logical errors returned by your FSM handlers
 /// and runtime failures of the Tokio task itself (e.g., panics or
-/// cancellation).
+/// cancellation). The task's `site()` tells where in its graph the FSM was
+/// when either happened.
 ///
 /// # Type Parameters
 ///
@@ -72,4 +1593,92 @@ pub enum TaskError<E> {
     /// The background task failed due to a panic or external cancellation.
     #[error("Task join error: {0}")]
     Join(#[from] tokio::task::JoinError),
+    /// The task panicked, with this message, or was aborted (`None`) on a
+    /// custom `Runtime`, which has no `JoinError` to report it with.
+    #[error("Task failed: {message}", message = .0.as_deref().unwrap_or("aborted"))]
+    Runtime(Option<String>),
+}
+
+/// Polls `future`, catching a panic in any poll and returning its message
+/// instead of unwinding.
+///
+/// The future is treated as unwind safe: whatever it had mutated before
+/// panicking stays as it was.
+///
+/// Internal-only: This wraps handlers of FSMs declared with `on_panic`.
+#[doc(hidden)]
+pub async fn catch_unwind<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(panic_message(payload))),
+        }
+    })
+    .await
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => (*message).to_owned(),
+            Err(_) => "Box<dyn Any>".to_owned(),
+        },
+    }
+}
+
+/// Where in its graph an FSM was when its task failed: the last published
+/// state, and the event whose handler was running, if any. Returned by
+/// [`FsmTask::site`] and the generated task's `site()`:
+///
+/// ```rust,ignore
+/// if let Err(err) = (&mut task).await {
+///     tracing::error!(%err, site = %task.site(), "FSM failed");
+/// }
+/// ```
+///
+/// A panic in a handler is reported with the state the handler was called
+/// in, since its transition was never committed. Failures outside of event
+/// handlers, e.g. in `#[on_timeout]`, have no event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureSite {
+    /// Name of the state the FSM was in.
+    pub state: &'static str,
+    /// Name of the event being handled, if any.
+    pub event: Option<&'static str>,
+}
+
+impl fmt::Display for FailureSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.event {
+            Some(event) => write!(f, "in state `{}` handling `{event}`", self.state),
+            None => write!(f, "in state `{}`", self.state),
+        }
+    }
+}
+
+/// The index, within the event enum's `NAMES`, of the event being handled,
+/// readable from the task after the run loop has died.
+///
+/// Internal-only: This is shared by generated run loops and tasks.
+#[doc(hidden)]
+#[derive(Debug, Clone, Default)]
+pub struct ActiveEvent(std::sync::Arc<AtomicUsize>);
+
+impl ActiveEvent {
+    #[inline]
+    pub fn set(&self, index: usize) {
+        self.0.store(index + 1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn clear(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<usize> {
+        self.0.load(Ordering::Relaxed).checked_sub(1)
+    }
 }
@@ -0,0 +1,175 @@
+//! HTTP introspection endpoint for running FSMs.
+//!
+//! Register handles with a [`Registry`] and mount [`router`] into an axum app:
+//!
+//! ```rust,ignore
+//! let registry = tokio_fsm::debug::Registry::new();
+//! registry.register(&handle);
+//!
+//! let app = axum::Router::new().nest("/debug/fsm", tokio_fsm::debug::router(registry));
+//! ```
+//!
+//! | Route           | Response                                               |
+//! |-----------------|--------------------------------------------------------|
+//! | `GET /`         | Every registered instance with its state and queue depth |
+//! | `GET /{id}`     | One instance, including its recent transitions        |
+//! | `GET /{id}/mermaid` | The instance's FSM as a Mermaid `stateDiagram-v2` |
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+};
+use serde::Serialize;
+
+use crate::{FsmHandle, Inspector, TransitionCause, TransitionRecord};
+
+/// The set of FSM instances exposed by [`router`].
+///
+/// Cloning a registry shares it. Instances that have shut down are reported
+/// as stopped until the next [`register`](Self::register), which drops them.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    instances: Arc<RwLock<BTreeMap<u64, Inspector>>>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exposes the instance behind `handle`.
+    pub fn register<H: FsmHandle>(&self, handle: &H) {
+        let inspector = handle.inspector();
+        let mut instances = self
+            .instances
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        instances.retain(|_, inspector| !is_stopped(inspector));
+        instances.insert(inspector.id.as_u64(), inspector);
+    }
+
+    /// Stops exposing the instance with the given id.
+    pub fn unregister(&self, id: crate::InstanceId) {
+        self.instances
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&id.as_u64());
+    }
+
+    fn get(&self, id: u64) -> Option<Inspector> {
+        self.instances
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&id)
+            .cloned()
+    }
+
+    fn all(&self) -> Vec<Inspector> {
+        self.instances
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Builds the introspection routes over `registry`.
+pub fn router(registry: Registry) -> Router {
+    Router::new()
+        .route("/", get(list))
+        .route("/{id}", get(detail))
+        .route("/{id}/mermaid", get(mermaid))
+        .with_state(registry)
+}
+
+#[derive(Serialize)]
+struct Summary {
+    id: u64,
+    fsm: &'static str,
+    state: &'static str,
+    stopped: bool,
+    queue_depth: usize,
+}
+
+#[derive(Serialize)]
+struct Detail {
+    #[serde(flatten)]
+    summary: Summary,
+    recent_transitions: Vec<Change>,
+}
+
+#[derive(Serialize)]
+struct Change {
+    seq: u64,
+    from: &'static str,
+    to: &'static str,
+    cause: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'static str>,
+}
+
+impl From<TransitionRecord> for Change {
+    fn from(record: TransitionRecord) -> Self {
+        Self {
+            seq: record.seq,
+            from: record.from,
+            to: record.to,
+            cause: record.cause.as_str(),
+            event: record.cause.event(),
+        }
+    }
+}
+
+fn is_stopped(inspector: &Inspector) -> bool {
+    (inspector.last_change)().cause == TransitionCause::Shutdown
+}
+
+fn summarize(inspector: &Inspector) -> Summary {
+    let last = (inspector.last_change)();
+    Summary {
+        id: inspector.id.as_u64(),
+        fsm: inspector.stats.fsm(),
+        state: last.to,
+        stopped: last.cause == TransitionCause::Shutdown,
+        queue_depth: inspector.stats.queue_depth(),
+    }
+}
+
+async fn list(State(registry): State<Registry>) -> Json<Vec<Summary>> {
+    Json(registry.all().iter().map(summarize).collect())
+}
+
+async fn detail(
+    State(registry): State<Registry>,
+    Path(id): Path<u64>,
+) -> Result<Json<Detail>, StatusCode> {
+    let inspector = registry.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(Detail {
+        summary: summarize(&inspector),
+        recent_transitions: inspector
+            .stats
+            .history()
+            .into_iter()
+            .map(Change::from)
+            .collect(),
+    }))
+}
+
+async fn mermaid(
+    State(registry): State<Registry>,
+    Path(id): Path<u64>,
+) -> Result<&'static str, StatusCode> {
+    registry
+        .get(id)
+        .map(|inspector| inspector.mermaid)
+        .ok_or(StatusCode::NOT_FOUND)
+}
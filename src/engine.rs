@@ -0,0 +1,564 @@
+//! The run loop shared by every generated FSM.
+//!
+//! The macro implements [`Machine`] with the FSM's dispatch table and hooks;
+//! the queues, shutdown handling and timeout plumbing around them live here,
+//! once, rather than in every expansion.
+//!
+//! Internal-only: This is driven by generated code.
+
+use std::{any::Any, collections::VecDeque, future::Future, sync::Arc};
+
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    Ack, Acked, Clock, CoalesceKey, Coalescer, Control, DropReason, Envelope, EventMeta,
+    ShutdownMode, Sources, StateChange, StatsRecorder, Timer, TransitionCause, TransitionFeed,
+    TransitionRecord, Watermarks, WorkSet,
+    rt::Instant,
+    schedule::{Schedule, Scheduler},
+};
+
+/// The receiving end of an FSM's event queue, whichever channel backend
+/// `#[fsm(channel = ...)]` selected.
+pub trait EventQueue<E>: Send {
+    /// Waits for the next envelope, or `None` once every sender is gone.
+    fn recv(&mut self) -> impl Future<Output = Option<Envelope<E>>> + Send;
+
+    /// Takes the next already-queued envelope, without waiting.
+    fn try_recv(&mut self) -> Option<Envelope<E>>;
+
+    /// The number of queued envelopes.
+    fn queued(&self) -> usize;
+}
+
+impl<E: Send> EventQueue<E> for mpsc::Receiver<Envelope<E>> {
+    fn recv(&mut self) -> impl Future<Output = Option<Envelope<E>>> + Send {
+        mpsc::Receiver::recv(self)
+    }
+
+    fn try_recv(&mut self) -> Option<Envelope<E>> {
+        mpsc::Receiver::try_recv(self).ok()
+    }
+
+    fn queued(&self) -> usize {
+        mpsc::Receiver::len(self)
+    }
+}
+
+#[cfg(feature = "flume")]
+impl<E: Send> EventQueue<E> for flume::Receiver<Envelope<E>> {
+    async fn recv(&mut self) -> Option<Envelope<E>> {
+        self.recv_async().await.ok()
+    }
+
+    fn try_recv(&mut self) -> Option<Envelope<E>> {
+        flume::Receiver::try_recv(self).ok()
+    }
+
+    fn queued(&self) -> usize {
+        flume::Receiver::len(self)
+    }
+}
+
+#[cfg(feature = "kanal")]
+impl<E: Send> EventQueue<E> for kanal::AsyncReceiver<Envelope<E>> {
+    async fn recv(&mut self) -> Option<Envelope<E>> {
+        kanal::AsyncReceiver::recv(self).await.ok()
+    }
+
+    fn try_recv(&mut self) -> Option<Envelope<E>> {
+        kanal::AsyncReceiver::try_recv(self).ok().flatten()
+    }
+
+    fn queued(&self) -> usize {
+        kanal::AsyncReceiver::len(self)
+    }
+}
+
+/// A generated FSM, as seen by [`run`]: its state, the queues it owns and the
+/// handlers to call.
+///
+/// Hooks the FSM doesn't declare keep their default, which does nothing.
+pub trait Machine: Send {
+    type State: Copy + PartialEq + Send + Sync + 'static;
+    type Event: Send + 'static;
+    type Error: Send;
+
+    /// The FSM's type name, for drop reports.
+    const NAME: &'static str;
+
+    fn state(&self) -> Self::State;
+
+    fn state_name(state: Self::State) -> &'static str;
+
+    fn is_terminal(state: Self::State) -> bool;
+
+    fn event_name(event: &Self::Event) -> &'static str;
+
+    fn coalesce_key(event: &Self::Event) -> Option<CoalesceKey>;
+
+    /// Reads the FSM's clock, which queued events are stamped and their
+    /// deadlines checked against.
+    fn now(&self) -> Instant;
+
+    /// Whether a committed `Transition::halt` ended the run.
+    fn halted(&self) -> bool;
+
+    /// Whether a `#[preempt]` event cancelled the handler of the event
+    /// dispatched last.
+    fn preempted(&self) -> bool;
+
+    /// Whether the FSM keeps running once every handle is gone.
+    fn keeps_orphaned(&self) -> bool;
+
+    fn context(&self) -> &dyn Any;
+
+    fn stats(&self) -> &StatsRecorder;
+
+    fn coalescer(&self) -> &Coalescer;
+
+    fn transitions(&self) -> &TransitionFeed<Self::State>;
+
+    fn watermarks(&self) -> &Watermarks;
+
+    /// Follow-up events scheduled by handlers, and preempting events put off
+    /// until the running handler is done.
+    fn pending(&mut self) -> &mut VecDeque<Acked<Self::Event>>;
+
+    /// Queued events a purge kept, taken off the queue whole, with their
+    /// acks and deadlines, and handled before anything still in it.
+    fn requeued(&mut self) -> &mut VecDeque<Envelope<Self::Event>>;
+
+    /// `spawn_work` tasks and attached streams, borrowed together so both
+    /// can be polled at once.
+    fn background(&mut self) -> (&mut WorkSet<Self::Event>, &mut Sources<Self::Event>);
+
+    /// Handles `event` in the current state.
+    fn dispatch(
+        &mut self,
+        event: Self::Event,
+        meta: Option<EventMeta>,
+        priority: &mut mpsc::UnboundedReceiver<Acked<Self::Event>>,
+        state_tx: &watch::Sender<StateChange<Self::State>>,
+        timer: &mut Timer,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Runs the `#[on_start]` handler.
+    fn start(
+        &mut self,
+        state_tx: &watch::Sender<StateChange<Self::State>>,
+        timer: &mut Timer,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        let _ = (state_tx, timer);
+        async { Ok(()) }
+    }
+
+    /// Runs whatever the current state does on its own before waiting:
+    /// syncing `#[submachine]` children and its `#[auto]` handler. Returns
+    /// `true` if the auto handler ran.
+    fn step(
+        &mut self,
+        state_tx: &watch::Sender<StateChange<Self::State>>,
+        timer: &mut Timer,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        let _ = (state_tx, timer);
+        async { Ok(false) }
+    }
+
+    /// Runs the `#[on_timeout]` handler once the state timeout has fired.
+    fn timeout(
+        &mut self,
+        state_tx: &watch::Sender<StateChange<Self::State>>,
+        timer: &mut Timer,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        let _ = (state_tx, timer);
+        async { Ok(()) }
+    }
+
+    /// Runs the `#[on_orphaned]` handler once the last handle is gone.
+    fn orphaned(
+        &mut self,
+        state_tx: &watch::Sender<StateChange<Self::State>>,
+        timer: &mut Timer,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        let _ = (state_tx, timer);
+        async { Ok(()) }
+    }
+
+    /// The `#[schedule]` entries the run loop sends events on.
+    fn schedules(&self) -> Vec<Schedule<Self::Event>> {
+        Vec::new()
+    }
+
+    /// Runs the `#[on_shutdown]` hook as the run loop exits.
+    fn shutdown(&mut self, mode: ShutdownMode) -> impl Future<Output = ()> + Send {
+        let _ = mode;
+        async {}
+    }
+}
+
+/// Takes the next queued event, skipping events superseded by a newer one
+/// with the same `coalesce_by` key and events past their deadline.
+pub fn next_queued<M: Machine>(
+    machine: &mut M,
+    events: &mut impl EventQueue<M::Event>,
+) -> Option<(M::Event, EventMeta)> {
+    next_opened(machine, events).map(|(event, meta, _)| (event, meta))
+}
+
+/// Like [`next_queued`], keeping the event's [`Ack`].
+fn next_opened<M: Machine>(
+    machine: &mut M,
+    events: &mut impl EventQueue<M::Event>,
+) -> Option<(M::Event, EventMeta, Option<Ack>)> {
+    loop {
+        let envelope = match machine.requeued().pop_front() {
+            Some(envelope) => envelope,
+            None => events.try_recv()?,
+        };
+        if let Some(opened) = open(machine, envelope, events.queued()) {
+            return Some(opened);
+        }
+    }
+}
+
+/// Takes the event out of a dequeued `envelope`, or `None` if it was dropped.
+fn open<M: Machine>(
+    machine: &M,
+    envelope: Envelope<M::Event>,
+    queue_len: usize,
+) -> Option<(M::Event, EventMeta, Option<Ack>)> {
+    dequeued(machine, queue_len);
+    let now = machine.now();
+    let expired = envelope.is_expired(now);
+    let (event, meta, ack) = envelope.open(now);
+    let reason = if let Some(key) = M::coalesce_key(&event)
+        && machine.coalescer().dequeue(key)
+    {
+        DropReason::Superseded
+    } else if expired {
+        DropReason::Expired
+    } else {
+        return Some((event, meta, ack));
+    };
+    reason.trace(
+        M::NAME,
+        M::state_name(machine.state()),
+        M::event_name(&event),
+    );
+    if let Some(ack) = ack {
+        ack(Err(reason));
+    }
+    None
+}
+
+/// Accounts for an envelope taken off the queue, leaving `queue_len` behind.
+fn dequeued<M: Machine>(machine: &M, queue_len: usize) {
+    if crate::__private::INTROSPECT {
+        machine.stats().dequeued();
+    }
+    machine.watermarks().observe(queue_len);
+}
+
+/// Reports a purged event and fails the `call` waiting on it, if any.
+fn discard_purged<M: Machine>(machine: &M, event: &M::Event, ack: Option<Ack>) {
+    DropReason::Purged.trace(
+        M::NAME,
+        M::state_name(machine.state()),
+        M::event_name(event),
+    );
+    if let Some(ack) = ack {
+        ack(Err(DropReason::Purged));
+    }
+}
+
+/// Tells the handle's `call` which state its event left the FSM in, or that
+/// a preempting event cancelled its handler.
+fn acknowledge<M: Machine>(machine: &M, ack: Option<Ack>) {
+    if let Some(ack) = ack {
+        if machine.preempted() {
+            ack(Err(DropReason::Preempted));
+        } else {
+            ack(Ok(&machine.state()));
+        }
+    }
+}
+
+/// Removes every queued event as the run loop exits, in the order it would
+/// have handled them. Expired and superseded events are reported as such
+/// rather than returned.
+fn drain_queued<M: Machine>(
+    machine: &mut M,
+    priority: &mut mpsc::UnboundedReceiver<Acked<M::Event>>,
+    events: &mut impl EventQueue<M::Event>,
+) -> Vec<M::Event> {
+    let mut drained: Vec<M::Event> = machine
+        .pending()
+        .drain(..)
+        .map(|(event, _)| event)
+        .collect();
+    drained.extend(std::iter::from_fn(|| priority.try_recv().ok()).map(|(event, _)| event));
+    drained.extend(std::iter::from_fn(|| {
+        next_queued(machine, events).map(|(event, _)| event)
+    }));
+    drained
+}
+
+/// Removes the queued events `matches` selects and returns them in the order
+/// the run loop would have handled them, failing their `call`s with
+/// [`DropReason::Purged`]. The rest keep their place: queued survivors stay
+/// whole in [`Machine::requeued`], so a `call` waiting on one still hears
+/// back and a TTL still applies.
+fn purge_queued<M: Machine>(
+    machine: &mut M,
+    priority: &mut mpsc::UnboundedReceiver<Acked<M::Event>>,
+    events: &mut impl EventQueue<M::Event>,
+    matches: &mut (dyn FnMut(&M::Event) -> bool + Send),
+) -> Vec<M::Event> {
+    let mut purged = Vec::new();
+    // Follow-ups, then preempting events, which are still handled before
+    // anything queued.
+    let mut kept = VecDeque::new();
+    let lanes = machine
+        .pending()
+        .drain(..)
+        .chain(std::iter::from_fn(|| priority.try_recv().ok()))
+        .collect::<Vec<_>>();
+    for (event, ack) in lanes {
+        if matches(&event) {
+            discard_purged(machine, &event, ack);
+            purged.push(event);
+        } else {
+            kept.push_back((event, ack));
+        }
+    }
+    *machine.pending() = kept;
+
+    let mut queued: VecDeque<_> = machine.requeued().drain(..).collect();
+    queued.extend(std::iter::from_fn(|| events.try_recv()));
+    for envelope in queued {
+        if !matches(&envelope.event) {
+            machine.requeued().push_back(envelope);
+            continue;
+        }
+        // Matches are handed back even if they expired or were superseded,
+        // giving back their coalesce counts like handled events do.
+        dequeued(machine, events.queued());
+        let Envelope { event, ack, .. } = envelope;
+        if let Some(key) = M::coalesce_key(&event) {
+            machine.coalescer().dequeue(key);
+        }
+        discard_purged(machine, &event, ack);
+        purged.push(event);
+    }
+    purged
+}
+
+/// Reports a transition to `target` that `handler` returned outside its
+/// `#[targets]`. Graph validation relied on the list, so the transition is
+/// refused and the FSM stays in `state`.
+pub fn reject_off_target(
+    fsm: &'static str,
+    state: &'static str,
+    handler: &'static str,
+    target: &'static str,
+) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        target: "tokio_fsm",
+        fsm,
+        state,
+        handler,
+        to = target,
+        "transition outside #[targets] refused"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (fsm, state, handler, target);
+}
+
+/// Hands the change just published on `state_tx` to the FSM's
+/// `subscribe_transitions` receivers and appends it to the instance's
+/// introspection history.
+pub fn record_change<M: Machine>(machine: &M, state_tx: &watch::Sender<StateChange<M::State>>) {
+    let change = *state_tx.borrow();
+    machine.transitions().publish(change);
+    if crate::__private::INTROSPECT {
+        machine.stats().record_transition(TransitionRecord {
+            seq: change.seq,
+            from: M::state_name(change.from),
+            to: M::state_name(change.to),
+            cause: change.cause,
+        });
+    }
+}
+
+/// The event loop. Borrows its channels so that a restart can run it again
+/// on the same queues.
+pub async fn run<M: Machine>(
+    machine: &mut M,
+    events: &mut impl EventQueue<M::Event>,
+    priority: &mut mpsc::UnboundedReceiver<Acked<M::Event>>,
+    control: &mut mpsc::UnboundedReceiver<Control<M::Event>>,
+    shutdown: &mut watch::Receiver<Option<ShutdownMode>>,
+    state_tx: &watch::Sender<StateChange<M::State>>,
+    clock: Option<Arc<dyn Clock>>,
+) -> Result<(), M::Error> {
+    let mut timer = Timer::new(clock.clone());
+    let mut scheduler = Scheduler::new(machine.schedules(), clock);
+    // Set once every handle is gone while the FSM is detached.
+    let mut orphaned = false;
+    machine.start(state_tx, &mut timer).await?;
+
+    let mode = loop {
+        // A halting transition ends the loop like an immediate shutdown,
+        // before anything else runs in its target state.
+        if machine.halted() {
+            break ShutdownMode::Immediate;
+        }
+        // Nothing outside can stop an orphaned FSM, so it ends once no
+        // transition can move it on.
+        if orphaned && M::is_terminal(machine.state()) {
+            break ShutdownMode::Graceful;
+        }
+        // Entering a state with an `#[auto]` handler runs it before
+        // anything else.
+        if machine.step(state_tx, &mut timer).await? {
+            continue;
+        }
+
+        // Follow-up events scheduled by handlers run before anything else in
+        // the queue.
+        if let Some((event, ack)) = machine.pending().pop_front() {
+            if *shutdown.borrow() == Some(ShutdownMode::Immediate) {
+                machine.pending().push_front((event, ack));
+                break ShutdownMode::Immediate;
+            }
+            machine
+                .dispatch(event, None, priority, state_tx, &mut timer)
+                .await?;
+            acknowledge(machine, ack);
+            continue;
+        }
+        // Then purge survivors, which were queued ahead of anything still
+        // in the queue.
+        if let Some(envelope) = machine.requeued().pop_front() {
+            if *shutdown.borrow() == Some(ShutdownMode::Immediate) {
+                machine.requeued().push_front(envelope);
+                break ShutdownMode::Immediate;
+            }
+            if let Some((event, meta, ack)) = open(machine, envelope, events.queued()) {
+                machine
+                    .dispatch(event, Some(meta), priority, state_tx, &mut timer)
+                    .await?;
+                acknowledge(machine, ack);
+            }
+            continue;
+        }
+
+        let (work, sources) = machine.background();
+        // Polled in order: timeouts, shutdown, control requests, preempting
+        // events, `spawn_work` completions and scheduled events take priority
+        // over queued events.
+        tokio::select! {
+            biased;
+
+            _ = &mut timer => {
+                machine.timeout(state_tx, &mut timer).await?;
+            }
+            changed = shutdown.changed(), if !orphaned => {
+                let mode = match changed {
+                    Ok(()) => *shutdown.borrow(),
+                    // Every handle is gone. A detached FSM keeps running; the
+                    // closed channel is polled no more.
+                    Err(_) if machine.keeps_orphaned() => {
+                        orphaned = true;
+                        machine.orphaned(state_tx, &mut timer).await?;
+                        None
+                    }
+                    // Otherwise nothing can be sent any more except by the
+                    // FSM itself, so finish up gracefully.
+                    Err(_) => Some(ShutdownMode::Graceful),
+                };
+                match mode {
+                    Some(ShutdownMode::Immediate) => break ShutdownMode::Immediate,
+                    Some(ShutdownMode::Graceful) => {
+                        while !machine.halted() {
+                            // Auto transitions and submachine sync still
+                            // run between the events being drained.
+                            if machine.step(state_tx, &mut timer).await? {
+                                continue;
+                            }
+                            if let Some((event, ack)) = machine
+                                .pending()
+                                .pop_front()
+                                .or_else(|| priority.try_recv().ok())
+                            {
+                                machine.dispatch(event, None, priority, state_tx, &mut timer).await?;
+                                acknowledge(machine, ack);
+                            } else if let Some((event, meta, ack)) = next_opened(machine, events) {
+                                machine.dispatch(event, Some(meta), priority, state_tx, &mut timer).await?;
+                                acknowledge(machine, ack);
+                            } else {
+                                break;
+                            }
+                        }
+                        break ShutdownMode::Graceful;
+                    }
+                    None => {}
+                }
+            }
+            Some(command) = control.recv() => {
+                match command {
+                    Control::Drain(reply) => {
+                        let _ = reply.send(purge_queued(machine, priority, events, &mut |_| true));
+                    }
+                    Control::Ping(reply) => {
+                        let _ = reply.send(());
+                    }
+                    Control::Inspect(inspect) => inspect(machine.context()),
+                    Control::Attach(source) => machine.background().1.attach(source),
+                    Control::Purge(mut matches, reply) => {
+                        let _ = reply.send(purge_queued(machine, priority, events, &mut *matches));
+                    }
+                }
+            }
+            Some((event, ack)) = priority.recv() => {
+                machine.dispatch(event, None, priority, state_tx, &mut timer).await?;
+                acknowledge(machine, ack);
+            }
+            Some(event) = work.join_next() => {
+                machine.dispatch(event, None, priority, state_tx, &mut timer).await?;
+            }
+            event = &mut scheduler => {
+                machine.dispatch(event, None, priority, state_tx, &mut timer).await?;
+            }
+            envelope = events.recv() => {
+                let Some(envelope) = envelope else { break ShutdownMode::Graceful };
+                if let Some((event, meta, ack)) = open(machine, envelope, events.queued()) {
+                    machine.dispatch(event, Some(meta), priority, state_tx, &mut timer).await?;
+                    acknowledge(machine, ack);
+                }
+            }
+            // Attached streams come last, like a second queue.
+            event = sources.recv() => {
+                machine.dispatch(event, None, priority, state_tx, &mut timer).await?;
+            }
+        }
+    };
+
+    if crate::__private::TRACE_DROPS {
+        let state = M::state_name(machine.state());
+        for event in drain_queued(machine, priority, events) {
+            DropReason::ShuttingDown.trace(M::NAME, state, M::event_name(&event));
+        }
+    }
+    // The hook runs before observers see the `Shutdown` change, so they can
+    // rely on whatever it flushed.
+    machine.shutdown(mode).await;
+
+    let state = machine.state();
+    let now = timer.now();
+    state_tx.send_modify(|change| *change = change.next(state, TransitionCause::Shutdown, now));
+    record_change(machine, state_tx);
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use crate::{
+    core::{ChildTask, FsmTask, InstanceId, ShutdownMode, ShutdownSignal},
+    rt::JoinSet,
+};
+
+/// A set of running FSM tasks of one type, joined as they complete.
+///
+/// Like a `JoinSet` of the tasks, but results keep their types and come with
+/// the instance they belong to:
+///
+/// ```rust,ignore
+/// let mut group = FsmGroup::new();
+/// for session in sessions {
+///     let (handle, task) = SessionFsm::spawn(session);
+///     registry.insert(handle.id(), handle);
+///     group.push(task);
+/// }
+/// while let Some((id, result)) = group.join_next().await {
+///     registry.remove(&id);
+///     if let Err(err) = result {
+///         tracing::warn!(%err, "session failed");
+///     }
+/// }
+/// ```
+///
+/// Dropping the group aborts the FSMs still running in it. To stop a fleet
+/// in an orderly way, use
+/// [`shutdown_graceful_within`](Self::shutdown_graceful_within).
+pub struct FsmGroup<T: FsmTask> {
+    tasks: JoinSet<(InstanceId, T::Output)>,
+    /// Shutdown signals of the FSMs not joined yet.
+    running: BTreeMap<InstanceId, ShutdownSignal>,
+}
+
+impl<T> FsmGroup<T>
+where
+    T: FsmTask,
+    T::Output: Send + 'static,
+{
+    /// Creates an empty group.
+    pub fn new() -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            running: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a running FSM's task to the group.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    pub fn push(&mut self, task: T) {
+        let id = task.id();
+        self.running.insert(id, task.shutdown_signal());
+        let task = ChildTask(task);
+        self.tasks.spawn(async move { (id, task.await) });
+    }
+
+    /// Waits for the next FSM in the group to stop, returning its instance id
+    /// and what its task resolved to, or `None` once the group is empty.
+    pub async fn join_next(&mut self) -> Option<(InstanceId, T::Output)> {
+        loop {
+            match self.tasks.join_next().await? {
+                Ok(done) => {
+                    self.running.remove(&done.0);
+                    return Some(done);
+                }
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Shuts every FSM in the group down gracefully and waits up to `within`,
+    /// shared by all of them, for their queues to drain.
+    ///
+    /// FSMs still running at the deadline are aborted and reported as
+    /// stragglers. The group is empty afterwards.
+    pub async fn shutdown_graceful_within(
+        &mut self,
+        within: Duration,
+    ) -> ShutdownReport<T::Output> {
+        for signal in self.running.values() {
+            signal.send(ShutdownMode::Graceful);
+        }
+
+        let mut stopped = Vec::with_capacity(self.running.len());
+        let deadline = crate::rt::sleep(within);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                biased;
+
+                done = self.join_next() => match done {
+                    Some(done) => stopped.push(done),
+                    None => break,
+                },
+                _ = &mut deadline => break,
+            }
+        }
+
+        let stragglers = std::mem::take(&mut self.running).into_keys().collect();
+        self.tasks.shutdown().await;
+        ShutdownReport {
+            stopped,
+            stragglers,
+        }
+    }
+
+    /// Returns the number of FSMs in the group that haven't been joined yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns `true` if the group has no FSMs left to join.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// The outcome of [`FsmGroup::shutdown_graceful_within`].
+#[derive(Debug)]
+pub struct ShutdownReport<R> {
+    /// The FSMs that stopped before the deadline, in the order they did, with
+    /// what their tasks resolved to.
+    pub stopped: Vec<(InstanceId, R)>,
+    /// The FSMs that were still running at the deadline and were aborted, in
+    /// id order.
+    pub stragglers: Vec<InstanceId>,
+}
+
+impl<R> ShutdownReport<R> {
+    /// Returns `true` if every FSM stopped before the deadline.
+    pub fn is_clean(&self) -> bool {
+        self.stragglers.is_empty()
+    }
+}
+
+impl<T> Default for FsmGroup<T>
+where
+    T: FsmTask,
+    T::Output: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Extend<T> for FsmGroup<T>
+where
+    T: FsmTask,
+    T::Output: Send + 'static,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, tasks: I) {
+        for task in tasks {
+            self.push(task);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for FsmGroup<T>
+where
+    T: FsmTask,
+    T::Output: Send + 'static,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(tasks: I) -> Self {
+        let mut group = Self::new();
+        group.extend(tasks);
+        group
+    }
+}
+
+impl<T: FsmTask> fmt::Debug for FsmGroup<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsmGroup")
+            .field("len", &self.tasks.len())
+            .finish()
+    }
+}
@@ -0,0 +1,378 @@
+//! Driving and observing FSM instances over gRPC.
+//!
+//! [`ControlPlane`] serves an [`FsmManager`] as the `tokio_fsm.v1.ControlPlane`
+//! service described by [`PROTO`], and [`ControlPlaneClient`] calls it:
+//!
+//! ```rust,ignore
+//! let orders = FsmManager::<OrderFsmHandle>::new();
+//! tonic::transport::Server::builder()
+//!     .add_service(ControlPlane::new(orders.clone()))
+//!     .serve(addr)
+//!     .await?;
+//!
+//! let mut client = ControlPlaneClient::new(Channel::from_static("http://[::1]:50051").connect().await?);
+//! let state = client.send_event("order-1", r#""Ship""#).await?;
+//! ```
+//!
+//! Events arrive as JSON, so the FSM needs `#[fsm(serde)]`. States are sent
+//! by name.
+
+use std::{convert::Infallible, fmt::Display};
+
+use serde::de::DeserializeOwned;
+use tokio_stream::{StreamExt, wrappers::WatchStream};
+use tonic::{
+    Request, Response, Status, Streaming,
+    codegen::{Body, BoxFuture, BoxStream, Bytes, Context, Poll, Service, StdError, http},
+    server::{NamedService, ServerStreamingService, UnaryService},
+};
+
+pub use crate::manager::{FsmManager, ManagerError};
+use crate::{FsmCall, FsmHandle, StateChange};
+
+/// The service definition [`ControlPlane`] implements, for generating
+/// clients in other languages.
+pub const PROTO: &str = include_str!("../proto/control_plane.proto");
+
+/// The fully qualified name of the service.
+pub const SERVICE_NAME: &str = "tokio_fsm.v1.ControlPlane";
+
+/// Request for `SendEvent`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SendEventRequest {
+    #[prost(string, tag = "1")]
+    pub instance: String,
+    /// The event, as JSON.
+    #[prost(string, tag = "2")]
+    pub event: String,
+}
+
+/// Response to `SendEvent`: the state the event left the instance in.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SendEventResponse {
+    #[prost(string, tag = "1")]
+    pub state: String,
+}
+
+/// Request for `GetState`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetStateRequest {
+    #[prost(string, tag = "1")]
+    pub instance: String,
+}
+
+/// Response to `GetState`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetStateResponse {
+    #[prost(string, tag = "1")]
+    pub state: String,
+    /// The `seq` of the change that entered `state`.
+    #[prost(uint64, tag = "2")]
+    pub seq: u64,
+    /// Events waiting in the instance's queue.
+    #[prost(uint64, tag = "3")]
+    pub queue_len: u64,
+}
+
+/// Request for `SubscribeTransitions`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeTransitionsRequest {
+    #[prost(string, tag = "1")]
+    pub instance: String,
+}
+
+/// A [`StateChange`], as streamed by `SubscribeTransitions`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Transition {
+    #[prost(uint64, tag = "1")]
+    pub seq: u64,
+    #[prost(string, tag = "2")]
+    pub from: String,
+    #[prost(string, tag = "3")]
+    pub to: String,
+    /// The [`TransitionCause`](crate::TransitionCause), as returned by its
+    /// `as_str`.
+    #[prost(string, tag = "4")]
+    pub cause: String,
+    /// The event whose handler caused the change, for `event` causes.
+    #[prost(string, optional, tag = "5")]
+    pub event: Option<String>,
+}
+
+impl<S: Display> From<StateChange<S>> for Transition {
+    fn from(change: StateChange<S>) -> Self {
+        Self {
+            seq: change.seq,
+            from: change.from.to_string(),
+            to: change.to.to_string(),
+            cause: change.cause.as_str().to_owned(),
+            event: change.cause.event().map(str::to_owned),
+        }
+    }
+}
+
+/// The `tokio_fsm.v1.ControlPlane` service over the instances in an
+/// [`FsmManager`].
+///
+/// `SendEvent` waits until the event has been handled, like the handle's
+/// `call`. `SubscribeTransitions` starts with the current state and ends
+/// when the instance stops; a slow reader sees only the latest change, as
+/// with the handle's `subscribe`.
+pub struct ControlPlane<H: FsmHandle> {
+    manager: FsmManager<H>,
+}
+
+impl<H: FsmHandle> ControlPlane<H> {
+    /// Serves the instances in `manager`, including any registered later.
+    pub fn new(manager: FsmManager<H>) -> Self {
+        Self { manager }
+    }
+
+    /// Returns the manager being served.
+    pub fn manager(&self) -> &FsmManager<H> {
+        &self.manager
+    }
+}
+
+impl<H: FsmHandle> Clone for ControlPlane<H> {
+    fn clone(&self) -> Self {
+        Self {
+            manager: self.manager.clone(),
+        }
+    }
+}
+
+impl<H: FsmHandle> std::fmt::Debug for ControlPlane<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ControlPlane")
+            .field("manager", &self.manager)
+            .finish()
+    }
+}
+
+impl<H: FsmHandle> NamedService for ControlPlane<H> {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl<H, B> Service<http::Request<B>> for ControlPlane<H>
+where
+    H: FsmCall,
+    H::Event: DeserializeOwned,
+    H::State: Display,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let manager = self.manager.clone();
+        match request.uri().path() {
+            "/tokio_fsm.v1.ControlPlane/SendEvent" => {
+                Box::pin(async move { Ok(grpc().unary(SendEventSvc(manager), request).await) })
+            }
+            "/tokio_fsm.v1.ControlPlane/GetState" => {
+                Box::pin(async move { Ok(grpc().unary(GetStateSvc(manager), request).await) })
+            }
+            "/tokio_fsm.v1.ControlPlane/SubscribeTransitions" => Box::pin(async move {
+                Ok(grpc()
+                    .server_streaming(SubscribeTransitionsSvc(manager), request)
+                    .await)
+            }),
+            _ => Box::pin(async move { Ok(Status::unimplemented("").into_http()) }),
+        }
+    }
+}
+
+fn grpc<T, U>() -> tonic::server::Grpc<tonic_prost::ProstCodec<T, U>>
+where
+    T: prost::Message + Send + 'static,
+    U: prost::Message + Default + Send + 'static,
+{
+    tonic::server::Grpc::new(tonic_prost::ProstCodec::default())
+}
+
+struct SendEventSvc<H: FsmHandle>(FsmManager<H>);
+
+impl<H> UnaryService<SendEventRequest> for SendEventSvc<H>
+where
+    H: FsmCall,
+    H::Event: DeserializeOwned,
+    H::State: Display,
+{
+    type Response = SendEventResponse;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<SendEventRequest>) -> Self::Future {
+        let manager = self.0.clone();
+        Box::pin(async move {
+            let SendEventRequest { instance, event } = request.into_inner();
+            let event = serde_json::from_str(&event)
+                .map_err(|err| Status::invalid_argument(format!("invalid event: {err}")))?;
+            let state = manager.call(&instance, event).await?;
+            Ok(Response::new(SendEventResponse {
+                state: state.to_string(),
+            }))
+        })
+    }
+}
+
+struct GetStateSvc<H: FsmHandle>(FsmManager<H>);
+
+impl<H> UnaryService<GetStateRequest> for GetStateSvc<H>
+where
+    H: FsmHandle,
+    H::State: Display,
+{
+    type Response = GetStateResponse;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<GetStateRequest>) -> Self::Future {
+        let handle = self.0.get(&request.get_ref().instance);
+        Box::pin(async move {
+            let handle = handle.ok_or(ManagerError::<H::Event>::NotFound)?;
+            let change = *handle.subscribe().borrow();
+            Ok(Response::new(GetStateResponse {
+                state: change.to.to_string(),
+                seq: change.seq,
+                queue_len: handle.queue_len() as u64,
+            }))
+        })
+    }
+}
+
+struct SubscribeTransitionsSvc<H: FsmHandle>(FsmManager<H>);
+
+impl<H> ServerStreamingService<SubscribeTransitionsRequest> for SubscribeTransitionsSvc<H>
+where
+    H: FsmHandle,
+    H::State: Display,
+{
+    type Response = Transition;
+    type ResponseStream = BoxStream<Transition>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<SubscribeTransitionsRequest>) -> Self::Future {
+        let handle = self.0.get(&request.get_ref().instance);
+        Box::pin(async move {
+            let handle = handle.ok_or(ManagerError::<H::Event>::NotFound)?;
+            let changes = WatchStream::new(handle.subscribe()).map(|change| Ok(change.into()));
+            Ok(Response::new(Box::pin(changes) as Self::ResponseStream))
+        })
+    }
+}
+
+impl<E> From<ManagerError<E>> for Status {
+    fn from(err: ManagerError<E>) -> Self {
+        let message = err.to_string();
+        match err {
+            ManagerError::NotFound => Status::not_found(message),
+            ManagerError::Exists => Status::already_exists(message),
+            ManagerError::Closed(_) => Status::failed_precondition(message),
+            ManagerError::Dropped | ManagerError::Discarded(_) => Status::aborted(message),
+        }
+    }
+}
+
+/// A client for the `tokio_fsm.v1.ControlPlane` service, over any gRPC
+/// transport, such as a `tonic::transport::Channel`.
+#[derive(Debug, Clone)]
+pub struct ControlPlaneClient<T> {
+    inner: tonic::client::Grpc<T>,
+}
+
+impl<T> ControlPlaneClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::Body>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// Calls the service over `transport`.
+    pub fn new(transport: T) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(transport),
+        }
+    }
+
+    /// Sends `event`, as JSON, to instance `instance` and waits until it has
+    /// been handled, returning the name of the state it left the instance in.
+    pub async fn send_event(
+        &mut self,
+        instance: impl Into<String>,
+        event: impl Into<String>,
+    ) -> Result<String, Status> {
+        let request = SendEventRequest {
+            instance: instance.into(),
+            event: event.into(),
+        };
+        let response: SendEventResponse = self
+            .unary(request, "/tokio_fsm.v1.ControlPlane/SendEvent")
+            .await?;
+        Ok(response.state)
+    }
+
+    /// Returns the current state of instance `instance`.
+    pub async fn get_state(
+        &mut self,
+        instance: impl Into<String>,
+    ) -> Result<GetStateResponse, Status> {
+        let request = GetStateRequest {
+            instance: instance.into(),
+        };
+        self.unary(request, "/tokio_fsm.v1.ControlPlane/GetState")
+            .await
+    }
+
+    /// Streams the state changes of instance `instance`, starting with its
+    /// current state.
+    pub async fn subscribe_transitions(
+        &mut self,
+        instance: impl Into<String>,
+    ) -> Result<Streaming<Transition>, Status> {
+        let request = SubscribeTransitionsRequest {
+            instance: instance.into(),
+        };
+        self.ready().await?;
+        let response = self
+            .inner
+            .server_streaming(
+                Request::new(request),
+                http::uri::PathAndQuery::from_static(
+                    "/tokio_fsm.v1.ControlPlane/SubscribeTransitions",
+                ),
+                tonic_prost::ProstCodec::default(),
+            )
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn unary<M, R>(&mut self, request: M, path: &'static str) -> Result<R, Status>
+    where
+        M: prost::Message + Send + Sync + 'static,
+        R: prost::Message + Default + Send + Sync + 'static,
+    {
+        self.ready().await?;
+        let response = self
+            .inner
+            .unary(
+                Request::new(request),
+                http::uri::PathAndQuery::from_static(path),
+                tonic_prost::ProstCodec::default(),
+            )
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn ready(&mut self) -> Result<(), Status> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|err| Status::unknown(format!("service was not ready: {}", err.into())))
+    }
+}
@@ -32,24 +32,45 @@
 //! The `Context` is the shared, mutable data owned by the FSM. Every handler
 //! has access to `&mut self.context`.
 //!
+//! Pure-coordination FSMs can omit `type Context`; it then defaults to `()`
+//! and `spawn()` takes no argument. Handlers that can fail declare
+//! `type Error`; it defaults to `std::convert::Infallible` when omitted.
+//! Returning `Err` from a handler of type
+//! `Result<Transition<Next>, Self::Error>` stops the FSM, and its task
+//! resolves to `TaskError::Fsm`.
+//!
 //! ### Transitions
 //! Handlers return a `Transition<NextState>`. This explicitly defines the next
 //! state the FSM should move to. The macro validates that `NextState` is a
 //! known state and that the transition is reachable.
 //!
+//! A transition can also carry [`Effect`]s, such as sending an event to
+//! another FSM. Effects are executed by the run loop after the new state is
+//! committed, so handlers never block on a peer's queue.
+//!
+//! A workflow that knows it is finished returns
+//! `Transition::to(Done).halt()`: the FSM enters `Done`, stops without a
+//! shutdown signal, and its task resolves to the context.
+//!
 //! ### Timeouts
-//! `tokio-fsm` supports state-level timeouts via `#[state_timeout]`. These are
-//! implemented using single, stack-pinned `tokio::time::Sleep` futures,
-//! ensuring zero heap allocations during transitions.
+//! `tokio-fsm` supports state-level timeouts via `#[state_timeout]`. Each FSM
+//! allocates a single `tokio::time::Sleep` when it starts and resets it on
+//! every transition, so arming a timeout doesn't allocate. A custom
+//! [`Clock`] set with the builder's `clock` allocates a sleep per armed
+//! timeout instead.
 //!
 //! ## Architecture
 //!
 //! 1. **Validation Layer**: At compile-time, the macro builds a directed graph
 //!    of your FSM. It verifies that all states are reachable and that
 //!    transitions are logically consistent.
-//! 2. **Codegen Layer**: Generates a tight, state-gated match loop. There is
-//!    **no runtime engine**; the generated code is identical to what a senior
-//!    engineer would write by hand.
+//! 2. **Codegen Layer**: Generates the state and event enums, the handle, and a
+//!    state-gated `match` that calls your handlers directly, without virtual
+//!    dispatch.
+//! 3. **Run Loop**: The queues, shutdown, timeouts and supervision around that
+//!    `match` are written once, in a run loop generic over the `Machine` trait
+//!    the macro implements. It is monomorphised for each FSM, so it compiles
+//!    down to a loop specific to that machine rather than a shared interpreter.
 //!
 //! ## Example: Basic Worker
 //!
@@ -63,7 +84,6 @@
 //! #[fsm(initial = Idle)]
 //! impl WorkerFsm {
 //!     type Context = WorkerContext;
-//!     type Error = std::convert::Infallible;
 //!
 //!     #[on(state = Idle, event = Start)]
 //!     async fn on_start(&mut self) -> Transition<Running> {
@@ -94,10 +114,99 @@
 //! Ensure that event `Y` is defined in at least one `#[on(..., event = Y)]`
 //! attribute.
 
+// Lets the FSMs in `patterns` use the macro's `tokio_fsm::` paths.
+extern crate self as tokio_fsm;
+
+#[cfg(feature = "axum")]
+pub mod axum;
+mod bridge;
+mod clock;
+#[cfg(feature = "codec")]
+mod codec;
 mod core;
+#[cfg(feature = "debug-http")]
+pub mod debug;
+mod engine;
+mod group;
+#[cfg(feature = "tonic")]
+pub mod grpc;
+mod manager;
+pub mod patterns;
+mod pipe;
+mod pool;
+mod rt;
+mod runtime;
+mod schedule;
+#[cfg(feature = "tower")]
+mod service;
+mod snapshot;
+mod stats;
+mod submachine;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 
 #[doc(inline)]
 pub use tokio_fsm_macros::*;
 
+#[doc(inline)]
+pub use crate::bridge::*;
+#[doc(inline)]
+pub use crate::clock::*;
+#[cfg(feature = "codec")]
+#[doc(inline)]
+pub use crate::codec::*;
 #[doc(inline)]
 pub use crate::core::*;
+#[doc(inline)]
+pub use crate::group::*;
+#[doc(inline)]
+pub use crate::manager::*;
+#[doc(inline)]
+pub use crate::pipe::*;
+#[doc(inline)]
+pub use crate::pool::*;
+#[doc(inline)]
+pub use crate::runtime::*;
+#[cfg(feature = "tower")]
+#[doc(inline)]
+pub use crate::service::*;
+#[doc(inline)]
+pub use crate::snapshot::*;
+#[doc(inline)]
+pub use crate::stats::*;
+#[doc(inline)]
+pub use crate::submachine::*;
+
+/// Re-exports used by generated code. Not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "arbitrary")]
+    pub use arbitrary;
+    #[cfg(feature = "flume")]
+    pub use flume;
+    pub use futures_core;
+    #[cfg(feature = "sink")]
+    pub use futures_sink;
+    #[cfg(feature = "kanal")]
+    pub use kanal;
+    #[cfg(feature = "serde")]
+    pub use serde;
+
+    pub use crate::{
+        clock::{now_on, sleep_on},
+        engine::{EventQueue, Machine, next_queued, record_change, reject_off_target, run},
+        schedule::{Schedule, Scheduler},
+    };
+
+    /// Spawning, channels and time, swappable for a simulator's.
+    pub mod rt {
+        pub use crate::rt::*;
+    }
+
+    /// Whether transitions and queue depth are tracked for introspection.
+    pub const INTROSPECT: bool = cfg!(feature = "debug-http");
+
+    /// Whether dropped events are reported, letting generated code skip the
+    /// bookkeeping otherwise.
+    pub const TRACE_DROPS: bool = cfg!(feature = "tracing");
+}
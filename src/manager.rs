@@ -0,0 +1,186 @@
+//! A registry of live FSM instances, keyed by id.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{CallError, DropReason, FsmCall, FsmHandle, TransitionCause};
+
+/// The live instances of one kind of FSM, by id.
+///
+/// Cloning a manager shares it. Instances stay registered after they stop,
+/// answering with [`ManagerError::Closed`], until [`reap`](Self::reap) or
+/// [`remove`](Self::remove) drops them.
+pub struct FsmManager<H: FsmHandle> {
+    instances: Arc<RwLock<HashMap<String, H>>>,
+}
+
+impl<H: FsmHandle> FsmManager<H> {
+    /// Creates an empty manager.
+    pub fn new() -> Self {
+        Self {
+            instances: Arc::default(),
+        }
+    }
+
+    /// Spawns an instance with `spawn` and registers it as `id`, returning
+    /// whatever else `spawn` returned (usually its task).
+    ///
+    /// Nothing is spawned if `id` is taken.
+    pub fn create<T>(
+        &self,
+        id: impl Into<String>,
+        spawn: impl FnOnce() -> (H, T),
+    ) -> Result<T, ManagerError<H::Event>> {
+        let mut instances = self.write();
+        let slot = match instances.entry(id.into()) {
+            Entry::Occupied(_) => return Err(ManagerError::Exists),
+            Entry::Vacant(slot) => slot,
+        };
+        let (handle, rest) = spawn();
+        slot.insert(handle);
+        Ok(rest)
+    }
+
+    /// Registers an already spawned instance as `id`, returning the handle
+    /// it replaced.
+    pub fn insert(&self, id: impl Into<String>, handle: H) -> Option<H> {
+        self.write().insert(id.into(), handle)
+    }
+
+    /// Returns the handle of instance `id`.
+    pub fn get(&self, id: &str) -> Option<H> {
+        self.read().get(id).cloned()
+    }
+
+    /// Stops tracking instance `id`, returning its handle.
+    pub fn remove(&self, id: &str) -> Option<H> {
+        self.write().remove(id)
+    }
+
+    /// Sends `event` to instance `id`, waiting for queue capacity.
+    pub async fn send(&self, id: &str, event: H::Event) -> Result<(), ManagerError<H::Event>> {
+        let handle = self.get(id).ok_or(ManagerError::NotFound)?;
+        handle
+            .send(event)
+            .await
+            .map_err(|err| ManagerError::Closed(err.0))
+    }
+
+    /// Sends `event` to instance `id` and waits until it has been handled,
+    /// like the handle's `call`.
+    pub async fn call(&self, id: &str, event: H::Event) -> Result<H::State, ManagerError<H::Event>>
+    where
+        H: FsmCall,
+    {
+        let handle = self.get(id).ok_or(ManagerError::NotFound)?;
+        Ok(handle.call(event).await?)
+    }
+
+    /// Returns the current state of instance `id`.
+    pub fn state(&self, id: &str) -> Option<H::State> {
+        self.read().get(id).map(FsmHandle::current_state)
+    }
+
+    /// Drops every instance that has stopped, returning their ids.
+    pub fn reap(&self) -> Vec<String> {
+        let mut reaped = Vec::new();
+        self.write().retain(|id, handle| {
+            let stopped = is_stopped(handle);
+            if stopped {
+                reaped.push(id.clone());
+            }
+            !stopped
+        });
+        reaped
+    }
+
+    /// Returns the ids of every registered instance, in no particular order.
+    pub fn ids(&self) -> Vec<String> {
+        self.read().keys().cloned().collect()
+    }
+
+    /// Returns the number of registered instances.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns `true` if no instances are registered.
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, H>> {
+        self.instances
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, H>> {
+        self.instances
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<H: FsmHandle> Clone for FsmManager<H> {
+    fn clone(&self) -> Self {
+        Self {
+            instances: Arc::clone(&self.instances),
+        }
+    }
+}
+
+impl<H: FsmHandle> Default for FsmManager<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: FsmHandle> std::fmt::Debug for FsmManager<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FsmManager")
+            .field("instances", &self.len())
+            .finish()
+    }
+}
+
+/// Whether the FSM behind `handle` has shut down or its task has ended.
+fn is_stopped<H: FsmHandle>(handle: &H) -> bool {
+    let changes = handle.subscribe();
+    changes.has_changed().is_err() || changes.borrow().cause == TransitionCause::Shutdown
+}
+
+/// Error returned by [`FsmManager`] operations.
+///
+/// With the `axum` feature it is also a response, with the status noted on
+/// each variant. With `tonic` it converts to a `tonic::Status`.
+#[derive(Debug, thiserror::Error)]
+pub enum ManagerError<E> {
+    /// No instance has the id (`404 Not Found`).
+    #[error("instance not found")]
+    NotFound,
+    /// `create` was given an id already in use (`409 Conflict`).
+    #[error("instance already exists")]
+    Exists,
+    /// The instance has stopped; the event is returned (`410 Gone`).
+    #[error("instance has stopped")]
+    Closed(E),
+    /// The event was dropped before it was handled (`409 Conflict`).
+    #[error("event dropped before it was handled")]
+    Dropped,
+    /// The instance's queue policy discarded the event (`409 Conflict`).
+    #[error("event discarded: {0}")]
+    Discarded(DropReason),
+}
+
+impl<E> From<CallError<E>> for ManagerError<E> {
+    fn from(err: CallError<E>) -> Self {
+        match err {
+            CallError::Closed(event) => Self::Closed(event),
+            CallError::Dropped => Self::Dropped,
+            CallError::Discarded(reason) => Self::Discarded(reason),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Ready-made FSMs for patterns that are easy to get subtly wrong by hand.
+//!
+//! Each machine is built with [`fsm`](crate::fsm) like any other and is
+//! configured through its context:
+//!
+//! * [`circuit_breaker`]: Closed / Open / HalfOpen, opening after repeated
+//!   failures and probing again after a cool-down.
+//! * [`retry`]: Attempts separated by exponential backoff, up to a maximum
+//!   number of attempts.
+//! * [`lease`]: A lease that expires unless renewed by heartbeats.
+//!
+//! Durations come from the configuration at runtime rather than from
+//! `#[state_timeout]`, so the timers are started with `spawn_work` and tagged
+//! with a generation number; a timer that fires after its state was left or
+//! renewed is ignored.
+
+use std::{convert::Infallible, time::Duration};
+
+pub mod circuit_breaker;
+pub mod lease;
+pub mod retry;
+
+/// Resolves to `generation` once `delay` has elapsed.
+async fn timer(delay: Duration, generation: u64) -> Result<u64, Infallible> {
+    crate::rt::sleep(delay).await;
+    Ok(generation)
+}
+
+fn unreachable<T>(never: Infallible) -> T {
+    match never {}
+}
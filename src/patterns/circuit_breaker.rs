@@ -0,0 +1,167 @@
+//! A circuit breaker that stops calls to a failing dependency.
+//!
+//! Callers check [`CircuitBreakerFsmState::permits_calls`] before calling
+//! and report the outcome as `Success` or `Failure`:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use tokio_fsm::patterns::circuit_breaker::{
+//!     CircuitBreaker, CircuitBreakerConfig, CircuitBreakerFsm, CircuitBreakerFsmEvent,
+//! };
+//!
+//! # async fn call_dependency() -> Result<(), ()> { Ok(()) }
+//! # async fn example() {
+//! let (breaker, _task) = CircuitBreakerFsm::spawn(CircuitBreaker::new(CircuitBreakerConfig {
+//!     failure_threshold: 3,
+//!     open_for: Duration::from_secs(10),
+//!     ..Default::default()
+//! }));
+//!
+//! if breaker.current_state().permits_calls() {
+//!     let event = match call_dependency().await {
+//!         Ok(()) => CircuitBreakerFsmEvent::Success,
+//!         Err(()) => CircuitBreakerFsmEvent::Failure,
+//!     };
+//!     breaker.send(event).await.unwrap();
+//! }
+//! # }
+//! ```
+//!
+//! * `Closed`: Calls go through. `failure_threshold` consecutive failures open
+//!   the circuit; a success resets the count.
+//! * `Open`: Calls are refused for `open_for`, then the circuit half-opens.
+//!   Outcomes of calls started before it opened are ignored.
+//! * `HalfOpen`: Trial calls go through. Any failure re-opens the circuit;
+//!   `close_after` consecutive successes close it.
+
+use std::time::Duration;
+
+use crate::{Transition, fsm};
+
+/// Thresholds and timings of a [`CircuitBreakerFsm`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures in `Closed` that open the circuit.
+    pub failure_threshold: u32,
+    /// How long the circuit stays `Open` before admitting trial calls.
+    pub open_for: Duration,
+    /// Consecutive successes in `HalfOpen` that close the circuit.
+    pub close_after: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+            close_after: 1,
+        }
+    }
+}
+
+/// Context of a [`CircuitBreakerFsm`].
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    failures: u32,
+    successes: u32,
+    generation: u64,
+}
+
+impl CircuitBreaker {
+    /// A closed circuit breaker with the given configuration.
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            failures: 0,
+            successes: 0,
+            generation: 0,
+        }
+    }
+
+    /// The configuration this breaker was created with.
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// Consecutive failures counted in `Closed`.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(CircuitBreakerConfig::default())
+    }
+}
+
+#[fsm(initial = Closed)]
+impl CircuitBreakerFsm {
+    type Context = CircuitBreaker;
+
+    #[on(state = Closed, event = Success)]
+    async fn reset_failures(&mut self) -> Transition<Closed> {
+        self.context.failures = 0;
+        Transition::to(Closed)
+    }
+
+    #[on(state = Closed, event = Failure)]
+    async fn count_failure(&mut self) -> Result<Transition<Closed>, Transition<Open>> {
+        self.context.failures += 1;
+        if self.context.failures < self.context.config.failure_threshold {
+            return Ok(Transition::to(Closed));
+        }
+        self.open();
+        Err(Transition::to(Open))
+    }
+
+    #[on(state = Open, event = Cooldown)]
+    async fn half_open(
+        &mut self,
+        generation: u64,
+    ) -> Result<Transition<HalfOpen>, Transition<Open>> {
+        if generation != self.context.generation {
+            return Err(Transition::to(Open));
+        }
+        self.context.successes = 0;
+        Ok(Transition::to(HalfOpen))
+    }
+
+    #[on(state = HalfOpen, event = Success)]
+    async fn count_success(&mut self) -> Result<Transition<Closed>, Transition<HalfOpen>> {
+        self.context.successes += 1;
+        if self.context.successes < self.context.config.close_after {
+            return Err(Transition::to(HalfOpen));
+        }
+        self.context.failures = 0;
+        Ok(Transition::to(Closed))
+    }
+
+    #[on(state = HalfOpen, event = Failure)]
+    async fn reopen(&mut self) -> Transition<Open> {
+        self.open();
+        Transition::to(Open)
+    }
+}
+
+impl CircuitBreakerFsm {
+    /// Starts the `open_for` cool-down that ends in `Cooldown`.
+    fn open(&mut self) {
+        self.context.failures = 0;
+        self.context.generation += 1;
+        self.spawn_work(
+            super::timer(self.context.config.open_for, self.context.generation),
+            CircuitBreakerFsmEvent::Cooldown,
+            super::unreachable,
+        );
+    }
+}
+
+impl CircuitBreakerFsmState {
+    /// Whether calls should be attempted in this state.
+    pub fn permits_calls(&self) -> bool {
+        !self.is_open()
+    }
+}
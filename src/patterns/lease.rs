@@ -0,0 +1,96 @@
+//! A lease kept alive by heartbeats.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use tokio_fsm::patterns::lease::{Lease, LeaseFsm, LeaseFsmEvent, LeaseFsmState};
+//!
+//! # async fn example() {
+//! let (lease, _task) = LeaseFsm::spawn(Lease::new(Duration::from_secs(15)));
+//! lease.send(LeaseFsmEvent::Acquire).await.unwrap();
+//!
+//! let mut heartbeat = tokio::time::interval(Duration::from_secs(5));
+//! while lease.current_state() == LeaseFsmState::Held {
+//!     heartbeat.tick().await;
+//!     lease.send(LeaseFsmEvent::Heartbeat).await.unwrap();
+//! }
+//! # }
+//! ```
+//!
+//! * `Released`: Not held. `Acquire` takes the lease.
+//! * `Held`: Each `Heartbeat` extends the lease by `ttl` from now; without one
+//!   the lease becomes `Expired`. `Release` gives it up.
+//! * `Expired`: The holder stopped heartbeating. `Acquire` takes the lease
+//!   again.
+//!
+//! Heartbeat comfortably more often than `ttl`, since the heartbeat waits in
+//! the event queue like any other event.
+
+use std::time::Duration;
+
+use crate::{Transition, fsm};
+
+/// Context of a [`LeaseFsm`].
+#[derive(Debug, Clone)]
+pub struct Lease {
+    ttl: Duration,
+    generation: u64,
+}
+
+impl Lease {
+    /// A released lease that expires `ttl` after the last acquire or
+    /// heartbeat.
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, generation: 0 }
+    }
+
+    /// How long the lease lasts without a heartbeat.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+#[fsm(initial = Released)]
+impl LeaseFsm {
+    type Context = Lease;
+
+    #[on(state = Released, event = Acquire)]
+    #[on(state = Expired, event = Acquire)]
+    async fn acquire(&mut self) -> Transition<Held> {
+        self.renew();
+        Transition::to(Held)
+    }
+
+    #[on(state = Held, event = Heartbeat)]
+    async fn heartbeat(&mut self) -> Transition<Held> {
+        self.renew();
+        Transition::to(Held)
+    }
+
+    #[on(state = Held, event = Release)]
+    async fn release(&mut self) -> Transition<Released> {
+        // Invalidates the pending expiry.
+        self.context.generation += 1;
+        Transition::to(Released)
+    }
+
+    #[on(state = Held, event = Expire)]
+    async fn expire(&mut self, generation: u64) -> Result<Transition<Expired>, Transition<Held>> {
+        if generation != self.context.generation {
+            return Err(Transition::to(Held));
+        }
+        Ok(Transition::to(Expired))
+    }
+}
+
+impl LeaseFsm {
+    /// Schedules `Expire` for `ttl` from now, superseding earlier ones.
+    fn renew(&mut self) {
+        self.context.generation += 1;
+        self.spawn_work(
+            super::timer(self.context.ttl, self.context.generation),
+            LeaseFsmEvent::Expire,
+            super::unreachable,
+        );
+    }
+}
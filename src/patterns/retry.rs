@@ -0,0 +1,190 @@
+//! Retrying an operation with exponential backoff.
+//!
+//! [`retry`] drives a [`RetryFsm`] for a single operation:
+//!
+//! ```rust,no_run
+//! use tokio_fsm::patterns::retry::{RetryPolicy, retry};
+//!
+//! # async fn connect() -> Result<(), std::io::Error> { Ok(()) }
+//! # async fn example() -> Result<(), std::io::Error> {
+//! retry(RetryPolicy::default(), |_attempt| connect()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The machine can also be driven by hand: send `Start`, make an attempt
+//! whenever it enters `Attempting`, and report the outcome as `Success` or
+//! `Failure`. After a failure it waits in `Backoff`, then enters `Attempting`
+//! again, or ends in `Exhausted` once `max_attempts` have failed. `Start`
+//! restarts a machine that ended in `Succeeded` or `Exhausted`.
+
+use std::{future::Future, time::Duration};
+
+use crate::{Transition, fsm};
+
+/// How many attempts to make and how long to wait between them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made before giving up, including the first one. The first
+    /// attempt is always made.
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt.
+    pub initial_backoff: Duration,
+    /// Factor applied to the delay after each further failure.
+    pub multiplier: f64,
+    /// Upper bound on the delay.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay after the given failed attempt, counting from `1`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        if delay.is_finite() && delay < self.max_backoff.as_secs_f64() {
+            Duration::from_secs_f64(delay)
+        } else {
+            self.max_backoff
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Context of a [`RetryFsm`].
+#[derive(Debug, Clone)]
+pub struct Retry {
+    policy: RetryPolicy,
+    attempt: u32,
+    generation: u64,
+}
+
+impl Retry {
+    /// A retry sequence following `policy`.
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempt: 0,
+            generation: 0,
+        }
+    }
+
+    /// The policy this sequence follows.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// The current attempt, counting from `1`; `0` before `Start`.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+#[fsm(initial = Idle)]
+impl RetryFsm {
+    type Context = Retry;
+
+    #[on(state = Idle, event = Start)]
+    #[on(state = Succeeded, event = Start)]
+    #[on(state = Exhausted, event = Start)]
+    async fn start(&mut self) -> Transition<Attempting> {
+        self.context.attempt = 1;
+        Transition::to(Attempting)
+    }
+
+    #[on(state = Attempting, event = Success)]
+    async fn succeed(&mut self) -> Transition<Succeeded> {
+        Transition::to(Succeeded)
+    }
+
+    #[on(state = Attempting, event = Failure)]
+    async fn back_off(&mut self) -> Result<Transition<Backoff>, Transition<Exhausted>> {
+        let attempt = self.context.attempt;
+        if attempt >= self.context.policy.max_attempts {
+            return Err(Transition::to(Exhausted));
+        }
+        self.context.generation += 1;
+        self.spawn_work(
+            super::timer(
+                self.context.policy.backoff(attempt),
+                self.context.generation,
+            ),
+            RetryFsmEvent::Elapsed,
+            super::unreachable,
+        );
+        Ok(Transition::to(Backoff))
+    }
+
+    #[on(state = Backoff, event = Elapsed)]
+    async fn next_attempt(
+        &mut self,
+        generation: u64,
+    ) -> Result<Transition<Attempting>, Transition<Backoff>> {
+        if generation != self.context.generation {
+            return Err(Transition::to(Backoff));
+        }
+        self.context.attempt += 1;
+        Ok(Transition::to(Attempting))
+    }
+}
+
+/// Runs `operation` until it succeeds or `policy.max_attempts` attempts have
+/// failed, returning the last error in that case.
+///
+/// `operation` receives the attempt number, counting from `1`.
+pub async fn retry<T, E, F, Fut>(policy: RetryPolicy, mut operation: F) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let (handle, _task) = RetryFsm::spawn(Retry::new(policy));
+    let mut changes = handle.subscribe();
+    let _ = handle.send(RetryFsmEvent::Start).await;
+
+    let mut seen = 0;
+    let mut attempt = 0;
+    let mut last_error = None;
+    let outcome = loop {
+        // The FSM only stops once `handle` is shut down below.
+        let change = *changes
+            .wait_for(|change| {
+                change.seq > seen
+                    && matches!(
+                        change.to,
+                        RetryFsmState::Attempting | RetryFsmState::Exhausted
+                    )
+            })
+            .await
+            .expect("retry FSM stopped early");
+        seen = change.seq;
+        if change.to == RetryFsmState::Exhausted {
+            break Err(last_error.take().expect("exhausted only after a failure"));
+        }
+
+        attempt += 1;
+        match operation(attempt).await {
+            Ok(value) => break Ok(value),
+            Err(error) => {
+                last_error = Some(error);
+                let _ = handle.send(RetryFsmEvent::Failure).await;
+            }
+        }
+    };
+    handle.shutdown_immediate();
+    outcome
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+
+use crate::{
+    core::{FsmHandle, StateChange},
+    rt::JoinHandle,
+};
+
+/// Forwards the transitions of FSM `from` into FSM `to`, for workflows where
+/// one machine's progress drives the next:
+///
+/// ```rust,ignore
+/// let pipe = tokio_fsm::pipe(&order, &shipping, |change| {
+///     let paid = change.cause == TransitionCause::Event("Pay");
+///     paid.then_some(ShippingFsmEvent::Prepare)
+/// });
+/// ```
+///
+/// Every state change after the call is passed to `map`, in order and
+/// without skipping any, as `subscribe_transitions` delivers them, and the
+/// event it returns, if any, is sent to `to`. This includes the final
+/// [`TransitionCause::Shutdown`](crate::TransitionCause::Shutdown) change
+/// published when `from` stops. Changes are buffered while `to` is full.
+///
+/// Forwarding runs on a background task that ends once `from` stops or `to`
+/// stops accepting events, and is aborted when the returned [`Pipe`] is
+/// dropped. Until then it holds a handle to `to`, keeping it running.
+pub fn pipe<A, B, F>(from: &A, to: &B, mut map: F) -> Pipe
+where
+    A: FsmHandle,
+    B: FsmHandle,
+    F: FnMut(StateChange<A::State>) -> Option<B::Event> + Send + 'static,
+{
+    let mut changes = from.subscribe_transitions();
+    let to = to.clone();
+    let task = crate::rt::spawn(None, async move {
+        while let Some(change) = changes.recv().await {
+            if let Some(event) = map(change)
+                && to.send(event).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    Pipe { task }
+}
+
+/// The forwarding task started by [`pipe`]. Dropping it stops forwarding.
+pub struct Pipe {
+    task: JoinHandle<()>,
+}
+
+impl Pipe {
+    /// Returns `true` once forwarding has ended, because either FSM stopped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops forwarding, like dropping the pipe.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipe")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+
+use crate::core::FsmHandle;
+
+/// A fixed set of identical FSMs, with events routed to one of them by key.
+///
+/// Every event for a given key goes to the same shard, so per-entity
+/// ordering is preserved while different entities are handled in parallel:
+///
+/// ```rust,ignore
+/// let (pool, tasks) = FsmPool::spawn(8, |_shard| OrderFsm::spawn(Default::default()));
+/// pool.send(&order_id, OrderFsmEvent::Pay).await?;
+/// ```
+///
+/// Clones share the same shards. Routing uses a fixed hash, so a key maps to
+/// the same shard for the lifetime of the process, but not across builds.
+pub struct FsmPool<H: FsmHandle> {
+    shards: Arc<[H]>,
+}
+
+impl<H: FsmHandle> FsmPool<H> {
+    /// Spawns `shards` machines with `spawn(index)`, returning the pool and
+    /// whatever else `spawn` returned for each shard (usually its task), in
+    /// shard order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn spawn<T>(shards: usize, mut spawn: impl FnMut(usize) -> (H, T)) -> (Self, Vec<T>) {
+        let (handles, rest) = (0..shards).map(&mut spawn).unzip::<_, _, Vec<H>, Vec<T>>();
+        (Self::from_handles(handles), rest)
+    }
+
+    /// Builds a pool over already spawned machines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `handles` is empty.
+    pub fn from_handles(handles: impl IntoIterator<Item = H>) -> Self {
+        let shards: Arc<[H]> = handles.into_iter().collect();
+        assert!(!shards.is_empty(), "an FsmPool needs at least one shard");
+        Self { shards }
+    }
+
+    /// Returns the handle of the shard `key` is routed to.
+    pub fn shard<K: Hash + ?Sized>(&self, key: &K) -> &H {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Returns the index of the shard `key` is routed to.
+    pub fn shard_index<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Sends an event to the shard `key` is routed to, waiting for queue
+    /// capacity.
+    pub async fn send<K: Hash + ?Sized>(
+        &self,
+        key: &K,
+        event: H::Event,
+    ) -> Result<(), SendError<H::Event>> {
+        self.shard(key).send(event).await
+    }
+
+    /// Attempts to send an event to the shard `key` is routed to without
+    /// awaiting capacity.
+    pub fn try_send<K: Hash + ?Sized>(
+        &self,
+        key: &K,
+        event: H::Event,
+    ) -> Result<(), TrySendError<H::Event>> {
+        self.shard(key).try_send(event)
+    }
+
+    /// Returns the handles of every shard, in shard order.
+    pub fn handles(&self) -> &[H] {
+        &self.shards
+    }
+
+    /// Returns the number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Always `false`: a pool has at least one shard.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl<H: FsmHandle> Clone for FsmPool<H> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: Arc::clone(&self.shards),
+        }
+    }
+}
+
+impl<H: FsmHandle + fmt::Debug> fmt::Debug for FsmPool<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsmPool")
+            .field("shards", &self.shards)
+            .finish()
+    }
+}
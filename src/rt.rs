@@ -0,0 +1,65 @@
+//! The runtime primitives FSMs are built on: spawning, channels and time.
+//!
+//! Generated code and the runtime types reach tokio only through this module,
+//! so that deterministic simulators can stand in for it:
+//!
+//! * Under [turmoil], tokio itself runs the simulation. Time is tokio's
+//!   (paused) clock, so these are plain tokio.
+//! * Under madsim (`--cfg madsim`, with `tokio` patched to `madsim-tokio`), the
+//!   same paths resolve to the simulated runtime. Runtime handles don't exist
+//!   there, so `spawn_on` spawns on the current node.
+//! * On `wasm32-unknown-unknown`, tokio has no timer and the standard library
+//!   no clock, so time comes from the browser instead; see `wasm.rs`.
+//!
+//! Nothing here reads the wall clock or picks randomly: `Instant`s come from
+//! tokio's clock and every `select!` in the run loop is `biased`.
+//!
+//! Internal-only: This is used by generated code.
+//!
+//! [turmoil]: https://docs.rs/turmoil
+
+use std::future::Future;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use tokio::time::{Instant, Sleep, sleep, sleep_until};
+pub use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::{AbortHandle, JoinHandle, JoinSet},
+};
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod wasm;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use wasm::{Instant, Sleep, sleep, sleep_until};
+
+/// Spawns `future` on `runtime`, or on the current runtime if `None`.
+pub fn spawn<F>(runtime: Option<&tokio::runtime::Handle>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match runtime {
+        #[cfg(not(madsim))]
+        Some(runtime) => runtime.spawn(future),
+        _ => tokio::spawn(future),
+    }
+}
+
+/// Spawns `future` on the current runtime without keeping a handle to it.
+///
+/// In the browser, where there is no Tokio runtime, it runs on the JS event
+/// loop instead.
+pub fn spawn_detached<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    wasm_bindgen_futures::spawn_local(future);
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    drop(tokio::spawn(future));
+}
+
+/// The current time on the runtime's clock, which simulators control.
+pub fn now() -> Instant {
+    Instant::now()
+}
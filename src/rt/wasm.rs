@@ -0,0 +1,83 @@
+//! Time for `wasm32-unknown-unknown`, where tokio's timer can't run and
+//! `std::time::Instant::now()` panics.
+//!
+//! `Instant` reads `performance.now()` and `Sleep` waits on `setTimeout`, with
+//! the same methods as the tokio types they replace.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use gloo_timers::future::TimeoutFuture;
+pub use web_time::Instant;
+
+/// A browser timer, standing in for tokio's `Sleep`.
+///
+/// No timeout is scheduled until the sleep is first polled, so FSMs that
+/// keep a disarmed sleep around cost the event loop nothing.
+pub struct Sleep {
+    deadline: Instant,
+    timeout: Option<TimeoutFuture>,
+}
+
+// SAFETY: Without the `atomics` target feature, wasm32 has a single thread,
+// so the JS handle inside the timeout is never touched from another one.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for Sleep {}
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Sync for Sleep {}
+
+impl Sleep {
+    /// The time the sleep completes at.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Moves the deadline, cancelling any scheduled timeout.
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        let this = self.get_mut();
+        this.deadline = deadline;
+        this.timeout = None;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            let remaining = this.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                this.timeout = None;
+                return Poll::Ready(());
+            }
+            // `setTimeout` takes whole milliseconds and may fire early or, for
+            // long delays, in chunks, so the deadline is checked again after.
+            let timeout = this.timeout.get_or_insert_with(|| {
+                let millis = remaining.as_micros().div_ceil(1000);
+                TimeoutFuture::new(millis.min(i32::MAX as u128) as u32)
+            });
+            match Pin::new(timeout).poll(cx) {
+                Poll::Ready(()) => this.timeout = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Waits until `duration` has elapsed.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Waits until `deadline` is reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        timeout: None,
+    }
+}
@@ -0,0 +1,394 @@
+//! Async runtimes other than Tokio's, selected with `#[fsm(runtime = ...)]`.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use crate::{
+    Clock, ClockSleep,
+    rt::{AbortHandle, Instant, JoinHandle, JoinSet, mpsc, oneshot, watch},
+};
+
+/// A boxed task, as passed to [`Runtime::spawn`].
+pub type RuntimeTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The executor and timer an FSM declared with `#[fsm(runtime = MyRuntime)]`
+/// is spawned on, instead of the caller's Tokio runtime.
+///
+/// Its [`Clock`] impl measures state timeouts, the builder's `watchdog` and
+/// `send_after` delays unless the builder sets another clock, and `spawn`
+/// also runs the FSM's background work: `spawn_work` tasks, `#[submachine]`
+/// watchers and `send_after` timers. Channels need no counterpart: tokio's
+/// `sync` channels the FSM is built on work on any executor. A child FSM
+/// runs on the runtime it was declared with.
+pub trait Runtime: Clock + Default {
+    /// Runs `task` in the background until it completes.
+    fn spawn(&self, task: RuntimeTask);
+}
+
+/// Tokio, as a [`Runtime`]. FSMs declared without `runtime` use Tokio
+/// directly; this is for code generic over runtimes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+impl Clock for TokioRuntime {
+    fn now(&self) -> Instant {
+        crate::rt::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        Box::pin(crate::rt::sleep_until(deadline))
+    }
+}
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: RuntimeTask) {
+        crate::rt::spawn(None, task);
+    }
+}
+
+/// The browser's event loop and timers, on `wasm32-unknown-unknown`.
+///
+/// FSMs declared without `runtime` already spawn here when built for the
+/// browser; this is for code generic over runtimes.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WasmRuntime;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Clock for WasmRuntime {
+    fn now(&self) -> Instant {
+        crate::rt::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        Box::pin(crate::rt::sleep_until(deadline))
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Runtime for WasmRuntime {
+    fn spawn(&self, task: RuntimeTask) {
+        wasm_bindgen_futures::spawn_local(task);
+    }
+}
+
+/// The `smol` executor and timer, with the `smol` feature.
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "smol")]
+impl Clock for SmolRuntime {
+    fn now(&self) -> Instant {
+        crate::rt::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        let deadline = deadline.into_std();
+        Box::pin(async move {
+            smol::Timer::at(deadline).await;
+        })
+    }
+}
+
+#[cfg(feature = "smol")]
+impl Runtime for SmolRuntime {
+    fn spawn(&self, task: RuntimeTask) {
+        smol::spawn(task).detach();
+    }
+}
+
+/// The `async-std` executor and timer, with the `async-std` feature.
+#[cfg(feature = "async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-std")]
+impl Clock for AsyncStdRuntime {
+    fn now(&self) -> Instant {
+        crate::rt::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ClockSleep {
+        let duration = deadline.saturating_duration_since(crate::rt::now());
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl Runtime for AsyncStdRuntime {
+    fn spawn(&self, task: RuntimeTask) {
+        drop(async_std::task::spawn(task));
+    }
+}
+
+/// Why a spawned FSM task produced no output.
+///
+/// Internal-only: This is mapped to a `TaskError` by generated tasks.
+#[doc(hidden)]
+#[derive(Debug)]
+pub enum JoinFailure {
+    Tokio(tokio::task::JoinError),
+    /// The task panicked on a custom runtime, with this message, or was
+    /// aborted (`None`).
+    Runtime(Option<String>),
+}
+
+/// The background task running an FSM, on Tokio or a custom [`Runtime`].
+///
+/// Internal-only: This is held by generated tasks.
+#[doc(hidden)]
+pub enum TaskHandle<T> {
+    Tokio(JoinHandle<T>),
+    Runtime {
+        /// The task's output, or the message it panicked with.
+        output: oneshot::Receiver<Result<T, String>>,
+        /// Aborts the task when sent to. Dropping it leaves the task running,
+        /// like dropping a `JoinHandle`.
+        abort: Mutex<Option<oneshot::Sender<()>>>,
+    },
+}
+
+impl<T: Send + 'static> TaskHandle<T> {
+    /// Spawns `future` on `runtime`, catching a panic so it can be reported
+    /// to whoever awaits the task.
+    pub fn spawn<R: Runtime>(
+        runtime: &R,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Self {
+        let (output_tx, output) = oneshot::channel();
+        let (abort, mut aborted) = oneshot::channel();
+        runtime.spawn(Box::pin(async move {
+            tokio::select! {
+                biased;
+
+                Ok(()) = &mut aborted => {}
+                result = crate::catch_unwind(future) => {
+                    let _ = output_tx.send(result);
+                }
+            }
+        }));
+        Self::Runtime {
+            output,
+            abort: Mutex::new(Some(abort)),
+        }
+    }
+}
+
+impl<T: Send + 'static> TaskHandle<T> {
+    /// Spawns `future` on `runtime`, or on the current Tokio runtime if
+    /// `None`. Built for the browser, it runs on `WasmRuntime` instead.
+    pub fn spawn_default(
+        runtime: Option<&tokio::runtime::Handle>,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> Self {
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        {
+            let _ = runtime;
+            Self::spawn(&WasmRuntime, future)
+        }
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        Self::Tokio(crate::rt::spawn(runtime, future))
+    }
+}
+
+impl<T> TaskHandle<T> {
+    /// Stops the task at its next await point.
+    pub fn abort(&self) {
+        match self {
+            Self::Tokio(handle) => handle.abort(),
+            Self::Runtime { abort, .. } => {
+                let abort = abort
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .take();
+                if let Some(abort) = abort {
+                    let _ = abort.send(());
+                }
+            }
+        }
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinFailure>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.get_mut() {
+            Self::Tokio(handle) => Pin::new(handle).poll(cx).map_err(JoinFailure::Tokio),
+            Self::Runtime { output, .. } => Pin::new(output).poll(cx).map(|output| match output {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(message)) => Err(JoinFailure::Runtime(Some(message))),
+                Err(_) => Err(JoinFailure::Runtime(None)),
+            }),
+        }
+    }
+}
+
+/// Spawns a task on an FSM's custom [`Runtime`], whichever it is.
+///
+/// Internal-only: This is created by generated code.
+#[doc(hidden)]
+pub type Spawner = fn(RuntimeTask);
+
+/// The [`Spawner`] of `R`.
+///
+/// Internal-only: This is called by generated code.
+#[doc(hidden)]
+pub fn spawner<R: Runtime>() -> Spawner {
+    |task| R::default().spawn(task)
+}
+
+/// Cancels a background task of a running FSM, such as a `send_after` timer,
+/// on Tokio or the FSM's custom [`Runtime`].
+#[derive(Debug)]
+pub struct WorkHandle(WorkAbort);
+
+#[derive(Debug)]
+enum WorkAbort {
+    Tokio(AbortHandle),
+    Runtime(Mutex<Option<oneshot::Sender<()>>>),
+}
+
+impl WorkHandle {
+    /// Stops the task at its next await point. Dropping the handle leaves
+    /// it running.
+    pub fn abort(&self) {
+        match &self.0 {
+            WorkAbort::Tokio(handle) => handle.abort(),
+            WorkAbort::Runtime(abort) => {
+                let abort = abort
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .take();
+                if let Some(abort) = abort {
+                    let _ = abort.send(());
+                }
+            }
+        }
+    }
+
+    /// Spawns `future` detached, on `spawner` if the FSM has a custom
+    /// [`Runtime`], or on the current Tokio runtime.
+    ///
+    /// Internal-only: This is called by generated code.
+    #[doc(hidden)]
+    pub fn spawn(
+        spawner: Option<Spawner>,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        match spawner {
+            Some(spawner) => {
+                let (abort, mut aborted) = oneshot::channel();
+                spawner(Box::pin(async move {
+                    tokio::select! {
+                        biased;
+
+                        Ok(()) = &mut aborted => {}
+                        () = future => {}
+                    }
+                }));
+                Self(WorkAbort::Runtime(Mutex::new(Some(abort))))
+            }
+            None => Self(WorkAbort::Tokio(
+                crate::rt::spawn(None, future).abort_handle(),
+            )),
+        }
+    }
+}
+
+/// The background work of a running FSM, whose results the run loop
+/// dispatches as events: a `JoinSet` on Tokio, or tasks spawned on the
+/// FSM's custom [`Runtime`]. Dropping it aborts every task still running.
+///
+/// Internal-only: This is owned by generated FSMs.
+#[doc(hidden)]
+pub enum WorkSet<E> {
+    Tokio(JoinSet<E>),
+    Runtime {
+        spawner: Spawner,
+        /// Results, or the message a task panicked with.
+        done_tx: mpsc::UnboundedSender<Result<E, String>>,
+        done: mpsc::UnboundedReceiver<Result<E, String>>,
+        /// Never sent to: dropping it stops every task spawned on it.
+        stop: watch::Sender<()>,
+    },
+}
+
+impl<E: Send + 'static> WorkSet<E> {
+    pub fn new(spawner: Option<Spawner>) -> Self {
+        match spawner {
+            Some(spawner) => {
+                let (done_tx, done) = mpsc::unbounded_channel();
+                Self::Runtime {
+                    spawner,
+                    done_tx,
+                    done,
+                    stop: watch::Sender::new(()),
+                }
+            }
+            None => Self::Tokio(JoinSet::new()),
+        }
+    }
+
+    /// Aborts every task and forgets results not dispatched yet.
+    pub fn clear(&mut self) {
+        let spawner = match self {
+            Self::Tokio(_) => None,
+            Self::Runtime { spawner, .. } => Some(*spawner),
+        };
+        *self = Self::new(spawner);
+    }
+
+    /// Runs `future` in the background, handing its output to
+    /// [`join_next`](Self::join_next).
+    pub fn spawn(&mut self, future: impl Future<Output = E> + Send + 'static) -> WorkHandle {
+        match self {
+            Self::Tokio(set) => WorkHandle(WorkAbort::Tokio(set.spawn(future))),
+            Self::Runtime {
+                spawner,
+                done_tx,
+                stop,
+                ..
+            } => {
+                let done_tx = done_tx.clone();
+                let mut stop = stop.subscribe();
+                let (abort, mut aborted) = oneshot::channel();
+                spawner(Box::pin(async move {
+                    tokio::select! {
+                        biased;
+
+                        _ = stop.changed() => {}
+                        Ok(()) = &mut aborted => {}
+                        result = crate::catch_unwind(future) => {
+                            let _ = done_tx.send(result);
+                        }
+                    }
+                }));
+                WorkHandle(WorkAbort::Runtime(Mutex::new(Some(abort))))
+            }
+        }
+    }
+
+    /// Waits for the next task to finish, or `None` once none are left on
+    /// Tokio. A panic in the task resumes here.
+    pub async fn join_next(&mut self) -> Option<E> {
+        match self {
+            Self::Tokio(set) => loop {
+                match set.join_next().await? {
+                    Ok(output) => return Some(output),
+                    Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                    Err(_) => {}
+                }
+            },
+            Self::Runtime { done, .. } => match done.recv().await? {
+                Ok(output) => Some(output),
+                Err(message) => std::panic::resume_unwind(Box::new(message)),
+            },
+        }
+    }
+}
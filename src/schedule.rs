@@ -0,0 +1,139 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use crate::{Clock, Timer, rt::Instant};
+
+/// An event the run loop sends on a schedule, declared with `#[schedule]`.
+///
+/// Internal-only: This is built by generated code.
+#[doc(hidden)]
+pub struct Schedule<E> {
+    /// When the schedule next matches, strictly after the given time.
+    next_after: Box<dyn Fn(SystemTime) -> Option<SystemTime> + Send + Sync>,
+    event: fn() -> E,
+}
+
+impl<E> Schedule<E> {
+    /// Sends `event` at every time `next_after` returns.
+    pub fn new(
+        next_after: impl Fn(SystemTime) -> Option<SystemTime> + Send + Sync + 'static,
+        event: fn() -> E,
+    ) -> Self {
+        Self {
+            next_after: Box::new(next_after),
+            event,
+        }
+    }
+
+    /// Sends `event` whenever the cron `expression` matches, in UTC.
+    ///
+    /// # Panics
+    ///
+    /// If `expression` doesn't parse; `#[schedule]` checks it at compile
+    /// time.
+    #[cfg(feature = "cron")]
+    pub fn cron(expression: &str, event: fn() -> E) -> Self {
+        let schedule: cron::Schedule = expression.parse().expect("invalid cron expression");
+        Self::new(
+            move |after| {
+                let after = chrono::DateTime::<chrono::Utc>::from(after);
+                schedule.after(&after).next().map(SystemTime::from)
+            },
+            event,
+        )
+    }
+}
+
+/// Resolves to each scheduled event of a running FSM as it falls due, on the
+/// FSM's clock.
+///
+/// Wall-clock times are mapped onto the clock from the moment the scheduler
+/// is created, so moving a manual clock moves the schedules too. If the run
+/// loop falls behind, missed times are skipped rather than sent in a burst.
+///
+/// Internal-only: This is driven by the run loop.
+#[doc(hidden)]
+pub struct Scheduler<E> {
+    entries: Vec<Entry<E>>,
+    /// The timer, with the clock's and the wall clock's time when the
+    /// scheduler was created. `None` without schedules.
+    timer: Option<(Timer, Instant, SystemTime)>,
+}
+
+struct Entry<E> {
+    schedule: Schedule<E>,
+    next: Option<SystemTime>,
+}
+
+impl<E> Scheduler<E> {
+    pub fn new(schedules: Vec<Schedule<E>>, clock: Option<Arc<dyn Clock>>) -> Self {
+        if schedules.is_empty() {
+            return Self {
+                entries: Vec::new(),
+                timer: None,
+            };
+        }
+        let timer = Timer::new(clock);
+        let (start, wall_start) = (timer.now(), SystemTime::now());
+        let entries = schedules
+            .into_iter()
+            .map(|schedule| Entry {
+                next: (schedule.next_after)(wall_start),
+                schedule,
+            })
+            .collect();
+        let mut scheduler = Self {
+            entries,
+            timer: Some((timer, start, wall_start)),
+        };
+        scheduler.arm();
+        scheduler
+    }
+
+    /// Sets the timer for the earliest next time of any schedule.
+    fn arm(&mut self) {
+        let Some((timer, start, wall_start)) = &mut self.timer else {
+            return;
+        };
+        match self.entries.iter().filter_map(|entry| entry.next).min() {
+            Some(next) => {
+                let offset = next.duration_since(*wall_start).unwrap_or(Duration::ZERO);
+                timer.reset_at(*start + offset);
+            }
+            None => timer.disarm(),
+        }
+    }
+}
+
+impl<E> Future for Scheduler<E> {
+    type Output = E;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<E> {
+        let this = &mut *self;
+        let Some((timer, start, wall_start)) = &mut this.timer else {
+            return Poll::Pending;
+        };
+        if Pin::new(&mut *timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let now = *wall_start + timer.now().saturating_duration_since(*start);
+        let Some(entry) = this
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.next.is_some())
+            .min_by_key(|entry| entry.next)
+        else {
+            return Poll::Pending;
+        };
+        let due = entry.next.unwrap_or(now);
+        entry.next = (entry.schedule.next_after)(due.max(now));
+        let event = (entry.schedule.event)();
+        this.arm();
+        Poll::Ready(event)
+    }
+}
@@ -0,0 +1,102 @@
+//! FSM handles as [`tower_service::Service`]s, with the `tower` feature.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{CallError, FsmCall, Reservation};
+
+/// An FSM handle as a `Service<[FsmName]Event, Response = [FsmName]State>`,
+/// so FSMs can sit behind tower middleware such as timeouts, rate limits
+/// and load shedding.
+///
+/// Each request is sent with the handle's `call`, and its response is the
+/// state the event's handler left the FSM in:
+///
+/// ```rust,ignore
+/// let service = ServiceBuilder::new()
+///     .timeout(Duration::from_secs(1))
+///     .service(FsmService::new(handle));
+/// let state = service.oneshot(OrderFsmEvent::Pay).await?;
+/// ```
+///
+/// `poll_ready` waits for room in the FSM's queue and holds it for the next
+/// `call`, so a full queue holds up readiness rather than the response, and
+/// middleware such as load shedding sees it. Only the default tokio channel
+/// can hold room ahead of a send; with the `flume` and `kanal` backends the
+/// service is always ready and the response waits for capacity instead.
+pub struct FsmService<H: FsmCall> {
+    handle: H,
+    /// The reservation `poll_ready` is waiting for.
+    reserving: Option<Reserving<H::Event>>,
+    /// Room held for the next `call`.
+    reserved: Option<Reservation<H::Event>>,
+}
+
+/// A pending [`FsmCall::reserve`].
+type Reserving<E> = Pin<Box<dyn Future<Output = Reservation<E>> + Send>>;
+
+impl<H: FsmCall> FsmService<H> {
+    /// Wraps `handle`.
+    pub fn new(handle: H) -> Self {
+        Self {
+            handle,
+            reserving: None,
+            reserved: None,
+        }
+    }
+
+    /// Returns the wrapped handle.
+    pub fn handle(&self) -> &H {
+        &self.handle
+    }
+
+    /// Unwraps the handle.
+    pub fn into_inner(self) -> H {
+        self.handle
+    }
+}
+
+/// Clones hold no room of their own until polled ready.
+impl<H: FsmCall> Clone for FsmService<H> {
+    fn clone(&self) -> Self {
+        Self::new(self.handle.clone())
+    }
+}
+
+impl<H: FsmCall + std::fmt::Debug> std::fmt::Debug for FsmService<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FsmService")
+            .field("handle", &self.handle)
+            .field("reserved", &self.reserved)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<H: FsmCall> tower_service::Service<H::Event> for FsmService<H> {
+    type Response = H::State;
+    type Error = CallError<H::Event>;
+    type Future = Pin<Box<dyn Future<Output = Result<H::State, CallError<H::Event>>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.reserved.is_none() {
+            let reserving = self.reserving.get_or_insert_with(|| {
+                let handle = self.handle.clone();
+                Box::pin(async move { handle.reserve().await })
+            });
+            let reservation = std::task::ready!(reserving.as_mut().poll(cx));
+            self.reserving = None;
+            self.reserved = Some(reservation);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, event: H::Event) -> Self::Future {
+        let handle = self.handle.clone();
+        // Called without `poll_ready`, the send waits for capacity itself.
+        let reservation = self.reserved.take().unwrap_or_else(Reservation::none);
+        Box::pin(async move { handle.call_reserved(reservation, event).await })
+    }
+}
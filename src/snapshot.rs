@@ -0,0 +1,251 @@
+use std::str::FromStr;
+
+use crate::core::ParseNameError;
+
+/// A point-in-time copy of an FSM's state and context, tagged with the
+/// context schema version it was written with.
+///
+/// Build one from a `#[persist]` hook with
+/// `Snapshot::new(MyContext::VERSION, self.state, &self.context)`, and resume
+/// it with the generated `MyFsm::spawn_from(snapshot)`. Snapshots written by
+/// older code are upgraded with [`Snapshot::migrate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot<S, C> {
+    /// Schema version of `context` (and of the state names).
+    pub version: u32,
+    /// The state the FSM was in.
+    pub state: S,
+    /// The FSM's context.
+    pub context: C,
+}
+
+impl<S, C> Snapshot<S, C> {
+    /// Creates a snapshot at the given schema version.
+    pub fn new(version: u32, state: S, context: C) -> Self {
+        Self {
+            version,
+            state,
+            context,
+        }
+    }
+}
+
+impl<R> Snapshot<String, R> {
+    /// Upgrades a snapshot read in its raw form (state as a name, context as
+    /// `C::Raw`) to the current schema and decodes it.
+    ///
+    /// Runs [`MigrateContext::upgrade`] once per version step, then parses the
+    /// state name into `S` and builds the context with
+    /// [`MigrateContext::from_raw`].
+    pub fn migrate<S, C>(mut self) -> Result<Snapshot<S, C>, MigrationError>
+    where
+        S: FromStr<Err = ParseNameError>,
+        C: MigrateContext<Raw = R>,
+    {
+        if self.version > C::VERSION {
+            return Err(MigrationError::UnsupportedVersion {
+                found: self.version,
+                current: C::VERSION,
+            });
+        }
+        while self.version < C::VERSION {
+            C::upgrade(&mut self)?;
+            self.version += 1;
+        }
+
+        Ok(Snapshot {
+            version: self.version,
+            state: self.state.parse()?,
+            context: C::from_raw(self.context)?,
+        })
+    }
+}
+
+/// Schema evolution for a context stored in [`Snapshot`]s.
+///
+/// # Example
+///
+/// ```rust
+/// use tokio_fsm::{MigrateContext, MigrationError, Snapshot};
+///
+/// /// v0 stored `{ "total": u64 }`; v1 renamed it and added `currency`.
+/// struct Order {
+///     amount: u64,
+///     currency: String,
+/// }
+///
+/// impl MigrateContext for Order {
+///     const VERSION: u32 = 1;
+///     type Raw = Vec<(String, String)>;
+///
+///     fn upgrade(snapshot: &mut Snapshot<String, Self::Raw>) -> Result<(), MigrationError> {
+///         if snapshot.version == 0 {
+///             for (key, _) in &mut snapshot.context {
+///                 if key == "total" {
+///                     *key = "amount".into();
+///                 }
+///             }
+///             snapshot.context.push(("currency".into(), "EUR".into()));
+///             // The `Billed` state was renamed to `Invoiced`.
+///             if snapshot.state == "Billed" {
+///                 snapshot.state = "Invoiced".into();
+///             }
+///         }
+///         Ok(())
+///     }
+///
+///     fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError> {
+///         let field = |name: &str| {
+///             raw.iter()
+///                 .find(|(key, _)| key == name)
+///                 .map(|(_, value)| value.clone())
+///                 .ok_or_else(|| MigrationError::custom(format!("missing field {name}")))
+///         };
+///         Ok(Order {
+///             amount: field("amount")?.parse().map_err(MigrationError::custom)?,
+///             currency: field("currency")?,
+///         })
+///     }
+/// }
+/// ```
+pub trait MigrateContext: Sized {
+    /// Current schema version, written into new snapshots.
+    const VERSION: u32;
+
+    /// Version-independent form old contexts are read as, such as
+    /// `serde_json::Value`.
+    type Raw;
+
+    /// Upgrades `snapshot` from `snapshot.version` to the next version.
+    ///
+    /// State renames are applied by rewriting `snapshot.state`. The version
+    /// number is bumped by the caller.
+    fn upgrade(snapshot: &mut Snapshot<String, Self::Raw>) -> Result<(), MigrationError>;
+
+    /// Decodes a context already upgraded to [`VERSION`](Self::VERSION).
+    fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError>;
+}
+
+/// An event as stored in an event-sourcing journal, tagged with the event
+/// schema version it was written with.
+///
+/// Append one per handled event with
+/// `JournalEntry::new(<MyFsmEvent as UpcastEvent>::VERSION, event)`, and
+/// rebuild the FSM by feeding the upcast events to a core:
+///
+/// ```rust,ignore
+/// let mut core = LedgerFsm::builder(Ledger::default()).core();
+/// for line in journal.lines() {
+///     let raw: JournalEntry<serde_json::Value> = serde_json::from_str(line)?;
+///     core.process(raw.upcast()?).await?;
+/// }
+/// ```
+///
+/// Entries written by older code are upgraded with
+/// [`JournalEntry::upcast`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JournalEntry<E> {
+    /// Schema version of `event`.
+    pub version: u32,
+    /// The event.
+    pub event: E,
+}
+
+impl<E> JournalEntry<E> {
+    /// Creates an entry at the given schema version.
+    pub fn new(version: u32, event: E) -> Self {
+        Self { version, event }
+    }
+}
+
+impl<R> JournalEntry<R> {
+    /// Upgrades an entry read in its raw form to the current schema and
+    /// decodes its event.
+    ///
+    /// Runs [`UpcastEvent::upcast`] once per version step, then builds the
+    /// event with [`UpcastEvent::from_raw`].
+    pub fn upcast<E>(mut self) -> Result<E, MigrationError>
+    where
+        E: UpcastEvent<Raw = R>,
+    {
+        if self.version > E::VERSION {
+            return Err(MigrationError::UnsupportedVersion {
+                found: self.version,
+                current: E::VERSION,
+            });
+        }
+        while self.version < E::VERSION {
+            E::upcast(&mut self)?;
+            self.version += 1;
+        }
+        E::from_raw(self.event)
+    }
+}
+
+/// Schema evolution for events stored in [`JournalEntry`]s, implemented on
+/// the generated event enum.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// /// v0 called `Deposit` `Credit`, with the amount as its payload; v1 added
+/// /// the currency.
+/// impl UpcastEvent for LedgerFsmEvent {
+///     const VERSION: u32 = 1;
+///     type Raw = serde_json::Value;
+///
+///     fn upcast(entry: &mut JournalEntry<Self::Raw>) -> Result<(), MigrationError> {
+///         if entry.version == 0 {
+///             if let Some(amount) = entry.event.get("Credit") {
+///                 entry.event = json!({ "Deposit": { "amount": amount, "currency": "EUR" } });
+///             }
+///         }
+///         Ok(())
+///     }
+///
+///     fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError> {
+///         serde_json::from_value(raw).map_err(MigrationError::custom)
+///     }
+/// }
+/// ```
+pub trait UpcastEvent: Sized {
+    /// Current schema version, written into new entries.
+    const VERSION: u32;
+
+    /// Version-independent form old events are read as, such as
+    /// `serde_json::Value`.
+    type Raw;
+
+    /// Upgrades `entry` from `entry.version` to the next version.
+    ///
+    /// Event renames and new payload fields are applied by rewriting
+    /// `entry.event`. The version number is bumped by the caller.
+    fn upcast(entry: &mut JournalEntry<Self::Raw>) -> Result<(), MigrationError>;
+
+    /// Decodes an event already upgraded to [`VERSION`](Self::VERSION).
+    fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError>;
+}
+
+/// Errors raised while upgrading a [`Snapshot`] or a [`JournalEntry`].
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    /// The snapshot or entry was written by a newer schema than this build
+    /// knows.
+    #[error("schema version {found} is newer than the current version {current}")]
+    UnsupportedVersion { found: u32, current: u32 },
+    /// The (migrated) state name does not exist in the FSM.
+    #[error(transparent)]
+    State(#[from] ParseNameError),
+    /// A user-defined migration step failed.
+    #[error("migration failed: {0}")]
+    Custom(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl MigrationError {
+    /// Wraps an error raised by a migration step.
+    pub fn custom(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self::Custom(error.into())
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicIsize, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::core::{InstanceId, TransitionCause};
+
+/// Number of histogram buckets. Bucket `i` holds durations below `2^i`
+/// microseconds; the last one also collects anything slower.
+const BUCKETS: usize = 32;
+
+/// Latency distribution of a single handler.
+///
+/// Durations are bucketed by powers of two microseconds, so quantiles are
+/// approximate (within a factor of two) but recording is allocation-free.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    /// Adds a sample.
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros();
+        let bucket = if micros == 0 {
+            0
+        } else {
+            (u128::BITS - micros.leading_zeros()) as usize
+        };
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    /// Number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Mean duration, or zero if nothing was recorded.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.sum.as_nanos() / u128::from(self.count)) as u64)
+    }
+
+    /// Slowest recorded duration.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Approximate `q`-quantile (e.g. `0.99` for p99): the upper bound of the
+    /// bucket containing that rank, capped at [`max`](Self::max).
+    pub fn quantile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_micros(1 << i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Handler latencies of one FSM instance, keyed by `(state, event)`.
+///
+/// Obtained from the generated `handle.stats()`. Timeout handlers are
+/// recorded under the event name `"timeout"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsmStats {
+    handlers: BTreeMap<(&'static str, &'static str), LatencyHistogram>,
+}
+
+impl FsmStats {
+    /// Latencies of the handler run for `event` in `state`, if it ever ran.
+    pub fn handler(&self, state: &str, event: &str) -> Option<&LatencyHistogram> {
+        self.handlers
+            .iter()
+            .find(|((s, e), _)| *s == state && *e == event)
+            .map(|(_, histogram)| histogram)
+    }
+
+    /// Iterates over `(state, event, latencies)` in name order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &'static str, &LatencyHistogram)> {
+        self.handlers
+            .iter()
+            .map(|(&(state, event), histogram)| (state, event, histogram))
+    }
+}
+
+/// Number of transitions kept for introspection.
+const HISTORY: usize = 32;
+
+/// A state change with type-erased state names, as kept in an instance's
+/// recent history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TransitionRecord {
+    pub seq: u64,
+    pub from: &'static str,
+    pub to: &'static str,
+    pub cause: TransitionCause,
+}
+
+/// Shared sink the run loop records handler latencies into.
+///
+/// With the `debug-http` feature it also tracks recent transitions and the
+/// number of queued events for introspection.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct StatsRecorder {
+    fsm: &'static str,
+    stats: Arc<Mutex<FsmStats>>,
+    history: Arc<Mutex<VecDeque<TransitionRecord>>>,
+    queued: Arc<AtomicIsize>,
+    /// Registered once per handler, since building the labels allocates.
+    #[cfg(feature = "metrics")]
+    histograms: Arc<Mutex<BTreeMap<(&'static str, &'static str), metrics::Histogram>>>,
+}
+
+impl StatsRecorder {
+    pub fn new(fsm: &'static str) -> Self {
+        Self {
+            fsm,
+            stats: Arc::default(),
+            history: Arc::default(),
+            queued: Arc::default(),
+            #[cfg(feature = "metrics")]
+            histograms: Arc::default(),
+        }
+    }
+
+    pub fn fsm(&self) -> &'static str {
+        self.fsm
+    }
+
+    pub fn record_transition(&self, record: TransitionRecord) {
+        let mut history = self
+            .history
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// Recent transitions, oldest first.
+    pub fn history(&self) -> Vec<TransitionRecord> {
+        self.history
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    pub fn enqueued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Events sent but not yet taken off the queue. A send and its receipt
+    /// may be counted out of order, so this is approximate.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed).max(0) as usize
+    }
+
+    pub fn record(&self, state: &'static str, event: &'static str, elapsed: Duration) {
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .handlers
+            .entry((state, event))
+            .or_default()
+            .record(elapsed);
+
+        #[cfg(feature = "metrics")]
+        self.histograms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry((state, event))
+            .or_insert_with(|| {
+                metrics::histogram!(
+                    "tokio_fsm_handler_duration_seconds",
+                    "fsm" => self.fsm,
+                    "state" => state,
+                    "event" => event,
+                )
+            })
+            .record(elapsed.as_secs_f64());
+    }
+
+    pub fn snapshot(&self) -> FsmStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+/// A budget for how long an event handler may run, set with the builder's
+/// `watchdog`.
+///
+/// Internal-only: This is driven by generated code.
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct Watchdog<E> {
+    pub budget: Duration,
+    /// Sent to the FSM when a handler overruns, if set with `watchdog_event`.
+    pub event: Option<E>,
+}
+
+impl<E: Clone> Watchdog<E> {
+    /// Reports a handler that has been running for longer than the budget,
+    /// returning the event to send in response.
+    ///
+    /// With the `tracing` feature this is a warning on the `tokio_fsm`
+    /// target; with `metrics` it increments
+    /// `tokio_fsm_watchdog_fired_total`.
+    pub fn fire(&self, fsm: &'static str, state: &'static str, event: &'static str) -> Option<E> {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            target: "tokio_fsm",
+            fsm,
+            state,
+            event,
+            budget_ms = self.budget.as_millis() as u64,
+            "handler exceeded its watchdog budget"
+        );
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "tokio_fsm_watchdog_fired_total",
+            "fsm" => fsm,
+            "state" => state,
+            "event" => event,
+        )
+        .increment(1);
+        #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+        let _ = (fsm, state, event);
+        self.event.clone()
+    }
+}
+
+/// Type-erased, read-only view of a running FSM instance, used by
+/// introspection tools such as the `debug-http` router.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct Inspector {
+    pub id: InstanceId,
+    pub mermaid: &'static str,
+    pub stats: StatsRecorder,
+    pub last_change: Arc<dyn Fn() -> TransitionRecord + Send + Sync>,
+}
+
+impl std::fmt::Debug for Inspector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inspector")
+            .field("id", &self.id)
+            .field("fsm", &self.stats.fsm())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{
+    WorkHandle, WorkSet,
+    core::{ChildTask, FsmHandle, FsmTask},
+};
+
+/// Spawns an FSM as the child of a `#[submachine]` state.
+///
+/// Internal-only: This is implemented for every FSM by the `#[fsm]` macro.
+#[doc(hidden)]
+pub trait Submachine {
+    /// The FSM's `type Context`.
+    type Context;
+    /// The generated `[FsmName]Handle`.
+    type Handle: FsmHandle;
+    /// The generated `[FsmName]Task`.
+    type Task: FsmTask;
+
+    /// Spawns the FSM in its initial state. Commands pushed via `emit` are
+    /// dropped.
+    fn spawn_submachine(context: Self::Context) -> (Self::Handle, Self::Task);
+
+    /// Whether no transition leaves `state`.
+    fn is_terminal(state: &<Self::Handle as FsmHandle>::State) -> bool;
+}
+
+/// A running child FSM, owned by the parent while it stays in the
+/// `#[submachine]` state. Dropping the slot aborts the child.
+///
+/// Internal-only: This is created by generated run loops.
+#[doc(hidden)]
+pub struct SubmachineSlot<H> {
+    handle: H,
+    watcher: WorkHandle,
+}
+
+impl<H: FsmHandle> SubmachineSlot<H> {
+    /// Spawns the child `M` with a default context, and a watcher on `work`
+    /// that reports the child's final state via `on_done` once it reaches a
+    /// terminal state or stops.
+    pub fn spawn<M, E>(work: &mut WorkSet<E>, on_done: fn(H::State) -> E) -> Self
+    where
+        M: Submachine<Handle = H>,
+        M::Context: Default,
+        E: Send + 'static,
+    {
+        let (handle, task) = M::spawn_submachine(M::Context::default());
+        let mut changes = handle.subscribe();
+        let child = handle.clone();
+        let watcher = work.spawn(async move {
+            let mut task = ChildTask(task);
+            tokio::select! {
+                biased;
+
+                terminal = changes.wait_for(|change| M::is_terminal(&change.to)) => {
+                    if let Ok(change) = terminal {
+                        return on_done(change.to);
+                    }
+                }
+                _ = &mut task => {}
+            }
+            on_done(child.current_state())
+        });
+        Self { handle, watcher }
+    }
+
+    /// Forwards `event` to the child, handing it back if the child has
+    /// stopped.
+    pub async fn forward(&self, event: H::Event) -> Result<(), H::Event> {
+        self.handle.send(event).await.map_err(|err| err.0)
+    }
+}
+
+impl<H> Drop for SubmachineSlot<H> {
+    fn drop(&mut self) {
+        self.watcher.abort();
+    }
+}
@@ -0,0 +1,177 @@
+//! Utilities for asserting the allocation behavior of FSMs in tests.
+//!
+//! Install [`CountingAllocator`] as the global allocator of a test binary,
+//! then measure a transition with [`count_allocations`]:
+//!
+//! ```rust,ignore
+//! use tokio_fsm::test_util::{CountingAllocator, count_allocations};
+//!
+//! #[global_allocator]
+//! static ALLOC: CountingAllocator = CountingAllocator::system();
+//!
+//! #[tokio::test] // current-thread runtime: the FSM runs on the test thread
+//! async fn transitions_do_not_allocate() {
+//!     let (handle, _task) = OrderFsm::spawn(Default::default());
+//!     // Warm up so lazily grown buffers (stats, channel blocks of 32
+//!     // events) reach their steady size.
+//!     for _ in 0..32 {
+//!         handle.send(OrderFsmEvent::Pay).await.unwrap();
+//!         handle.send(OrderFsmEvent::Refund).await.unwrap();
+//!     }
+//!     handle.wait_for_state(OrderFsmState::Refunded).await.unwrap();
+//!
+//!     let (_, allocations) = count_allocations(async {
+//!         handle.send(OrderFsmEvent::Pay).await.unwrap();
+//!         handle.wait_for_state(OrderFsmState::Paid).await.unwrap();
+//!     })
+//!     .await;
+//!     assert_eq!(allocations.count, 0, "{allocations:?}");
+//! }
+//! ```
+//!
+//! Allocations are counted per thread, so tests running in parallel don't
+//! see each other's allocations. On a multi-threaded runtime the FSM task
+//! may run on another worker and go uncounted; use a current-thread runtime.
+//!
+//! With the `proptest` feature, [`FsmModel`] generates random event sequences
+//! that follow an FSM's transition table and [`drive`] runs them against a
+//! spawned FSM, checking an invariant on the context after every step.
+//!
+//! [`FsmTester`] steps a single FSM under paused time, asserting each
+//! transition and advancing the clock to fire timeouts. [`TraceRecorder`]
+//! captures a run as a [`Trace`] that [`replay`] checks a fresh FSM against.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    future::Future,
+};
+
+use crate::core::FsmHandle;
+
+#[cfg(feature = "proptest")]
+mod model;
+mod tester;
+mod trace;
+
+#[cfg(feature = "proptest")]
+pub use self::model::*;
+pub use self::{tester::*, trace::*};
+
+/// Waits for the FSM behind `handle` to handle its queued events and finish
+/// the handler in progress.
+async fn settle<H: FsmHandle>(handle: &H) {
+    loop {
+        tokio::task::yield_now().await;
+        if handle.queue_len() == 0 {
+            break;
+        }
+    }
+    // Answered between handlers, once `#[auto]` transitions have run.
+    let _ = handle.inspect(|_| ()).await;
+}
+
+thread_local! {
+    static COUNT: Cell<u64> = const { Cell::new(0) };
+    static BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A global allocator that counts the allocations made by each thread,
+/// delegating the actual work to `A`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CountingAllocator<A = System>(pub A);
+
+impl CountingAllocator<System> {
+    /// Counts allocations served by the system allocator.
+    pub const fn system() -> Self {
+        Self(System)
+    }
+}
+
+fn note(size: usize) {
+    // `try_with` keeps allocations made during thread teardown working.
+    let _ = COUNT.try_with(|count| count.set(count.get() + 1));
+    let _ = BYTES.try_with(|bytes| bytes.set(bytes.get() + size as u64));
+}
+
+// SAFETY: every call is forwarded unchanged to the wrapped allocator.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        note(layout.size());
+        unsafe { self.0.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        note(layout.size());
+        unsafe { self.0.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        note(new_size);
+        unsafe { self.0.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) }
+    }
+}
+
+/// Allocations (including reallocations) counted on one thread.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Allocations {
+    /// Number of allocation calls.
+    pub count: u64,
+    /// Total bytes requested.
+    pub bytes: u64,
+}
+
+impl Allocations {
+    /// Allocations made by the current thread so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`CountingAllocator`] is not the global allocator, so a
+    /// misconfigured test can't pass vacuously.
+    pub fn current() -> Self {
+        let before = COUNT.with(Cell::get);
+        drop(std::hint::black_box(Box::new(0u8)));
+        assert!(
+            COUNT.with(Cell::get) > before,
+            "tokio_fsm::test_util::CountingAllocator is not installed as the #[global_allocator]"
+        );
+        Self::read()
+    }
+
+    fn read() -> Self {
+        Self {
+            count: COUNT.with(Cell::get),
+            bytes: BYTES.with(Cell::get),
+        }
+    }
+
+    fn since(start: Self) -> Self {
+        let end = Self::read();
+        Self {
+            count: end.count - start.count,
+            bytes: end.bytes - start.bytes,
+        }
+    }
+}
+
+/// Runs `f` and returns its result with the allocations it made.
+pub fn count_allocations_sync<R>(f: impl FnOnce() -> R) -> (R, Allocations) {
+    let start = Allocations::current();
+    let output = f();
+    (output, Allocations::since(start))
+}
+
+/// Awaits `future` and returns its output with every allocation made on the
+/// current thread meanwhile.
+///
+/// That includes other tasks polled in between, which on a current-thread
+/// runtime covers the FSM's run loop and handlers.
+pub async fn count_allocations<F: Future>(future: F) -> (F::Output, Allocations) {
+    let start = Allocations::current();
+    let output = future.await;
+    (output, Allocations::since(start))
+}
@@ -0,0 +1,201 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use proptest::{
+    collection::{SizeRange, vec},
+    prelude::*,
+    sample::Index,
+};
+
+use crate::{
+    clock::ManualClock,
+    core::{FsmHandle, PingError},
+};
+
+/// One step of a generated run.
+#[derive(Debug, Clone)]
+pub enum Step<E> {
+    /// Send the event and wait for it to be handled.
+    Send(E),
+    /// Move the [`ManualClock`] forward, firing the state timeouts that
+    /// expire.
+    Advance(Duration),
+}
+
+/// Generates event sequences that follow an FSM's transition table.
+///
+/// Each sequence is a walk from `initial` over `MyFsm::transitions()`,
+/// picking one of the current state's outgoing events at random. Payload-less
+/// events are built from their name; events carrying a payload need a
+/// strategy registered with [`payload`](Self::payload), or are never picked.
+///
+/// ```rust,ignore
+/// let model = FsmModel::new(AccountFsmState::Open, AccountFsm::transitions())
+///     .payload("Deposit", (1..100u64).prop_map(AccountFsmEvent::Deposit));
+///
+/// proptest!(|(steps in model.steps(1..50))| {
+///     let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+///     runtime.block_on(async {
+///         let clock = ManualClock::new();
+///         let (handle, _task) = AccountFsm::builder(0).clock(clock.clone()).spawn();
+///         drive(&handle, &clock, steps, |_, balance| assert!(*balance < 10_000)).await
+///     })?;
+/// });
+/// ```
+///
+/// A handler returning `Result<Transition<A>, Transition<B>>` appears in the
+/// table once per target, so the walk may assume a different outcome than
+/// the FSM takes. Events the FSM then ignores are dropped as usual.
+pub struct FsmModel<S: 'static, E> {
+    initial: S,
+    transitions: &'static [(S, &'static str, S)],
+    payloads: Vec<(&'static str, BoxedStrategy<E>)>,
+    advance_up_to: Option<Duration>,
+}
+
+impl<S, E> FsmModel<S, E>
+where
+    S: Copy + PartialEq + fmt::Debug + 'static,
+    E: FromStr + Clone + fmt::Debug + 'static,
+{
+    /// Creates a model walking `transitions` from `initial`.
+    pub fn new(initial: S, transitions: &'static [(S, &'static str, S)]) -> Self {
+        Self {
+            initial,
+            transitions,
+            payloads: Vec::new(),
+            advance_up_to: None,
+        }
+    }
+
+    /// Builds the event named `event` with `strategy`, e.g.
+    /// `(1..100u64).prop_map(MyFsmEvent::Deposit)`.
+    pub fn payload(
+        mut self,
+        event: &'static str,
+        strategy: impl Strategy<Value = E> + 'static,
+    ) -> Self {
+        self.payloads.retain(|(name, _)| *name != event);
+        self.payloads.push((event, strategy.boxed()));
+        self
+    }
+
+    /// Mixes [`Step::Advance`] steps of up to `max` into the sequences, so
+    /// state timeouts fire along the way.
+    pub fn advance_up_to(mut self, max: Duration) -> Self {
+        self.advance_up_to = Some(max);
+        self
+    }
+
+    fn event(&self, name: &str) -> Option<BoxedStrategy<E>> {
+        if let Some((_, strategy)) = self.payloads.iter().find(|(n, _)| *n == name) {
+            return Some(strategy.clone());
+        }
+        E::from_str(name).ok().map(|event| Just(event).boxed())
+    }
+
+    /// Generates sequences of `len` steps, or fewer if the walk reaches a
+    /// state with no usable outgoing event.
+    pub fn steps(&self, len: impl Into<SizeRange>) -> BoxedStrategy<Vec<Step<E>>> {
+        let model = self.clone();
+        let advance = match self.advance_up_to {
+            Some(_) => proptest::bool::weighted(0.25).boxed(),
+            None => Just(false).boxed(),
+        };
+        vec((any::<Index>(), advance), len)
+            .prop_flat_map(move |choices| model.walk(choices))
+            .boxed()
+    }
+
+    fn walk(&self, choices: Vec<(Index, bool)>) -> Vec<BoxedStrategy<Step<E>>> {
+        let mut state = self.initial;
+        let mut steps = Vec::with_capacity(choices.len());
+        for (choice, advance) in choices {
+            if let (Some(max), true) = (self.advance_up_to, advance) {
+                let max = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+                steps.push(
+                    (0..=max)
+                        .prop_map(|millis| Step::Advance(Duration::from_millis(millis)))
+                        .boxed(),
+                );
+                continue;
+            }
+            let outgoing: Vec<(BoxedStrategy<E>, S)> = self
+                .transitions
+                .iter()
+                .filter(|(from, ..)| *from == state)
+                .filter_map(|(_, event, to)| Some((self.event(event)?, *to)))
+                .collect();
+            if outgoing.is_empty() {
+                break;
+            }
+            let (event, to) = choice.get(&outgoing).clone();
+            steps.push(event.prop_map(Step::Send).boxed());
+            state = to;
+        }
+        steps
+    }
+}
+
+impl<S: Copy, E> Clone for FsmModel<S, E> {
+    fn clone(&self) -> Self {
+        Self {
+            initial: self.initial,
+            transitions: self.transitions,
+            payloads: self.payloads.clone(),
+            advance_up_to: self.advance_up_to,
+        }
+    }
+}
+
+impl<S: fmt::Debug, E> fmt::Debug for FsmModel<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsmModel")
+            .field("initial", &self.initial)
+            .field("transitions", &self.transitions.len())
+            .field(
+                "payloads",
+                &self
+                    .payloads
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("advance_up_to", &self.advance_up_to)
+            .finish()
+    }
+}
+
+/// Runs `steps` against a spawned FSM whose builder was given `clock`, and
+/// calls `check` with the state and a copy of the context after each one.
+///
+/// Each `Send` step waits for the queue to drain before reading the context,
+/// so `check` sees the event's outcome. Use a current-thread runtime, where
+/// the FSM task gets to run whenever the driver yields.
+pub async fn drive<H>(
+    handle: &H,
+    clock: &ManualClock,
+    steps: Vec<Step<H::Event>>,
+    mut check: impl FnMut(H::State, &H::Context),
+) -> Result<(), PingError>
+where
+    H: FsmHandle,
+    H::Context: Clone,
+{
+    for step in steps {
+        match step {
+            Step::Send(event) => {
+                handle.send(event).await.map_err(|_| PingError::Stopped)?;
+                loop {
+                    tokio::task::yield_now().await;
+                    if handle.queue_len() == 0 {
+                        break;
+                    }
+                }
+            }
+            Step::Advance(duration) => clock.advance(duration),
+        }
+        let context = handle.inspect(|context| context.clone()).await?;
+        check(handle.current_state(), &context);
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::{fmt, time::Duration};
+
+use crate::{
+    core::{ChildTask, FsmHandle, StateChange},
+    rt::watch,
+    submachine::Submachine,
+};
+
+/// Drives a spawned FSM step by step under paused tokio time, asserting each
+/// transition instead of sleeping and polling `subscribe()`:
+///
+/// ```rust,ignore
+/// #[tokio::test]
+/// async fn order_times_out() {
+///     let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+///     fsm.expect_transition(OrderFsmState::Open, OrderFsmEvent::Pay, OrderFsmState::Paid)
+///         .await;
+///     fsm.advance(Duration::from_secs(30)).await;
+///     fsm.assert_state(OrderFsmState::Expired);
+/// }
+/// ```
+///
+/// Every transition must be accounted for: one left unasserted after
+/// [`advance`](Self::advance) fails the next `expect_transition`, naming the
+/// state it went to. The tester pauses time on creation (unless it already
+/// is) and needs a current-thread runtime, as `#[tokio::test]` provides.
+/// Dropping it aborts the FSM.
+pub struct FsmTester<M: Submachine> {
+    handle: M::Handle,
+    changes: watch::Receiver<StateChange<<M::Handle as FsmHandle>::State>>,
+    seen: u64,
+    _task: ChildTask<M::Task>,
+}
+
+impl<M> FsmTester<M>
+where
+    M: Submachine,
+    <M::Handle as FsmHandle>::State: PartialEq + fmt::Debug,
+{
+    /// Pauses time and spawns the FSM with `context`.
+    pub fn new(context: M::Context) -> Self {
+        if !time_is_paused() {
+            tokio::time::pause();
+        }
+        let (handle, task) = M::spawn_submachine(context);
+        let changes = handle.subscribe();
+        Self {
+            handle,
+            changes,
+            seen: 0,
+            _task: ChildTask(task),
+        }
+    }
+
+    /// The handle of the FSM under test.
+    pub fn handle(&self) -> &M::Handle {
+        &self.handle
+    }
+
+    /// Sends `event` from state `from` and waits for it to be handled,
+    /// panicking unless it causes exactly one transition, to `to`.
+    pub async fn expect_transition(
+        &mut self,
+        from: <M::Handle as FsmHandle>::State,
+        event: <M::Handle as FsmHandle>::Event,
+        to: <M::Handle as FsmHandle>::State,
+    ) {
+        super::settle(&self.handle).await;
+        let before = *self.changes.borrow_and_update();
+        assert!(
+            before.seq == self.seen,
+            "unexpected transition to {:?} before sending the event",
+            before.to
+        );
+        assert_eq!(before.to, from, "FSM is not in the expected state");
+
+        if self.handle.send(event).await.is_err() {
+            panic!("FSM stopped in {:?} before the event was sent", before.to);
+        }
+        super::settle(&self.handle).await;
+
+        let after = *self.changes.borrow_and_update();
+        match after.seq - before.seq {
+            0 => panic!("event caused no transition from {from:?}"),
+            1 => assert!(
+                after.from == from && after.to == to,
+                "expected {from:?} -> {to:?}, got {:?} -> {:?}",
+                after.from,
+                after.to
+            ),
+            n => panic!(
+                "expected {from:?} -> {to:?}, got {n} transitions ending in {:?}",
+                after.to
+            ),
+        }
+        self.seen = after.seq;
+    }
+
+    /// Moves paused time forward by `duration`, firing the state timeouts
+    /// that expire, and waits for the FSM to handle them. Check where it
+    /// ended up with [`assert_state`](Self::assert_state).
+    pub async fn advance(&mut self, duration: Duration) {
+        // Sleeping lets the runtime auto-advance, rounding to its 1ms timer
+        // ticks like the FSM's own timeouts. `time::advance` doesn't, so a
+        // timeout of exactly `duration` could be left a tick short.
+        tokio::time::sleep(duration).await;
+        super::settle(&self.handle).await;
+    }
+
+    /// Panics unless the FSM is in `state`, accepting any transitions since
+    /// the last assertion.
+    #[track_caller]
+    pub fn assert_state(&mut self, state: <M::Handle as FsmHandle>::State) {
+        let current = *self.changes.borrow_and_update();
+        assert_eq!(current.to, state, "FSM is not in the expected state");
+        self.seen = current.seq;
+    }
+}
+
+impl<M: Submachine> fmt::Debug for FsmTester<M>
+where
+    <M::Handle as FsmHandle>::State: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsmTester")
+            .field("state", &self.changes.borrow().to)
+            .field("seen", &self.seen)
+            .finish()
+    }
+}
+
+/// Whether tokio's clock is paused. Tokio doesn't expose this, but a paused
+/// clock stands still while the thread sleeps.
+fn time_is_paused() -> bool {
+    let before = tokio::time::Instant::now();
+    std::thread::sleep(Duration::from_micros(1));
+    tokio::time::Instant::now() == before
+}
@@ -0,0 +1,195 @@
+use std::{fmt, time::Duration};
+
+use tokio::sync::mpsc::error::SendError;
+
+use crate::{core::FsmHandle, rt::Instant};
+
+/// A recorded run of an FSM: the events it was sent, the transitions they
+/// caused and when, for golden-trace regression tests.
+///
+/// With the `serde` feature (and `#[fsm(serde)]` on the FSM) a trace can be
+/// written to a file by [`TraceRecorder`] once and checked by [`replay`] on
+/// every later run, catching behavior changes across refactors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trace<S, E> {
+    /// The events, in the order they were sent.
+    pub steps: Vec<TraceStep<S, E>>,
+    /// Time from the start of the recording to its end.
+    pub duration: Duration,
+    /// The state the FSM ended up in.
+    pub final_state: S,
+}
+
+/// One event of a [`Trace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceStep<S, E> {
+    /// Time from the start of the recording to the send.
+    pub at: Duration,
+    /// The event sent.
+    pub event: E,
+    /// The state the FSM was in when the event was sent. It differs from the
+    /// previous step's `to` if a state timeout fired in between.
+    pub from: S,
+    /// The state the FSM settled in after handling the event, including any
+    /// `#[auto]` transitions it led to.
+    pub to: S,
+}
+
+/// Records a [`Trace`] of the events sent through it to an FSM.
+///
+/// ```rust,ignore
+/// let (handle, _task) = OrderFsm::spawn(Order::default());
+/// let mut recorder = TraceRecorder::new(handle);
+/// recorder.send(OrderFsmEvent::Pay).await?;
+/// tokio::time::sleep(Duration::from_secs(30)).await;
+/// let trace = recorder.finish().await;
+/// std::fs::write("tests/golden/order.json", serde_json::to_string_pretty(&trace)?)?;
+/// ```
+///
+/// Each `send` waits for the event to be handled before recording where the
+/// FSM ended up. Record under paused time (`#[tokio::test(start_paused =
+/// true)]`) so timestamps, and the timeouts they trigger, replay exactly.
+pub struct TraceRecorder<H: FsmHandle> {
+    handle: H,
+    started: Instant,
+    steps: Vec<TraceStep<H::State, H::Event>>,
+}
+
+impl<H> TraceRecorder<H>
+where
+    H: FsmHandle,
+    H::Event: Clone,
+{
+    /// Starts recording the events sent to `handle`'s FSM, timestamping them
+    /// from now.
+    pub fn new(handle: H) -> Self {
+        Self {
+            handle,
+            started: crate::rt::now(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// The handle of the FSM being recorded. Events sent through it directly
+    /// are not recorded.
+    pub fn handle(&self) -> &H {
+        &self.handle
+    }
+
+    /// Sends `event`, waits for it to be handled and records the transition.
+    pub async fn send(&mut self, event: H::Event) -> Result<(), SendError<H::Event>> {
+        super::settle(&self.handle).await;
+        let at = self.started.elapsed();
+        let from = self.handle.current_state();
+        self.handle.send(event.clone()).await?;
+        super::settle(&self.handle).await;
+        self.steps.push(TraceStep {
+            at,
+            event,
+            from,
+            to: self.handle.current_state(),
+        });
+        Ok(())
+    }
+
+    /// Ends the recording, noting the time and the FSM's final state.
+    pub async fn finish(self) -> Trace<H::State, H::Event> {
+        super::settle(&self.handle).await;
+        Trace {
+            steps: self.steps,
+            duration: self.started.elapsed(),
+            final_state: self.handle.current_state(),
+        }
+    }
+}
+
+impl<H: FsmHandle> fmt::Debug for TraceRecorder<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceRecorder")
+            .field("id", &self.handle.id())
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+/// Re-drives a freshly spawned FSM with the events of `trace`, each at its
+/// recorded time, and checks that it goes through the same transitions.
+///
+/// ```rust,ignore
+/// #[tokio::test(start_paused = true)]
+/// async fn order_matches_golden_trace() {
+///     let trace = serde_json::from_str(include_str!("golden/order.json")).unwrap();
+///     let (handle, _task) = OrderFsm::spawn(Order::default());
+///     replay(&handle, &trace).await.unwrap();
+/// }
+/// ```
+///
+/// Replay under paused time, like the recording, so it runs instantly and
+/// state timeouts fire at the same points.
+pub async fn replay<H>(
+    handle: &H,
+    trace: &Trace<H::State, H::Event>,
+) -> Result<(), ReplayError<H::State>>
+where
+    H: FsmHandle,
+    H::State: PartialEq + fmt::Debug,
+    H::Event: Clone,
+{
+    let started = crate::rt::now();
+    for (step, recorded) in trace.steps.iter().enumerate() {
+        crate::rt::sleep_until(started + recorded.at).await;
+        super::settle(handle).await;
+        let actual = handle.current_state();
+        if actual != recorded.from {
+            return Err(ReplayError::Before {
+                step,
+                expected: recorded.from,
+                actual,
+            });
+        }
+        handle
+            .send(recorded.event.clone())
+            .await
+            .map_err(|_| ReplayError::Stopped { step })?;
+        super::settle(handle).await;
+        let actual = handle.current_state();
+        if actual != recorded.to {
+            return Err(ReplayError::After {
+                step,
+                expected: recorded.to,
+                actual,
+            });
+        }
+    }
+    crate::rt::sleep_until(started + trace.duration).await;
+    super::settle(handle).await;
+    let actual = handle.current_state();
+    if actual != trace.final_state {
+        return Err(ReplayError::End {
+            expected: trace.final_state,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Error returned by [`replay`] at the first point the FSM strays from the
+/// trace. Steps are numbered from zero.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ReplayError<S: fmt::Debug> {
+    /// The FSM was in the wrong state when the step's event was due, e.g.
+    /// because a state timeout fired differently.
+    #[error("expected {expected:?} before step {step}, got {actual:?}")]
+    Before { step: usize, expected: S, actual: S },
+    /// The step's event led to a different state.
+    #[error("step {step} led to {actual:?} instead of {expected:?}")]
+    After { step: usize, expected: S, actual: S },
+    /// The FSM ended in a different state.
+    #[error("expected the trace to end in {expected:?}, got {actual:?}")]
+    End { expected: S, actual: S },
+    /// The FSM stopped before the step's event could be sent.
+    #[error("FSM stopped before step {step}")]
+    Stopped { step: usize },
+}
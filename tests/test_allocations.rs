@@ -0,0 +1,92 @@
+#![cfg(feature = "test-util")]
+
+use tokio_fsm::{
+    Transition, fsm,
+    test_util::{CountingAllocator, count_allocations, count_allocations_sync},
+};
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator::system();
+
+#[derive(Debug, Default)]
+pub struct Book {
+    position: i64,
+    fills: Vec<u64>,
+}
+
+#[fsm(initial = Flat, channel_size = 16)]
+impl QuoteFsm {
+    type Context = Book;
+
+    #[on(state = Flat, event = Buy)]
+    async fn handle_buy(&mut self, qty: i64) -> Transition<Long> {
+        self.context.position += qty;
+        Transition::to(Long)
+    }
+
+    #[on(state = Long, event = Sell)]
+    async fn handle_sell(&mut self, qty: i64) -> Transition<Flat> {
+        self.context.position -= qty;
+        Transition::to(Flat)
+    }
+
+    #[on(state = Long, event = Fill)]
+    async fn handle_fill(&mut self, id: u64) -> Transition<Long> {
+        // Deliberately allocating user code.
+        self.context.fills.push(id);
+        Transition::to(Long)
+    }
+}
+
+async fn round_trip(handle: &QuoteFsmHandle) {
+    handle.send(QuoteFsmEvent::Buy(5)).await.unwrap();
+    handle.wait_for_state(QuoteFsmState::Long).await.unwrap();
+    handle.send(QuoteFsmEvent::Sell(5)).await.unwrap();
+    handle.wait_for_state(QuoteFsmState::Flat).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_transitions_do_not_allocate() {
+    let (handle, _task) = QuoteFsm::spawn(Book::default());
+
+    // Warm up so per-handler stats exist and the event channel has grown
+    // the blocks it recycles from then on (tokio allocates 32 slots at a
+    // time).
+    for _ in 0..32 {
+        round_trip(&handle).await;
+    }
+
+    let (_, allocations) = count_allocations(async {
+        for _ in 0..1000 {
+            round_trip(&handle).await;
+        }
+    })
+    .await;
+    assert_eq!(allocations.count, 0, "{allocations:?}");
+}
+
+#[tokio::test]
+async fn test_allocating_handler_is_detected() {
+    let (handle, _task) = QuoteFsm::spawn(Book::default());
+    handle.send(QuoteFsmEvent::Buy(1)).await.unwrap();
+    handle.wait_for_state(QuoteFsmState::Long).await.unwrap();
+
+    let (_, allocations) = count_allocations(async {
+        handle.send(QuoteFsmEvent::Fill(7)).await.unwrap();
+        let mut changes = handle.subscribe();
+        changes.wait_for(|change| change.seq == 2).await.unwrap();
+    })
+    .await;
+    assert!(allocations.count >= 1, "{allocations:?}");
+    assert!(allocations.bytes >= 8, "{allocations:?}");
+}
+
+#[test]
+fn test_sync_counting() {
+    let (_, none) = count_allocations_sync(|| 1 + 1);
+    assert_eq!(none.count, 0);
+
+    let (_, some) = count_allocations_sync(|| vec![0u8; 64]);
+    assert_eq!(some.count, 1);
+    assert_eq!(some.bytes, 64);
+}
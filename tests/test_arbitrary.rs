@@ -0,0 +1,98 @@
+#![cfg(feature = "arbitrary")]
+
+use std::collections::BTreeSet;
+
+use arbitrary::{Arbitrary, Unstructured};
+use tokio_fsm::{Transition, fsm};
+
+#[derive(Debug, Clone, Default)]
+pub struct Wallet {
+    pub balance: u64,
+}
+
+#[fsm(initial = Open)]
+impl WalletFsm {
+    type Context = Wallet;
+
+    #[on(state = Open, event = Deposit)]
+    async fn handle_deposit(&mut self, amount: u32) -> Transition<Open> {
+        self.context.balance += u64::from(amount);
+        Transition::to(Open)
+    }
+
+    #[on(state = Open, event = Withdraw)]
+    async fn handle_withdraw(&mut self, amount: u32) -> Transition<Open> {
+        self.context.balance = self.context.balance.saturating_sub(u64::from(amount));
+        Transition::to(Open)
+    }
+
+    #[on(state = Open, event = Close)]
+    async fn handle_close(&mut self) -> Transition<Closed> {
+        Transition::to(Closed)
+    }
+}
+
+/// A payload without an `Arbitrary` impl.
+#[derive(Debug, Clone)]
+pub struct Opaque;
+
+#[fsm(initial = Idle)]
+impl OpaqueFsm {
+    #[on(state = Idle, event = Load)]
+    async fn handle_load(&mut self, _payload: Opaque) -> Transition<Idle> {
+        Transition::to(Idle)
+    }
+}
+
+#[test]
+fn test_every_event_variant_is_generated() {
+    let names: BTreeSet<_> = (0..=u8::MAX)
+        .filter_map(|byte| {
+            let bytes = [byte, 7, 7, 7, 7];
+            WalletFsmEvent::arbitrary(&mut Unstructured::new(&bytes)).ok()
+        })
+        .map(|event| event.as_str())
+        .collect();
+    assert_eq!(names, WalletFsmEvent::NAMES.into_iter().collect());
+}
+
+#[test]
+fn test_payloads_come_from_the_input() {
+    let bytes = [0, 42, 0, 0, 0];
+    let event = WalletFsmEvent::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+    assert!(matches!(event, WalletFsmEvent::Deposit(42)));
+}
+
+#[tokio::test]
+async fn test_fuzz_style_event_sequence() {
+    let bytes: Vec<u8> = (0..512u32).map(|i| (i * 37 % 251) as u8).collect();
+    let mut input = Unstructured::new(&bytes);
+    let mut events = Vec::new();
+    while !input.is_empty() {
+        events.push(WalletFsmEvent::arbitrary(&mut input).unwrap());
+    }
+    assert!(events.len() > 1);
+
+    let mut expected = 0u64;
+    for event in &events {
+        match *event {
+            WalletFsmEvent::Deposit(amount) => expected += u64::from(amount),
+            WalletFsmEvent::Withdraw(amount) => {
+                expected = expected.saturating_sub(u64::from(amount))
+            }
+            WalletFsmEvent::Close => break,
+        }
+    }
+
+    let (handle, task) = WalletFsm::spawn(Wallet::default());
+    for event in events {
+        handle.send(event).await.unwrap();
+    }
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap().balance, expected);
+}
+
+#[test]
+fn test_enum_with_unsupported_payload_still_compiles() {
+    let _ = OpaqueFsmEvent::Load(Opaque);
+}
@@ -0,0 +1,129 @@
+#![cfg(feature = "axum")]
+
+use axum::{
+    Json, Router,
+    body::{Body, to_bytes},
+    extract::State,
+    http::{Request, StatusCode},
+    routing::{get, post},
+};
+use tokio_fsm::{
+    Transition,
+    axum::{FsmManager, Instance, ManagerError},
+    fsm,
+};
+use tower::ServiceExt;
+
+#[fsm(initial = Drafted, serde)]
+impl TicketFsm {
+    type Context = String;
+
+    #[on(state = Drafted, event = Submit)]
+    async fn handle_submit(&mut self) -> Transition<Submitted> {
+        Transition::to(Submitted)
+    }
+
+    #[on(state = Submitted, event = Resolve)]
+    async fn handle_resolve(&mut self) -> Transition<Resolved> {
+        Transition::to(Resolved)
+    }
+}
+
+type Tickets = FsmManager<TicketFsmHandle>;
+
+async fn open(
+    State(tickets): State<Tickets>,
+    Json(id): Json<String>,
+) -> Result<StatusCode, ManagerError<TicketFsmEvent>> {
+    tickets.create(id.clone(), || TicketFsm::spawn(id))?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn submit(
+    Instance(ticket): Instance<TicketFsmHandle>,
+) -> Result<Json<TicketFsmState>, ManagerError<TicketFsmEvent>> {
+    Ok(Json(ticket.call(TicketFsmEvent::Submit).await?))
+}
+
+async fn status(Instance(ticket): Instance<TicketFsmHandle>) -> Json<TicketFsmState> {
+    Json(ticket.current_state())
+}
+
+fn app(tickets: Tickets) -> Router {
+    Router::new()
+        .route("/tickets", post(open))
+        .route("/tickets/{id}", get(status))
+        .route("/tickets/{id}/submit", post(submit))
+        .with_state(tickets)
+}
+
+async fn request(tickets: &Tickets, request: Request<Body>) -> (StatusCode, String) {
+    let response = app(tickets.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn create(id: &str) -> Request<Body> {
+    Request::post("/tickets")
+        .header("content-type", "application/json")
+        .body(Body::from(format!("\"{id}\"")))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_manager_routes_requests_to_instances() {
+    let tickets = Tickets::new();
+
+    assert_eq!(
+        request(&tickets, create("t-1")).await.0,
+        StatusCode::CREATED
+    );
+    let (status, body) = request(&tickets, create("t-1")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body, r#"{"error":"instance already exists"}"#);
+
+    let submit = Request::post("/tickets/t-1/submit").body(Body::empty());
+    let (status, body) = request(&tickets, submit.unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#""Submitted""#);
+
+    let (status, body) = request(
+        &tickets,
+        Request::get("/tickets/t-1").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, r#""Submitted""#));
+
+    let missing = Request::get("/tickets/t-2").body(Body::empty()).unwrap();
+    assert_eq!(request(&tickets, missing).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_manager_reaps_stopped_instances() {
+    let tickets = Tickets::new();
+    let task = tickets
+        .create("t-1", || TicketFsm::spawn("t-1".into()))
+        .unwrap();
+    tickets
+        .create("t-2", || TicketFsm::spawn("t-2".into()))
+        .unwrap();
+
+    let state = tickets.call("t-1", TicketFsmEvent::Submit).await.unwrap();
+    assert_eq!(state, TicketFsmState::Submitted);
+    assert_eq!(tickets.state("t-1"), Some(TicketFsmState::Submitted));
+    assert!(matches!(
+        tickets.send("t-3", TicketFsmEvent::Submit).await,
+        Err(ManagerError::NotFound)
+    ));
+
+    tickets.get("t-1").unwrap().shutdown_graceful();
+    assert_eq!(task.await.unwrap(), "t-1");
+    assert!(matches!(
+        tickets.send("t-1", TicketFsmEvent::Resolve).await,
+        Err(ManagerError::Closed(TicketFsmEvent::Resolve))
+    ));
+
+    assert_eq!(tickets.reap(), ["t-1"]);
+    assert_eq!(tickets.ids(), ["t-2"]);
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use tokio_fsm::{Bridge, BridgeError, EventSource, FsmManager, Transition, fsm};
+
+#[fsm(initial = Quoted)]
+impl ShipmentFsm {
+    type Context = ();
+
+    #[on(state = Quoted, event = Book)]
+    async fn handle_book(&mut self) -> Transition<Booked> {
+        Transition::to(Booked)
+    }
+
+    #[on(state = Booked, event = Deliver)]
+    async fn handle_deliver(&mut self) -> Transition<Delivered> {
+        Transition::to(Delivered)
+    }
+}
+
+/// A message: its offset, the shipment it is for and the event's name.
+type Message = (u64, &'static str, &'static str);
+
+type Commits = Arc<Mutex<Vec<(u64, Option<ShipmentFsmState>)>>>;
+
+/// A topic in memory. Each commit records the shipment's state at that
+/// moment, to show that commits follow transitions.
+struct Topic {
+    messages: VecDeque<Message>,
+    shipments: FsmManager<ShipmentFsmHandle>,
+    commits: Commits,
+}
+
+impl EventSource for Topic {
+    type Message = Message;
+    type Error = Infallible;
+
+    async fn next(&mut self) -> Option<Result<Message, Infallible>> {
+        self.messages.pop_front().map(Ok)
+    }
+
+    async fn commit(&mut self, message: &Message) -> Result<(), Infallible> {
+        let (offset, id, _) = *message;
+        let state = self.shipments.state(id);
+        self.commits.lock().unwrap().push((offset, state));
+        Ok(())
+    }
+}
+
+impl Topic {
+    fn new(messages: Vec<Message>, shipments: &FsmManager<ShipmentFsmHandle>) -> Self {
+        Self {
+            messages: messages.into(),
+            shipments: shipments.clone(),
+            commits: Commits::default(),
+        }
+    }
+}
+
+/// Routes a message to its shipment, skipping pings.
+fn decode(&(_, id, event): &Message) -> Result<Option<(String, ShipmentFsmEvent)>, String> {
+    if event == "Ping" {
+        return Ok(None);
+    }
+    let event = event
+        .parse::<ShipmentFsmEvent>()
+        .map_err(|err| err.to_string())?;
+    Ok(Some((id.to_owned(), event)))
+}
+
+#[tokio::test]
+async fn test_bridge_commits_after_each_transition() {
+    let shipments = FsmManager::new();
+    let topic = Topic::new(
+        vec![
+            (0, "a", "Book"),
+            (1, "b", "Book"),
+            (2, "a", "Ping"),
+            (3, "a", "Deliver"),
+        ],
+        &shipments,
+    );
+    let commits = Arc::clone(&topic.commits);
+    Bridge::new(topic, shipments.clone(), decode)
+        .spawn_missing(|_| ShipmentFsm::spawn(()).0)
+        .run()
+        .await
+        .unwrap();
+
+    use ShipmentFsmState::*;
+    assert_eq!(
+        *commits.lock().unwrap(),
+        [
+            (0, Some(Booked)),
+            (1, Some(Booked)),
+            (2, Some(Booked)),
+            (3, Some(Delivered)),
+        ]
+    );
+    assert_eq!(shipments.len(), 2);
+}
+
+#[tokio::test]
+async fn test_bridge_leaves_failed_messages_uncommitted() {
+    let shipments = FsmManager::new();
+    let topic = Topic::new(vec![(0, "a", "Book")], &shipments);
+    let commits = Arc::clone(&topic.commits);
+    let err = Bridge::new(topic, shipments, decode)
+        .run()
+        .await
+        .unwrap_err();
+    assert!(matches!(err, BridgeError::NotFound(id) if id == "a"));
+    assert!(commits.lock().unwrap().is_empty());
+
+    let shipments = FsmManager::new();
+    let (handle, _task) = ShipmentFsm::spawn(());
+    shipments.insert("a", handle);
+    let topic = Topic::new(vec![(0, "a", "Book"), (1, "a", "Lose")], &shipments);
+    let commits = Arc::clone(&topic.commits);
+    let err = Bridge::new(topic, shipments, decode)
+        .run()
+        .await
+        .unwrap_err();
+    assert!(matches!(err, BridgeError::Decode(_)));
+    assert_eq!(commits.lock().unwrap().len(), 1);
+
+    let shipments = FsmManager::new();
+    let (handle, task) = ShipmentFsm::spawn(());
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    shipments.insert("a", handle);
+    let topic = Topic::new(vec![(0, "a", "Book")], &shipments);
+    let commits = Arc::clone(&topic.commits);
+    let err = Bridge::new(topic, shipments, decode)
+        .run()
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        BridgeError::Closed {
+            event: ShipmentFsmEvent::Book,
+            ..
+        }
+    ));
+    assert!(commits.lock().unwrap().is_empty());
+}
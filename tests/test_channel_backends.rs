@@ -0,0 +1,77 @@
+//! The alternative channel backends behave like the default tokio channel.
+
+#[cfg(feature = "flume")]
+mod flume_backend {
+    use tokio::sync::mpsc::error::TrySendError;
+    use tokio_fsm::{Transition, fsm};
+
+    #[derive(Debug, Default)]
+    pub struct Counter {
+        pub hits: usize,
+    }
+
+    #[fsm(initial = Open, channel = flume, channel_size = 1)]
+    impl FlumeFsm {
+        type Context = Counter;
+
+        #[on(state = Open, event = Hit)]
+        async fn handle_hit(&mut self) -> Transition<Open> {
+            self.context.hits += 1;
+            Transition::to(Open)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flume_backend_delivers_and_drains() {
+        let (handle, task) = FlumeFsm::spawn(Counter::default());
+
+        handle.send(FlumeFsmEvent::Hit).await.unwrap();
+        handle.send_into(FlumeFsmEvent::Hit).await.unwrap();
+        handle.shutdown_graceful();
+        assert_eq!(task.await.unwrap().hits, 2);
+
+        assert!(matches!(
+            handle.try_send(FlumeFsmEvent::Hit),
+            Err(TrySendError::Closed(FlumeFsmEvent::Hit))
+        ));
+        assert!(handle.send(FlumeFsmEvent::Hit).await.is_err());
+    }
+}
+
+#[cfg(feature = "kanal")]
+mod kanal_backend {
+    use tokio::sync::mpsc::error::TrySendError;
+    use tokio_fsm::{Transition, fsm};
+
+    #[derive(Debug, Default)]
+    pub struct Counter {
+        pub hits: usize,
+    }
+
+    #[fsm(initial = Open, channel = kanal, channel_size = 1)]
+    impl KanalFsm {
+        type Context = Counter;
+
+        #[on(state = Open, event = Hit)]
+        async fn handle_hit(&mut self) -> Transition<Open> {
+            self.context.hits += 1;
+            Transition::to(Open)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kanal_backend_delivers_and_drains() {
+        let (handle, task) = KanalFsm::spawn(Counter::default());
+
+        handle.send(KanalFsmEvent::Hit).await.unwrap();
+        handle.send_into(KanalFsmEvent::Hit).await.unwrap();
+        handle.shutdown_graceful();
+        assert_eq!(task.await.unwrap().hits, 2);
+
+        assert!(matches!(
+            handle.try_send(KanalFsmEvent::Hit),
+            Err(TrySendError::Closed(KanalFsmEvent::Hit))
+        ));
+        assert!(handle.send(KanalFsmEvent::Hit).await.is_err());
+    }
+}
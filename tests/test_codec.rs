@@ -0,0 +1,81 @@
+#![cfg(feature = "codec")]
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use tokio_fsm::{EventCodec, FramedError, JsonCodec, Transition, drive_framed, fsm};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[fsm(initial = Closed, serde)]
+impl ValveFsm {
+    type Context = u32;
+
+    #[on(state = Closed, event = Open)]
+    async fn handle_open(&mut self, percent: u32) -> Transition<Opened> {
+        self.context = percent;
+        Transition::to(Opened)
+    }
+
+    #[on(state = Opened, event = Shut)]
+    async fn handle_shut(&mut self) -> Transition<Closed> {
+        Transition::to(Closed)
+    }
+}
+
+async fn next_json(
+    peer: &mut Framed<tokio::io::DuplexStream, LengthDelimitedCodec>,
+) -> serde_json::Value {
+    let frame = peer.next().await.unwrap().unwrap();
+    serde_json::from_slice(&frame).unwrap()
+}
+
+#[tokio::test]
+async fn test_drive_framed_over_a_byte_stream() {
+    let (handle, task) = ValveFsm::spawn(0);
+    let (ours, theirs) = tokio::io::duplex(1024);
+    let mut peer = Framed::new(theirs, LengthDelimitedCodec::new());
+    let driver = tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            let transport = Framed::new(ours, LengthDelimitedCodec::new());
+            drive_framed(&handle, JsonCodec, transport).await
+        }
+    });
+
+    let initial = next_json(&mut peer).await;
+    assert_eq!(initial["to"], "Closed");
+    assert_eq!(initial["cause"], "Initial");
+
+    peer.send(Bytes::from_static(br#"{"Open":40}"#))
+        .await
+        .unwrap();
+    let change = next_json(&mut peer).await;
+    assert_eq!(
+        change,
+        serde_json::json!({"seq": 1, "from": "Closed", "to": "Opened", "cause": {"Event": "Open"}})
+    );
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 40);
+    assert_eq!(next_json(&mut peer).await["cause"], "Shutdown");
+    driver.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_drive_framed_rejects_undecodable_frames() {
+    let (handle, _task) = ValveFsm::spawn(0);
+    let (ours, theirs) = tokio::io::duplex(1024);
+    let mut peer = Framed::new(theirs, LengthDelimitedCodec::new());
+    peer.send(Bytes::from_static(b"Open")).await.unwrap();
+
+    let transport = Framed::new(ours, LengthDelimitedCodec::new());
+    let result = drive_framed(&handle, JsonCodec, transport).await;
+    assert!(matches!(result, Err(FramedError::Decode(_))));
+    assert_eq!(handle.current_state(), ValveFsmState::Closed);
+}
+
+#[test]
+fn test_json_codec_encodes_states() {
+    let frame =
+        EventCodec::<ValveFsmEvent, _>::encode_state(&mut JsonCodec, &ValveFsmState::Opened);
+    assert_eq!(frame.unwrap(), br#""Opened""#);
+}
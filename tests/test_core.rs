@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use futures_util::FutureExt;
+use tokio_fsm::{ManualClock, Transition, fsm};
+
+#[fsm(initial = Locked)]
+impl TurnstileFsm {
+    type Context = u32;
+
+    #[on(state = Locked, event = Coin)]
+    #[state_timeout(duration = "10s")]
+    async fn handle_coin(&mut self) -> Transition<Unlocked> {
+        self.context += 1;
+        Transition::to(Unlocked)
+    }
+
+    #[on(state = Unlocked, event = Push)]
+    async fn handle_push(&mut self) -> Transition<Rotating> {
+        Transition::to(Rotating).then(TurnstileFsmEvent::Rotated)
+    }
+
+    #[on(state = Rotating, event = Rotated)]
+    async fn handle_rotated(&mut self) -> Transition<Locked> {
+        Transition::to(Locked)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Locked> {
+        Transition::to(Locked)
+    }
+}
+
+/// Drives a core future without any runtime; turnstile handlers never wait.
+fn ready<T>(future: impl Future<Output = T>) -> T {
+    future
+        .now_or_never()
+        .expect("the core should not need a runtime")
+}
+
+#[test]
+fn test_core_processes_events_without_a_runtime() {
+    let mut core = TurnstileFsm::core(0);
+    assert_eq!(core.state(), TurnstileFsmState::Locked);
+
+    let state = ready(core.process(TurnstileFsmEvent::Coin)).unwrap();
+    assert_eq!(state, TurnstileFsmState::Unlocked);
+    assert!(core.timeout_deadline().is_some());
+
+    // The follow-up runs before `process` returns.
+    let state = ready(core.process(TurnstileFsmEvent::Push)).unwrap();
+    assert_eq!(state, TurnstileFsmState::Locked);
+    assert_eq!(core.timeout_deadline(), None);
+
+    // Unhandled events are dropped.
+    let state = ready(core.process(TurnstileFsmEvent::Push)).unwrap();
+    assert_eq!(state, TurnstileFsmState::Locked);
+    assert_eq!(core.into_context(), 1);
+}
+
+#[test]
+fn test_core_fires_timeouts_only_when_polled() {
+    let clock = ManualClock::new();
+    let mut core = TurnstileFsm::builder(0).clock(clock.clone()).core();
+    let changes = core.subscribe();
+
+    ready(core.process(TurnstileFsmEvent::Coin)).unwrap();
+    clock.advance(Duration::from_secs(5));
+    let state = ready(core.poll_timeout()).unwrap();
+    assert_eq!(state, TurnstileFsmState::Unlocked);
+
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(core.state(), TurnstileFsmState::Unlocked);
+    let state = ready(core.poll_timeout()).unwrap();
+    assert_eq!(state, TurnstileFsmState::Locked);
+    assert_eq!(core.timeout_deadline(), None);
+
+    let change = *changes.borrow();
+    assert_eq!(change.to, TurnstileFsmState::Locked);
+    assert_eq!(change.cause, tokio_fsm::TransitionCause::Timeout);
+}
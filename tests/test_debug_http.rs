@@ -0,0 +1,100 @@
+#![cfg(feature = "debug-http")]
+
+use axum::{
+    body::{Body, to_bytes},
+    http::{Request, StatusCode},
+};
+use tokio_fsm::{Transition, debug, fsm};
+use tower::ServiceExt;
+
+#[fsm(initial = Parked, channel_size = 8)]
+impl ElevatorFsm {
+    #[on(state = Parked, event = Call)]
+    async fn handle_call(&mut self) -> Transition<Moving> {
+        Transition::to(Moving)
+    }
+
+    #[on(state = Moving, event = Arrive)]
+    async fn handle_arrive(&mut self) -> Transition<Parked> {
+        Transition::to(Parked)
+    }
+}
+
+async fn get(registry: &debug::Registry, uri: &str) -> (StatusCode, String) {
+    let response = debug::router(registry.clone())
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_router_reports_state_history_and_queue_depth() {
+    let (handle, _task) = ElevatorFsm::spawn();
+    let registry = debug::Registry::new();
+    registry.register(&handle);
+    let id = handle.id().as_u64();
+
+    handle.send(ElevatorFsmEvent::Call).await.unwrap();
+    handle
+        .wait_for_state(ElevatorFsmState::Moving)
+        .await
+        .unwrap();
+
+    let (status, body) = get(&registry, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    let list: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        list,
+        serde_json::json!([{
+            "id": id,
+            "fsm": "ElevatorFsm",
+            "state": "Moving",
+            "stopped": false,
+            "queue_depth": 0,
+        }])
+    );
+
+    let (status, body) = get(&registry, &format!("/{id}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let detail: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        detail["recent_transitions"],
+        serde_json::json!([{
+            "seq": 1,
+            "from": "Parked",
+            "to": "Moving",
+            "cause": "event",
+            "event": "Call",
+        }])
+    );
+
+    let (status, body) = get(&registry, &format!("/{id}/mermaid")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, ElevatorFsm::mermaid());
+
+    let (status, _) = get(&registry, "/999999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_queue_depth_counts_unprocessed_events() {
+    let (handle, _task) = ElevatorFsm::spawn();
+    let registry = debug::Registry::new();
+    registry.register(&handle);
+
+    // The single-threaded test runtime hasn't polled the FSM yet.
+    handle.try_send(ElevatorFsmEvent::Call).unwrap();
+    handle.try_send(ElevatorFsmEvent::Arrive).unwrap();
+    let (_, body) = get(&registry, "/").await;
+    let list: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list[0]["queue_depth"], 2);
+
+    let mut changes = handle.subscribe();
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+    let (_, body) = get(&registry, "/").await;
+    let list: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list[0]["queue_depth"], 0);
+}
@@ -0,0 +1,44 @@
+use tokio_fsm::Fsm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Fsm)]
+#[transition(Stopped -> Playing on Play)]
+#[transition(Playing -> Paused on Pause)]
+#[transition(Paused -> Playing on Play)]
+#[transition(Playing -> Stopped on Stop)]
+#[transition(Paused -> Stopped on Stop)]
+enum Player {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+#[tokio::test]
+async fn test_derived_fsm_follows_table() {
+    let (handle, task) = PlayerFsm::spawn();
+    assert_eq!(handle.current_state(), PlayerFsmState::Stopped);
+
+    handle.send(PlayerFsmEvent::Play).await.unwrap();
+    handle.send(PlayerFsmEvent::Pause).await.unwrap();
+    // No `Paused -> ... on Pause` row: ignored.
+    handle.send(PlayerFsmEvent::Pause).await.unwrap();
+    handle.send(PlayerFsmEvent::Play).await.unwrap();
+    handle.send(PlayerFsmEvent::Stop).await.unwrap();
+
+    let mut changes = handle.subscribe();
+    let change = *changes.wait_for(|change| change.seq == 4).await.unwrap();
+    assert_eq!(Player::from(change.from), Player::Playing);
+    assert_eq!(Player::from(change.to), Player::Stopped);
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[test]
+fn test_derived_fsm_introspection() {
+    assert_eq!(
+        PlayerFsmState::from(Player::Paused).valid_events(),
+        &["Play", "Stop"]
+    );
+    assert!(PlayerFsm::mermaid().contains("Playing --> Paused: Pause"));
+    assert_eq!(PlayerFsm::transitions().len(), 5);
+}
@@ -1,6 +1,7 @@
 use std::time::Duration;
 
-use tokio_fsm::{Transition, fsm};
+use futures_util::FutureExt;
+use tokio_fsm::{ManualClock, Transition, TransitionCause, fsm};
 
 #[derive(Debug, Default)]
 pub struct TestContext {
@@ -108,6 +109,36 @@ async fn test_fsm_timeout() {
     assert_eq!(final_context.transition_count, 2); // Start + Timeout
 }
 
+#[tokio::test]
+async fn test_timeout_follows_injected_clock() {
+    let clock = ManualClock::new();
+    let (handle, task) = IntegrationFsm::builder(TestContext::default())
+        .clock(clock.clone())
+        .spawn();
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    handle
+        .wait_for_state(IntegrationFsmState::Pending)
+        .await
+        .unwrap();
+
+    // Short of the 100ms timeout: the FSM stays put however long we wait.
+    clock.advance(Duration::from_millis(99));
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(handle.current_state(), IntegrationFsmState::Pending);
+
+    clock.advance(Duration::from_millis(1));
+    handle
+        .wait_for_state(IntegrationFsmState::Failed)
+        .await
+        .unwrap();
+
+    handle.shutdown_immediate();
+    assert_eq!(task.await.unwrap().transition_count, 2);
+}
+
 #[tokio::test]
 async fn test_fsm_graceful_shutdown() {
     let context = TestContext::default();
@@ -129,3 +160,1214 @@ async fn test_fsm_graceful_shutdown() {
     assert_eq!(final_context.transition_count, 2);
     assert_eq!(final_context.job_data, vec!["queued"]);
 }
+
+#[derive(Debug, Default)]
+pub struct Journal {
+    pub buffered: Vec<String>,
+    pub flushed: Vec<String>,
+    pub closed: Vec<tokio_fsm::ShutdownMode>,
+}
+
+#[fsm(initial = Recording)]
+impl JournalFsm {
+    type Context = Journal;
+
+    #[on(state = Recording, event = Write)]
+    async fn handle_write(&mut self, line: String) -> Transition<Recording> {
+        self.context.buffered.push(line);
+        Transition::to(Recording)
+    }
+
+    #[on(state = Recording, event = Seal)]
+    async fn handle_seal(&mut self) -> Transition<Sealed> {
+        Transition::to(Sealed).halt()
+    }
+
+    #[on_shutdown]
+    async fn flush(&mut self, mode: tokio_fsm::ShutdownMode) {
+        let buffered = std::mem::take(&mut self.context.buffered);
+        self.context.flushed.extend(buffered);
+        self.context.closed.push(mode);
+    }
+}
+
+#[tokio::test]
+async fn test_on_shutdown_hook_runs_after_graceful_drain() {
+    let (handle, task) = JournalFsm::spawn(Journal::default());
+    handle
+        .send(JournalFsmEvent::Write("a".into()))
+        .await
+        .unwrap();
+    handle
+        .send(JournalFsmEvent::Write("b".into()))
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+
+    let journal = task.await.unwrap();
+    assert_eq!(journal.flushed, vec!["a", "b"]);
+    assert_eq!(journal.closed, vec![tokio_fsm::ShutdownMode::Graceful]);
+}
+
+#[tokio::test]
+async fn test_on_shutdown_hook_receives_immediate_mode() {
+    let (handle, task) = JournalFsm::spawn(Journal::default());
+    let mut changes = handle.subscribe();
+    handle
+        .send(JournalFsmEvent::Write("a".into()))
+        .await
+        .unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    handle.shutdown_immediate();
+
+    let journal = task.await.unwrap();
+    assert_eq!(journal.flushed, vec!["a"]);
+    assert_eq!(journal.closed, vec![tokio_fsm::ShutdownMode::Immediate]);
+}
+
+#[tokio::test]
+async fn test_on_shutdown_hook_runs_when_halted_or_abandoned() {
+    let (handle, task) = JournalFsm::spawn(Journal::default());
+    handle.send(JournalFsmEvent::Seal).await.unwrap();
+    let journal = task.await.unwrap();
+    assert_eq!(journal.closed, vec![tokio_fsm::ShutdownMode::Immediate]);
+
+    // Dropping every handle finishes up gracefully.
+    let (handle, task) = JournalFsm::spawn(Journal::default());
+    drop(handle);
+    let journal = task.await.unwrap();
+    assert_eq!(journal.closed, vec![tokio_fsm::ShutdownMode::Graceful]);
+}
+
+#[fsm(initial = Placed)]
+impl ParcelFsm {
+    #[on(state = Placed, event = Ship)]
+    async fn handle_ship(&mut self) -> Transition<InTransit> {
+        Transition::to(InTransit)
+    }
+
+    #[on(state = InTransit, event = Scan)]
+    async fn handle_scan(&mut self) -> Transition<InTransit> {
+        Transition::to(InTransit)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_time_in_current_state_survives_self_transitions() {
+    let (handle, task) = ParcelFsm::spawn();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(handle.time_in_current_state(), Duration::from_secs(5));
+
+    let mut changes = handle.subscribe();
+    handle.send(ParcelFsmEvent::Ship).await.unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    assert_eq!(handle.time_in_current_state(), Duration::ZERO);
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    handle.send(ParcelFsmEvent::Scan).await.unwrap();
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(handle.time_in_current_state(), Duration::from_secs(4));
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_time_in_current_state_follows_injected_clock() {
+    let clock = ManualClock::new();
+    let (handle, task) = ParcelFsm::builder().clock(clock.clone()).spawn();
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(handle.time_in_current_state(), Duration::from_secs(5));
+
+    let mut changes = handle.subscribe();
+    handle.send(ParcelFsmEvent::Ship).await.unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    assert_eq!(handle.time_in_current_state(), Duration::ZERO);
+
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(handle.time_in_current_state(), Duration::from_secs(90));
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_versioned_state_tracks_round_trips() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+    assert_eq!(
+        handle.current_state_versioned(),
+        (0, IntegrationFsmState::Idle)
+    );
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    handle
+        .send(IntegrationFsmEvent::Process("a".to_string()))
+        .await
+        .unwrap();
+    handle
+        .send(IntegrationFsmEvent::Process("b".to_string()))
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    task.await.unwrap();
+
+    // Active -> Active still bumps the sequence; the final bump is the
+    // shutdown notification.
+    assert_eq!(
+        handle.current_state_versioned(),
+        (4, IntegrationFsmState::Active)
+    );
+}
+
+#[tokio::test]
+async fn test_state_change_reports_cause() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+    let mut changes = handle.subscribe();
+    assert_eq!(changes.borrow().cause, TransitionCause::Initial);
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    changes.changed().await.unwrap();
+    let change = *changes.borrow_and_update();
+    assert_eq!(change.from, IntegrationFsmState::Idle);
+    assert_eq!(change.to, IntegrationFsmState::Pending);
+    assert_eq!(change.cause, TransitionCause::Event("Start"));
+
+    changes.changed().await.unwrap();
+    assert_eq!(changes.borrow_and_update().cause, TransitionCause::Timeout);
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+    assert_eq!(handle.last_change().cause, TransitionCause::Shutdown);
+}
+
+#[tokio::test]
+async fn test_wait_for_any_state() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    // Nothing else is sent, so the Pending timeout decides the outcome.
+    let reached = handle
+        .wait_for_any_state(&[IntegrationFsmState::Done, IntegrationFsmState::Failed])
+        .await
+        .unwrap();
+    assert_eq!(reached, IntegrationFsmState::Failed);
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_until_predicate() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    let state = handle
+        .wait_until(|state| *state != IntegrationFsmState::Idle)
+        .await
+        .unwrap();
+    assert_eq!(state, IntegrationFsmState::Pending);
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handle_identity() {
+    use std::hash::BuildHasher;
+
+    let (handle_a, task_a) = IntegrationFsm::spawn(TestContext::default());
+    let (handle_b, task_b) = IntegrationFsm::spawn(TestContext::default());
+
+    assert_eq!(handle_a, handle_a.clone());
+    assert_eq!(handle_a.id(), handle_a.clone().id());
+    assert_ne!(handle_a, handle_b);
+
+    let hasher = std::hash::RandomState::new();
+    assert_eq!(
+        hasher.hash_one(&handle_a),
+        hasher.hash_one(handle_a.clone())
+    );
+    handle_a.shutdown_immediate();
+    handle_b.shutdown_immediate();
+    task_a.await.unwrap();
+    task_b.await.unwrap();
+}
+
+#[test]
+fn test_mermaid_export() {
+    let expected = "\
+stateDiagram-v2
+    [*] --> Idle
+    Idle --> Pending: Start
+    Pending --> Active: Process
+    Active --> Active: Process
+    Active --> Done: Finish
+    Pending --> Failed: timeout (100ms)
+    Active --> Failed: timeout (100ms)
+";
+    assert_eq!(IntegrationFsm::mermaid(), expected);
+}
+
+#[test]
+fn test_json_definition() {
+    let definition: serde_json::Value = serde_json::from_str(IntegrationFsm::definition()).unwrap();
+
+    assert_eq!(definition["name"], "IntegrationFsm");
+    assert_eq!(definition["initial_state"], "Idle");
+    assert_eq!(definition["states"][1]["name"], "Pending");
+    assert_eq!(definition["states"][1]["timeouts_ms"][0], 100);
+    assert_eq!(definition["events"][1]["name"], "Process");
+    assert_eq!(definition["events"][1]["payload"], "String");
+    assert_eq!(
+        definition["transitions"][0],
+        serde_json::json!({ "from": "Idle", "to": "Pending", "event": "Start" })
+    );
+    assert_eq!(
+        definition["transitions"][4],
+        serde_json::json!({ "from": "Pending", "to": "Failed", "timeout_ms": 100 })
+    );
+}
+
+#[test]
+fn test_transition_table() {
+    use IntegrationFsmState::*;
+
+    assert_eq!(
+        IntegrationFsm::transitions(),
+        &[
+            (Idle, "Start", Pending),
+            (Pending, "Process", Active),
+            (Active, "Process", Active),
+            (Active, "Finish", Done),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_accepts_checks_current_state() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    assert!(handle.accepts(&IntegrationFsmEvent::Start));
+    assert!(!handle.accepts(&IntegrationFsmEvent::Finish));
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    handle
+        .wait_for_state(IntegrationFsmState::Pending)
+        .await
+        .unwrap();
+    assert!(!handle.accepts(&IntegrationFsmEvent::Start));
+    assert!(handle.accepts(&IntegrationFsmEvent::Process(String::new())));
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stats_record_handler_latency() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    handle.send(IntegrationFsmEvent::Start).await.unwrap();
+    for i in 0..3 {
+        handle
+            .send(IntegrationFsmEvent::Process(i.to_string()))
+            .await
+            .unwrap();
+    }
+    handle
+        .wait_until(|_| {
+            handle
+                .stats()
+                .iter()
+                .map(|(_, _, h)| h.count())
+                .sum::<u64>()
+                == 4
+        })
+        .await
+        .unwrap();
+
+    let stats = handle.stats();
+    assert_eq!(stats.handler("Idle", "Start").unwrap().count(), 1);
+    assert_eq!(stats.handler("Pending", "Process").unwrap().count(), 1);
+    let active = stats.handler("Active", "Process").unwrap();
+    assert_eq!(active.count(), 2);
+    assert!(active.quantile(0.99) <= active.max());
+    assert!(stats.handler("Active", "Finish").is_none());
+
+    // Timeout handlers are keyed by the state that timed out.
+    handle
+        .wait_for_state(IntegrationFsmState::Failed)
+        .await
+        .unwrap();
+    assert_eq!(
+        handle.stats().handler("Active", "timeout").unwrap().count(),
+        1
+    );
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_drain_pending_returns_queued_events() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    // The single-threaded test runtime hasn't polled the FSM yet, so these
+    // are all still queued.
+    handle.try_send(IntegrationFsmEvent::Start).unwrap();
+    for data in ["a", "b"] {
+        handle
+            .try_send(IntegrationFsmEvent::Process(data.to_string()))
+            .unwrap();
+    }
+
+    let drained = handle.drain_pending().await;
+    let names: Vec<_> = drained.iter().map(|event| event.as_str()).collect();
+    assert_eq!(names, ["Start", "Process", "Process"]);
+    assert_eq!(handle.current_state(), IntegrationFsmState::Idle);
+    assert!(handle.drain_pending().await.is_empty());
+
+    handle.shutdown_immediate();
+    assert_eq!(task.await.unwrap().transition_count, 0);
+    assert!(handle.drain_pending().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_ping_round_trips_through_run_loop() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+    assert!(handle.ping().await.is_ok());
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+    assert_eq!(handle.ping().await, Err(tokio_fsm::PingError::Stopped));
+}
+
+#[tokio::test]
+async fn test_call_returns_the_state_its_event_left() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    let state = handle.call(IntegrationFsmEvent::Start).await.unwrap();
+    assert_eq!(state, IntegrationFsmState::Pending);
+    // Unhandled in `Pending`, so nothing changes.
+    let state = handle.call(IntegrationFsmEvent::Finish).await.unwrap();
+    assert_eq!(state, IntegrationFsmState::Pending);
+
+    // A purged event is never handled. Polling the call once enqueues its
+    // event without letting the FSM run.
+    let mut call = std::pin::pin!(handle.call(IntegrationFsmEvent::Finish));
+    assert!(futures_util::poll!(call.as_mut()).is_pending());
+    handle.purge(|event| event.as_str() == "Finish").await;
+    assert!(matches!(
+        call.await,
+        Err(tokio_fsm::CallError::Discarded(
+            tokio_fsm::DropReason::Purged
+        ))
+    ));
+
+    // One that survives a purge keeps its place and is still answered.
+    let mut call = std::pin::pin!(handle.call(IntegrationFsmEvent::Finish));
+    assert!(futures_util::poll!(call.as_mut()).is_pending());
+    assert!(
+        handle
+            .purge(|event| event.as_str() == "Start")
+            .await
+            .is_empty()
+    );
+    assert_eq!(call.await.unwrap(), IntegrationFsmState::Pending);
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+    assert!(matches!(
+        handle.call(IntegrationFsmEvent::Finish).await,
+        Err(tokio_fsm::CallError::Closed(IntegrationFsmEvent::Finish))
+    ));
+}
+
+#[tokio::test]
+async fn test_purge_removes_matching_queued_events() {
+    let (handle, task) = IntegrationFsm::spawn(TestContext::default());
+
+    // Still queued: the single-threaded runtime hasn't polled the FSM yet.
+    handle.try_send(IntegrationFsmEvent::Start).unwrap();
+    for data in ["keep", "drop", "keep too"] {
+        handle
+            .try_send(IntegrationFsmEvent::Process(data.to_string()))
+            .unwrap();
+    }
+
+    let purged = handle
+        .purge(|event| matches!(event, IntegrationFsmEvent::Process(data) if data == "drop"))
+        .await;
+    assert!(matches!(&purged[..], [IntegrationFsmEvent::Process(data)] if data == "drop"));
+
+    handle
+        .wait_for_state(IntegrationFsmState::Active)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    let context = task.await.unwrap();
+    assert_eq!(context.job_data, ["keep", "keep too"]);
+    assert!(handle.purge(|_| true).await.is_empty());
+}
+
+#[derive(Debug, Clone)]
+pub struct Reading {
+    pub sensor: u32,
+    pub celsius: i32,
+}
+
+#[fsm(initial = Monitoring)]
+impl ThermostatFsm {
+    type Context = Vec<(u32, i32)>;
+
+    #[on(state = Monitoring, event = Sample, coalesce_by = sensor)]
+    async fn handle_sample(&mut self, reading: Reading) -> Transition<Monitoring> {
+        self.context.push((reading.sensor, reading.celsius));
+        Transition::to(Monitoring)
+    }
+
+    #[on(state = Monitoring, event = Calibrate)]
+    async fn handle_calibrate(&mut self) -> Transition<Monitoring> {
+        self.context.push((0, 0));
+        Transition::to(Monitoring)
+    }
+}
+
+#[tokio::test]
+async fn test_coalesced_events_keep_only_the_latest_per_key() {
+    let (handle, task) = ThermostatFsm::spawn(Vec::new());
+    let mut changes = handle.subscribe();
+
+    // All queued before the FSM runs: only the last reading per sensor
+    // should be handled, in the position it was queued.
+    for (sensor, celsius) in [(1, 20), (2, 30), (1, 21), (1, 22)] {
+        handle
+            .try_send(ThermostatFsmEvent::Sample(Reading { sensor, celsius }))
+            .unwrap();
+    }
+    handle.try_send(ThermostatFsmEvent::Calibrate).unwrap();
+    handle
+        .try_send(ThermostatFsmEvent::Sample(Reading {
+            sensor: 2,
+            celsius: 31,
+        }))
+        .unwrap();
+
+    changes.wait_for(|change| change.seq == 3).await.unwrap();
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), [(1, 22), (0, 0), (2, 31)]);
+}
+
+#[tokio::test]
+async fn test_cancelled_send_does_not_supersede_later_events() {
+    let (handle, task) = ThermostatFsm::spawn(Vec::new());
+    let mut changes = handle.subscribe();
+
+    // Fill the queue, then give up on a sample waiting for capacity.
+    let mut queued = 0;
+    while handle.try_send(ThermostatFsmEvent::Calibrate).is_ok() {
+        queued += 1;
+    }
+    let sample = ThermostatFsmEvent::Sample(Reading {
+        sensor: 1,
+        celsius: 20,
+    });
+    assert!(handle.send(sample).now_or_never().is_none());
+
+    changes
+        .wait_for(|change| change.seq == queued)
+        .await
+        .unwrap();
+    handle
+        .send(ThermostatFsmEvent::Sample(Reading {
+            sensor: 1,
+            celsius: 21,
+        }))
+        .await
+        .unwrap();
+    changes
+        .wait_for(|change| change.seq == queued + 1)
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap().last(), Some(&(1, 21)));
+}
+
+#[tokio::test]
+async fn test_purge_returns_superseded_events_too() {
+    let (handle, task) = ThermostatFsm::spawn(Vec::new());
+    let mut changes = handle.subscribe();
+
+    // Still queued: the first reading is superseded by the second.
+    for celsius in [20, 21] {
+        handle
+            .try_send(ThermostatFsmEvent::Sample(Reading { sensor: 1, celsius }))
+            .unwrap();
+    }
+    let purged = handle.purge(|event| event.as_str() == "Sample").await;
+    let celsius: Vec<_> = purged
+        .iter()
+        .map(|event| match event {
+            ThermostatFsmEvent::Sample(reading) => reading.celsius,
+            other => panic!("unexpected {other:?}"),
+        })
+        .collect();
+    assert_eq!(celsius, [20, 21]);
+
+    // Their coalesce counts went with them, so a new reading is handled.
+    handle
+        .send(ThermostatFsmEvent::Sample(Reading {
+            sensor: 1,
+            celsius: 22,
+        }))
+        .await
+        .unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), [(1, 22)]);
+}
+
+#[tokio::test]
+async fn test_drain_pending_hands_back_every_queued_event() {
+    let (handle, task) = ThermostatFsm::spawn(Vec::new());
+
+    // Still queued: the first reading is superseded by the one being called.
+    handle
+        .try_send(ThermostatFsmEvent::Sample(Reading {
+            sensor: 1,
+            celsius: 20,
+        }))
+        .unwrap();
+    let mut call = std::pin::pin!(handle.call(ThermostatFsmEvent::Sample(Reading {
+        sensor: 1,
+        celsius: 21,
+    })));
+    assert!(futures_util::poll!(call.as_mut()).is_pending());
+
+    let drained = handle.drain_pending().await;
+    let celsius: Vec<_> = drained
+        .iter()
+        .map(|event| match event {
+            ThermostatFsmEvent::Sample(reading) => reading.celsius,
+            other => panic!("unexpected {other:?}"),
+        })
+        .collect();
+    assert_eq!(celsius, [20, 21]);
+    assert!(matches!(
+        call.await,
+        Err(tokio_fsm::CallError::Discarded(
+            tokio_fsm::DropReason::Purged
+        ))
+    ));
+
+    handle.shutdown_graceful();
+    assert!(task.await.unwrap().is_empty());
+}
+
+#[fsm(initial = Quiet)]
+impl ReminderFsm {
+    type Context = u32;
+
+    #[on(state = Quiet, event = Arm)]
+    async fn handle_arm(&mut self) -> Transition<Armed> {
+        self.handle()
+            .send_after(Duration::from_millis(10), ReminderFsmEvent::Ring);
+        Transition::to(Armed)
+    }
+
+    #[on(state = Armed, event = Ring)]
+    async fn handle_ring(&mut self) -> Transition<Quiet> {
+        self.context += 1;
+        Transition::to(Quiet)
+    }
+}
+
+#[tokio::test]
+async fn test_handlers_schedule_events_through_own_handle() {
+    let (handle, task) = ReminderFsm::spawn(0);
+    let mut changes = handle.subscribe();
+
+    handle.send(ReminderFsmEvent::Arm).await.unwrap();
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+    assert_eq!(handle.current_state(), ReminderFsmState::Quiet);
+
+    // The FSM's own sender doesn't keep it alive.
+    let sender = handle.sender();
+    drop(handle);
+    assert_eq!(task.await.unwrap(), 1);
+    assert!(sender.send(ReminderFsmEvent::Arm).await.is_err());
+}
+
+#[fsm(initial = Counting, on_last_handle = detach)]
+impl CountdownFsm {
+    type Context = u32;
+
+    #[on(state = Counting, event = Tick)]
+    async fn handle_tick(&mut self) -> Transition<Counting> {
+        self.context -= 1;
+        if self.context == 0 {
+            return Transition::to(Counting).halt();
+        }
+        self.handle()
+            .send_after(Duration::from_millis(1), CountdownFsmEvent::Tick);
+        Transition::to(Counting)
+    }
+}
+
+#[tokio::test]
+async fn test_detached_fsm_outlives_its_handles() {
+    let (handle, task) = CountdownFsm::spawn(3);
+    let sender = handle.sender();
+    handle.send(CountdownFsmEvent::Tick).await.unwrap();
+    drop(handle);
+
+    // Still accepting events from its own sender until the countdown halts.
+    tokio::task::yield_now().await;
+    assert!(sender.send(CountdownFsmEvent::Tick).await.is_ok());
+    assert_eq!(task.await.unwrap(), 0);
+}
+
+#[fsm(initial = Drifted)]
+impl ReconcilerFsm {
+    type Context = u32;
+
+    #[on(state = Drifted, event = Tick)]
+    async fn handle_tick(&mut self) -> Result<Transition<Reconciled>, Transition<Drifted>> {
+        self.context -= 1;
+        if self.context == 0 {
+            return Ok(Transition::to(Reconciled));
+        }
+        self.handle()
+            .send_after(Duration::from_millis(1), ReconcilerFsmEvent::Tick);
+        Err(Transition::to(Drifted))
+    }
+}
+
+#[tokio::test]
+async fn test_detached_builder_runs_until_terminal_state() {
+    let (handle, task) = ReconcilerFsm::builder(3).detached().spawn();
+    let mut changes = handle.subscribe();
+    handle.send(ReconcilerFsmEvent::Tick).await.unwrap();
+    drop(handle);
+
+    assert_eq!(task.await.unwrap(), 0);
+    let last = *changes.borrow_and_update();
+    assert_eq!(last.to, ReconcilerFsmState::Reconciled);
+    assert_eq!(last.cause, TransitionCause::Shutdown);
+
+    // Without it, the FSM stops with its last handle.
+    let (handle, task) = ReconcilerFsm::spawn(3);
+    handle.send(ReconcilerFsmEvent::Tick).await.unwrap();
+    drop(handle);
+    assert_eq!(task.await.unwrap(), 2);
+}
+
+#[derive(Debug, Default)]
+pub struct Session {
+    pub linger: bool,
+    pub persisted: bool,
+}
+
+#[fsm(initial = Connected)]
+impl SessionFsm {
+    type Context = Session;
+
+    #[on(state = Connected, event = Ping)]
+    async fn handle_ping(&mut self) -> Transition<Connected> {
+        Transition::to(Connected)
+    }
+
+    #[on(state = Lingering, event = Expire)]
+    async fn handle_expire(&mut self) -> Transition<Disconnected> {
+        Transition::to(Disconnected)
+    }
+
+    #[on_orphaned]
+    async fn handle_orphaned(&mut self) -> Result<Transition<Lingering>, Transition<Disconnected>> {
+        if self.context.linger {
+            self.handle()
+                .send_after(Duration::from_millis(1), SessionFsmEvent::Expire);
+            return Ok(Transition::to(Lingering));
+        }
+        self.context.persisted = true;
+        Err(Transition::to(Disconnected).halt())
+    }
+}
+
+#[tokio::test]
+async fn test_on_orphaned_handler_decides_how_to_wind_down() {
+    let (handle, task) = SessionFsm::spawn(Session::default());
+    let mut changes = handle.subscribe();
+    handle.send(SessionFsmEvent::Ping).await.unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    drop(handle);
+
+    assert!(task.await.unwrap().persisted);
+    let last = *changes.borrow_and_update();
+    assert_eq!(last.to, SessionFsmState::Disconnected);
+    assert_eq!(last.seq, 3);
+}
+
+#[tokio::test]
+async fn test_on_orphaned_handler_can_keep_running() {
+    let (handle, task) = SessionFsm::spawn(Session {
+        linger: true,
+        ..Session::default()
+    });
+    let mut changes = handle.subscribe();
+    drop(handle);
+
+    let orphaned = *changes
+        .wait_for(|change| change.to == SessionFsmState::Lingering)
+        .await
+        .unwrap();
+    assert_eq!(orphaned.cause, TransitionCause::Orphaned);
+
+    // Reaching the terminal `Disconnected` ends the orphaned FSM.
+    assert!(!task.await.unwrap().persisted);
+    assert_eq!(changes.borrow().to, SessionFsmState::Disconnected);
+}
+
+#[fsm(initial = Working)]
+impl StepFsm {
+    #[on(state = Working, event = Complete)]
+    async fn handle_complete(&mut self) -> Transition<Completed> {
+        Transition::to(Completed)
+    }
+}
+
+#[fsm(initial = Planning)]
+impl SagaFsm {
+    type Context = Vec<StepFsmHandle>;
+
+    #[on(state = Planning, event = Begin)]
+    async fn handle_begin(&mut self, finish_step: bool) -> Transition<Running> {
+        let (step, task) = StepFsm::spawn();
+        if finish_step {
+            // Dropping the only handle stops the step once it is complete.
+            step.try_send(StepFsmEvent::Complete).unwrap();
+        } else {
+            self.context.push(step);
+        }
+        self.spawn_child(task, |outcome| SagaFsmEvent::StepDone(outcome.is_ok()));
+        Transition::to(Running)
+    }
+
+    #[on(state = Running, event = StepDone)]
+    async fn handle_step_done(&mut self, ok: bool) -> Transition<Finished> {
+        assert!(ok);
+        Transition::to(Finished)
+    }
+}
+
+#[tokio::test]
+async fn test_child_completion_is_delivered_to_parent() {
+    let (handle, task) = SagaFsm::spawn(Vec::new());
+
+    handle.send(SagaFsmEvent::Begin(true)).await.unwrap();
+    handle.wait_for_state(SagaFsmState::Finished).await.unwrap();
+    handle.shutdown_graceful();
+    assert!(task.await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_child_is_aborted_when_parent_stops() {
+    let (handle, task) = SagaFsm::spawn(Vec::new());
+    let mut changes = handle.subscribe();
+
+    handle.send(SagaFsmEvent::Begin(false)).await.unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    handle.shutdown_immediate();
+    let steps = task.await.unwrap();
+
+    // The step never completed, but its task is gone along with the parent.
+    let mut step_changes = steps[0].subscribe();
+    while step_changes.changed().await.is_ok() {}
+    assert_eq!(steps[0].current_state(), StepFsmState::Working);
+    assert!(steps[0].send(StepFsmEvent::Complete).await.is_err());
+}
+
+#[fsm(initial = Unprobed)]
+impl PlacementFsm {
+    type Context = Option<String>;
+
+    #[on(state = Unprobed, event = Probe)]
+    async fn handle_probe(&mut self) -> Transition<Probed> {
+        self.context = std::thread::current().name().map(str::to_owned);
+        Transition::to(Probed)
+    }
+}
+
+#[tokio::test]
+async fn test_spawn_on_places_fsm_on_given_runtime() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("dedicated-fsm")
+        .enable_time()
+        .build()
+        .unwrap();
+
+    let (handle, task) = PlacementFsm::spawn_on(runtime.handle(), None);
+    handle.send(PlacementFsmEvent::Probe).await.unwrap();
+    handle
+        .wait_for_state(PlacementFsmState::Probed)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap().as_deref(), Some("dedicated-fsm"));
+
+    runtime.shutdown_background();
+}
+
+#[fsm(initial = Steady)]
+impl FragileFsm {
+    #[on(state = Steady, event = Nudge)]
+    async fn handle_nudge(&mut self) -> Transition<Wobbly> {
+        Transition::to(Wobbly)
+    }
+
+    #[on(state = Wobbly, event = Shatter)]
+    async fn handle_shatter(&mut self) -> Transition<Steady> {
+        panic!("handler bug");
+    }
+}
+
+#[tokio::test]
+async fn test_task_error_reports_failure_site() {
+    let (handle, mut task) = FragileFsm::spawn();
+    handle.send(FragileFsmEvent::Nudge).await.unwrap();
+    handle.send(FragileFsmEvent::Shatter).await.unwrap();
+
+    let err = (&mut task).await.unwrap_err();
+    let tokio_fsm::TaskError::Join(join_error) = &err else {
+        panic!("expected a join error, got {err:?}");
+    };
+    assert!(join_error.is_panic());
+    assert_eq!(
+        task.site(),
+        tokio_fsm::FailureSite {
+            state: "Wobbly",
+            event: Some("Shatter"),
+        }
+    );
+    assert_eq!(
+        task.site().to_string(),
+        "in state `Wobbly` handling `Shatter`"
+    );
+}
+
+#[fsm(initial = Serving, on_panic = Faulted)]
+impl ResilientFsm {
+    type Context = Vec<String>;
+
+    #[on(state = Serving, event = Trip)]
+    async fn handle_trip(&mut self) -> Transition<Serving> {
+        panic!("request handler bug");
+    }
+
+    #[on(state = Faulted, event = Reset)]
+    async fn handle_reset(&mut self) -> Transition<Serving> {
+        let message = self.last_panic().unwrap_or_default().to_owned();
+        self.context.push(message);
+        Transition::to(Serving)
+    }
+}
+
+#[tokio::test]
+async fn test_handler_panic_diverts_to_on_panic_state() {
+    let (handle, task) = ResilientFsm::spawn(Vec::new());
+    let mut changes = handle.subscribe();
+    handle.send(ResilientFsmEvent::Trip).await.unwrap();
+    handle
+        .wait_for_state(ResilientFsmState::Faulted)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes.borrow_and_update().cause,
+        tokio_fsm::TransitionCause::Panicked
+    );
+
+    handle.send(ResilientFsmEvent::Reset).await.unwrap();
+    handle
+        .wait_for_state(ResilientFsmState::Serving)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), vec!["request handler bug".to_owned()]);
+}
+
+#[fsm(initial = Cold, restart = on_error, max_restarts = 1, backoff = "10ms", restart_from = Rebooting)]
+impl FlakyFsm {
+    type Context = u32;
+
+    #[on(state = Cold, event = Boot)]
+    #[on(state = Rebooting, event = Boot)]
+    async fn handle_boot(&mut self) -> Transition<Online> {
+        self.context += 1;
+        Transition::to(Online)
+    }
+
+    #[on(state = Online, event = Crash)]
+    async fn handle_crash(&mut self) -> Transition<Online> {
+        panic!("lost the connection");
+    }
+}
+
+#[tokio::test]
+async fn test_restart_policy_resets_to_restart_state() {
+    let (handle, task) = FlakyFsm::spawn(0);
+    let mut changes = handle.subscribe();
+    handle.send(FlakyFsmEvent::Boot).await.unwrap();
+    handle.send(FlakyFsmEvent::Crash).await.unwrap();
+    handle
+        .wait_for_state(FlakyFsmState::Rebooting)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes.borrow_and_update().cause,
+        tokio_fsm::TransitionCause::Restarted
+    );
+
+    // The restarted machine starts over from the context it was spawned with.
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_restart_policy_surfaces_failure_once_exhausted() {
+    let (handle, mut task) = FlakyFsm::spawn(0);
+    handle.send(FlakyFsmEvent::Boot).await.unwrap();
+    handle.send(FlakyFsmEvent::Crash).await.unwrap();
+    handle
+        .wait_for_state(FlakyFsmState::Rebooting)
+        .await
+        .unwrap();
+    handle.send(FlakyFsmEvent::Boot).await.unwrap();
+    handle.send(FlakyFsmEvent::Crash).await.unwrap();
+
+    let err = (&mut task).await.unwrap_err();
+    let tokio_fsm::TaskError::Join(join_error) = &err else {
+        panic!("expected a join error, got {err:?}");
+    };
+    assert!(join_error.is_panic());
+    assert_eq!(task.site().event, Some("Crash"));
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum PaymentError {
+    #[error("card {0} declined")]
+    Declined(String),
+    #[error("settlement timed out")]
+    Unsettled,
+}
+
+#[fsm(initial = Awaiting)]
+impl PaymentFsm {
+    type Context = u32;
+    type Error = PaymentError;
+
+    #[on(state = Awaiting, event = Charge)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_charge(&mut self, card: String) -> Result<Transition<Charged>, Self::Error> {
+        if card == "stolen" {
+            return Err(PaymentError::Declined(card));
+        }
+        self.context += 1;
+        Ok(Transition::to(Charged))
+    }
+
+    #[on(state = Charged, event = Settle)]
+    async fn handle_settle(&mut self) -> Transition<Settled> {
+        Transition::to(Settled)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Result<Transition<Awaiting>, Self::Error> {
+        Err(PaymentError::Unsettled)
+    }
+}
+
+#[tokio::test]
+async fn test_handler_error_stops_the_fsm() {
+    let (handle, mut task) = PaymentFsm::spawn(0);
+    handle
+        .send(PaymentFsmEvent::Charge("stolen".into()))
+        .await
+        .unwrap();
+
+    let err = (&mut task).await.unwrap_err();
+    let tokio_fsm::TaskError::Fsm(error) = &err else {
+        panic!("expected an FSM error, got {err:?}");
+    };
+    assert_eq!(*error, PaymentError::Declined("stolen".into()));
+    assert_eq!(task.site().state, "Awaiting");
+    assert_eq!(task.site().event, Some("Charge"));
+    assert_eq!(err.to_string(), "FSM error: card stolen declined");
+    assert!(handle.send(PaymentFsmEvent::Settle).await.is_err());
+}
+
+#[tokio::test]
+async fn test_handler_ok_commits_the_transition() {
+    let (handle, task) = PaymentFsm::spawn(0);
+    handle
+        .send(PaymentFsmEvent::Charge("visa".into()))
+        .await
+        .unwrap();
+    handle.send(PaymentFsmEvent::Settle).await.unwrap();
+    handle
+        .wait_for_state(PaymentFsmState::Settled)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timeout_handler_error_stops_the_fsm() {
+    let (handle, mut task) = PaymentFsm::spawn(0);
+    handle
+        .send(PaymentFsmEvent::Charge("visa".into()))
+        .await
+        .unwrap();
+
+    let err = (&mut task).await.unwrap_err();
+    let tokio_fsm::TaskError::Fsm(error) = &err else {
+        panic!("expected an FSM error, got {err:?}");
+    };
+    assert_eq!(*error, PaymentError::Unsettled);
+    assert_eq!(task.site().state, "Charged");
+    assert_eq!(task.site().event, None);
+}
+
+#[fsm(initial = Sampling)]
+impl MeterFsm {
+    type Context = u32;
+
+    #[on(state = Sampling, event = Tick)]
+    async fn handle_tick(&mut self) -> Transition<Sampling> {
+        self.context += 1;
+        Transition::to(Sampling)
+    }
+
+    #[on(state = Sampling, event = Stall)]
+    async fn handle_stall(&mut self) -> Transition<Sampling> {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        Transition::to(Sampling)
+    }
+}
+
+#[tokio::test]
+async fn test_events_past_their_ttl_are_dropped() {
+    let (handle, task) = MeterFsm::spawn(0);
+    handle.send(MeterFsmEvent::Stall).await.unwrap();
+    // Both ticks queue behind the stall; only the one without a TTL survives.
+    handle
+        .send_with_ttl(MeterFsmEvent::Tick, std::time::Duration::from_millis(10))
+        .await
+        .unwrap();
+    handle
+        .send_with_ttl(MeterFsmEvent::Tick, std::time::Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_ttl_runs_on_the_fsm_clock() {
+    let clock = ManualClock::new();
+    let (handle, task) = MeterFsm::builder(0).clock(clock.clone()).spawn();
+    // Still queued when the clock moves past the first deadline.
+    handle
+        .send_with_ttl(MeterFsmEvent::Tick, Duration::from_secs(10))
+        .await
+        .unwrap();
+    handle
+        .send_with_ttl(MeterFsmEvent::Tick, Duration::from_secs(60))
+        .await
+        .unwrap();
+    clock.advance(Duration::from_secs(30));
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 1);
+}
+
+/// Adapts a channel into a `Stream`, like a message-bus subscription.
+struct Feed(tokio::sync::mpsc::UnboundedReceiver<MeterFsmEvent>);
+
+impl futures_core::Stream for Feed {
+    type Item = MeterFsmEvent;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<MeterFsmEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+#[tokio::test]
+async fn test_attached_sources_feed_the_fsm() {
+    let (handle, task) = MeterFsm::spawn(0);
+    let mut changes = handle.subscribe();
+    let (bus_a, feed_a) = tokio::sync::mpsc::unbounded_channel();
+    let (bus_b, feed_b) = tokio::sync::mpsc::unbounded_channel();
+    handle.attach_source(Feed(feed_a));
+    handle.attach_source(Feed(feed_b));
+
+    bus_a.send(MeterFsmEvent::Tick).unwrap();
+    bus_b.send(MeterFsmEvent::Tick).unwrap();
+    bus_a.send(MeterFsmEvent::Tick).unwrap();
+    // An ended source is dropped without stopping the FSM.
+    drop(bus_b);
+    changes.wait_for(|change| change.seq == 3).await.unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 3);
+}
+
+#[fsm(initial = Listening)]
+impl LagFsm {
+    type Context = Vec<Option<std::time::Duration>>;
+
+    #[on(state = Listening, event = Hold)]
+    async fn handle_hold(&mut self) -> Transition<Listening> {
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        Transition::to(Listening).then(LagFsmEvent::Measure)
+    }
+
+    #[on(state = Listening, event = Measure)]
+    async fn handle_measure(&mut self) -> Transition<Listening> {
+        let delay = self.event_meta().map(|meta| meta.queue_delay());
+        self.context.push(delay);
+        Transition::to(Listening)
+    }
+}
+
+#[tokio::test]
+async fn test_event_meta_reports_queue_delay() {
+    let (handle, task) = LagFsm::spawn(Vec::new());
+    handle.send(LagFsmEvent::Hold).await.unwrap();
+    handle.send(LagFsmEvent::Measure).await.unwrap();
+    handle.shutdown_graceful();
+
+    // The follow-up skipped the queue; the sent event waited out the hold.
+    let delays = task.await.unwrap();
+    assert_eq!(delays.len(), 2);
+    assert_eq!(delays[0], None);
+    assert!(delays[1].unwrap() >= std::time::Duration::from_millis(25));
+}
+
+#[tokio::test]
+async fn test_event_meta_is_stamped_on_the_fsm_clock() {
+    let clock = ManualClock::new();
+    let (handle, task) = LagFsm::builder(Vec::new()).clock(clock.clone()).spawn();
+    handle.send(LagFsmEvent::Measure).await.unwrap();
+    clock.advance(Duration::from_secs(7));
+    handle.shutdown_graceful();
+
+    assert_eq!(task.await.unwrap(), [Some(Duration::from_secs(7))]);
+}
@@ -0,0 +1,526 @@
+use tokio_fsm::{Transition, fsm};
+
+#[fsm(initial = Idle)]
+impl LampFsm {
+    type Context = ();
+    type Error = std::convert::Infallible;
+
+    #[on(state = Idle, event = TurnOn)]
+    async fn handle_on(&mut self, _brightness: u8) -> Transition<Lit> {
+        Transition::to(Lit)
+    }
+
+    #[on(state = Lit, event = TurnOff)]
+    async fn handle_off(&mut self) -> Transition<Idle> {
+        Transition::to(Idle)
+    }
+}
+
+#[fsm(initial = Staged, non_exhaustive)]
+impl ReleaseFsm {
+    #[on(state = Staged, event = Ship)]
+    async fn handle_ship(&mut self) -> Transition<Shipped> {
+        Transition::to(Shipped)
+    }
+}
+
+// `Error` as a variant name must not clash with the `TryFrom` impls'
+// `type Error`.
+#[fsm(initial = Healthy, state_repr = u8)]
+impl ProbeFsm {
+    type Error = std::convert::Infallible;
+
+    #[on(state = Healthy, event = Error)]
+    async fn handle_error(&mut self) -> Transition<Error> {
+        Transition::to(Error)
+    }
+}
+
+#[fsm(initial = Untagged, event_derive(PartialEq, Eq, Hash))]
+impl LabelFsm {
+    #[on(state = Untagged, event = Label)]
+    async fn handle_label(&mut self, _label: String) -> Transition<Tagged> {
+        Transition::to(Tagged)
+    }
+
+    #[on(state = Tagged, event = Clear)]
+    async fn handle_clear(&mut self) -> Transition<Untagged> {
+        Transition::to(Untagged)
+    }
+}
+
+#[test]
+fn test_display_and_as_str() {
+    assert_eq!(LampFsmState::Lit.as_str(), "Lit");
+    assert_eq!(LampFsmState::Idle.to_string(), "Idle");
+    assert_eq!(LampFsmEvent::TurnOn(80).as_str(), "TurnOn");
+    assert_eq!(LampFsmEvent::TurnOff.to_string(), "TurnOff");
+}
+
+#[test]
+fn test_variants_may_be_named_error() {
+    assert_eq!(ProbeFsmState::try_from("Error"), Ok(ProbeFsmState::Error));
+    assert!(ProbeFsmEvent::try_from("Error").is_ok());
+    assert_eq!(ProbeFsmState::try_from(1u8), Ok(ProbeFsmState::Error));
+}
+
+#[test]
+fn test_variant_iteration() {
+    assert_eq!(LampFsmState::ALL, [LampFsmState::Idle, LampFsmState::Lit]);
+    assert_eq!(LampFsmEvent::NAMES, ["TurnOn", "TurnOff"]);
+}
+
+#[test]
+fn test_parse_from_str() {
+    assert_eq!("Lit".parse::<LampFsmState>().unwrap(), LampFsmState::Lit);
+    assert_eq!(
+        LampFsmState::try_from(LampFsmState::Idle.as_str()).unwrap(),
+        LampFsmState::Idle
+    );
+    assert!("Dim".parse::<LampFsmState>().is_err());
+
+    assert!(matches!(
+        "TurnOff".parse::<LampFsmEvent>(),
+        Ok(LampFsmEvent::TurnOff)
+    ));
+    // Events carrying a payload can't be built from a name alone.
+    let err = LampFsmEvent::try_from("TurnOn").unwrap_err();
+    assert_eq!(err.name(), "TurnOn");
+}
+
+#[test]
+fn test_event_derive_adds_comparisons() {
+    let label = |name: &str| LabelFsmEvent::Label(name.to_string());
+    assert_eq!(label("urgent"), label("urgent"));
+    assert_ne!(label("urgent"), LabelFsmEvent::Clear);
+
+    let unique: std::collections::HashSet<_> =
+        [label("a"), label("b"), label("a"), LabelFsmEvent::Clear]
+            .into_iter()
+            .collect();
+    assert_eq!(unique.len(), 3);
+}
+
+#[test]
+fn test_valid_events_per_state() {
+    assert_eq!(LampFsmState::Idle.valid_events(), &["TurnOn"]);
+    assert_eq!(LampFsmState::Lit.valid_events(), &["TurnOff"]);
+}
+
+#[test]
+fn test_state_predicates() {
+    assert!(LampFsmState::Idle.is_idle());
+    assert!(!LampFsmState::Idle.is_lit());
+    assert!(LampFsmState::Lit.is_lit());
+}
+
+#[fsm(initial = Standby, state_repr = u8)]
+impl AlarmFsm {
+    #[on(state = Standby, event = Arm)]
+    async fn handle_arm(&mut self) -> Transition<Armed> {
+        Transition::to(Armed)
+    }
+
+    #[on(state = Armed, event = Trip)]
+    async fn handle_trip(&mut self) -> Transition<Ringing> {
+        Transition::to(Ringing)
+    }
+}
+
+#[test]
+fn test_state_repr_discriminants() {
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    assert_eq!(std::mem::size_of::<AlarmFsmState>(), 1);
+    let discriminants: Vec<u8> = AlarmFsmState::ALL.into_iter().map(u8::from).collect();
+    assert_eq!(discriminants, [0, 1, 2]);
+
+    let stored = AtomicU8::new(AlarmFsmState::Armed.into());
+    assert_eq!(
+        AlarmFsmState::try_from(stored.load(Ordering::Relaxed)),
+        Ok(AlarmFsmState::Armed)
+    );
+    let err = AlarmFsmState::try_from(7).unwrap_err();
+    assert_eq!(err.value(), 7);
+    assert_eq!(
+        err.to_string(),
+        "7 is not the discriminant of any AlarmFsmState"
+    );
+}
+
+/// Stand-in for a wire protocol enum defined in another crate.
+#[derive(Debug, PartialEq)]
+pub enum WireMessage {
+    PowerOn(u8),
+    PowerOff { reason: String },
+    Heartbeat,
+}
+
+#[fsm(initial = Dark)]
+#[external_event(from = WireMessage, map(TurnOn = PowerOn, TurnOff = PowerOff))]
+impl RemoteLampFsm {
+    // Neither `type Context` nor `type Error` is declared: they default to
+    // `()` and `Infallible`, and `spawn` takes no argument.
+
+    #[on(state = Dark, event = TurnOn)]
+    async fn handle_on(&mut self, _brightness: u8) -> Transition<Bright> {
+        Transition::to(Bright)
+    }
+
+    #[on(state = Bright, event = TurnOff)]
+    async fn handle_off(&mut self) -> Transition<Dark> {
+        Transition::to(Dark)
+    }
+}
+
+#[test]
+fn test_external_event_conversion() {
+    assert!(matches!(
+        RemoteLampFsmEvent::try_from(WireMessage::PowerOn(10)),
+        Ok(RemoteLampFsmEvent::TurnOn(10))
+    ));
+    assert!(matches!(
+        RemoteLampFsmEvent::try_from(WireMessage::PowerOff {
+            reason: "idle".into()
+        }),
+        Ok(RemoteLampFsmEvent::TurnOff)
+    ));
+    assert_eq!(
+        RemoteLampFsmEvent::try_from(WireMessage::Heartbeat).unwrap_err(),
+        WireMessage::Heartbeat
+    );
+}
+
+#[tokio::test]
+async fn test_send_external_drops_unmapped_messages() {
+    let (handle, task) = RemoteLampFsm::spawn();
+
+    handle.send_external(WireMessage::Heartbeat).await.unwrap();
+    handle
+        .send_external(WireMessage::PowerOn(50))
+        .await
+        .unwrap();
+    handle
+        .wait_for_state(RemoteLampFsmState::Bright)
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    let result: Result<(), tokio_fsm::TaskError<std::convert::Infallible>> = task.await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_send_into_uses_payload_conversion() {
+    assert!(matches!(LampFsmEvent::from(42u8), LampFsmEvent::TurnOn(42)));
+
+    let (handle, task) = LampFsm::spawn(());
+    handle.send_into(75u8).await.unwrap();
+    handle.wait_for_state(LampFsmState::Lit).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+/// A producer that only knows its payload type converts into the events.
+async fn produce<H: tokio_fsm::FsmHandle>(handle: &H, payloads: Vec<impl Into<H::Event>>) {
+    for payload in payloads {
+        handle.send_into(payload).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_generic_producers_send_payloads() {
+    let (handle, task) = LampFsm::spawn(());
+    produce(&handle, vec![40u8]).await;
+    handle.wait_for_state(LampFsmState::Lit).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+fn reject_dim(event: &LampFsmEvent) -> Result<(), tokio_fsm::ValidationError> {
+    match event {
+        LampFsmEvent::TurnOn(brightness) if *brightness < 10 => {
+            Err(tokio_fsm::ValidationError::new("brightness below 10"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[tokio::test]
+async fn test_validator_rejects_events_before_enqueue() {
+    let (handle, task) = LampFsm::builder(()).validator(reject_dim).spawn();
+
+    match handle.submit(LampFsmEvent::TurnOn(3)).await {
+        Err(tokio_fsm::SubmitError::Invalid { event, error }) => {
+            assert!(matches!(event, LampFsmEvent::TurnOn(3)));
+            assert_eq!(error.message(), "brightness below 10");
+        }
+        other => panic!("expected a validation error, got {other:?}"),
+    }
+    assert!(matches!(
+        handle.try_submit(LampFsmEvent::TurnOn(5)),
+        Err(tokio_fsm::SubmitError::Invalid { .. })
+    ));
+    assert!(matches!(
+        handle.call(LampFsmEvent::TurnOn(4)).await,
+        Err(tokio_fsm::CallError::Discarded(
+            tokio_fsm::DropReason::Invalid
+        ))
+    ));
+    assert_eq!(handle.queue_len(), 0);
+    assert_eq!(handle.current_state(), LampFsmState::Idle);
+
+    handle.submit(LampFsmEvent::TurnOn(80)).await.unwrap();
+    handle.wait_for_state(LampFsmState::Lit).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    assert!(matches!(
+        handle.submit(LampFsmEvent::TurnOff).await,
+        Err(tokio_fsm::SubmitError::Closed(LampFsmEvent::TurnOff))
+    ));
+}
+
+#[tokio::test]
+async fn test_submit_sheds_events_above_watermark() {
+    let (handle, task) = LampFsm::builder(()).shed_above(2).spawn();
+
+    // Still queued: the single-threaded runtime hasn't polled the FSM yet.
+    handle.try_submit(LampFsmEvent::TurnOn(80)).unwrap();
+    handle.try_submit(LampFsmEvent::TurnOff).unwrap();
+    assert_eq!(handle.queue_len(), 2);
+    assert!(matches!(
+        handle.try_submit(LampFsmEvent::TurnOn(50)),
+        Err(tokio_fsm::SubmitError::Shed(LampFsmEvent::TurnOn(50)))
+    ));
+    assert!(matches!(
+        handle.submit(LampFsmEvent::TurnOn(50)).await,
+        Err(tokio_fsm::SubmitError::Shed(_))
+    ));
+    // `try_send` and `call` are shed too, with explicit errors; `send` can't
+    // report a rejection, so it bypasses shedding.
+    assert!(matches!(
+        handle.try_send(LampFsmEvent::TurnOn(60)),
+        Err(tokio::sync::mpsc::error::TrySendError::Full(
+            LampFsmEvent::TurnOn(60)
+        ))
+    ));
+    assert!(matches!(
+        handle.call(LampFsmEvent::TurnOn(60)).await,
+        Err(tokio_fsm::CallError::Discarded(tokio_fsm::DropReason::Shed))
+    ));
+    assert_eq!(handle.queue_len(), 2);
+    handle.send(LampFsmEvent::TurnOn(60)).await.unwrap();
+
+    let mut changes = handle.subscribe();
+    changes.wait_for(|change| change.seq == 3).await.unwrap();
+    assert_eq!(handle.queue_len(), 0);
+    handle.submit(LampFsmEvent::TurnOff).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    assert_eq!(handle.current_state(), LampFsmState::Idle);
+}
+
+#[tokio::test]
+async fn test_queue_pressure_crosses_watermarks() {
+    let (handle, task) = LampFsm::builder(()).watermarks(1, 3).spawn();
+    let mut pressure = handle.queue_pressure();
+    assert_eq!(*pressure.borrow(), tokio_fsm::QueuePressure::Normal);
+
+    // Still queued: the single-threaded runtime hasn't polled the FSM yet.
+    handle.try_send(LampFsmEvent::TurnOn(80)).unwrap();
+    handle.try_send(LampFsmEvent::TurnOff).unwrap();
+    assert!(!pressure.has_changed().unwrap());
+    handle.try_send(LampFsmEvent::TurnOn(80)).unwrap();
+    assert_eq!(
+        *pressure.borrow_and_update(),
+        tokio_fsm::QueuePressure::High
+    );
+
+    pressure.changed().await.unwrap();
+    assert_eq!(*pressure.borrow(), tokio_fsm::QueuePressure::Normal);
+    assert!(handle.queue_len() <= 1);
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    assert_eq!(handle.current_state(), LampFsmState::Lit);
+}
+
+#[tokio::test]
+async fn test_typed_handle_follows_transitions() {
+    let (handle, task) = LampFsm::spawn(());
+    let handle = handle.typed(Lit).unwrap_err();
+
+    let idle: LampFsmTypedHandle<Idle> = handle.typed(Idle).unwrap();
+    let lit: LampFsmTypedHandle<Lit> = idle.turn_on(60).await.unwrap();
+    lit.handle()
+        .wait_for_state(LampFsmState::Lit)
+        .await
+        .unwrap();
+    let idle: LampFsmTypedHandle<Idle> = lit.turn_off().await.unwrap();
+
+    let handle = idle.into_inner();
+    handle.wait_for_state(LampFsmState::Idle).await.unwrap();
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_event_send_methods() {
+    let (handle, task) = LampFsm::spawn(());
+    let mut changes = handle.subscribe();
+
+    handle.turn_on(60).await.unwrap();
+    handle.turn_off().await.unwrap();
+    let change = *changes.wait_for(|change| change.seq == 2).await.unwrap();
+    assert_eq!(change.cause, tokio_fsm::TransitionCause::Event("TurnOff"));
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    assert!(handle.turn_on(10).await.is_err());
+}
+
+#[fsm(initial = Spooling)]
+impl PrintFsm {
+    type Context = Vec<(String, u8)>;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Spooling, event = Print)]
+    async fn handle_print(&mut self, document: String, copies: u8) -> Transition<Spooling> {
+        self.context.push((document, copies));
+        Transition::to(Spooling)
+    }
+}
+
+#[tokio::test]
+async fn test_multi_argument_event_builder() {
+    let event = PrintFsmEvent::print()
+        .document("report.pdf".to_string())
+        .copies(2)
+        .build();
+    assert!(matches!(
+        &event,
+        PrintFsmEvent::Print(PrintFsmPrintPayload { document, copies: 2 }) if document == "report.pdf"
+    ));
+
+    let (handle, task) = PrintFsm::spawn(Vec::new());
+    handle.send(event).await.unwrap();
+    handle.print("memo.txt".to_string(), 1).await.unwrap();
+    handle.shutdown_graceful();
+    assert_eq!(
+        task.await.unwrap(),
+        [("report.pdf".to_string(), 2), ("memo.txt".to_string(), 1)]
+    );
+}
+
+#[test]
+#[should_panic(expected = "`copies` must be set")]
+fn test_multi_argument_event_builder_missing_field() {
+    PrintFsmEvent::print()
+        .document("draft.txt".to_string())
+        .build();
+}
+
+#[fsm(initial = Draft)]
+impl ArticleFsm {
+    #[on(state = Draft, event = Submit)]
+    async fn handle_submit(&mut self) -> Transition<Review> {
+        Transition::to(Review)
+    }
+
+    #[auto(state = Review)]
+    async fn handle_review(&mut self) -> Transition<Published> {
+        Transition::to(Published)
+    }
+
+    #[on(state = Published, event = Retract)]
+    async fn handle_retract(
+        &mut self,
+        reason: &'static str,
+    ) -> Result<Transition<Draft>, Transition<Published>> {
+        if reason.is_empty() {
+            Err(Transition::to(Published))
+        } else {
+            Ok(Transition::to(Draft))
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_typed_handle_skips_automatic_states() {
+    let (handle, task) = ArticleFsm::spawn();
+
+    let draft = handle.typed(Draft).unwrap();
+    let published: ArticleFsmTypedHandle<Published> = draft.submit().await.unwrap();
+
+    // `Retract` may leave the state unchanged, so it is only on the untyped
+    // handle.
+    let handle = published.into_inner();
+    handle
+        .wait_for_state(ArticleFsmState::Published)
+        .await
+        .unwrap();
+    handle.send(ArticleFsmEvent::Retract("typo")).await.unwrap();
+    handle.wait_for_state(ArticleFsmState::Draft).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[fsm(initial = Parked, emit_graph = "dot", emit_path = "target/fsm-graphs")]
+impl ValetFsm {
+    #[on(state = Parked, event = Fetch)]
+    #[state_timeout(duration = "5m")]
+    async fn handle_fetch(&mut self) -> Transition<Waiting> {
+        Transition::to(Waiting)
+    }
+
+    #[on(state = Waiting, event = Collect)]
+    async fn handle_collect(&mut self) -> Transition<Gone> {
+        Transition::to(Gone)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Parked> {
+        Transition::to(Parked)
+    }
+}
+
+#[test]
+fn test_emit_graph_writes_dot_at_compile_time() {
+    let dot = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/target/fsm-graphs/ValetFsm.dot"
+    ))
+    .unwrap();
+    assert!(dot.starts_with("digraph ValetFsm {"), "{dot}");
+    assert!(dot.contains("__start -> Parked;"), "{dot}");
+    assert!(
+        dot.contains(r#"Parked -> Waiting [label="Fetch"];"#),
+        "{dot}"
+    );
+    assert!(
+        dot.contains(r#"Waiting -> Parked [label="timeout (5m)", style=dashed];"#),
+        "{dot}"
+    );
+}
+
+#[tokio::test]
+async fn test_non_exhaustive_enums_behave_as_usual() {
+    // `#[non_exhaustive]` only restricts other crates; here the enums can
+    // still be matched and built as usual.
+    assert_eq!(ReleaseFsmState::ALL.len(), 2);
+    assert_eq!(ReleaseFsmEvent::NAMES, ["Ship"]);
+
+    let (handle, task) = ReleaseFsm::spawn();
+    handle.send(ReleaseFsmEvent::Ship).await.unwrap();
+    handle
+        .wait_for_state(ReleaseFsmState::Shipped)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
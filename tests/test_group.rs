@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+
+use tokio_fsm::{FsmGroup, TaskError, Transition, fsm};
+
+#[derive(Debug)]
+pub struct Leg {
+    pub fail: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("leg failed")]
+pub struct LegError;
+
+#[fsm(initial = Running)]
+impl RelayFsm {
+    type Context = Leg;
+    type Error = LegError;
+
+    #[on(state = Running, event = Finish)]
+    async fn handle_finish(&mut self) -> Result<Transition<Finished>, LegError> {
+        if self.context.fail {
+            return Err(LegError);
+        }
+        Ok(Transition::to(Finished).halt())
+    }
+}
+
+#[tokio::test]
+async fn test_group_yields_typed_results_by_instance() {
+    let mut group = FsmGroup::new();
+    let mut handles = Vec::new();
+    for fail in [false, true, false] {
+        let (handle, task) = RelayFsm::spawn(Leg { fail });
+        assert_eq!(task.id(), handle.id());
+        group.push(task);
+        handles.push((handle, fail));
+    }
+    assert_eq!(group.len(), 3);
+
+    for (handle, _) in &handles {
+        handle.send(RelayFsmEvent::Finish).await.unwrap();
+    }
+
+    let mut joined = HashSet::new();
+    while let Some((id, result)) = group.join_next().await {
+        let (_, fail) = handles
+            .iter()
+            .find(|(handle, _)| handle.id() == id)
+            .unwrap();
+        match result {
+            Ok(leg) => assert!(!leg.fail && !fail),
+            Err(TaskError::Fsm(LegError)) => assert!(fail),
+            Err(err) => panic!("unexpected error: {err}"),
+        }
+        joined.insert(id);
+    }
+    assert_eq!(joined.len(), 3);
+    assert!(group.is_empty());
+}
+
+#[tokio::test]
+async fn test_dropping_group_aborts_running_fsms() {
+    let (handle, task) = RelayFsm::spawn(Leg { fail: false });
+    let group: FsmGroup<_> = [task].into_iter().collect();
+    let mut changes = handle.subscribe();
+
+    drop(group);
+    assert!(changes.changed().await.is_err());
+    assert!(handle.send(RelayFsmEvent::Finish).await.is_err());
+}
+
+#[fsm(initial = Serving)]
+impl WorkerFsm {
+    type Context = u32;
+
+    #[on(state = Serving, event = Job)]
+    async fn handle_job(&mut self, stall: bool) -> Transition<Serving> {
+        if stall {
+            std::future::pending::<()>().await;
+        }
+        self.context += 1;
+        Transition::to(Serving)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shutdown_within_reports_stragglers() {
+    let mut group = FsmGroup::new();
+    let mut handles = Vec::new();
+    for stall in [false, true, false] {
+        let (handle, task) = WorkerFsm::spawn(0);
+        handle.send(WorkerFsmEvent::Job(false)).await.unwrap();
+        handle.send(WorkerFsmEvent::Job(stall)).await.unwrap();
+        group.push(task);
+        handles.push(handle);
+    }
+
+    let report = group
+        .shutdown_graceful_within(std::time::Duration::from_secs(5))
+        .await;
+
+    assert!(!report.is_clean());
+    assert_eq!(report.stragglers, vec![handles[1].id()]);
+    let mut stopped: Vec<_> = report
+        .stopped
+        .into_iter()
+        .map(|(id, result)| (id, result.unwrap()))
+        .collect();
+    stopped.sort();
+    assert_eq!(stopped, vec![(handles[0].id(), 2), (handles[2].id(), 2)]);
+    assert!(group.is_empty());
+    assert!(handles[1].send(WorkerFsmEvent::Job(false)).await.is_err());
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "tonic")]
+
+use tokio_fsm::{
+    FsmManager, Transition, fsm,
+    grpc::{ControlPlane, ControlPlaneClient},
+};
+use tonic::Code;
+
+#[fsm(initial = Parked, serde)]
+impl RoverFsm {
+    type Context = u32;
+
+    #[on(state = Parked, event = Drive)]
+    async fn handle_drive(&mut self) -> Transition<Roving> {
+        Transition::to(Roving)
+    }
+
+    #[on(state = Roving, event = Sample)]
+    async fn handle_sample(&mut self, count: u32) -> Transition<Roving> {
+        self.context += count;
+        Transition::to(Roving)
+    }
+
+    #[on(state = Roving, event = Park)]
+    async fn handle_park(&mut self) -> Transition<Parked> {
+        Transition::to(Parked)
+    }
+}
+
+fn client(rovers: &FsmManager<RoverFsmHandle>) -> ControlPlaneClient<ControlPlane<RoverFsmHandle>> {
+    ControlPlaneClient::new(ControlPlane::new(rovers.clone()))
+}
+
+#[tokio::test]
+async fn test_grpc_drives_and_observes_instances() {
+    let rovers = FsmManager::new();
+    let task = rovers.create("opportunity", || RoverFsm::spawn(0)).unwrap();
+    let mut client = client(&rovers);
+
+    let mut transitions = client.subscribe_transitions("opportunity").await.unwrap();
+    let initial = transitions.message().await.unwrap().unwrap();
+    assert_eq!(
+        (initial.to.as_str(), initial.cause.as_str()),
+        ("Parked", "initial")
+    );
+
+    let state = client
+        .send_event("opportunity", r#""Drive""#)
+        .await
+        .unwrap();
+    assert_eq!(state, "Roving");
+    let change = transitions.message().await.unwrap().unwrap();
+    assert_eq!(change.seq, 1);
+    assert_eq!(
+        (change.from.as_str(), change.to.as_str()),
+        ("Parked", "Roving")
+    );
+    assert_eq!(change.event.as_deref(), Some("Drive"));
+
+    let state = client
+        .send_event("opportunity", r#"{"Sample":3}"#)
+        .await
+        .unwrap();
+    assert_eq!(state, "Roving");
+
+    let status = client.get_state("opportunity").await.unwrap();
+    assert_eq!(status.state, "Roving");
+    assert_eq!(status.queue_len, 0);
+
+    rovers.get("opportunity").unwrap().shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 3);
+    // The stream ends once the instance has stopped.
+    while transitions.message().await.unwrap().is_some() {}
+}
+
+#[tokio::test]
+async fn test_grpc_reports_errors_as_statuses() {
+    let rovers = FsmManager::new();
+    let task = rovers.create("spirit", || RoverFsm::spawn(0)).unwrap();
+    let mut client = client(&rovers);
+
+    let err = client.get_state("curiosity").await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let err = client.subscribe_transitions("curiosity").await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    let err = client.send_event("spirit", "Drive").await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    rovers.get("spirit").unwrap().shutdown_graceful();
+    task.await.unwrap();
+    let err = client.send_event("spirit", r#""Drive""#).await.unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "proptest")]
+
+use std::time::Duration;
+
+use proptest::prelude::*;
+use tokio_fsm::{
+    ManualClock, Transition, fsm,
+    test_util::{FsmModel, Step, drive},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Account {
+    pub balance: u64,
+    pub deposits: u32,
+    pub thaws: u32,
+}
+
+#[fsm(initial = Open)]
+impl AccountFsm {
+    type Context = Account;
+
+    #[on(state = Open, event = Deposit)]
+    async fn handle_deposit(&mut self, amount: u64) -> Transition<Open> {
+        self.context.balance += amount;
+        self.context.deposits += 1;
+        Transition::to(Open)
+    }
+
+    #[on(state = Open, event = Freeze)]
+    #[state_timeout(duration = "5s")]
+    async fn handle_freeze(&mut self) -> Transition<Frozen> {
+        Transition::to(Frozen)
+    }
+
+    #[on(state = Frozen, event = Close)]
+    async fn handle_close(&mut self) -> Transition<Closed> {
+        Transition::to(Closed)
+    }
+
+    #[on_timeout]
+    async fn handle_thaw(&mut self) -> Transition<Open> {
+        self.context.thaws += 1;
+        Transition::to(Open)
+    }
+}
+
+fn model() -> FsmModel<AccountFsmState, AccountFsmEvent> {
+    FsmModel::new(AccountFsmState::Open, AccountFsm::transitions())
+        .payload("Deposit", (1..100u64).prop_map(AccountFsmEvent::Deposit))
+        .advance_up_to(Duration::from_secs(10))
+}
+
+fn run(steps: Vec<Step<AccountFsmEvent>>) -> Vec<(AccountFsmState, Account)> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let clock = ManualClock::new();
+        let (handle, _task) = AccountFsm::builder(Account::default())
+            .clock(clock.clone())
+            .spawn();
+        let mut seen = Vec::new();
+        drive(&handle, &clock, steps, |state, account| {
+            seen.push((state, account.clone()))
+        })
+        .await
+        .unwrap();
+        seen
+    })
+}
+
+proptest! {
+    #[test]
+    fn test_generated_sequences_follow_the_table(steps in model().steps(1..40)) {
+        let seen = run(steps.clone());
+        prop_assert_eq!(seen.len(), steps.len());
+
+        let mut previous = Account::default();
+        for (state, account) in &seen {
+            prop_assert!(account.balance >= previous.balance);
+            prop_assert!(account.balance <= u64::from(account.deposits) * 99);
+            if *state == AccountFsmState::Closed {
+                prop_assert_eq!(account.balance, previous.balance);
+            }
+            previous = account.clone();
+        }
+        // Before any timeout fires, every send follows the walk.
+        if seen.iter().all(|(_, account)| account.thaws == 0) {
+            let deposits = steps
+                .iter()
+                .filter(|step| matches!(step, Step::Send(AccountFsmEvent::Deposit(_))))
+                .count();
+            prop_assert_eq!(previous.deposits as usize, deposits);
+        }
+    }
+}
+
+#[test]
+fn test_walk_stops_at_terminal_state() {
+    let model = FsmModel::new(AccountFsmState::Frozen, AccountFsm::transitions());
+    let mut runner = proptest::test_runner::TestRunner::default();
+    let steps = model.steps(5).new_tree(&mut runner).unwrap().current();
+    assert_eq!(steps.len(), 1);
+    assert!(matches!(steps[0], Step::Send(AccountFsmEvent::Close)));
+}
+
+#[tokio::test]
+async fn test_inspect_reads_context_between_events() {
+    let (handle, _task) = AccountFsm::spawn(Account::default());
+    handle.send(AccountFsmEvent::Deposit(5)).await.unwrap();
+    handle.send(AccountFsmEvent::Deposit(7)).await.unwrap();
+    while handle.queue_len() > 0 {
+        tokio::task::yield_now().await;
+    }
+    let balance = handle.inspect(|account| account.balance).await.unwrap();
+    assert_eq!(balance, 12);
+}
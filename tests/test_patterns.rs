@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use tokio_fsm::patterns::{
+    circuit_breaker::{
+        CircuitBreaker, CircuitBreakerConfig, CircuitBreakerFsm, CircuitBreakerFsmEvent,
+        CircuitBreakerFsmState,
+    },
+    lease::{Lease, LeaseFsm, LeaseFsmEvent, LeaseFsmState},
+    retry::{RetryPolicy, retry},
+};
+
+#[tokio::test]
+async fn test_circuit_breaker_opens_and_recovers() {
+    let (breaker, task) = CircuitBreakerFsm::spawn(CircuitBreaker::new(CircuitBreakerConfig {
+        failure_threshold: 2,
+        open_for: Duration::from_millis(20),
+        close_after: 2,
+    }));
+
+    // A success in between resets the failure count.
+    for event in [
+        CircuitBreakerFsmEvent::Failure,
+        CircuitBreakerFsmEvent::Success,
+        CircuitBreakerFsmEvent::Failure,
+    ] {
+        breaker.send(event).await.unwrap();
+    }
+    let mut changes = breaker.subscribe();
+    changes.wait_for(|change| change.seq == 3).await.unwrap();
+    assert_eq!(breaker.current_state(), CircuitBreakerFsmState::Closed);
+
+    breaker.send(CircuitBreakerFsmEvent::Failure).await.unwrap();
+    breaker
+        .wait_for_state(CircuitBreakerFsmState::Open)
+        .await
+        .unwrap();
+    assert!(!breaker.current_state().permits_calls());
+
+    // Late outcomes while open are ignored.
+    breaker.send(CircuitBreakerFsmEvent::Success).await.unwrap();
+    breaker
+        .wait_for_state(CircuitBreakerFsmState::HalfOpen)
+        .await
+        .unwrap();
+    assert!(breaker.current_state().permits_calls());
+
+    // A failed trial re-opens it.
+    breaker.send(CircuitBreakerFsmEvent::Failure).await.unwrap();
+    breaker
+        .wait_for_state(CircuitBreakerFsmState::Open)
+        .await
+        .unwrap();
+    breaker
+        .wait_for_state(CircuitBreakerFsmState::HalfOpen)
+        .await
+        .unwrap();
+
+    breaker.send(CircuitBreakerFsmEvent::Success).await.unwrap();
+    breaker.send(CircuitBreakerFsmEvent::Success).await.unwrap();
+    breaker
+        .wait_for_state(CircuitBreakerFsmState::Closed)
+        .await
+        .unwrap();
+
+    breaker.shutdown_graceful();
+    assert_eq!(task.await.unwrap().failures(), 0);
+}
+
+#[test]
+fn test_retry_backoff_is_capped() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        initial_backoff: Duration::from_millis(100),
+        multiplier: 2.0,
+        max_backoff: Duration::from_millis(500),
+    };
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    assert_eq!(policy.backoff(4), Duration::from_millis(500));
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn test_retry_until_success() {
+    let policy = RetryPolicy {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let mut attempts = Vec::new();
+    let result = retry(policy, |attempt| {
+        attempts.push(attempt);
+        async move {
+            if attempt < 3 {
+                Err(attempt)
+            } else {
+                Ok("done")
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(result, Ok("done"));
+    assert_eq!(attempts, [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_retry_gives_up_with_last_error() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let result: Result<(), u32> = retry(policy, |attempt| async move { Err(attempt) }).await;
+    assert_eq!(result, Err(3));
+}
+
+#[tokio::test]
+async fn test_lease_expires_without_heartbeats() {
+    let (lease, _task) = LeaseFsm::spawn(Lease::new(Duration::from_millis(40)));
+    lease.send(LeaseFsmEvent::Acquire).await.unwrap();
+    lease.wait_for_state(LeaseFsmState::Held).await.unwrap();
+
+    // Heartbeats keep it held well past the ttl.
+    for _ in 0..6 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        lease.send(LeaseFsmEvent::Heartbeat).await.unwrap();
+    }
+    assert_eq!(lease.current_state(), LeaseFsmState::Held);
+
+    lease.wait_for_state(LeaseFsmState::Expired).await.unwrap();
+
+    lease.send(LeaseFsmEvent::Acquire).await.unwrap();
+    lease.wait_for_state(LeaseFsmState::Held).await.unwrap();
+    lease.send(LeaseFsmEvent::Release).await.unwrap();
+    lease.wait_for_state(LeaseFsmState::Released).await.unwrap();
+
+    // The expiry armed by the last acquire no longer applies.
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(lease.current_state(), LeaseFsmState::Released);
+}
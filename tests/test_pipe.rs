@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use tokio_fsm::{Transition, TransitionCause, fsm, pipe};
+
+#[fsm(initial = Open)]
+impl OrderFsm {
+    type Context = ();
+
+    #[on(state = Open, event = Pay)]
+    async fn handle_pay(&mut self) -> Transition<Paid> {
+        Transition::to(Paid)
+    }
+
+    #[on(state = Paid, event = Close)]
+    async fn handle_close(&mut self) -> Transition<Open> {
+        Transition::to(Open)
+    }
+}
+
+#[fsm(initial = Waiting)]
+impl ShippingFsm {
+    type Context = u32;
+
+    #[on(state = Waiting, event = Prepare)]
+    async fn handle_prepare(&mut self) -> Transition<Waiting> {
+        self.context += 1;
+        Transition::to(Waiting)
+    }
+}
+
+/// Lets spawned tasks run until they are all idle. Time is paused in the
+/// tests using it, so the sleep only completes once nothing else can run.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pipe_maps_transitions_into_events() {
+    let (order, order_task) = OrderFsm::spawn(());
+    let (shipping, shipping_task) = ShippingFsm::spawn(0);
+
+    let forward = pipe(&order, &shipping, |change| {
+        let paid = change.cause == TransitionCause::Event("Pay");
+        paid.then_some(ShippingFsmEvent::Prepare)
+    });
+
+    order.send(OrderFsmEvent::Pay).await.unwrap();
+    settle().await;
+    order.send(OrderFsmEvent::Close).await.unwrap();
+    settle().await;
+    order.send(OrderFsmEvent::Pay).await.unwrap();
+    settle().await;
+
+    order.shutdown_graceful();
+    order_task.await.unwrap();
+    settle().await;
+    assert!(forward.is_finished());
+
+    shipping.shutdown_graceful();
+    assert_eq!(shipping_task.await.unwrap(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pipe_forwards_every_transition_of_a_burst() {
+    let (order, order_task) = OrderFsm::spawn(());
+    let (shipping, shipping_task) = ShippingFsm::spawn(0);
+
+    let forward = pipe(&order, &shipping, |change| {
+        let paid = change.from != OrderFsmState::Paid && change.to == OrderFsmState::Paid;
+        paid.then_some(ShippingFsmEvent::Prepare)
+    });
+
+    // Handled back to back, faster than `subscribe` observers keep up.
+    for _ in 0..3 {
+        order.send(OrderFsmEvent::Pay).await.unwrap();
+        order.send(OrderFsmEvent::Close).await.unwrap();
+    }
+    order.shutdown_graceful();
+    order_task.await.unwrap();
+    settle().await;
+    assert!(forward.is_finished());
+
+    shipping.shutdown_graceful();
+    assert_eq!(shipping_task.await.unwrap(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_dropping_pipe_stops_forwarding() {
+    let (order, _order_task) = OrderFsm::spawn(());
+    let (shipping, shipping_task) = ShippingFsm::spawn(0);
+
+    let forward = pipe(&order, &shipping, |_| Some(ShippingFsmEvent::Prepare));
+    drop(forward);
+    settle().await;
+
+    order.send(OrderFsmEvent::Pay).await.unwrap();
+    settle().await;
+
+    shipping.shutdown_graceful();
+    assert_eq!(shipping_task.await.unwrap(), 0);
+}
@@ -0,0 +1,58 @@
+use tokio_fsm::{FsmPool, Transition, fsm};
+
+#[fsm(initial = Counting)]
+impl TallyFsm {
+    type Context = Vec<(u32, u32)>;
+
+    #[on(state = Counting, event = Record)]
+    async fn handle_record(&mut self, entry: (u32, u32)) -> Transition<Counting> {
+        self.context.push(entry);
+        Transition::to(Counting)
+    }
+}
+
+#[tokio::test]
+async fn test_pool_routes_each_key_to_one_shard_in_order() {
+    let (pool, tasks) = FsmPool::spawn(4, |_| TallyFsm::spawn(Vec::new()));
+    assert_eq!(pool.len(), 4);
+
+    for seq in 0..5 {
+        for key in 0..20u32 {
+            pool.send(&key, TallyFsmEvent::Record((key, seq)))
+                .await
+                .unwrap();
+        }
+    }
+
+    for handle in pool.handles() {
+        handle.shutdown_graceful();
+    }
+    let mut used = 0;
+    for (shard, task) in tasks.into_iter().enumerate() {
+        let entries = task.await.unwrap();
+        used += usize::from(!entries.is_empty());
+        for key in 0..20u32 {
+            let seqs: Vec<u32> = entries
+                .iter()
+                .filter(|(k, _)| *k == key)
+                .map(|(_, seq)| *seq)
+                .collect();
+            if pool.shard_index(&key) == shard {
+                assert_eq!(seqs, [0, 1, 2, 3, 4]);
+            } else {
+                assert!(seqs.is_empty());
+            }
+        }
+    }
+    assert!(used > 1, "keys should spread over several shards");
+}
+
+#[tokio::test]
+async fn test_pool_shard_lookup_is_stable() {
+    let (pool, _tasks) = FsmPool::spawn(3, |_| TallyFsm::spawn(Vec::new()));
+    let clone = pool.clone();
+    for key in ["order-1", "order-2", "order-3"] {
+        assert_eq!(pool.shard(key), clone.shard(key));
+        assert_eq!(pool.shard(key), &pool.handles()[pool.shard_index(key)]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    pin::pin,
+    sync::{Arc, LazyLock},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use tokio_fsm::{
+    Clock, ClockSleep, ManualClock, Runtime, RuntimeTask, TaskError, Transition, TransitionCause,
+    fsm,
+};
+
+/// Shared by every `ThreadRuntime`, so tests can move the FSM's time.
+static CLOCK: LazyLock<ManualClock> = LazyLock::new(ManualClock::new);
+
+/// Runs each task on its own thread, with no Tokio runtime anywhere.
+#[derive(Default)]
+struct ThreadRuntime;
+
+impl Clock for ThreadRuntime {
+    fn now(&self) -> tokio::time::Instant {
+        CLOCK.now()
+    }
+
+    fn sleep_until(&self, deadline: tokio::time::Instant) -> ClockSleep {
+        CLOCK.sleep_until(deadline)
+    }
+}
+
+impl Runtime for ThreadRuntime {
+    fn spawn(&self, task: RuntimeTask) {
+        thread::spawn(move || block_on(task));
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[fsm(initial = Docked, runtime = ThreadRuntime)]
+impl ShuttleFsm {
+    type Context = u32;
+
+    #[on(state = Docked, event = Launch)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_launch(&mut self) -> Transition<Orbiting> {
+        self.context += 1;
+        Transition::to(Orbiting)
+    }
+
+    #[on(state = Docked, event = Scuttle)]
+    async fn handle_scuttle(&mut self) -> Transition<Docked> {
+        panic!("shuttle scuttled")
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Docked> {
+        Transition::to(Docked)
+    }
+}
+
+#[fsm(initial = Idle, runtime = ThreadRuntime)]
+impl ProbeFsm {
+    type Context = u32;
+
+    #[on(state = Idle, event = Probe)]
+    async fn handle_probe(&mut self) -> Transition<Probing> {
+        self.spawn_work(async { Ok::<_, ()>(7) }, ProbeFsmEvent::Reply, |()| {
+            ProbeFsmEvent::Reply(0)
+        });
+        Transition::to(Probing)
+    }
+
+    #[on(state = Probing, event = Reply)]
+    async fn handle_reply(&mut self, value: u32) -> Transition<Waiting> {
+        self.context = value;
+        self.handle()
+            .send_after(Duration::from_secs(5), ProbeFsmEvent::Wake);
+        Transition::to(Waiting)
+    }
+
+    #[on(state = Waiting, event = Wake)]
+    async fn handle_wake(&mut self) -> Transition<Done> {
+        Transition::to(Done)
+    }
+}
+
+#[test]
+fn test_fsm_runs_on_a_custom_runtime() {
+    let (handle, task) = ShuttleFsm::spawn(0);
+    let mut changes = handle.subscribe();
+
+    block_on(handle.send(ShuttleFsmEvent::Launch)).unwrap();
+    block_on(changes.wait_for(|change| change.to == ShuttleFsmState::Orbiting)).unwrap();
+
+    // The runtime's clock times the state timeout. It is armed just after
+    // the transition is published, so keep moving time until it fires.
+    while changes.borrow().to != ShuttleFsmState::Docked {
+        CLOCK.advance(Duration::from_secs(30));
+        thread::sleep(Duration::from_millis(1));
+    }
+    let change = *changes.borrow();
+    assert_eq!(change.cause, TransitionCause::Timeout);
+
+    handle.shutdown_graceful();
+    assert_eq!(block_on(task).unwrap(), 1);
+}
+
+#[test]
+fn test_custom_runtime_reports_panics() {
+    let (handle, mut task) = ShuttleFsm::spawn(0);
+    block_on(handle.send(ShuttleFsmEvent::Scuttle)).unwrap();
+
+    match block_on(&mut task) {
+        Err(TaskError::Runtime(Some(message))) => {
+            assert!(message.contains("shuttle scuttled"));
+            assert_eq!(task.site().event, Some("Scuttle"));
+        }
+        other => panic!("expected a panic, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_aborting_a_custom_runtime_task() {
+    let (_handle, task) = ShuttleFsm::spawn(0);
+    task.abort();
+    assert!(matches!(block_on(task), Err(TaskError::Runtime(None))));
+}
+
+#[test]
+fn test_background_work_runs_on_a_custom_runtime() {
+    let (handle, task) = ProbeFsm::spawn(0);
+    let mut changes = handle.subscribe();
+
+    block_on(handle.send(ProbeFsmEvent::Probe)).unwrap();
+    block_on(changes.wait_for(|change| change.to == ProbeFsmState::Waiting)).unwrap();
+
+    // The `send_after` timer sleeps on the runtime's clock.
+    while changes.borrow().to != ProbeFsmState::Done {
+        CLOCK.advance(Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    handle.shutdown_graceful();
+    assert_eq!(block_on(task).unwrap(), 7);
+}
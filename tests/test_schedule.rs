@@ -0,0 +1,57 @@
+#![cfg(feature = "cron")]
+
+use std::time::Duration;
+
+use tokio_fsm::{ManualClock, Transition, fsm};
+
+#[fsm(initial = Watching)]
+#[schedule(cron = "0 */5 * * * *", event = Reconcile)]
+impl ReconcilerFsm {
+    type Context = u32;
+
+    #[on(state = Watching, event = Reconcile)]
+    async fn handle_reconcile(&mut self) -> Transition<Watching> {
+        self.context += 1;
+        Transition::to(Watching)
+    }
+}
+
+const FIVE_MINUTES: Duration = Duration::from_secs(5 * 60);
+
+#[tokio::test]
+async fn test_schedule_follows_injected_clock() {
+    let clock = ManualClock::new();
+    let (handle, task) = ReconcilerFsm::builder(0).clock(clock.clone()).spawn();
+    let mut changes = handle.subscribe();
+    // Schedules start from when the loop does.
+    handle.ping().await.unwrap();
+
+    // Every five minutes holds exactly one match, wherever the wall clock is.
+    clock.advance(FIVE_MINUTES);
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    clock.advance(FIVE_MINUTES);
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+
+    handle.shutdown_immediate();
+    assert_eq!(task.await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_schedule_skips_missed_times() {
+    let clock = ManualClock::new();
+    let (handle, task) = ReconcilerFsm::builder(0).clock(clock.clone()).spawn();
+    let mut changes = handle.subscribe();
+    handle.ping().await.unwrap();
+
+    // Half an hour passes at once: one reconcile, not six.
+    clock.advance(6 * FIVE_MINUTES);
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(handle.subscribe().borrow().seq, 1);
+
+    clock.advance(FIVE_MINUTES);
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+
+    handle.shutdown_immediate();
+    assert_eq!(task.await.unwrap(), 2);
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "serde")]
+
+use serde::{Deserialize, Serialize};
+use tokio_fsm::{Transition, fsm};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+}
+
+#[fsm(initial = Idle, serde)]
+impl SerdeFsm {
+    type Context = ();
+    type Error = std::convert::Infallible;
+
+    #[on(state = Idle, event = Start)]
+    async fn handle_start(&mut self, _job: Job) -> Transition<Running> {
+        Transition::to(Running)
+    }
+
+    #[on(state = Running, event = Stop)]
+    async fn handle_stop(&mut self) -> Transition<Idle> {
+        Transition::to(Idle)
+    }
+}
+
+#[test]
+fn test_generated_enums_round_trip() {
+    let state = serde_json::to_string(&SerdeFsmState::Running).unwrap();
+    assert_eq!(state, "\"Running\"");
+    let state: SerdeFsmState = serde_json::from_str(&state).unwrap();
+    assert_eq!(state, SerdeFsmState::Running);
+
+    let event = serde_json::to_string(&SerdeFsmEvent::Start(Job { id: 7 })).unwrap();
+    assert_eq!(event, r#"{"Start":{"id":7}}"#);
+    let event: SerdeFsmEvent = serde_json::from_str(&event).unwrap();
+    assert!(matches!(event, SerdeFsmEvent::Start(Job { id: 7 })));
+}
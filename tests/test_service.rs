@@ -0,0 +1,87 @@
+#![cfg(feature = "tower")]
+
+use std::time::Duration;
+
+use tokio_fsm::{CallError, FsmService, Transition, fsm};
+use tower::{Service, ServiceBuilder, ServiceExt, timeout::error::Elapsed};
+
+#[fsm(initial = Ready)]
+impl PrinterFsm {
+    type Context = Vec<String>;
+
+    #[on(state = Ready, event = Print)]
+    async fn handle_print(&mut self, page: String) -> Transition<Ready> {
+        self.context.push(page);
+        Transition::to(Ready)
+    }
+
+    #[on(state = Ready, event = Jam)]
+    async fn handle_jam(&mut self) -> Transition<Jammed> {
+        Transition::to(Jammed)
+    }
+
+    #[on(state = Jammed, event = Clear)]
+    async fn handle_clear(&mut self) -> Transition<Ready> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Transition::to(Ready)
+    }
+}
+
+#[tokio::test]
+async fn test_service_responds_with_the_resulting_state() {
+    let (handle, task) = PrinterFsm::spawn(Vec::new());
+    let mut service = FsmService::new(handle.clone());
+
+    let state = service
+        .ready()
+        .await
+        .unwrap()
+        .call(PrinterFsmEvent::Print("cover".into()))
+        .await
+        .unwrap();
+    assert_eq!(state, PrinterFsmState::Ready);
+    let state = service.clone().oneshot(PrinterFsmEvent::Jam).await.unwrap();
+    assert_eq!(state, PrinterFsmState::Jammed);
+
+    handle.shutdown_immediate();
+    assert_eq!(task.await.unwrap(), ["cover"]);
+    assert!(matches!(
+        service.oneshot(PrinterFsmEvent::Jam).await,
+        Err(CallError::Closed(PrinterFsmEvent::Jam))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_service_works_behind_middleware() {
+    let (handle, _task) = PrinterFsm::spawn(Vec::new());
+    let service = ServiceBuilder::new()
+        .timeout(Duration::from_secs(1))
+        .service(FsmService::new(handle));
+
+    let state = service.clone().oneshot(PrinterFsmEvent::Jam).await.unwrap();
+    assert_eq!(state, PrinterFsmState::Jammed);
+
+    let err = service.oneshot(PrinterFsmEvent::Clear).await.unwrap_err();
+    assert!(err.is::<Elapsed>());
+}
+
+#[tokio::test]
+async fn test_service_is_not_ready_while_the_queue_is_full() {
+    let (handle, task) = PrinterFsm::spawn(Vec::new());
+    let mut service = FsmService::new(handle.clone());
+
+    // Still queued: the single-threaded runtime hasn't polled the FSM yet.
+    while handle
+        .try_send(PrinterFsmEvent::Print("filler".into()))
+        .is_ok()
+    {}
+    let mut ready = std::pin::pin!(service.ready());
+    assert!(futures_util::poll!(ready.as_mut()).is_pending());
+
+    let service = ready.await.unwrap();
+    let state = service.call(PrinterFsmEvent::Jam).await.unwrap();
+    assert_eq!(state, PrinterFsmState::Jammed);
+
+    handle.shutdown_immediate();
+    task.await.unwrap();
+}
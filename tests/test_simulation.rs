@@ -0,0 +1,77 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio_fsm::{Transition, fsm};
+
+#[fsm(initial = Idle)]
+impl WatchFsm {
+    #[on(state = Idle, event = Arm)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_arm(&mut self) -> Transition<Armed> {
+        Transition::to(Armed)
+    }
+
+    #[on(state = Armed, event = Beat)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_beat(&mut self) -> Transition<Armed> {
+        Transition::to(Armed)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Expired> {
+        Transition::to(Expired)
+    }
+}
+
+/// Runs the FSM inside a turmoil simulation, returning each state change with
+/// the simulated time it happened at.
+fn simulate() -> Vec<(Duration, WatchFsmState)> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut sim = turmoil::Builder::new()
+        .simulation_duration(Duration::from_secs(120))
+        .build();
+
+    let client_log = log.clone();
+    sim.client("watch", async move {
+        let start = tokio::time::Instant::now();
+        let (handle, task) = WatchFsm::spawn();
+        let mut changes = handle.subscribe();
+        let recorder = tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let to = changes.borrow_and_update().to;
+                client_log.lock().unwrap().push((start.elapsed(), to));
+                if to == WatchFsmState::Expired {
+                    break;
+                }
+            }
+        });
+
+        handle.send(WatchFsmEvent::Arm).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        handle.send(WatchFsmEvent::Beat).await.unwrap();
+        recorder.await.unwrap();
+
+        handle.shutdown_graceful();
+        task.await.unwrap();
+        Ok(())
+    });
+    sim.run().unwrap();
+
+    log.lock().unwrap().clone()
+}
+
+#[test]
+fn test_runs_deterministically_under_turmoil() {
+    let first = simulate();
+    assert_eq!(
+        first,
+        [
+            (Duration::ZERO, WatchFsmState::Armed),
+            (Duration::from_secs(10), WatchFsmState::Armed),
+            (Duration::from_secs(40), WatchFsmState::Expired),
+        ]
+    );
+    assert_eq!(simulate(), first);
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "sink")]
+
+use futures_util::{SinkExt, StreamExt, stream};
+use tokio_fsm::{Transition, fsm};
+
+#[fsm(initial = Counting)]
+impl TallyFsm {
+    type Context = u32;
+
+    #[on(state = Counting, event = Add)]
+    async fn handle_add(&mut self, amount: u32) -> Transition<Counting> {
+        self.context += amount;
+        Transition::to(Counting)
+    }
+}
+
+#[tokio::test]
+async fn test_stream_forwards_into_handle() {
+    let (handle, task) = TallyFsm::spawn(0);
+
+    let events = stream::iter([1, 2, 3]).map(|amount| Ok(TallyFsmEvent::Add(amount)));
+    events.forward(handle.clone()).await.unwrap();
+
+    // The handle's inherent `send` shadows `SinkExt::send`.
+    let mut sink = handle.clone();
+    SinkExt::send(&mut sink, TallyFsmEvent::Add(4))
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap(), 10);
+
+    let err = SinkExt::send(&mut sink, TallyFsmEvent::Add(5))
+        .await
+        .unwrap_err();
+    assert!(matches!(err.0, TallyFsmEvent::Add(5)));
+}
@@ -0,0 +1,217 @@
+#![cfg(feature = "serde")]
+
+use serde::{Deserialize, Serialize};
+use tokio_fsm::{
+    JournalEntry, MigrateContext, MigrationError, Snapshot, Transition, UpcastEvent, fsm,
+};
+
+/// v1 of the context. v0 stored the amount as `total` and had no currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invoice {
+    pub amount: u64,
+    pub currency: String,
+}
+
+impl MigrateContext for Invoice {
+    const VERSION: u32 = 1;
+    type Raw = serde_json::Value;
+
+    fn upgrade(snapshot: &mut Snapshot<String, Self::Raw>) -> Result<(), MigrationError> {
+        if snapshot.version == 0 {
+            let context = snapshot
+                .context
+                .as_object_mut()
+                .ok_or_else(|| MigrationError::custom("context is not an object"))?;
+            let total = context
+                .remove("total")
+                .ok_or_else(|| MigrationError::custom("missing total"))?;
+            context.insert("amount".into(), total);
+            context.insert("currency".into(), "EUR".into());
+            // v0 called the `Sent` state `Billed`.
+            if snapshot.state == "Billed" {
+                snapshot.state = "Sent".into();
+            }
+        }
+        Ok(())
+    }
+
+    fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError> {
+        serde_json::from_value(raw).map_err(MigrationError::custom)
+    }
+}
+
+#[fsm(initial = Draft, serde)]
+impl InvoiceFsm {
+    type Context = Invoice;
+
+    #[on(state = Draft, event = Send)]
+    async fn handle_send(&mut self) -> Transition<Sent> {
+        Transition::to(Sent)
+    }
+
+    #[on(state = Sent, event = Pay)]
+    async fn handle_pay(&mut self) -> Transition<Paid> {
+        Transition::to(Paid)
+    }
+}
+
+#[tokio::test]
+async fn test_old_snapshot_is_migrated_and_resumed() {
+    let stored = r#"{"version":0,"state":"Billed","context":{"total":120}}"#;
+    let raw: Snapshot<String, serde_json::Value> = serde_json::from_str(stored).unwrap();
+    let snapshot: Snapshot<InvoiceFsmState, Invoice> = raw.migrate().unwrap();
+
+    assert_eq!(snapshot.version, Invoice::VERSION);
+    assert_eq!(snapshot.state, InvoiceFsmState::Sent);
+    assert_eq!(
+        snapshot.context,
+        Invoice {
+            amount: 120,
+            currency: "EUR".into()
+        }
+    );
+
+    let (handle, task) = InvoiceFsm::spawn_from(snapshot);
+    assert_eq!(handle.current_state(), InvoiceFsmState::Sent);
+    handle.send(InvoiceFsmEvent::Pay).await.unwrap();
+    handle.wait_for_state(InvoiceFsmState::Paid).await.unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap().amount, 120);
+}
+
+#[test]
+fn test_current_snapshot_round_trips() {
+    let snapshot = Snapshot::new(
+        Invoice::VERSION,
+        InvoiceFsmState::Paid,
+        Invoice {
+            amount: 5,
+            currency: "USD".into(),
+        },
+    );
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let raw: Snapshot<String, serde_json::Value> = serde_json::from_str(&json).unwrap();
+    assert_eq!(raw.migrate::<InvoiceFsmState, Invoice>().unwrap(), snapshot);
+}
+
+#[test]
+fn test_migration_errors() {
+    let newer = Snapshot::new(2, "Draft".to_string(), serde_json::json!({}));
+    assert!(matches!(
+        newer.migrate::<InvoiceFsmState, Invoice>(),
+        Err(MigrationError::UnsupportedVersion {
+            found: 2,
+            current: 1
+        })
+    ));
+
+    let unknown_state = Snapshot::new(
+        1,
+        "Void".to_string(),
+        serde_json::json!({"amount": 1, "currency": "EUR"}),
+    );
+    assert!(matches!(
+        unknown_state.migrate::<InvoiceFsmState, Invoice>(),
+        Err(MigrationError::State(_))
+    ));
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub amount: u64,
+    pub currency: String,
+}
+
+#[fsm(initial = Open, serde)]
+impl LedgerFsm {
+    type Context = Vec<Deposit>;
+
+    #[on(state = Open, event = Deposit)]
+    async fn handle_deposit(&mut self, deposit: Deposit) -> Transition<Open> {
+        self.context.push(deposit);
+        Transition::to(Open)
+    }
+
+    #[on(state = Open, event = Close)]
+    async fn handle_close(&mut self) -> Transition<Closed> {
+        Transition::to(Closed)
+    }
+}
+
+/// v0 called `Deposit` `Credit`, with the amount as its payload; v1 added the
+/// currency.
+impl UpcastEvent for LedgerFsmEvent {
+    const VERSION: u32 = 1;
+    type Raw = serde_json::Value;
+
+    fn upcast(entry: &mut JournalEntry<Self::Raw>) -> Result<(), MigrationError> {
+        if entry.version == 0
+            && let Some(amount) = entry.event.get("Credit")
+        {
+            entry.event = serde_json::json!({
+                "Deposit": { "amount": amount, "currency": "EUR" }
+            });
+        }
+        Ok(())
+    }
+
+    fn from_raw(raw: Self::Raw) -> Result<Self, MigrationError> {
+        serde_json::from_value(raw).map_err(MigrationError::custom)
+    }
+}
+
+#[tokio::test]
+async fn test_old_journal_is_upcast_and_replayed() {
+    let current = JournalEntry::new(
+        LedgerFsmEvent::VERSION,
+        LedgerFsmEvent::Deposit(Deposit {
+            amount: 7,
+            currency: "USD".into(),
+        }),
+    );
+    let journal = [
+        r#"{"version":0,"event":{"Credit":120}}"#.to_string(),
+        serde_json::to_string(&current).unwrap(),
+        r#"{"version":0,"event":"Close"}"#.to_string(),
+    ];
+
+    let mut core = LedgerFsm::builder(Vec::new()).core();
+    for line in &journal {
+        let raw: JournalEntry<serde_json::Value> = serde_json::from_str(line).unwrap();
+        core.process(raw.upcast().unwrap()).await.unwrap();
+    }
+
+    assert_eq!(core.state(), LedgerFsmState::Closed);
+    assert_eq!(
+        core.into_context(),
+        [
+            Deposit {
+                amount: 120,
+                currency: "EUR".into()
+            },
+            Deposit {
+                amount: 7,
+                currency: "USD".into()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_upcast_errors() {
+    let newer = JournalEntry::new(2, serde_json::json!("Close"));
+    assert!(matches!(
+        newer.upcast::<LedgerFsmEvent>(),
+        Err(MigrationError::UnsupportedVersion {
+            found: 2,
+            current: 1
+        })
+    ));
+
+    let unknown_event = JournalEntry::new(1, serde_json::json!({"Credit": 5}));
+    assert!(matches!(
+        unknown_event.upcast::<LedgerFsmEvent>(),
+        Err(MigrationError::Custom(_))
+    ));
+}
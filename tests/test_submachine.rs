@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use tokio_fsm::{Transition, fsm};
+
+#[fsm(initial = Packing)]
+impl ShippingFsm {
+    type Context = u32;
+
+    #[on(state = Packing, event = Pack)]
+    async fn handle_pack(&mut self) -> Transition<InTransit> {
+        Transition::to(InTransit)
+    }
+
+    #[on(state = InTransit, event = Deliver)]
+    async fn handle_deliver(&mut self) -> Transition<Delivered> {
+        Transition::to(Delivered)
+    }
+
+    #[on(state = InTransit, event = Drop)]
+    async fn handle_drop(&mut self) -> Transition<Lost> {
+        Transition::to(Lost)
+    }
+}
+
+#[fsm(initial = Cart)]
+impl OrderFsm {
+    type Context = Vec<ShippingFsmState>;
+
+    #[on(state = Cart, event = Checkout)]
+    #[on(state = Returned, event = Checkout)]
+    async fn handle_checkout(&mut self) -> Transition<Shipping> {
+        Transition::to(Shipping)
+    }
+
+    #[on(state = Shipping, event = Cancel)]
+    async fn handle_cancel(&mut self) -> Transition<Cart> {
+        Transition::to(Cart)
+    }
+
+    #[submachine(state = Shipping, fsm = ShippingFsm)]
+    async fn handle_shipped(
+        &mut self,
+        outcome: ShippingFsmState,
+    ) -> Result<Transition<Complete>, Transition<Returned>> {
+        self.context.push(outcome);
+        match outcome {
+            ShippingFsmState::Delivered => Ok(Transition::to(Complete)),
+            _ => Err(Transition::to(Returned)),
+        }
+    }
+}
+
+/// Lets spawned tasks run until they are all idle. Time is paused in the
+/// tests using it, so the sleep only completes once nothing else can run.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+}
+
+#[tokio::test]
+async fn test_child_outcome_maps_to_parent_transition() {
+    let (handle, task) = OrderFsm::spawn(Vec::new());
+
+    handle.send(OrderFsmEvent::Checkout).await.unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Pack))
+        .await
+        .unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Drop))
+        .await
+        .unwrap();
+    handle
+        .wait_for_state(OrderFsmState::Returned)
+        .await
+        .unwrap();
+
+    // Re-entering the state starts a fresh child.
+    handle.send(OrderFsmEvent::Checkout).await.unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Pack))
+        .await
+        .unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Deliver))
+        .await
+        .unwrap();
+    handle
+        .wait_for_state(OrderFsmState::Complete)
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(
+        task.await.unwrap(),
+        [ShippingFsmState::Lost, ShippingFsmState::Delivered]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_leaving_state_stops_child() {
+    let (handle, task) = OrderFsm::spawn(Vec::new());
+
+    handle.send(OrderFsmEvent::Checkout).await.unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Pack))
+        .await
+        .unwrap();
+    handle.send(OrderFsmEvent::Cancel).await.unwrap();
+    // Ignored outside `Shipping`.
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Deliver))
+        .await
+        .unwrap();
+    settle().await;
+    assert_eq!(handle.current_state(), OrderFsmState::Cart);
+
+    handle.shutdown_graceful();
+    assert!(task.await.unwrap().is_empty());
+}
+
+#[test]
+fn test_submachine_events_and_terminal_states() {
+    assert!(ShippingFsmState::Delivered.is_terminal());
+    assert!(!ShippingFsmState::InTransit.is_terminal());
+    assert_eq!(
+        OrderFsmState::Shipping.valid_events(),
+        ["Cancel", "ShippingDone", "Shipping"]
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn test_child_starts_while_draining_after_last_handle_drop() {
+    use std::sync::{Arc, Mutex};
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || Captured(writer.clone()))
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (handle, task) = OrderFsm::spawn(Vec::new());
+
+    // Both events are still queued when the last handle goes away; the
+    // child must be running by the time the second one is forwarded.
+    handle.send(OrderFsmEvent::Checkout).await.unwrap();
+    handle
+        .send(OrderFsmEvent::Shipping(ShippingFsmEvent::Pack))
+        .await
+        .unwrap();
+    drop(handle);
+    task.await.unwrap();
+
+    let lines = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+    assert!(!lines.contains("event dropped"), "{lines}");
+}
+
+/// Collects formatted log lines in memory.
+#[cfg(feature = "tracing")]
+struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(feature = "tracing")]
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use tokio_fsm::fsm_table;
+
+#[derive(Debug, Default)]
+pub struct Order {
+    checks: u32,
+    paid: u64,
+}
+
+async fn validate(order: &mut Order) {
+    order.checks += 1;
+}
+
+async fn charge(order: &mut Order, amount: u64) {
+    tokio::task::yield_now().await;
+    order.paid += amount;
+}
+
+fsm_table! {
+    OrderFsm {
+        type Context = Order;
+
+        Created + Validate [validate] => Validated,
+        Validated + Pay(u64) [charge] => Paid,
+        Validated + Cancel => Cancelled,
+        Paid + Ship => Shipped,
+    }
+}
+
+#[tokio::test]
+async fn test_table_fsm_runs_actions() {
+    let (handle, task) = OrderFsm::spawn(Order::default());
+    assert_eq!(handle.current_state(), OrderFsmState::Created);
+
+    handle.send(OrderFsmEvent::Validate).await.unwrap();
+    // Not in the table for `Validated`: ignored.
+    handle.send(OrderFsmEvent::Validate).await.unwrap();
+    handle.send(OrderFsmEvent::Pay(30)).await.unwrap();
+    handle.send(OrderFsmEvent::Ship).await.unwrap();
+    handle.wait_for_state(OrderFsmState::Shipped).await.unwrap();
+
+    handle.shutdown_graceful();
+    let order = task.await.unwrap();
+    assert_eq!(order.checks, 1);
+    assert_eq!(order.paid, 30);
+}
+
+#[test]
+fn test_table_fsm_structure() {
+    assert_eq!(OrderFsmState::Validated.valid_events(), &["Pay", "Cancel"]);
+    assert!(OrderFsm::mermaid().contains("Validated --> Cancelled: Cancel"));
+    assert_eq!(OrderFsm::transitions().len(), 4);
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "test-util")]
+
+use std::time::Duration;
+
+use tokio_fsm::{Transition, fsm, test_util::FsmTester};
+
+#[derive(Debug, Clone, Default)]
+pub struct Order {
+    pub reminders: u32,
+}
+
+#[fsm(initial = Open)]
+impl OrderFsm {
+    type Context = Order;
+
+    #[on(state = Open, event = Pay)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_pay(&mut self) -> Transition<Paid> {
+        Transition::to(Paid)
+    }
+
+    #[on(state = Paid, event = Remind)]
+    #[state_timeout(duration = "30s")]
+    async fn handle_remind(&mut self) -> Transition<Paid> {
+        self.context.reminders += 1;
+        Transition::to(Paid)
+    }
+
+    #[on(state = Paid, event = Ship)]
+    async fn handle_ship(&mut self) -> Transition<Shipped> {
+        Transition::to(Shipped)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Expired> {
+        Transition::to(Expired)
+    }
+}
+
+#[tokio::test]
+async fn test_expect_transition_and_timeout() {
+    let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+    fsm.expect_transition(OrderFsmState::Open, OrderFsmEvent::Pay, OrderFsmState::Paid)
+        .await;
+
+    fsm.advance(Duration::from_secs(20)).await;
+    fsm.assert_state(OrderFsmState::Paid);
+    fsm.expect_transition(
+        OrderFsmState::Paid,
+        OrderFsmEvent::Remind,
+        OrderFsmState::Paid,
+    )
+    .await;
+
+    // The reminder re-armed the timeout.
+    fsm.advance(Duration::from_secs(20)).await;
+    fsm.assert_state(OrderFsmState::Paid);
+    fsm.advance(Duration::from_secs(10)).await;
+    fsm.assert_state(OrderFsmState::Expired);
+
+    let reminders = fsm.handle().inspect(|order| order.reminders).await.unwrap();
+    assert_eq!(reminders, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_works_when_time_is_already_paused() {
+    let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+    fsm.expect_transition(OrderFsmState::Open, OrderFsmEvent::Pay, OrderFsmState::Paid)
+        .await;
+    fsm.expect_transition(
+        OrderFsmState::Paid,
+        OrderFsmEvent::Ship,
+        OrderFsmState::Shipped,
+    )
+    .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "unexpected transition to Expired")]
+async fn test_unasserted_transition_fails_fast() {
+    let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+    fsm.expect_transition(OrderFsmState::Open, OrderFsmEvent::Pay, OrderFsmState::Paid)
+        .await;
+    fsm.advance(Duration::from_secs(30)).await;
+    fsm.expect_transition(
+        OrderFsmState::Expired,
+        OrderFsmEvent::Ship,
+        OrderFsmState::Shipped,
+    )
+    .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "event caused no transition from Open")]
+async fn test_ignored_event_fails() {
+    let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+    fsm.expect_transition(
+        OrderFsmState::Open,
+        OrderFsmEvent::Ship,
+        OrderFsmState::Shipped,
+    )
+    .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "expected Open -> Shipped, got Open -> Paid")]
+async fn test_wrong_target_fails() {
+    let mut fsm = FsmTester::<OrderFsm>::new(Order::default());
+    fsm.expect_transition(
+        OrderFsmState::Open,
+        OrderFsmEvent::Pay,
+        OrderFsmState::Shipped,
+    )
+    .await;
+}
@@ -0,0 +1,114 @@
+#![cfg(all(feature = "test-util", feature = "serde"))]
+
+use std::time::Duration;
+
+use tokio_fsm::{
+    Transition, fsm,
+    test_util::{ReplayError, Trace, TraceRecorder, replay},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Cart {
+    pub items: u32,
+}
+
+#[fsm(initial = Open, serde)]
+impl CartFsm {
+    type Context = Cart;
+
+    #[on(state = Open, event = Add)]
+    async fn handle_add(&mut self, count: u32) -> Transition<Open> {
+        self.context.items += count;
+        Transition::to(Open)
+    }
+
+    #[on(state = Open, event = Checkout)]
+    #[state_timeout(duration = "10s")]
+    async fn handle_checkout(&mut self) -> Transition<Pending> {
+        Transition::to(Pending)
+    }
+
+    #[on(state = Pending, event = Pay)]
+    async fn handle_pay(&mut self) -> Transition<Paid> {
+        Transition::to(Paid)
+    }
+
+    #[on(state = Abandoned, event = Reopen)]
+    async fn handle_reopen(&mut self) -> Transition<Open> {
+        Transition::to(Open)
+    }
+
+    #[on_timeout]
+    async fn handle_timeout(&mut self) -> Transition<Abandoned> {
+        Transition::to(Abandoned)
+    }
+}
+
+async fn record() -> Trace<CartFsmState, CartFsmEvent> {
+    let (handle, _task) = CartFsm::spawn(Cart::default());
+    let mut recorder = TraceRecorder::new(handle);
+    recorder.send(CartFsmEvent::Add(2)).await.unwrap();
+    recorder.send(CartFsmEvent::Checkout).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(15)).await;
+    recorder.send(CartFsmEvent::Reopen).await.unwrap();
+    recorder.send(CartFsmEvent::Checkout).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    recorder.send(CartFsmEvent::Pay).await.unwrap();
+    recorder.finish().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_records_events_and_timeouts() {
+    let trace = record().await;
+    let steps: Vec<_> = trace
+        .steps
+        .iter()
+        .map(|step| (step.at.as_secs(), step.event.as_str(), step.from, step.to))
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            (0, "Add", CartFsmState::Open, CartFsmState::Open),
+            (0, "Checkout", CartFsmState::Open, CartFsmState::Pending),
+            // The checkout timed out in between.
+            (15, "Reopen", CartFsmState::Abandoned, CartFsmState::Open),
+            (15, "Checkout", CartFsmState::Open, CartFsmState::Pending),
+            (20, "Pay", CartFsmState::Pending, CartFsmState::Paid),
+        ]
+    );
+    assert_eq!(trace.duration, Duration::from_secs(20));
+    assert_eq!(trace.final_state, CartFsmState::Paid);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_replays_a_serialized_trace() {
+    let json = serde_json::to_string(&record().await).unwrap();
+    let trace: Trace<CartFsmState, CartFsmEvent> = serde_json::from_str(&json).unwrap();
+
+    let (handle, _task) = CartFsm::spawn(Cart::default());
+    replay(&handle, &trace).await.unwrap();
+    let items = handle.inspect(|cart| cart.items).await.unwrap();
+    assert_eq!(items, 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_replay_reports_divergence() {
+    let mut trace = record().await;
+    // Delay the payment past the checkout timeout.
+    trace.steps[4].at = Duration::from_secs(30);
+
+    let (handle, _task) = CartFsm::spawn(Cart::default());
+    let err = replay(&handle, &trace).await.unwrap_err();
+    assert_eq!(
+        err,
+        ReplayError::Before {
+            step: 4,
+            expected: CartFsmState::Pending,
+            actual: CartFsmState::Abandoned,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "expected Pending before step 4, got Abandoned"
+    );
+}
@@ -0,0 +1,128 @@
+#![cfg(feature = "tracing")]
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_fsm::{DropReason, Transition, fsm};
+
+#[fsm(initial = Closed, channel_size = 1)]
+impl DoorFsm {
+    #[on(state = Closed, event = Open)]
+    async fn handle_open(&mut self) -> Transition<Opened> {
+        Transition::to(Opened)
+    }
+}
+
+/// Stand-in for a wire protocol enum defined in another crate.
+#[derive(Debug)]
+pub enum Wire {
+    Open,
+    Ping,
+}
+
+#[fsm(initial = Lowered)]
+#[external_event(from = Wire, map(Open = Open))]
+impl GateFsm {
+    #[on(state = Lowered, event = Open)]
+    async fn handle_open(&mut self) -> Transition<Raised> {
+        Transition::to(Raised)
+    }
+}
+
+/// Collects formatted log lines in memory.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_dropped_events_are_traced_with_reason() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (handle, task) = DoorFsm::spawn();
+
+    // The single-threaded test runtime hasn't polled the FSM yet, so the
+    // first event fills the queue.
+    handle.try_send(DoorFsmEvent::Open).unwrap();
+    assert!(matches!(
+        handle.try_send(DoorFsmEvent::Open),
+        Err(TrySendError::Full(_))
+    ));
+
+    handle.wait_for_state(DoorFsmState::Opened).await.unwrap();
+    handle.send(DoorFsmEvent::Open).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+    assert!(handle.send(DoorFsmEvent::Open).await.is_err());
+
+    let lines = captured.lines();
+    let reasons = [
+        DropReason::QueueFull,
+        DropReason::NoHandler,
+        DropReason::ShuttingDown,
+    ];
+    assert_eq!(lines.len(), reasons.len(), "{lines:#?}");
+    for (line, reason) in lines.iter().zip(reasons) {
+        assert!(line.contains("event dropped"), "{line}");
+        assert!(line.contains("fsm=\"DoorFsm\""), "{line}");
+        assert!(line.contains("event=\"Open\""), "{line}");
+        assert!(line.contains(&format!("reason=\"{reason}\"")), "{line}");
+    }
+}
+
+#[tokio::test]
+async fn test_unmapped_external_messages_are_traced() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let (handle, task) = GateFsm::spawn();
+    handle.send_external(Wire::Ping).await.unwrap();
+    handle.shutdown_graceful();
+    task.await.unwrap();
+
+    let lines = captured.lines();
+    assert_eq!(lines.len(), 1, "{lines:#?}");
+    assert!(lines[0].contains("fsm=\"GateFsm\""), "{}", lines[0]);
+    assert!(lines[0].contains("Wire"), "{}", lines[0]);
+    assert!(
+        lines[0].contains(&format!("reason=\"{}\"", DropReason::NoHandler)),
+        "{}",
+        lines[0]
+    );
+}
@@ -0,0 +1,947 @@
+use tokio_fsm::{Effect, Transition, fsm};
+
+#[derive(Debug, Default)]
+pub struct PeerContext {
+    pub pings: usize,
+}
+
+#[fsm(initial = Waiting)]
+impl PeerFsm {
+    type Context = PeerContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Waiting, event = Ping)]
+    async fn handle_ping(&mut self) -> Transition<Pinged> {
+        self.context.pings += 1;
+        Transition::to(Pinged)
+    }
+}
+
+pub struct EmitterContext {
+    pub peer: PeerFsmHandle,
+}
+
+#[fsm(initial = Idle)]
+impl EmitterFsm {
+    type Context = EmitterContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Idle, event = Notify)]
+    async fn handle_notify(&mut self) -> Transition<Notified> {
+        Transition::to(Notified)
+            .with_effects(vec![Effect::send(&self.context.peer, PeerFsmEvent::Ping)])
+    }
+}
+
+#[tokio::test]
+async fn test_effects_are_delivered_after_commit() {
+    let (peer, peer_task) = PeerFsm::spawn(PeerContext::default());
+    let (emitter, emitter_task) = EmitterFsm::spawn(EmitterContext { peer: peer.clone() });
+
+    emitter.send(EmitterFsmEvent::Notify).await.unwrap();
+    emitter
+        .wait_for_state(EmitterFsmState::Notified)
+        .await
+        .unwrap();
+    peer.wait_for_state(PeerFsmState::Pinged).await.unwrap();
+
+    emitter.shutdown_graceful();
+    emitter_task.await.unwrap();
+    peer.shutdown_graceful();
+    assert_eq!(peer_task.await.unwrap().pings, 1);
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineContext {
+    pub steps: Vec<&'static str>,
+}
+
+#[fsm(initial = Created)]
+impl PipelineFsm {
+    type Context = PipelineContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Created, event = Validate)]
+    async fn handle_validate(&mut self) -> Transition<Validated> {
+        self.context.steps.push("validate");
+        Transition::to(Validated).then(PipelineFsmEvent::Charge)
+    }
+
+    #[on(state = Validated, event = Charge)]
+    async fn handle_charge(&mut self) -> Transition<Charged> {
+        self.context.steps.push("charge");
+        Transition::to(Charged).then(PipelineFsmEvent::Ship)
+    }
+
+    #[on(state = Charged, event = Ship)]
+    async fn handle_ship(&mut self) -> Transition<Shipped> {
+        self.context.steps.push("ship");
+        Transition::to(Shipped)
+    }
+}
+
+#[tokio::test]
+async fn test_follow_up_events_drive_pipeline() {
+    let (handle, task) = PipelineFsm::spawn(PipelineContext::default());
+
+    handle.send(PipelineFsmEvent::Validate).await.unwrap();
+    handle
+        .wait_for_state(PipelineFsmState::Shipped)
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    let context = task.await.unwrap();
+    assert_eq!(context.steps, vec!["validate", "charge", "ship"]);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    SendEmail(String),
+}
+
+#[fsm(initial = Pending)]
+impl SignupFsm {
+    type Context = ();
+    type Error = std::convert::Infallible;
+    type Output = Command;
+
+    #[on(state = Pending, event = Register)]
+    async fn handle_register(&mut self, email: String) -> Transition<Registered> {
+        self.emit(Command::SendEmail(email)).await.unwrap();
+        Transition::to(Registered)
+    }
+}
+
+#[tokio::test]
+async fn test_output_commands_reach_receiver() {
+    let (handle, task, mut commands) = SignupFsm::spawn(());
+
+    handle
+        .send(SignupFsmEvent::Register("a@example.com".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        commands.recv().await,
+        Some(Command::SendEmail("a@example.com".to_string()))
+    );
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[derive(Debug, Default)]
+pub struct PlayerContext {
+    pub stopped_by: Vec<&'static str>,
+}
+
+#[fsm(initial = Playing)]
+impl PlayerFsm {
+    type Context = PlayerContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Playing, event = Pause | Suspend)]
+    async fn handle_stop(&mut self) -> Transition<Stopped> {
+        let event = self.current_event().expect("called from an event handler");
+        self.context.stopped_by.push(event);
+        Transition::to(Stopped)
+    }
+
+    #[on(state = Stopped, event = Resume)]
+    async fn handle_resume(&mut self) -> Transition<Playing> {
+        Transition::to(Playing)
+    }
+}
+
+#[tokio::test]
+async fn test_multi_event_handler_reports_trigger() {
+    let (handle, task) = PlayerFsm::spawn(PlayerContext::default());
+
+    handle.send(PlayerFsmEvent::Suspend).await.unwrap();
+    handle.send(PlayerFsmEvent::Resume).await.unwrap();
+    handle.send(PlayerFsmEvent::Pause).await.unwrap();
+    handle
+        .wait_for_state(PlayerFsmState::Stopped)
+        .await
+        .unwrap();
+
+    assert!(PlayerFsmState::Playing.valid_events().contains(&"Pause"));
+    assert!(PlayerFsmState::Playing.valid_events().contains(&"Suspend"));
+
+    handle.shutdown_graceful();
+    let context = task.await.unwrap();
+    assert_eq!(context.stopped_by, vec!["Suspend", "Pause"]);
+}
+
+#[derive(Debug, Default)]
+pub struct LedgerContext {
+    pub journal: Vec<&'static str>,
+    pub disk_full: bool,
+}
+
+#[fsm(initial = Open)]
+impl LedgerFsm {
+    type Context = LedgerContext;
+
+    #[on(state = Open, event = Post)]
+    async fn handle_post(&mut self) -> Transition<Posted> {
+        Transition::to(Posted)
+    }
+
+    #[persist(on_error = Corrupted)]
+    async fn write_ahead(&mut self) -> Result<(), std::io::Error> {
+        if self.context.disk_full {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.context.journal.push(self.state.as_str());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_persist_hook_runs_after_each_transition() {
+    let (handle, task) = LedgerFsm::spawn(LedgerContext::default());
+
+    handle.send(LedgerFsmEvent::Post).await.unwrap();
+    handle.wait_for_state(LedgerFsmState::Posted).await.unwrap();
+
+    handle.shutdown_graceful();
+    assert_eq!(task.await.unwrap().journal, ["Posted"]);
+}
+
+#[tokio::test]
+async fn test_persist_failure_routes_to_error_state() {
+    let context = LedgerContext {
+        disk_full: true,
+        ..Default::default()
+    };
+    let (handle, task) = LedgerFsm::spawn(context);
+
+    handle.send(LedgerFsmEvent::Post).await.unwrap();
+    handle
+        .wait_for_state(LedgerFsmState::Corrupted)
+        .await
+        .unwrap();
+    let change = handle.last_change();
+    assert_eq!(change.from, LedgerFsmState::Open);
+    assert_eq!(change.cause, tokio_fsm::TransitionCause::PersistFailed);
+
+    handle.shutdown_graceful();
+    assert!(task.await.unwrap().journal.is_empty());
+}
+
+#[derive(Debug, Default)]
+pub struct ScreeningContext {
+    pub score: u32,
+}
+
+#[fsm(initial = Queued)]
+impl ScreeningFsm {
+    type Context = ScreeningContext;
+
+    #[on(state = Queued, event = Check)]
+    async fn handle_check(&mut self, score: u32) -> Transition<Screened> {
+        self.context.score = score;
+        Transition::to(Screened)
+    }
+
+    #[auto(state = Screened)]
+    async fn decide(&mut self) -> Result<Transition<Approved>, Transition<Rejected>> {
+        if self.context.score >= 50 {
+            Ok(Transition::to(Approved))
+        } else {
+            Err(Transition::to(Rejected))
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_auto_handler_runs_on_entry() {
+    use tokio_fsm::TransitionCause;
+
+    for (score, outcome) in [
+        (80, ScreeningFsmState::Approved),
+        (10, ScreeningFsmState::Rejected),
+    ] {
+        let (handle, task) = ScreeningFsm::spawn(ScreeningContext::default());
+        let mut changes = handle.subscribe();
+
+        handle.send(ScreeningFsmEvent::Check(score)).await.unwrap();
+        let change = *changes.wait_for(|change| change.seq == 2).await.unwrap();
+        assert_eq!(change.from, ScreeningFsmState::Screened);
+        assert_eq!(change.to, outcome);
+        assert_eq!(change.cause, TransitionCause::Auto);
+
+        handle.shutdown_graceful();
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_auto_handler_runs_while_draining_after_last_handle_drop() {
+    let (handle, task) = ScreeningFsm::spawn(ScreeningContext::default());
+    let changes = handle.subscribe();
+
+    // The FSM hasn't been polled yet, so the event is still queued when the
+    // last handle goes away.
+    handle.send(ScreeningFsmEvent::Check(80)).await.unwrap();
+    drop(handle);
+
+    assert_eq!(task.await.unwrap().score, 80);
+    assert_eq!(changes.borrow().to, ScreeningFsmState::Approved);
+}
+
+#[derive(Debug, Default)]
+pub struct GatewayContext {
+    pub charged: bool,
+}
+
+#[fsm(initial = Authorizing)]
+impl GatewayFsm {
+    type Context = GatewayContext;
+
+    #[on(state = Authorizing, event = Charge)]
+    async fn handle_charge(&mut self) -> Transition<Settled> {
+        // A gateway that never answers.
+        std::future::pending::<()>().await;
+        self.context.charged = true;
+        Transition::to(Settled)
+    }
+
+    #[on(state = Authorizing, event = Abort)]
+    #[preempt]
+    async fn handle_abort(&mut self) -> Transition<Aborted> {
+        Transition::to(Aborted)
+    }
+}
+
+#[tokio::test]
+async fn test_preempting_event_cancels_running_handler() {
+    let (handle, task) = GatewayFsm::spawn(GatewayContext::default());
+
+    handle.send(GatewayFsmEvent::Charge).await.unwrap();
+    tokio::task::yield_now().await;
+    // `call` answers once the preempting handler has run.
+    let state = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        handle.call(GatewayFsmEvent::Abort),
+    )
+    .await
+    .expect("Abort should preempt the stuck charge")
+    .unwrap();
+    assert_eq!(state, GatewayFsmState::Aborted);
+    let change = handle.last_change();
+    assert_eq!(change.from, GatewayFsmState::Authorizing);
+    assert_eq!(change.cause, tokio_fsm::TransitionCause::Event("Abort"));
+
+    handle.shutdown_graceful();
+    assert!(!task.await.unwrap().charged);
+}
+
+#[tokio::test]
+async fn test_call_of_preempted_event_is_discarded() {
+    let (handle, task) = GatewayFsm::spawn(GatewayContext::default());
+
+    let charge = tokio::spawn({
+        let handle = handle.clone();
+        async move { handle.call(GatewayFsmEvent::Charge).await }
+    });
+    // Let the charge get stuck in its handler.
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    handle.call(GatewayFsmEvent::Abort).await.unwrap();
+    assert!(matches!(
+        charge.await.unwrap(),
+        Err(tokio_fsm::CallError::Discarded(
+            tokio_fsm::DropReason::Preempted
+        ))
+    ));
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_watchdog_event_preempts_hung_handler() {
+    let (handle, task) = GatewayFsm::builder(GatewayContext::default())
+        .watchdog(std::time::Duration::from_millis(20))
+        .watchdog_event(GatewayFsmEvent::Abort)
+        .spawn();
+
+    handle.send(GatewayFsmEvent::Charge).await.unwrap();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        handle.wait_for_state(GatewayFsmState::Aborted),
+    )
+    .await
+    .expect("the watchdog should abort the stuck charge")
+    .unwrap();
+
+    handle.shutdown_graceful();
+    assert!(!task.await.unwrap().charged);
+}
+
+#[tokio::test]
+async fn test_watchdog_budget_runs_on_the_fsm_clock() {
+    let clock = tokio_fsm::ManualClock::new();
+    let (handle, task) = GatewayFsm::builder(GatewayContext::default())
+        .clock(clock.clone())
+        .watchdog(std::time::Duration::from_secs(3600))
+        .watchdog_event(GatewayFsmEvent::Abort)
+        .spawn();
+
+    handle.send(GatewayFsmEvent::Charge).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    clock.advance(std::time::Duration::from_secs(3600));
+    tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        handle.wait_for_state(GatewayFsmState::Aborted),
+    )
+    .await
+    .expect("advancing the clock past the budget should fire the watchdog")
+    .unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+static WORK_DROPPED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+struct WorkGuard;
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        WORK_DROPPED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FetchContext {
+    pub body: Option<String>,
+    pub error: Option<String>,
+}
+
+#[fsm(initial = Ready)]
+impl FetchFsm {
+    type Context = FetchContext;
+
+    #[on(state = Ready, event = Fetch)]
+    async fn handle_fetch(&mut self, url: &'static str) -> Transition<Fetching> {
+        self.spawn_work(
+            async move {
+                let _guard = WorkGuard;
+                tokio::task::yield_now().await;
+                if url == "https://slow" {
+                    std::future::pending::<()>().await;
+                }
+                if url.starts_with("https://") {
+                    Ok(format!("body of {url}"))
+                } else {
+                    Err(format!("refusing {url}"))
+                }
+            },
+            FetchFsmEvent::Fetched,
+            FetchFsmEvent::FetchFailed,
+        );
+        Transition::to(Fetching)
+    }
+
+    #[on(state = Fetching, event = Fetched)]
+    async fn handle_fetched(&mut self, body: String) -> Transition<Ready> {
+        self.context.body = Some(body);
+        Transition::to(Ready)
+    }
+
+    #[on(state = Fetching, event = FetchFailed)]
+    async fn handle_failed(&mut self, error: String) -> Transition<Ready> {
+        self.context.error = Some(error);
+        Transition::to(Ready)
+    }
+}
+
+#[tokio::test]
+async fn test_spawn_work_delivers_completion_events() {
+    let (handle, task) = FetchFsm::spawn(FetchContext::default());
+    let mut changes = handle.subscribe();
+
+    handle
+        .send(FetchFsmEvent::Fetch("https://example.com"))
+        .await
+        .unwrap();
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+    handle
+        .send(FetchFsmEvent::Fetch("ftp://example.com"))
+        .await
+        .unwrap();
+    changes.wait_for(|change| change.seq == 4).await.unwrap();
+    assert_eq!(handle.current_state(), FetchFsmState::Ready);
+
+    handle.shutdown_graceful();
+    let context = task.await.unwrap();
+    assert_eq!(context.body.as_deref(), Some("body of https://example.com"));
+    assert_eq!(context.error.as_deref(), Some("refusing ftp://example.com"));
+}
+
+#[tokio::test]
+async fn test_spawn_work_is_aborted_on_shutdown() {
+    let (handle, task) = FetchFsm::spawn(FetchContext::default());
+    let mut changes = handle.subscribe();
+
+    // Never completes; shutting down must abort it.
+    handle
+        .send(FetchFsmEvent::Fetch("https://slow"))
+        .await
+        .unwrap();
+    changes.wait_for(|change| change.seq == 1).await.unwrap();
+    tokio::task::yield_now().await;
+    let dropped = WORK_DROPPED.load(std::sync::atomic::Ordering::SeqCst);
+
+    handle.shutdown_immediate();
+    let context = task.await.unwrap();
+    assert!(context.body.is_none());
+    assert!(WORK_DROPPED.load(std::sync::atomic::Ordering::SeqCst) > dropped);
+}
+
+#[derive(Debug, Default)]
+pub struct WizardContext {
+    pub pages: Vec<&'static str>,
+}
+
+#[fsm(initial = Intro)]
+impl WizardFsm {
+    type Context = WizardContext;
+
+    #[on(state = Intro, event = Next)]
+    async fn handle_next(&mut self) -> Transition<Details> {
+        self.context.pages.push("intro");
+        Transition::to(Details)
+    }
+
+    #[on(state = Details, event = Finish)]
+    async fn handle_finish(&mut self) -> Transition<Finished> {
+        self.context.pages.push("details");
+        Transition::to(Finished)
+            .then(WizardFsmEvent::Restart)
+            .halt()
+    }
+
+    #[on(state = Finished, event = Restart)]
+    async fn handle_restart(&mut self) -> Transition<Intro> {
+        self.context.pages.push("restart");
+        Transition::to(Intro)
+    }
+}
+
+#[tokio::test]
+async fn test_halt_stops_the_fsm_from_a_handler() {
+    let (handle, task) = WizardFsm::spawn(WizardContext::default());
+    let mut changes = handle.subscribe();
+
+    handle.send(WizardFsmEvent::Next).await.unwrap();
+    handle.send(WizardFsmEvent::Finish).await.unwrap();
+    // Queued behind the halting event, so never handled.
+    handle.send(WizardFsmEvent::Restart).await.unwrap();
+
+    // No shutdown is requested: the task ends on its own.
+    let context = task.await.unwrap();
+    assert_eq!(context.pages, vec!["intro", "details"]);
+    assert_eq!(handle.current_state(), WizardFsmState::Finished);
+    assert_eq!(
+        changes.borrow_and_update().cause,
+        tokio_fsm::TransitionCause::Shutdown
+    );
+    assert!(handle.send(WizardFsmEvent::Next).await.is_err());
+}
+
+#[derive(Debug, Default)]
+pub struct BootContext {
+    pub unclean_shutdown: bool,
+}
+
+#[fsm(initial = Offline)]
+impl BootFsm {
+    type Context = BootContext;
+
+    #[on_start]
+    async fn boot(&mut self) -> Result<Transition<Offline>, Transition<Recovering>> {
+        if self.context.unclean_shutdown {
+            Err(Transition::to(Recovering))
+        } else {
+            Ok(Transition::to(Offline))
+        }
+    }
+
+    #[on(state = Offline, event = Begin)]
+    async fn handle_begin(&mut self) -> Transition<Running> {
+        Transition::to(Running)
+    }
+
+    #[on(state = Recovering, event = Recovered)]
+    async fn handle_recovered(&mut self) -> Transition<Offline> {
+        Transition::to(Offline)
+    }
+}
+
+#[tokio::test]
+async fn test_on_start_runs_before_first_event() {
+    use tokio_fsm::TransitionCause;
+
+    let (handle, task) = BootFsm::spawn(BootContext {
+        unclean_shutdown: true,
+    });
+    let mut changes = handle.subscribe();
+
+    // Already queued when the task starts, but handled in `Recovering`.
+    handle.send(BootFsmEvent::Begin).await.unwrap();
+    let change = *changes.wait_for(|change| change.seq == 1).await.unwrap();
+    assert_eq!(change.from, BootFsmState::Offline);
+    assert_eq!(change.to, BootFsmState::Recovering);
+    assert_eq!(change.cause, TransitionCause::Start);
+
+    handle.send(BootFsmEvent::Recovered).await.unwrap();
+    handle.wait_for_state(BootFsmState::Offline).await.unwrap();
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_on_start_can_stay_in_initial_state() {
+    let (handle, task) = BootFsm::spawn(BootContext::default());
+
+    handle.send(BootFsmEvent::Begin).await.unwrap();
+    handle.wait_for_state(BootFsmState::Running).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[fsm(initial = Triage)]
+impl TicketFsm {
+    #[on(state = Triage, event = Assess)]
+    #[targets(Urgent, Backlog)]
+    async fn handle_assess(&mut self, severity: u8) -> Transition<TicketFsmState> {
+        if severity > 3 {
+            Transition::to(TicketFsmState::Urgent)
+        } else {
+            Transition::to(TicketFsmState::Backlog)
+        }
+    }
+
+    #[on(state = Triage, event = Misroute)]
+    #[targets(Urgent)]
+    async fn handle_misroute(&mut self) -> Transition<TicketFsmState> {
+        Transition::to(TicketFsmState::Triage)
+    }
+
+    #[on(state = Urgent, event = Reopen)]
+    #[on(state = Backlog, event = Reopen)]
+    async fn handle_reopen(&mut self) -> Transition<TicketFsmState> {
+        Transition::to(TicketFsmState::Triage)
+    }
+}
+
+#[tokio::test]
+async fn test_dynamic_targets_are_chosen_at_runtime() {
+    let (handle, task) = TicketFsm::spawn();
+
+    handle.send(TicketFsmEvent::Assess(5)).await.unwrap();
+    handle.wait_for_state(TicketFsmState::Urgent).await.unwrap();
+    handle.send(TicketFsmEvent::Reopen).await.unwrap();
+    handle.wait_for_state(TicketFsmState::Triage).await.unwrap();
+    handle.send(TicketFsmEvent::Assess(1)).await.unwrap();
+    handle
+        .wait_for_state(TicketFsmState::Backlog)
+        .await
+        .unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+
+    // `#[targets]` narrows the edges; without it every state is a target.
+    let from_backlog: Vec<_> = TicketFsm::transitions()
+        .iter()
+        .filter(|(from, ..)| *from == TicketFsmState::Backlog)
+        .map(|(_, _, to)| *to)
+        .collect();
+    assert_eq!(from_backlog, TicketFsmState::ALL);
+    assert!(TicketFsm::transitions().contains(&(
+        TicketFsmState::Triage,
+        "Assess",
+        TicketFsmState::Backlog
+    )));
+}
+
+#[tokio::test]
+async fn test_dynamic_target_outside_targets_is_refused() {
+    let (handle, task) = TicketFsm::spawn();
+
+    // `Misroute` returns `Triage`, which its `#[targets]` doesn't list.
+    assert_eq!(
+        handle.call(TicketFsmEvent::Misroute).await.unwrap(),
+        TicketFsmState::Triage
+    );
+    assert_eq!(handle.last_change().seq, 0);
+
+    handle.send(TicketFsmEvent::Assess(5)).await.unwrap();
+    handle.wait_for_state(TicketFsmState::Urgent).await.unwrap();
+
+    handle.shutdown_graceful();
+    task.await.unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    price: u64,
+    valid_for: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct QuoteContext {
+    quote: Option<Quote>,
+    requests: u32,
+}
+
+#[fsm(initial = Requested)]
+impl QuoteFsm {
+    type Context = QuoteContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Requested, event = Price)]
+    async fn handle_price(&mut self, price: u64) -> Transition<Quoted> {
+        Transition::to_with(
+            Quoted,
+            Quote {
+                price,
+                valid_for: 30,
+            },
+        )
+    }
+
+    #[on(state = Quoted, event = Requote)]
+    async fn handle_requote(&mut self) -> Transition<Quoted> {
+        Transition::to(Quoted)
+    }
+
+    #[on(state = Quoted, event = Withdraw)]
+    async fn handle_withdraw(&mut self) -> Transition<Requested> {
+        Transition::to(Requested)
+    }
+
+    #[on_entry(state = Quoted)]
+    async fn enter_quoted(&mut self, quote: Quote) {
+        self.context.quote = Some(quote);
+    }
+
+    #[on_entry(state = Requested)]
+    async fn enter_requested(&mut self) {
+        self.context.quote = None;
+        self.context.requests += 1;
+    }
+}
+
+#[tokio::test]
+async fn test_on_entry_receives_transition_data() {
+    let (handle, task) = QuoteFsm::spawn(QuoteContext::default());
+    let mut changes = handle.subscribe();
+
+    handle.send(QuoteFsmEvent::Price(120)).await.unwrap();
+    // Entering without `to_with` leaves the value from the last one.
+    handle.send(QuoteFsmEvent::Requote).await.unwrap();
+    changes.wait_for(|change| change.seq == 2).await.unwrap();
+    let quote = handle
+        .inspect(|ctx: &QuoteContext| ctx.quote.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        quote,
+        Some(Quote {
+            price: 120,
+            valid_for: 30,
+        })
+    );
+
+    handle.send(QuoteFsmEvent::Withdraw).await.unwrap();
+    handle.shutdown_graceful();
+    let context = task.await.unwrap();
+    assert_eq!(context.quote, None);
+    assert_eq!(context.requests, 1);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decline {
+    code: u16,
+    retry_after: Option<std::time::Duration>,
+}
+
+#[derive(Debug, Default)]
+pub struct CardContext {
+    last_decline: Option<Decline>,
+}
+
+#[fsm(initial = Presented)]
+impl CardFsm {
+    type Context = CardContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Presented, event = Respond)]
+    async fn handle_respond(
+        &mut self,
+        code: u16,
+    ) -> Result<Transition<Captured>, Transition<Declined>> {
+        if code == 0 {
+            return Ok(Transition::to(Captured));
+        }
+        Err(Transition::to_with(
+            Declined,
+            Decline {
+                code,
+                retry_after: (code == 429).then(|| std::time::Duration::from_secs(5)),
+            },
+        ))
+    }
+
+    #[on(state = Declined, event = Retry)]
+    async fn handle_retry(&mut self) -> Transition<Presented> {
+        Transition::to(Presented)
+    }
+
+    #[on_entry(state = Declined)]
+    async fn enter_declined(&mut self, decline: Decline) {
+        self.context.last_decline = Some(decline);
+    }
+}
+
+#[tokio::test]
+async fn test_error_transition_carries_data_to_entry_hook() {
+    let (handle, task) = CardFsm::spawn(CardContext::default());
+
+    handle.send(CardFsmEvent::Respond(429)).await.unwrap();
+    handle.wait_for_state(CardFsmState::Declined).await.unwrap();
+    handle.shutdown_graceful();
+
+    let context = task.await.unwrap();
+    assert_eq!(
+        context.last_decline,
+        Some(Decline {
+            code: 429,
+            retry_after: Some(std::time::Duration::from_secs(5)),
+        })
+    );
+}
+
+/// A helper building the transition every jam ends in.
+pub struct JamReport {
+    attempts: u32,
+}
+
+impl From<JamReport> for Transition<Jammed> {
+    fn from(report: JamReport) -> Self {
+        let transition = Transition::to(Jammed);
+        if report.attempts > 2 {
+            transition.halt()
+        } else {
+            transition
+        }
+    }
+}
+
+#[fsm(initial = Shut)]
+impl DoorFsm {
+    type Context = u32;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Shut, event = Push)]
+    async fn handle_push(
+        &mut self,
+    ) -> Result<impl Into<Transition<Ajar>>, impl Into<Transition<Jammed>>> {
+        self.context += 1;
+        if self.context % 2 == 1 {
+            Ok(Ajar)
+        } else {
+            Err(JamReport {
+                attempts: self.context,
+            })
+        }
+    }
+
+    #[on(state = Ajar, event = Pull)]
+    #[on(state = Jammed, event = Pull)]
+    async fn handle_pull(&mut self) -> impl Into<Transition<Shut>> {
+        Shut
+    }
+}
+
+#[tokio::test]
+async fn test_handlers_return_anything_into_a_transition() {
+    let (handle, task) = DoorFsm::spawn(0);
+
+    handle.send(DoorFsmEvent::Push).await.unwrap();
+    handle.wait_for_state(DoorFsmState::Ajar).await.unwrap();
+    handle.send(DoorFsmEvent::Pull).await.unwrap();
+    handle.send(DoorFsmEvent::Push).await.unwrap();
+    handle.wait_for_state(DoorFsmState::Jammed).await.unwrap();
+    handle.send(DoorFsmEvent::Pull).await.unwrap();
+    handle.send(DoorFsmEvent::Push).await.unwrap();
+    handle.send(DoorFsmEvent::Pull).await.unwrap();
+    handle.send(DoorFsmEvent::Push).await.unwrap();
+
+    // The fourth push jams the door for good: the helper halts the FSM.
+    assert_eq!(task.await.unwrap(), 4);
+    assert_eq!(handle.current_state(), DoorFsmState::Jammed);
+}
+
+#[derive(Debug, Clone)]
+pub struct Blob {
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct ArchiveContext {
+    stored: usize,
+    tags: Vec<String>,
+}
+
+#[fsm(initial = Indexing)]
+impl ArchiveFsm {
+    type Context = ArchiveContext;
+    type Error = std::convert::Infallible;
+
+    #[on(state = Indexing, event = Ingest)]
+    async fn handle_ingest(&mut self, blob: &Blob) -> Transition<Indexing> {
+        tokio::task::yield_now().await;
+        self.context.stored += blob.bytes.len();
+        Transition::to(Indexing)
+    }
+
+    #[on(state = Indexing, event = Tag)]
+    async fn handle_tag(&mut self, blob: &Blob, label: String) -> Transition<Sealed> {
+        self.context.stored += blob.bytes.len();
+        self.context.tags.push(label);
+        Transition::to(Sealed)
+    }
+}
+
+#[tokio::test]
+async fn test_handlers_borrow_payloads() {
+    let (handle, task) = ArchiveFsm::spawn(ArchiveContext::default());
+
+    // The events own their payloads; the handlers only borrow them.
+    handle
+        .send(ArchiveFsmEvent::Ingest(Blob {
+            bytes: vec![0; 1024],
+        }))
+        .await
+        .unwrap();
+    handle
+        .tag(Blob { bytes: vec![0; 16] }, "cold".to_string())
+        .await
+        .unwrap();
+    handle
+        .wait_for_state(ArchiveFsmState::Sealed)
+        .await
+        .unwrap();
+    handle.shutdown_graceful();
+
+    let context = task.await.unwrap();
+    assert_eq!(context.stored, 1040);
+    assert_eq!(context.tags, ["cold"]);
+}
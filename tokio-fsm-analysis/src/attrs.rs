@@ -0,0 +1,251 @@
+//! Attribute parsing for FSM macro.
+
+use darling::{FromMeta, ast::NestedMeta};
+use syn::{
+    BinOp, Expr, Ident, LitStr, Meta, Path, Token,
+    parse::{Parse, ParseStream},
+};
+
+/// Arguments for the `#[fsm]` attribute.
+#[derive(Debug, FromMeta)]
+pub struct FsmArgs {
+    /// Initial state (required).
+    pub initial: Ident,
+
+    /// Channel size for event queue (default: 100).
+    #[darling(default = "default_channel_size")]
+    pub channel_size: usize,
+
+    /// Derive `Serialize`/`Deserialize` on the generated enums (requires the
+    /// `serde` feature of `tokio-fsm`).
+    #[darling(default)]
+    pub serde: bool,
+
+    /// Mark the generated State and Event enums `#[non_exhaustive]`.
+    #[darling(default)]
+    pub non_exhaustive: bool,
+
+    /// Integer representation of the generated State enum, e.g. `u8`.
+    #[darling(default)]
+    pub state_repr: Option<Ident>,
+
+    /// State entered when a handler panics, instead of the panic ending the
+    /// task.
+    #[darling(default)]
+    pub on_panic: Option<Ident>,
+
+    /// Restart policy for the run loop; only `on_error` is supported.
+    #[darling(default)]
+    pub restart: Option<Ident>,
+
+    /// Restarts allowed before the failure is surfaced (default: 3).
+    #[darling(default)]
+    pub max_restarts: Option<u32>,
+
+    /// Delay before each restart, e.g. `"1s"` (default: none).
+    #[darling(default)]
+    pub backoff: Option<LitStr>,
+
+    /// State the FSM restarts in (default: the initial state).
+    #[darling(default)]
+    pub restart_from: Option<Ident>,
+
+    /// What happens once every handle is dropped: `shutdown` (default) or
+    /// `detach`.
+    #[darling(default)]
+    pub on_last_handle: Option<Ident>,
+
+    /// Extra derives for the generated Event enum, e.g.
+    /// `event_derive(PartialEq, Hash)`.
+    #[darling(default)]
+    pub event_derive: darling::util::PathList,
+
+    /// Event channel backend: `tokio` (default), `flume` or `kanal`. The
+    /// alternatives require the matching feature of `tokio-fsm`.
+    #[darling(default)]
+    pub channel: Option<Ident>,
+
+    /// Runtime the FSM is spawned on instead of Tokio, e.g.
+    /// `tokio_fsm::SmolRuntime`. Must implement `tokio_fsm::Runtime`.
+    #[darling(default)]
+    pub runtime: Option<Path>,
+
+    /// Graph rendering written at expansion time: `"dot"` or `"mermaid"`.
+    #[darling(default)]
+    pub emit_graph: Option<LitStr>,
+
+    /// Directory `emit_graph` writes to, relative to the crate root
+    /// (default: `OUT_DIR`).
+    #[darling(default)]
+    pub emit_path: Option<LitStr>,
+}
+
+fn default_channel_size() -> usize {
+    100
+}
+
+/// Arguments for the `#[on(state = Idle, event = Start)]` attribute.
+#[derive(Debug, FromMeta)]
+pub struct OnAttr {
+    /// Source state this handler is valid in.
+    pub state: Ident,
+    /// Event(s) that trigger this handler.
+    pub event: EventNames,
+    /// Payload field identifying events that supersede each other while
+    /// queued; only the latest per key is handled.
+    #[darling(default)]
+    pub coalesce_by: Option<Ident>,
+}
+
+/// One or more event names, written as `Start` or `Pause | Suspend`.
+#[derive(Debug)]
+pub struct EventNames(pub Vec<Ident>);
+
+impl FromMeta for EventNames {
+    fn from_expr(expr: &Expr) -> darling::Result<Self> {
+        fn collect(expr: &Expr, names: &mut Vec<Ident>) -> darling::Result<()> {
+            match expr {
+                Expr::Path(path) => {
+                    let ident = path.path.require_ident().map_err(darling::Error::from)?;
+                    names.push(ident.clone());
+                    Ok(())
+                }
+                Expr::Binary(binary) if matches!(binary.op, BinOp::BitOr(_)) => {
+                    collect(&binary.left, names)?;
+                    collect(&binary.right, names)
+                }
+                Expr::Group(group) => collect(&group.expr, names),
+                _ => Err(darling::Error::custom(
+                    "expected an event name or names separated by `|`",
+                )
+                .with_span(expr)),
+            }
+        }
+
+        let mut names = Vec::new();
+        collect(expr, &mut names)?;
+        Ok(Self(names))
+    }
+}
+
+/// Arguments for the `#[state_timeout]` attribute.
+#[derive(Debug, Clone, FromMeta)]
+pub struct StateTimeoutAttr {
+    /// Duration string (e.g., "30s", "5m").
+    pub duration: LitStr,
+}
+
+/// Arguments for the `#[auto(state = Validated)]` attribute.
+#[derive(Debug, FromMeta)]
+pub struct AutoAttr {
+    /// State whose entry runs the handler.
+    pub state: Ident,
+}
+
+/// A row of a `#[derive(Fsm)]` transition table, written
+/// `#[transition(Idle -> Running on Start)]`.
+#[derive(Debug)]
+pub struct TransitionAttr {
+    /// State the transition leaves.
+    pub from: Ident,
+    /// State the transition enters.
+    pub to: Ident,
+    /// Event that triggers it.
+    pub event: Ident,
+}
+
+impl Parse for TransitionAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let from = input.parse()?;
+        input.parse::<Token![->]>()?;
+        let to = input.parse()?;
+        let on: Ident = input.parse()?;
+        if on != "on" {
+            return Err(syn::Error::new_spanned(on, "expected `on`"));
+        }
+        let event = input.parse()?;
+        Ok(Self { from, to, event })
+    }
+}
+
+/// Arguments for the `#[submachine(state = Shipping, fsm = ShippingFsm)]`
+/// attribute.
+#[derive(Debug, FromMeta)]
+pub struct SubmachineAttr {
+    /// State the child FSM runs in.
+    pub state: Ident,
+    /// The child FSM type.
+    pub fsm: Path,
+}
+
+/// Arguments for the `#[on_entry(state = Approved)]` attribute.
+#[derive(Debug, FromMeta)]
+pub struct EntryAttr {
+    /// State whose entry runs the hook.
+    pub state: Ident,
+}
+
+/// Arguments for the `#[persist(on_error = Failed)]` attribute.
+#[derive(Debug, FromMeta)]
+pub struct PersistAttr {
+    /// State entered when the hook fails.
+    pub on_error: Ident,
+}
+
+/// Arguments for the `#[external_event(from = Message, map(Start, Stop =
+/// Halt))]` attribute placed on the FSM's `impl` block.
+#[derive(Debug, FromMeta)]
+pub struct ExternalEventAttr {
+    /// External enum converted into FSM events.
+    pub from: Path,
+    /// Which external variant each FSM event is built from.
+    pub map: EventMap,
+}
+
+/// Arguments for the `#[schedule(cron = "0 */5 * * * *", event = Reconcile)]`
+/// attribute placed on the FSM's `impl` block.
+#[derive(Debug, FromMeta)]
+pub struct ScheduleAttr {
+    /// Cron expression with a seconds field: `sec min hour day month
+    /// weekday [year]`, in UTC.
+    pub cron: LitStr,
+    /// Payload-free event sent whenever the expression matches.
+    pub event: Ident,
+}
+
+/// `(event, variant)` pairs, written as `Event = Variant` or just `Event`
+/// when both share a name.
+#[derive(Debug)]
+pub struct EventMap(pub Vec<(Ident, Ident)>);
+
+impl FromMeta for EventMap {
+    fn from_list(items: &[NestedMeta]) -> darling::Result<Self> {
+        let mut pairs = Vec::new();
+        for item in items {
+            let pair = match item {
+                NestedMeta::Meta(Meta::Path(path)) => {
+                    let event = path.require_ident().map_err(darling::Error::from)?;
+                    (event.clone(), event.clone())
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) => {
+                    let event = nv.path.require_ident().map_err(darling::Error::from)?;
+                    let Expr::Path(variant) = &nv.value else {
+                        return Err(
+                            darling::Error::custom("expected a variant name").with_span(&nv.value)
+                        );
+                    };
+                    let variant = variant.path.require_ident().map_err(darling::Error::from)?;
+                    (event.clone(), variant.clone())
+                }
+                _ => {
+                    return Err(
+                        darling::Error::custom("expected `Event` or `Event = Variant`")
+                            .with_span(item),
+                    );
+                }
+            };
+            pairs.push(pair);
+        }
+        Ok(Self(pairs))
+    }
+}
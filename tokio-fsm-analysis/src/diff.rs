@@ -0,0 +1,73 @@
+//! Structural comparison between two versions of an FSM.
+
+use std::collections::BTreeSet;
+
+use crate::{graph::trigger_label, validation::FsmStructure};
+
+/// A transition in comparable form: `(from, trigger label, to)`.
+pub type TransitionKey = (String, String, String);
+
+/// The structural differences between two versions of an FSM.
+///
+/// All lists are sorted, so diffs are stable and easy to print.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsmDiff {
+    pub added_states: Vec<String>,
+    pub removed_states: Vec<String>,
+    pub added_events: Vec<String>,
+    pub removed_events: Vec<String>,
+    pub added_transitions: Vec<TransitionKey>,
+    pub removed_transitions: Vec<TransitionKey>,
+    /// Set when the initial state changed, as `(old, new)`.
+    pub initial_state_changed: Option<(String, String)>,
+}
+
+impl FsmDiff {
+    /// Returns `true` if both versions describe the same machine.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl FsmStructure {
+    /// Compares this FSM (the old version) against `new`.
+    pub fn diff(&self, new: &FsmStructure) -> FsmDiff {
+        let states = |fsm: &FsmStructure| -> BTreeSet<String> {
+            fsm.states.iter().map(|s| s.name.to_string()).collect()
+        };
+        let events = |fsm: &FsmStructure| -> BTreeSet<String> {
+            fsm.events.iter().map(|e| e.name.to_string()).collect()
+        };
+        let transitions = |fsm: &FsmStructure| -> BTreeSet<TransitionKey> {
+            fsm.edges()
+                .iter()
+                .map(|e| {
+                    (
+                        e.from.to_string(),
+                        trigger_label(&e.trigger),
+                        e.to.to_string(),
+                    )
+                })
+                .collect()
+        };
+
+        let (old_states, new_states) = (states(self), states(new));
+        let (old_events, new_events) = (events(self), events(new));
+        let (old_edges, new_edges) = (transitions(self), transitions(new));
+
+        FsmDiff {
+            added_states: new_states.difference(&old_states).cloned().collect(),
+            removed_states: old_states.difference(&new_states).cloned().collect(),
+            added_events: new_events.difference(&old_events).cloned().collect(),
+            removed_events: old_events.difference(&new_events).cloned().collect(),
+            added_transitions: new_edges.difference(&old_edges).cloned().collect(),
+            removed_transitions: old_edges.difference(&new_edges).cloned().collect(),
+            initial_state_changed: (self.initial_state != new.initial_state).then(|| {
+                (
+                    self.initial_state.to_string(),
+                    new.initial_state.to_string(),
+                )
+            }),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Graph renderings and metrics of a validated FSM.
+
+use std::fmt::Write;
+
+use quote::ToTokens;
+
+use crate::validation::{FsmStructure, GraphFormat, Trigger};
+
+/// Summary statistics of an FSM graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphMetrics {
+    /// Number of states, including the initial state.
+    pub states: usize,
+    /// Number of distinct events.
+    pub events: usize,
+    /// Number of edges, counting timeout edges.
+    pub transitions: usize,
+    /// States with no outgoing edges, in state order.
+    pub terminal_states: Vec<String>,
+    /// The largest number of outgoing edges of any single state.
+    pub max_out_degree: usize,
+}
+
+impl FsmStructure {
+    /// Computes summary statistics of the FSM graph.
+    pub fn metrics(&self) -> GraphMetrics {
+        let edges = self.edges();
+        let out_degree = |state: &syn::Ident| edges.iter().filter(|e| &e.from == state).count();
+
+        GraphMetrics {
+            states: self.states.len(),
+            events: self.events.len(),
+            transitions: edges.len(),
+            terminal_states: self
+                .states
+                .iter()
+                .filter(|s| out_degree(&s.name) == 0)
+                .map(|s| s.name.to_string())
+                .collect(),
+            max_out_degree: self
+                .states
+                .iter()
+                .map(|s| out_degree(&s.name))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Returns the label of an edge trigger, e.g. `Start` or `timeout (30s)`.
+pub fn trigger_label(trigger: &Trigger) -> String {
+    match trigger {
+        Trigger::Event(event) => event.to_string(),
+        Trigger::Timeout(duration) => {
+            format!("timeout ({})", humantime::format_duration(*duration))
+        }
+        Trigger::Auto => "auto".to_string(),
+        Trigger::Start => "start".to_string(),
+    }
+}
+
+/// Builds the Mermaid `stateDiagram-v2` source for the FSM.
+pub fn mermaid(fsm: &FsmStructure) -> String {
+    let mut out = String::from("stateDiagram-v2\n");
+    let _ = writeln!(out, "    [*] --> {}", fsm.initial_state);
+
+    for edge in fsm.edges() {
+        let _ = writeln!(
+            out,
+            "    {} --> {}: {}",
+            edge.from,
+            edge.to,
+            trigger_label(&edge.trigger)
+        );
+    }
+
+    out
+}
+
+/// Builds the Graphviz DOT source for the FSM. Timeout edges are dashed and
+/// automatic ones dotted.
+pub fn dot(fsm: &FsmStructure) -> String {
+    let mut out = format!("digraph {} {{\n    rankdir=LR;\n", fsm.fsm_name);
+    let _ = writeln!(out, "    __start [shape=point];");
+    let _ = writeln!(out, "    __start -> {};", fsm.initial_state);
+
+    for edge in fsm.edges() {
+        let style = match edge.trigger {
+            Trigger::Event(_) => "",
+            Trigger::Timeout(_) => ", style=dashed",
+            Trigger::Auto | Trigger::Start => ", style=dotted",
+        };
+        let _ = writeln!(
+            out,
+            "    {} -> {} [label={}{}];",
+            edge.from,
+            edge.to,
+            json_string(&trigger_label(&edge.trigger)),
+            style
+        );
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Renders the FSM in the given format.
+pub fn render(fsm: &FsmStructure, format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => dot(fsm),
+        GraphFormat::Mermaid => mermaid(fsm),
+    }
+}
+
+/// Builds a compact JSON document describing the FSM: its states (with entry
+/// timeouts), events (with payload types), and transitions.
+///
+/// The document is written by hand to keep this crate free of a JSON
+/// dependency; its shape is:
+///
+/// ```json
+/// {
+///   "name": "WorkerFsm",
+///   "initial_state": "Idle",
+///   "states": [{ "name": "Idle", "timeouts_ms": [] }],
+///   "events": [{ "name": "Job", "payload": "Job" }],
+///   "transitions": [
+///     { "from": "Idle", "to": "Working", "event": "Job" },
+///     { "from": "Working", "to": "Failed", "timeout_ms": 30000 },
+///     { "from": "Failed", "to": "Idle", "auto": true }
+///   ]
+/// }
+/// ```
+pub fn definition_json(fsm: &FsmStructure) -> String {
+    let timeouts = fsm.state_timeouts();
+
+    let states: Vec<String> = fsm
+        .states
+        .iter()
+        .map(|state| {
+            let timeouts_ms: Vec<String> = timeouts
+                .iter()
+                .filter(|(name, _)| **name == state.name)
+                .map(|(_, duration)| duration.as_millis().to_string())
+                .collect();
+            format!(
+                r#"{{"name":{},"timeouts_ms":[{}]}}"#,
+                json_string(&state.name.to_string()),
+                timeouts_ms.join(",")
+            )
+        })
+        .collect();
+
+    let events: Vec<String> = fsm
+        .events
+        .iter()
+        .map(|event| {
+            let payload = event
+                .payload_type
+                .as_ref()
+                .map_or_else(|| "null".to_string(), |ty| json_string(&type_name(ty)));
+            format!(
+                r#"{{"name":{},"payload":{}}}"#,
+                json_string(&event.name.to_string()),
+                payload
+            )
+        })
+        .collect();
+
+    let transitions: Vec<String> = fsm
+        .edges()
+        .iter()
+        .map(|edge| {
+            let trigger = match &edge.trigger {
+                Trigger::Event(event) => format!(r#""event":{}"#, json_string(&event.to_string())),
+                Trigger::Timeout(duration) => format!(r#""timeout_ms":{}"#, duration.as_millis()),
+                Trigger::Auto => r#""auto":true"#.to_string(),
+                Trigger::Start => r#""start":true"#.to_string(),
+            };
+            format!(
+                r#"{{"from":{},"to":{},{}}}"#,
+                json_string(&edge.from.to_string()),
+                json_string(&edge.to.to_string()),
+                trigger
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"name":{},"initial_state":{},"states":[{}],"events":[{}],"transitions":[{}]}}"#,
+        json_string(&fsm.fsm_name.to_string()),
+        json_string(&fsm.initial_state.to_string()),
+        states.join(","),
+        events.join(","),
+        transitions.join(",")
+    )
+}
+
+/// Renders a type as compact source text, e.g. `Vec<u8>` rather than the
+/// token-spaced `Vec < u8 >`.
+pub fn type_name(ty: &syn::Type) -> String {
+    let spaced = ty.to_token_stream().to_string();
+    let chars: Vec<char> = spaced.chars().collect();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, &c)| {
+            c != ' '
+                || (i > 0 && i + 1 < chars.len() && is_word(chars[i - 1]) && is_word(chars[i + 1]))
+        })
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,96 @@
+//! Parsing, validation and graph analysis for `tokio-fsm` definitions.
+//!
+//! This crate contains the front half of the `#[fsm]` macro pipeline as a
+//! regular library, so external tools can lint FSM definitions, compute graph
+//! metrics, and diff machines between versions without expanding any code.
+//!
+//! # Example
+//!
+//! ```rust
+//! let source = r#"
+//!     #[fsm(initial = Idle)]
+//!     impl WorkerFsm {
+//!         type Context = ();
+//!         type Error = std::convert::Infallible;
+//!
+//!         #[on(state = Idle, event = Start)]
+//!         async fn on_start(&mut self) -> Transition<Running> {
+//!             Transition::to(Running)
+//!         }
+//!     }
+//! "#;
+//!
+//! let machines = tokio_fsm_analysis::parse_source(source).unwrap();
+//! let fsm = machines[0].as_ref().unwrap();
+//! assert_eq!(fsm.fsm_name, "WorkerFsm");
+//! assert_eq!(fsm.metrics().transitions, 1);
+//! ```
+
+use darling::FromMeta;
+use syn::Item;
+
+pub mod attrs;
+pub mod diff;
+pub mod graph;
+pub mod table;
+pub mod validation;
+
+pub use crate::{diff::FsmDiff, graph::GraphMetrics, validation::FsmStructure};
+
+/// Parses Rust source text and analyzes every `#[fsm]` impl block and
+/// `fsm_table!` invocation in it.
+///
+/// Returns one entry per machine, in source order. A block that fails
+/// to parse or validate yields the same error the macro would report.
+pub fn parse_source(source: &str) -> syn::Result<Vec<syn::Result<FsmStructure>>> {
+    let file = syn::parse_file(source)?;
+    Ok(parse_file(&file))
+}
+
+/// Analyzes every `#[fsm]` impl block and `fsm_table!` invocation in a parsed
+/// file, including those nested in inline modules.
+pub fn parse_file(file: &syn::File) -> Vec<syn::Result<FsmStructure>> {
+    let mut machines = Vec::new();
+    collect_items(&file.items, &mut machines);
+    machines
+}
+
+fn collect_items(items: &[Item], machines: &mut Vec<syn::Result<FsmStructure>>) {
+    for item in items {
+        match item {
+            Item::Impl(impl_block) => {
+                if let Some(attr) = impl_block.attrs.iter().find(|a| a.path().is_ident("fsm")) {
+                    machines.push(parse_impl(attr, impl_block));
+                }
+            }
+            Item::Macro(item)
+                if item
+                    .mac
+                    .path
+                    .segments
+                    .last()
+                    .is_some_and(|segment| segment.ident == "fsm_table") =>
+            {
+                machines.push(
+                    item.mac
+                        .parse_body::<table::FsmTable>()
+                        .and_then(|table| table.structure()),
+                );
+            }
+            Item::Mod(module) => {
+                if let Some((_, items)) = &module.content {
+                    collect_items(items, machines);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_impl(attr: &syn::Attribute, impl_block: &syn::ItemImpl) -> syn::Result<FsmStructure> {
+    let args = attrs::FsmArgs::from_meta(&attr.meta)?;
+    // The macro sees the impl block without its own `#[fsm]` attribute.
+    let mut impl_block = impl_block.clone();
+    impl_block.attrs.retain(|a| !a.path().is_ident("fsm"));
+    FsmStructure::parse(args, &impl_block)
+}
@@ -0,0 +1,196 @@
+//! The transition-table front-end used by `fsm_table!`.
+//!
+//! A table is lowered to the `impl` block `#[fsm]` would receive, one handler
+//! per row, so it is validated and generated exactly like the attribute
+//! form:
+//!
+//! ```text
+//! OrderFsm {
+//!     type Context = Order;
+//!
+//!     Created + Validate [validate] => Validated,
+//!     Validated + Pay(u64) [charge] => Paid,
+//!     Paid + Ship => Shipped,
+//! }
+//! ```
+//!
+//! The first row's source state is the initial state. An action in brackets
+//! is awaited as `action(&mut context)`, or `action(&mut context, payload)`
+//! for events that carry one, before the transition is taken.
+
+use darling::{FromMeta, ast::NestedMeta};
+use quote::{format_ident, quote};
+use syn::{
+    Ident, ItemImpl, Path, Token, Type, bracketed, parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+};
+
+use crate::{attrs::FsmArgs, validation::FsmStructure};
+
+/// A parsed `Name { rows }` transition table.
+#[derive(Debug)]
+pub struct FsmTable {
+    /// Name of the generated FSM type.
+    pub name: Ident,
+    /// Type given by `type Context = ...;`, if any.
+    pub context: Option<Type>,
+    /// The rows, in source order.
+    pub rows: Vec<TableRow>,
+}
+
+/// One `From + Event(Payload) [action] => To` row.
+#[derive(Debug)]
+pub struct TableRow {
+    /// State the transition leaves.
+    pub from: Ident,
+    /// Event that triggers it.
+    pub event: Ident,
+    /// Payload carried by the event.
+    pub payload: Option<Type>,
+    /// Function awaited before the transition is taken.
+    pub action: Option<Path>,
+    /// State the transition enters.
+    pub to: Ident,
+}
+
+impl Parse for FsmTable {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        let body;
+        syn::braced!(body in input);
+
+        let context = if body.peek(Token![type]) {
+            body.parse::<Token![type]>()?;
+            let ident: Ident = body.parse()?;
+            if ident != "Context" {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "expected `type Context = ...;`",
+                ));
+            }
+            body.parse::<Token![=]>()?;
+            let ty = body.parse()?;
+            body.parse::<Token![;]>()?;
+            Some(ty)
+        } else {
+            None
+        };
+
+        let rows = Punctuated::<TableRow, Token![,]>::parse_terminated(&body)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &name,
+                "a transition table needs at least one `From + Event => To` row",
+            ));
+        }
+        for (index, row) in rows.iter().enumerate() {
+            if rows[..index]
+                .iter()
+                .any(|earlier| earlier.from == row.from && earlier.event == row.event)
+            {
+                return Err(syn::Error::new_spanned(
+                    &row.event,
+                    format!(
+                        "'{} + {}' appears in more than one row",
+                        row.from, row.event
+                    ),
+                ));
+            }
+        }
+        Ok(Self {
+            name,
+            context,
+            rows,
+        })
+    }
+}
+
+impl Parse for TableRow {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let from = input.parse()?;
+        input.parse::<Token![+]>()?;
+        let event = input.parse()?;
+        let payload = if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Some(content.parse()?)
+        } else {
+            None
+        };
+        let action = if input.peek(syn::token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            Some(content.parse()?)
+        } else {
+            None
+        };
+        input.parse::<Token![=>]>()?;
+        let to = input.parse()?;
+        Ok(Self {
+            from,
+            event,
+            payload,
+            action,
+            to,
+        })
+    }
+}
+
+impl FsmTable {
+    /// The initial state: the source state of the first row.
+    pub fn initial(&self) -> &Ident {
+        &self.rows[0].from
+    }
+
+    /// The `#[fsm]` arguments the table implies.
+    pub fn args(&self) -> syn::Result<FsmArgs> {
+        let initial = self.initial();
+        let args = NestedMeta::parse_meta_list(quote! { initial = #initial })?;
+        FsmArgs::from_list(&args).map_err(syn::Error::from)
+    }
+
+    /// The `impl` block `#[fsm]` would receive for this table.
+    pub fn to_impl(&self) -> ItemImpl {
+        let name = &self.name;
+        let context = self
+            .context
+            .as_ref()
+            .map(|ty| quote! { type Context = #ty; });
+        let handlers = self.rows.iter().enumerate().map(|(index, row)| {
+            let TableRow {
+                from, event, to, ..
+            } = row;
+            let method = format_ident!("transition_{}", index, span = event.span());
+            let (param, args) = match &row.payload {
+                Some(ty) => (quote! { , payload: #ty }, quote! { , payload }),
+                None => (quote! {}, quote! {}),
+            };
+            let action = match &row.action {
+                Some(action) => quote! { #action(&mut self.context #args).await; },
+                None if row.payload.is_some() => quote! { let _ = payload; },
+                None => quote! {},
+            };
+            quote! {
+                #[on(state = #from, event = #event)]
+                async fn #method(&mut self #param) -> tokio_fsm::Transition<#to> {
+                    #action
+                    tokio_fsm::Transition::to(#to)
+                }
+            }
+        });
+        syn::parse_quote! {
+            impl #name {
+                #context
+                #(#handlers)*
+            }
+        }
+    }
+
+    /// Validates the table, as `fsm_table!` does before generating code.
+    pub fn structure(&self) -> syn::Result<FsmStructure> {
+        FsmStructure::parse(self.args()?, &self.to_impl())
+    }
+}
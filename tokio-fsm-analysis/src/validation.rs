@@ -0,0 +1,1542 @@
+//! Validation and semantic analysis for FSM structure.
+//!
+//! This module is the first layer of the macro pipeline. It:
+//! 1. Parses the `impl` block to extract states, events, and handlers
+//! 2. Derives semantic fields (timeout durations, payload presence, result
+//!    types)
+//! 3. Validates the FSM graph (reachability from initial state)
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use darling::FromMeta;
+use petgraph::{algo::has_path_connecting, graph::DiGraph};
+use quote::format_ident;
+use syn::{
+    Error, FnArg, GenericArgument, Ident, ImplItem, LitStr, PathArguments, ReturnType, Type,
+};
+
+use crate::attrs;
+
+/// Represents a discovered state in the FSM.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct State {
+    pub name: Ident,
+}
+
+/// Represents a discovered event in the FSM.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: Ident,
+    pub payload_type: Option<Type>,
+    /// Fields of the generated payload struct, for events whose handler takes
+    /// several payload arguments.
+    pub fields: Vec<(Ident, Type)>,
+}
+
+/// Represents a handler method in the FSM, including all derived semantic
+/// fields.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub method: syn::ImplItemFn,
+    /// Events handled by this method, in declaration order.
+    pub events: Vec<Event>,
+    pub is_timeout_handler: bool,
+    /// State whose entry runs this handler, for `#[auto]` handlers.
+    pub auto_state: Option<Ident>,
+    /// Whether this is the `#[on_start]` handler, run before the first event.
+    pub is_start_handler: bool,
+    /// Whether this is the `#[on_orphaned]` handler, run once every handle is
+    /// dropped.
+    pub is_orphaned_handler: bool,
+    pub return_states: Vec<State>,
+    /// Whether the handler returns `Transition<{Fsm}State>`, choosing its
+    /// target at runtime among `return_states`: its `#[targets(...)]`, or
+    /// every state.
+    pub dynamic: bool,
+    /// Whether `return_states` was narrowed with `#[targets(...)]`.
+    pub narrowed: bool,
+    /// Whether the handler returns `impl Into<Transition<_>>`, converted
+    /// before it is committed.
+    pub converts: bool,
+
+    // Derived semantic fields (previously in IR)
+    /// Source states this handler is valid in.
+    pub source_states: Vec<Ident>,
+    /// Every `(state, event)` pair that dispatches to this handler.
+    pub triggers: Vec<(Ident, Ident)>,
+    /// Whether the event carries a payload argument.
+    pub has_payload: bool,
+    /// The payload arguments, when the handler takes more than one. Its events
+    /// then carry them in a generated payload struct.
+    pub payload_fields: Vec<(Ident, Type)>,
+    /// Whether each payload argument is borrowed (`job: &Job`) from the event
+    /// the run loop owns, rather than moved into the handler.
+    pub payload_borrowed: Vec<bool>,
+    /// `(event, payload field)` pairs declared with `coalesce_by`.
+    pub coalesce: Vec<(Ident, Ident)>,
+    /// Whether this handler's events preempt other handlers running in its
+    /// source states (`#[preempt]`).
+    pub preempt: bool,
+    /// Whether the return type is `Result<Transition<A>, Transition<B>>`.
+    pub is_result: bool,
+    /// Whether the return type is `Result<Transition<A>, Self::Error>`, whose
+    /// `Err` stops the FSM with that error.
+    pub is_fallible: bool,
+    /// The child FSM whose outcome this handler maps, for `#[submachine]`
+    /// handlers.
+    pub submachine: Option<Submachine>,
+    /// Parsed timeout duration for the target state, if any.
+    pub timeout: Option<Duration>,
+}
+
+/// What causes an [`Edge`] to be taken.
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// A handler for the named event.
+    Event(Ident),
+    /// A state timeout of the given duration.
+    Timeout(Duration),
+    /// An `#[auto]` handler, run as soon as the source state is entered.
+    Auto,
+    /// The `#[on_start]` handler, run in the initial state before the first
+    /// event.
+    Start,
+}
+
+/// A single transition in the FSM graph.
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: Ident,
+    pub to: Ident,
+    pub trigger: Trigger,
+}
+
+/// MPSC implementation carrying events to the FSM, chosen with
+/// `#[fsm(channel = ...)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelBackend {
+    #[default]
+    Tokio,
+    Flume,
+    Kanal,
+}
+
+impl ChannelBackend {
+    fn parse(ident: &Ident) -> syn::Result<Self> {
+        match ident.to_string().as_str() {
+            "tokio" => Ok(Self::Tokio),
+            "flume" => Ok(Self::Flume),
+            "kanal" => Ok(Self::Kanal),
+            other => Err(Error::new_spanned(
+                ident,
+                format!(
+                    "Unknown channel backend '{}', expected one of: tokio, flume, kanal",
+                    other
+                ),
+            )),
+        }
+    }
+}
+
+/// What the run loop does once every handle is dropped, chosen with
+/// `#[fsm(on_last_handle = ...)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LastHandlePolicy {
+    /// Shut down gracefully, resolving the task with the context.
+    #[default]
+    Shutdown,
+    /// Keep running on timeouts, attached sources and the FSM's own sender
+    /// until a transition halts it.
+    Detach,
+}
+
+impl LastHandlePolicy {
+    fn parse(ident: &Ident) -> syn::Result<Self> {
+        match ident.to_string().as_str() {
+            "shutdown" => Ok(Self::Shutdown),
+            "detach" => Ok(Self::Detach),
+            other => Err(Error::new_spanned(
+                ident,
+                format!(
+                    "Unknown on_last_handle policy '{}', expected one of: shutdown, detach",
+                    other
+                ),
+            )),
+        }
+    }
+}
+
+/// How the run loop is restarted after a failure, declared with
+/// `#[fsm(restart = on_error, ...)]`.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Restarts allowed before the failure ends the task.
+    pub max_restarts: u32,
+    /// Delay before each restart.
+    pub backoff: Duration,
+    /// State the FSM restarts in.
+    pub from: Ident,
+}
+
+impl RestartPolicy {
+    fn parse(args: &attrs::FsmArgs) -> syn::Result<Option<Self>> {
+        let Some(restart) = &args.restart else {
+            if let Some(from) = &args.restart_from {
+                return Err(Error::new_spanned(from, "restart_from requires restart"));
+            }
+            if let Some(backoff) = &args.backoff {
+                return Err(Error::new_spanned(backoff, "backoff requires restart"));
+            }
+            if args.max_restarts.is_some() {
+                return Err(Error::new(
+                    proc_macro2::Span::call_site(),
+                    "max_restarts requires restart",
+                ));
+            }
+            return Ok(None);
+        };
+        if restart != "on_error" {
+            return Err(Error::new_spanned(
+                restart,
+                format!("Unknown restart policy '{}', expected on_error", restart),
+            ));
+        }
+
+        let backoff = match &args.backoff {
+            Some(lit) => humantime::parse_duration(&lit.value()).map_err(|e| {
+                Error::new_spanned(lit, format!("Invalid duration '{}': {}", lit.value(), e))
+            })?,
+            None => Duration::ZERO,
+        };
+        Ok(Some(Self {
+            max_restarts: args.max_restarts.unwrap_or(3),
+            backoff,
+            from: args
+                .restart_from
+                .clone()
+                .unwrap_or_else(|| args.initial.clone()),
+        }))
+    }
+}
+
+/// Graph rendering written during macro expansion, chosen with
+/// `#[fsm(emit_graph = "...")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT, written to `[FsmName].dot`.
+    Dot,
+    /// Mermaid `stateDiagram-v2`, written to `[FsmName].mmd`.
+    Mermaid,
+}
+
+impl GraphFormat {
+    fn parse(lit: &LitStr) -> syn::Result<Self> {
+        match lit.value().as_str() {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            other => Err(Error::new_spanned(
+                lit,
+                format!(
+                    "Unknown graph format '{}', expected one of: dot, mermaid",
+                    other
+                ),
+            )),
+        }
+    }
+
+    /// File extension of the written graph.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Dot => "dot",
+            Self::Mermaid => "mmd",
+        }
+    }
+}
+
+/// The `#[persist]` hook, run after every transition.
+#[derive(Debug, Clone)]
+pub struct PersistHook {
+    /// The hook method, `async fn(&mut self) -> Result<(), E>`.
+    pub method: Ident,
+    /// State entered when the hook returns `Err`.
+    pub on_error: Ident,
+}
+
+/// An `#[on_entry(state = S)]` hook, run after every transition into `state`.
+#[derive(Debug, Clone)]
+pub struct EntryHook {
+    pub state: Ident,
+    /// The hook method, `async fn(&mut self)` or `async fn(&mut self, data:
+    /// T)`.
+    pub method: Ident,
+    /// The value the hook takes, passed with `Transition::to_with`.
+    pub data: Option<Type>,
+}
+
+/// A child FSM run while the parent is in `state`, declared with
+/// `#[submachine(state = S, fsm = ChildFsm)]`.
+#[derive(Debug, Clone)]
+pub struct Submachine {
+    pub state: Ident,
+    /// Path of the child FSM type.
+    pub fsm: syn::Path,
+}
+
+impl Submachine {
+    /// The event carrying child events to forward, named after the state.
+    pub fn forward_event(&self) -> &Ident {
+        &self.state
+    }
+
+    /// The event delivering the child's final state to the handler, e.g.
+    /// `ShippingDone`.
+    pub fn done_event(&self) -> Ident {
+        format_ident!("{}Done", self.state)
+    }
+
+    /// Field of the parent FSM holding the running child.
+    pub fn slot_ident(&self) -> Ident {
+        format_ident!("submachine_{}", self.state.to_string().to_lowercase())
+    }
+
+    /// Path of a type generated for the child, e.g. `ShippingFsmEvent` for
+    /// `suffix = "Event"`.
+    pub fn child_type(&self, suffix: &str) -> syn::Path {
+        let mut path = self.fsm.clone();
+        if let Some(last) = path.segments.last_mut() {
+            last.ident = format_ident!("{}{}", last.ident, suffix);
+        }
+        path
+    }
+}
+
+/// An external enum bridged into FSM events via `#[external_event]`.
+#[derive(Debug, Clone)]
+pub struct ExternalEvent {
+    /// Path of the external enum.
+    pub source: syn::Path,
+    /// `(event, variant)` pairs; unmapped variants are rejected by `TryFrom`.
+    pub mappings: Vec<(Ident, Ident)>,
+}
+
+/// An event injected on a cron schedule via `#[schedule]`.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// The cron expression, as written.
+    pub cron: LitStr,
+    /// The payload-free event it sends.
+    pub event: Ident,
+}
+
+/// The complete FSM structure after parsing and validation.
+#[derive(Debug)]
+pub struct FsmStructure {
+    pub fsm_name: Ident,
+    pub initial_state: Ident,
+    pub channel_size: usize,
+    pub channel: ChannelBackend,
+    /// Whether to derive serde traits on the generated enums.
+    pub serde: bool,
+    /// Whether the generated enums are `#[non_exhaustive]`.
+    pub non_exhaustive: bool,
+    /// Integer type the State enum is `#[repr]`'d as, if declared.
+    pub state_repr: Option<Ident>,
+    /// State a panicking handler diverts the FSM to, if declared.
+    pub on_panic: Option<Ident>,
+    /// Restart policy for the run loop, if declared.
+    pub restart: Option<RestartPolicy>,
+    /// What the run loop does once every handle is dropped.
+    pub on_last_handle: LastHandlePolicy,
+    /// Runtime the FSM is spawned on instead of Tokio, if declared.
+    pub runtime: Option<syn::Path>,
+    /// Extra derives for the event enum, beyond `Debug` and `Clone`.
+    pub event_derives: Vec<syn::Path>,
+    /// Graph written during expansion, if requested.
+    pub emit_graph: Option<GraphFormat>,
+    /// Directory the graph is written to, relative to the crate root; `None`
+    /// means `OUT_DIR`.
+    pub emit_path: Option<String>,
+    pub context_type: Type,
+    /// Whether `type Context` was declared; when omitted it is `()` and
+    /// `spawn` takes no argument.
+    pub has_context: bool,
+    pub error_type: Type,
+    /// Command type handlers can emit, declared via `type Output = ...`.
+    pub output_type: Option<Type>,
+    pub states: Vec<State>,
+    pub events: Vec<Event>,
+    pub handlers: Vec<Handler>,
+    /// External enums converted into events.
+    pub external_events: Vec<ExternalEvent>,
+    /// Events injected on cron schedules.
+    pub schedules: Vec<Schedule>,
+    /// Durability hook run after each transition, if declared.
+    pub persist: Option<PersistHook>,
+    /// Method declared with `#[on_shutdown]`, run once the loop exits.
+    pub on_shutdown: Option<Ident>,
+    /// `#[on_entry]` hooks, at most one per state.
+    pub entry_hooks: Vec<EntryHook>,
+}
+
+impl FsmStructure {
+    // --- Ident helpers (previously in helpers.rs) ---
+
+    pub fn state_enum_ident(&self) -> Ident {
+        format_ident!("{}State", self.fsm_name)
+    }
+
+    pub fn event_enum_ident(&self) -> Ident {
+        format_ident!("{}Event", self.fsm_name)
+    }
+
+    pub fn handle_ident(&self) -> Ident {
+        format_ident!("{}Handle", self.fsm_name)
+    }
+
+    pub fn sender_ident(&self) -> Ident {
+        format_ident!("{}Sender", self.fsm_name)
+    }
+
+    pub fn typed_handle_ident(&self) -> Ident {
+        format_ident!("{}TypedHandle", self.fsm_name)
+    }
+
+    pub fn builder_ident(&self) -> Ident {
+        format_ident!("{}Builder", self.fsm_name)
+    }
+
+    pub fn task_ident(&self) -> Ident {
+        format_ident!("{}Task", self.fsm_name)
+    }
+
+    pub fn core_ident(&self) -> Ident {
+        format_ident!("{}Core", self.fsm_name)
+    }
+
+    /// The payload field events named `event` are coalesced by, if any.
+    pub fn coalesce_field(&self, event: &Ident) -> Option<&Ident> {
+        self.handlers
+            .iter()
+            .flat_map(|h| &h.coalesce)
+            .find(|(name, _)| name == event)
+            .map(|(_, field)| field)
+    }
+
+    /// Child FSMs run in `#[submachine]` states, in handler order.
+    pub fn submachines(&self) -> impl Iterator<Item = &Submachine> {
+        self.handlers.iter().filter_map(|h| h.submachine.as_ref())
+    }
+
+    /// The submachine whose events are forwarded by `event`, if any.
+    pub fn forwarded_to(&self, event: &Ident) -> Option<&Submachine> {
+        self.submachines().find(|s| s.forward_event() == event)
+    }
+
+    /// Events that preempt handlers running in `state`, i.e. those with a
+    /// `#[preempt]` handler for `state`.
+    pub fn preempting_events(&self, state: &Ident) -> Vec<&Event> {
+        let mut events: Vec<&Event> = Vec::new();
+        for handler in self.handlers.iter().filter(|h| h.preempt) {
+            for (from, name) in &handler.triggers {
+                if from == state && !events.iter().any(|e| &e.name == name) {
+                    events.extend(handler.events.iter().filter(|e| &e.name == name));
+                }
+            }
+        }
+        events
+    }
+
+    /// The state the FSM settles in after handling `event` in `from`, when it
+    /// is known statically: the handler has a single target, as do the
+    /// `#[auto]` handlers it leads through.
+    pub fn static_target(&self, from: &Ident, event: &Ident) -> Option<&Ident> {
+        let handler = self
+            .handlers
+            .iter()
+            .find(|h| h.triggers.iter().any(|(s, e)| s == from && e == event))?;
+        let mut target = handler.single_target()?;
+        while let Some(auto) = self
+            .handlers
+            .iter()
+            .find(|h| h.auto_state.as_ref() == Some(target))
+        {
+            target = auto.single_target()?;
+        }
+        Some(target)
+    }
+
+    // --- Graph helpers ---
+
+    /// Returns the state timeouts armed on entry to each state, in state
+    /// order. A state reached through handlers with different timeouts appears
+    /// once per distinct duration.
+    pub fn state_timeouts(&self) -> Vec<(&Ident, Duration)> {
+        let mut timeouts = Vec::new();
+        for state in &self.states {
+            for handler in &self.handlers {
+                // Only the success target of a handler arms its timeout.
+                if let (Some(duration), Some(target)) =
+                    (handler.timeout, handler.return_states.first())
+                    && target.name == state.name
+                    && !timeouts.contains(&(&state.name, duration))
+                {
+                    timeouts.push((&state.name, duration));
+                }
+            }
+        }
+        timeouts
+    }
+
+    /// Returns every transition of the FSM: event-driven edges in handler
+    /// order, followed by automatic and then timeout edges.
+    pub fn edges(&self) -> Vec<Edge> {
+        let mut edges = Vec::new();
+
+        for handler in &self.handlers {
+            for (from, event) in &handler.triggers {
+                for to in &handler.return_states {
+                    edges.push(Edge {
+                        from: from.clone(),
+                        to: to.name.clone(),
+                        trigger: Trigger::Event(event.clone()),
+                    });
+                }
+            }
+        }
+
+        for handler in &self.handlers {
+            if let Some(from) = &handler.auto_state {
+                for to in &handler.return_states {
+                    edges.push(Edge {
+                        from: from.clone(),
+                        to: to.name.clone(),
+                        trigger: Trigger::Auto,
+                    });
+                }
+            }
+        }
+
+        if let Some(start_handler) = self.handlers.iter().find(|h| h.is_start_handler) {
+            for to in &start_handler.return_states {
+                edges.push(Edge {
+                    from: self.initial_state.clone(),
+                    to: to.name.clone(),
+                    trigger: Trigger::Start,
+                });
+            }
+        }
+
+        if let Some(timeout_handler) = self.handlers.iter().find(|h| h.is_timeout_handler) {
+            for (state, duration) in self.state_timeouts() {
+                for to in &timeout_handler.return_states {
+                    edges.push(Edge {
+                        from: state.clone(),
+                        to: to.name.clone(),
+                        trigger: Trigger::Timeout(duration),
+                    });
+                }
+            }
+        }
+
+        edges
+    }
+
+    // --- Parsing ---
+
+    /// Parse the impl block and extract the complete FSM structure.
+    pub fn parse(args: attrs::FsmArgs, impl_block: &syn::ItemImpl) -> syn::Result<Self> {
+        let fsm_name = match &*impl_block.self_ty {
+            syn::Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .ok_or_else(|| Error::new_spanned(&impl_block.self_ty, "Expected FSM type name"))?
+                .ident
+                .clone(),
+            _ => {
+                return Err(Error::new_spanned(
+                    &impl_block.self_ty,
+                    "Expected type path for FSM",
+                ));
+            }
+        };
+
+        let restart = RestartPolicy::parse(&args)?;
+        let initial_state = args.initial;
+        let channel = match &args.channel {
+            Some(ident) => ChannelBackend::parse(ident)?,
+            None => ChannelBackend::Tokio,
+        };
+        let on_last_handle = match &args.on_last_handle {
+            Some(ident) => LastHandlePolicy::parse(ident)?,
+            None => LastHandlePolicy::Shutdown,
+        };
+        let emit_graph = args
+            .emit_graph
+            .as_ref()
+            .map(GraphFormat::parse)
+            .transpose()?;
+        if let (Some(path), None) = (&args.emit_path, emit_graph) {
+            return Err(Error::new_spanned(path, "emit_path requires emit_graph"));
+        }
+        let emit_path = args.emit_path.map(|path| path.value());
+
+        // Extract associated types
+        let mut context_type = None;
+        let mut error_type = None;
+        let mut output_type = None;
+
+        for item in &impl_block.items {
+            if let ImplItem::Type(ty) = item {
+                if ty.ident == "Context" {
+                    context_type = Some(ty.ty.clone());
+                } else if ty.ident == "Error" {
+                    error_type = Some(ty.ty.clone());
+                } else if ty.ident == "Output" {
+                    output_type = Some(ty.ty.clone());
+                }
+            }
+        }
+
+        // Pure-coordination FSMs may omit `type Context`.
+        let has_context = context_type.is_some();
+        let context_type = context_type.unwrap_or_else(|| syn::parse_quote!(()));
+        // FSMs that never fail may omit `type Error`.
+        let error_type = error_type.unwrap_or_else(|| syn::parse_quote!(std::convert::Infallible));
+
+        // Parse methods
+        let mut handlers = Vec::new();
+        let mut event_names = HashSet::new();
+        let mut events = Vec::new();
+        // States keep their discovery order (initial state first) so that
+        // generated enums and tables are stable across builds.
+        let mut state_names = vec![initial_state.clone()];
+        let mut add_state = |name: &Ident| {
+            if !state_names.contains(name) {
+                state_names.push(name.clone());
+            }
+        };
+
+        if let Some(on_panic) = &args.on_panic {
+            add_state(on_panic);
+        }
+
+        if let Some(restart) = &restart {
+            add_state(&restart.from);
+        }
+
+        let mut persist: Option<PersistHook> = None;
+        let mut on_shutdown: Option<Ident> = None;
+        let mut entry_hooks: Vec<EntryHook> = Vec::new();
+
+        for item in &impl_block.items {
+            if let ImplItem::Fn(method) = item {
+                if let Some(attr) = method.attrs.iter().find(|a| a.path().is_ident("persist")) {
+                    let persist_attr = attrs::PersistAttr::from_meta(&attr.meta)?;
+                    if persist.is_some() {
+                        return Err(Error::new_spanned(
+                            attr,
+                            "Only one #[persist] hook is allowed",
+                        ));
+                    }
+                    add_state(&persist_attr.on_error);
+                    persist = Some(PersistHook {
+                        method: method.sig.ident.clone(),
+                        on_error: persist_attr.on_error,
+                    });
+                    continue;
+                }
+
+                if let Some(attr) = method
+                    .attrs
+                    .iter()
+                    .find(|a| a.path().is_ident("on_shutdown"))
+                {
+                    attr.meta.require_path_only()?;
+                    if on_shutdown.is_some() {
+                        return Err(Error::new_spanned(
+                            attr,
+                            "Only one #[on_shutdown] hook is allowed",
+                        ));
+                    }
+                    if method.sig.inputs.len() != 2 {
+                        return Err(Error::new_spanned(
+                            &method.sig,
+                            "#[on_shutdown] hooks take `&mut self` and the `tokio_fsm::ShutdownMode`",
+                        ));
+                    }
+                    on_shutdown = Some(method.sig.ident.clone());
+                    continue;
+                }
+
+                if let Some(attr) = method.attrs.iter().find(|a| a.path().is_ident("on_entry")) {
+                    let entry_attr = attrs::EntryAttr::from_meta(&attr.meta)?;
+                    if entry_hooks
+                        .iter()
+                        .any(|hook| hook.state == entry_attr.state)
+                    {
+                        return Err(Error::new_spanned(
+                            &entry_attr.state,
+                            format!(
+                                "State '{}' has more than one #[on_entry] hook",
+                                entry_attr.state
+                            ),
+                        ));
+                    }
+                    let data = match method.sig.inputs.iter().nth(1) {
+                        _ if method.sig.inputs.len() > 2 => {
+                            return Err(Error::new_spanned(
+                                &method.sig.inputs,
+                                "#[on_entry] hooks take `&mut self` and at most the value passed with `Transition::to_with`",
+                            ));
+                        }
+                        Some(FnArg::Typed(arg)) => Some((*arg.ty).clone()),
+                        _ => None,
+                    };
+                    entry_hooks.push(EntryHook {
+                        state: entry_attr.state,
+                        method: method.sig.ident.clone(),
+                        data,
+                    });
+                    continue;
+                }
+
+                let handler = Handler::parse(method, &fsm_name)?;
+
+                if handler.is_start_handler && handlers.iter().any(|h: &Handler| h.is_start_handler)
+                {
+                    return Err(Error::new_spanned(
+                        &method.sig.ident,
+                        "Only one #[on_start] handler is allowed",
+                    ));
+                }
+                if handler.is_orphaned_handler
+                    && handlers.iter().any(|h: &Handler| h.is_orphaned_handler)
+                {
+                    return Err(Error::new_spanned(
+                        &method.sig.ident,
+                        "Only one #[on_orphaned] handler is allowed",
+                    ));
+                }
+
+                for (event, field) in &handler.coalesce {
+                    let conflict = handlers
+                        .iter()
+                        .flat_map(|h: &Handler| &h.coalesce)
+                        .any(|(other, other_field)| other == event && other_field != field);
+                    if conflict {
+                        return Err(Error::new_spanned(
+                            field,
+                            format!("Event '{}' is coalesced by different keys", event),
+                        ));
+                    }
+                }
+
+                // Collect source states
+                for state in &handler.source_states {
+                    add_state(state);
+                }
+
+                // Collect states from return types
+                for state in &handler.return_states {
+                    add_state(&state.name);
+                }
+
+                // Collect events
+                for event in &handler.events {
+                    if !event_names.contains(&event.name) {
+                        event_names.insert(event.name.clone());
+                        events.push(event.clone());
+                    }
+                }
+
+                handlers.push(handler);
+            }
+        }
+
+        // Each submachine state accepts the child's events, wrapped in an
+        // event named after the state.
+        let mut submachine_states: Vec<&Ident> = Vec::new();
+        for submachine in handlers.iter().filter_map(|h| h.submachine.as_ref()) {
+            let forward = submachine.forward_event();
+            if submachine_states.contains(&forward) {
+                return Err(Error::new_spanned(
+                    forward,
+                    format!("State '{}' has more than one #[submachine]", forward),
+                ));
+            }
+            if event_names.contains(forward) {
+                return Err(Error::new_spanned(
+                    forward,
+                    format!(
+                        "Event '{}' clashes with the event forwarding to the submachine of state '{}'",
+                        forward, forward
+                    ),
+                ));
+            }
+            submachine_states.push(forward);
+            events.push(Event {
+                name: forward.clone(),
+                payload_type: Some(Type::Path(syn::TypePath {
+                    qself: None,
+                    path: submachine.child_type("Event"),
+                })),
+                fields: Vec::new(),
+            });
+        }
+
+        let states: Vec<State> = state_names.into_iter().map(|name| State { name }).collect();
+
+        if let Some(repr) = &args.state_repr {
+            // The largest discriminant each type can hold.
+            let max: u128 = match repr.to_string().as_str() {
+                "u8" => u8::MAX.into(),
+                "i8" => i8::MAX as u128,
+                "u16" => u16::MAX.into(),
+                "i16" => i16::MAX as u128,
+                "u32" | "usize" => u32::MAX.into(),
+                "i32" | "isize" => i32::MAX as u128,
+                "u64" => u64::MAX.into(),
+                "i64" => i64::MAX as u128,
+                _ => {
+                    return Err(Error::new_spanned(
+                        repr,
+                        format!(
+                            "Unsupported state_repr '{}'. Expected an integer type such as u8",
+                            repr
+                        ),
+                    ));
+                }
+            };
+            if states.len() as u128 - 1 > max {
+                return Err(Error::new_spanned(
+                    repr,
+                    format!("{} states don't fit in state_repr '{}'", states.len(), repr),
+                ));
+            }
+        }
+
+        for hook in &entry_hooks {
+            if !states.iter().any(|state| state.name == hook.state) {
+                return Err(Error::new_spanned(
+                    &hook.state,
+                    format!(
+                        "State '{}' in #[on_entry] is not a state of the FSM",
+                        hook.state
+                    ),
+                ));
+            }
+        }
+
+        // A dynamic handler without `#[targets]` may move to any state.
+        for handler in &mut handlers {
+            if handler.dynamic && !handler.narrowed {
+                handler.return_states = states.clone();
+            }
+        }
+
+        let mut external_events = Vec::new();
+        let mut schedules = Vec::new();
+        for attr in &impl_block.attrs {
+            if attr.path().is_ident("external_event") {
+                let external = attrs::ExternalEventAttr::from_meta(&attr.meta)?;
+                for (event, _) in &external.map.0 {
+                    if !event_names.contains(event) {
+                        return Err(Error::new_spanned(
+                            event,
+                            format!(
+                                "Event '{}' in #[external_event] is not handled by any #[on]",
+                                event
+                            ),
+                        ));
+                    }
+                }
+                external_events.push(ExternalEvent {
+                    source: external.from,
+                    mappings: external.map.0,
+                });
+            } else if attr.path().is_ident("schedule") {
+                let schedule = attrs::ScheduleAttr::from_meta(&attr.meta)?;
+                let Some(event) = events.iter().find(|e| e.name == schedule.event) else {
+                    return Err(Error::new_spanned(
+                        &schedule.event,
+                        format!(
+                            "Event '{}' in #[schedule] is not handled by any #[on]",
+                            schedule.event
+                        ),
+                    ));
+                };
+                if event.payload_type.is_some() {
+                    return Err(Error::new_spanned(
+                        &schedule.event,
+                        format!(
+                            "Event '{}' in #[schedule] carries a payload; scheduled events can't",
+                            schedule.event
+                        ),
+                    ));
+                }
+                #[cfg(feature = "cron")]
+                if let Err(err) = schedule.cron.value().parse::<cron::Schedule>() {
+                    return Err(Error::new_spanned(
+                        &schedule.cron,
+                        format!("Invalid cron expression: {}", err),
+                    ));
+                }
+                schedules.push(Schedule {
+                    cron: schedule.cron,
+                    event: schedule.event,
+                });
+            }
+        }
+
+        let fsm = Self {
+            fsm_name,
+            initial_state,
+            channel_size: args.channel_size,
+            channel,
+            serde: args.serde,
+            non_exhaustive: args.non_exhaustive,
+            state_repr: args.state_repr,
+            on_panic: args.on_panic,
+            restart,
+            on_last_handle,
+            runtime: args.runtime,
+            event_derives: args.event_derive.to_vec(),
+            emit_graph,
+            emit_path,
+            context_type,
+            has_context,
+            error_type,
+            output_type,
+            states,
+            events,
+            handlers,
+            external_events,
+            schedules,
+            persist,
+            on_shutdown,
+            entry_hooks,
+        };
+
+        fsm.validate()?;
+
+        Ok(fsm)
+    }
+
+    /// Validate the FSM graph for reachability.
+    ///
+    /// Constructs a directed graph where:
+    /// - **Nodes**: FSM states
+    /// - **Edges**: Transitions from declared source states to return states
+    ///
+    /// Checks:
+    /// 1. All declared states exist as nodes
+    /// 2. All states are reachable from the initial state
+    fn validate(&self) -> syn::Result<()> {
+        let mut graph = DiGraph::<&Ident, ()>::new();
+        let mut nodes = HashMap::new();
+
+        for state in &self.states {
+            let node = graph.add_node(&state.name);
+            nodes.insert(&state.name, node);
+        }
+
+        let initial_node = nodes.get(&self.initial_state).ok_or_else(|| {
+            syn::Error::new_spanned(
+                &self.initial_state,
+                "Initial state not found in discovered states",
+            )
+        })?;
+
+        for handler in &self.handlers {
+            for target in &handler.return_states {
+                let target_node = nodes.get(&target.name).ok_or_else(|| {
+                    syn::Error::new_spanned(
+                        &target.name,
+                        format!(
+                            "Target state '{}' not found in discovered states",
+                            target.name
+                        ),
+                    )
+                })?;
+
+                if handler.source_states.is_empty() {
+                    // Timeout handlers have no source states — they can fire from any
+                    // state that has a timeout. Add edges from all states.
+                    for &source_node in nodes.values() {
+                        graph.add_edge(source_node, *target_node, ());
+                    }
+                } else {
+                    // State-gated: add edges only from declared source states
+                    for source_ident in &handler.source_states {
+                        let source_node = nodes.get(source_ident).ok_or_else(|| {
+                            syn::Error::new_spanned(
+                                source_ident,
+                                format!(
+                                    "Source state '{}' in #[state(...)] not found in FSM states",
+                                    source_ident
+                                ),
+                            )
+                        })?;
+                        graph.add_edge(*source_node, *target_node, ());
+                    }
+                }
+            }
+        }
+
+        // A failing persist hook can divert any transition to its error state.
+        if let Some(persist) = &self.persist {
+            let target_node = nodes[&persist.on_error];
+            for &source_node in nodes.values() {
+                graph.add_edge(source_node, target_node, ());
+            }
+        }
+
+        // So can a panicking handler, to the `on_panic` state.
+        if let Some(on_panic) = &self.on_panic {
+            let target_node = nodes[on_panic];
+            for &source_node in nodes.values() {
+                graph.add_edge(source_node, target_node, ());
+            }
+        }
+
+        // A restart re-enters the FSM at `restart_from` from wherever it failed.
+        if let Some(restart) = &self.restart {
+            let target_node = nodes[&restart.from];
+            for &source_node in nodes.values() {
+                graph.add_edge(source_node, target_node, ());
+            }
+        }
+
+        self.validate_auto()?;
+
+        // Check reachability from initial state to all other states
+        for state in &self.states {
+            let state_name = &state.name;
+            if !has_path_connecting(&graph, *initial_node, nodes[state_name], None) {
+                return Err(syn::Error::new_spanned(
+                    state_name,
+                    format!(
+                        "State '{}' is unreachable from initial state '{}'",
+                        state_name, self.initial_state
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FsmStructure {
+    /// Checks that each state has at most one `#[auto]` handler and that
+    /// automatic transitions can't loop forever.
+    fn validate_auto(&self) -> syn::Result<()> {
+        let mut graph = DiGraph::<&Ident, ()>::new();
+        let mut nodes = HashMap::new();
+        for state in &self.states {
+            nodes.insert(&state.name, graph.add_node(&state.name));
+        }
+
+        let mut seen = HashSet::new();
+        for handler in &self.handlers {
+            let Some(state) = &handler.auto_state else {
+                continue;
+            };
+            if !seen.insert(state) {
+                return Err(Error::new_spanned(
+                    state,
+                    format!("State '{}' has more than one #[auto] handler", state),
+                ));
+            }
+            for target in &handler.return_states {
+                graph.add_edge(nodes[state], nodes[&target.name], ());
+            }
+        }
+
+        if let Err(cycle) = petgraph::algo::toposort(&graph, None) {
+            let state = graph[cycle.node_id()];
+            return Err(Error::new_spanned(
+                state,
+                format!("#[auto] handlers form a cycle through state '{}'", state),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler {
+    /// The only state this handler can transition to, unless it returns a
+    /// `Result` of two.
+    pub fn single_target(&self) -> Option<&Ident> {
+        match self.return_states.as_slice() {
+            [state] if !self.is_result => Some(&state.name),
+            _ => None,
+        }
+    }
+
+    /// Parse a method into a Handler with all semantic fields derived.
+    fn parse(method: &syn::ImplItemFn, fsm_name: &Ident) -> syn::Result<Self> {
+        let mut events: Vec<Event> = Vec::new();
+        let mut is_timeout_handler = false;
+        let mut auto_state = None;
+        let mut is_start_handler = false;
+        let mut is_orphaned_handler = false;
+        let mut preempt = false;
+        let mut coalesce: Vec<(Ident, Ident)> = Vec::new();
+        let mut state_timeout_attr = None;
+        let mut source_states = Vec::new();
+        let mut triggers = Vec::new();
+        let mut submachine: Option<Submachine> = None;
+        let mut targets: Option<Vec<Ident>> = None;
+
+        let args: Vec<&syn::PatType> = method
+            .sig
+            .inputs
+            .iter()
+            .skip(1)
+            .filter_map(|arg| match arg {
+                FnArg::Typed(pat_type) => Some(pat_type),
+                FnArg::Receiver(_) => None,
+            })
+            .collect();
+        let takes_payload = !args.is_empty();
+        let payload_borrowed = args.iter().map(|arg| payload_arg(&arg.ty).1).collect();
+        let payload_type = match args.as_slice() {
+            [arg] => Some(payload_arg(&arg.ty).0),
+            _ => None,
+        };
+        let payload_fields = if args.len() > 1 {
+            args.iter()
+                .map(|arg| match arg.pat.as_ref() {
+                    syn::Pat::Ident(pat) => {
+                        let name = pat.ident.to_string();
+                        let name = name.trim_start_matches('_');
+                        if name == "build" {
+                            return Err(Error::new_spanned(
+                                &pat.ident,
+                                "Payload argument `build` clashes with the event builder's `build` method",
+                            ));
+                        }
+                        Ok((format_ident!("{}", name), payload_arg(&arg.ty).0))
+                    }
+                    other => Err(Error::new_spanned(
+                        other,
+                        "Handlers taking several payload arguments need plain `name: Type` arguments",
+                    )),
+                })
+                .collect::<syn::Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        // Each event of a multi-argument handler carries its own payload struct.
+        let event_payload = |event: &Ident| {
+            if payload_fields.is_empty() {
+                payload_type.clone()
+            } else {
+                let ident = payload_struct_ident(fsm_name, event);
+                Some(syn::parse_quote!(#ident))
+            }
+        };
+
+        // Parse attributes
+        for attr in &method.attrs {
+            if attr.path().is_ident("on") {
+                let on_attr: attrs::OnAttr = attrs::OnAttr::from_meta(&attr.meta)?;
+                // Multiple #[on(...)] attributes are allowed for multi-state handlers,
+                // and `event = A | B` routes several events to the same handler.
+                if !source_states.contains(&on_attr.state) {
+                    source_states.push(on_attr.state.clone());
+                }
+                if let Some(field) = &on_attr.coalesce_by {
+                    if !takes_payload {
+                        return Err(Error::new_spanned(
+                            field,
+                            "coalesce_by requires an event payload to read the key from",
+                        ));
+                    }
+                    for name in &on_attr.event.0 {
+                        if !coalesce.iter().any(|(event, _)| event == name) {
+                            coalesce.push((name.clone(), field.clone()));
+                        }
+                    }
+                }
+                for name in on_attr.event.0 {
+                    if !events.iter().any(|e| e.name == name) {
+                        events.push(Event {
+                            name: name.clone(),
+                            payload_type: event_payload(&name),
+                            fields: payload_fields.clone(),
+                        });
+                    }
+                    triggers.push((on_attr.state.clone(), name));
+                }
+            } else if attr.path().is_ident("auto") {
+                let auto_attr = attrs::AutoAttr::from_meta(&attr.meta)?;
+                if auto_state.is_some() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "A handler can only have one #[auto] attribute",
+                    ));
+                }
+                if !source_states.contains(&auto_attr.state) {
+                    source_states.push(auto_attr.state.clone());
+                }
+                auto_state = Some(auto_attr.state);
+            } else if attr.path().is_ident("submachine") {
+                let sub_attr = attrs::SubmachineAttr::from_meta(&attr.meta)?;
+                if submachine.is_some() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "A handler can only have one #[submachine] attribute",
+                    ));
+                }
+                let sub = Submachine {
+                    state: sub_attr.state,
+                    fsm: sub_attr.fsm,
+                };
+                let done = sub.done_event();
+                if !source_states.contains(&sub.state) {
+                    source_states.push(sub.state.clone());
+                }
+                events.push(Event {
+                    name: done.clone(),
+                    payload_type: payload_type.clone(),
+                    fields: Vec::new(),
+                });
+                triggers.push((sub.state.clone(), done));
+                submachine = Some(sub);
+            } else if attr.path().is_ident("preempt") {
+                attr.meta.require_path_only()?;
+                preempt = true;
+            } else if attr.path().is_ident("on_start") {
+                attr.meta.require_path_only()?;
+                is_start_handler = true;
+            } else if attr.path().is_ident("on_orphaned") {
+                attr.meta.require_path_only()?;
+                is_orphaned_handler = true;
+            } else if attr.path().is_ident("on_timeout") {
+                is_timeout_handler = true;
+            } else if attr.path().is_ident("targets") {
+                let list = attr.parse_args_with(
+                    syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated,
+                )?;
+                if list.is_empty() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "#[targets] needs at least one state",
+                    ));
+                }
+                targets = Some(list.into_iter().collect());
+            } else if attr.path().is_ident("state_timeout") {
+                state_timeout_attr = Some(attrs::StateTimeoutAttr::from_meta(&attr.meta)?);
+            }
+        }
+
+        if auto_state.is_some() {
+            if !events.is_empty() || is_timeout_handler {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    "#[auto] handlers cannot also be #[on] or #[on_timeout] handlers",
+                ));
+            }
+            if takes_payload {
+                return Err(Error::new_spanned(
+                    &method.sig.inputs,
+                    "#[auto] handlers take no payload",
+                ));
+            }
+        }
+
+        if is_start_handler {
+            if !events.is_empty() || is_timeout_handler || auto_state.is_some() {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    "#[on_start] handlers cannot also be #[on], #[auto] or #[on_timeout] handlers",
+                ));
+            }
+            if takes_payload {
+                return Err(Error::new_spanned(
+                    &method.sig.inputs,
+                    "#[on_start] handlers take no payload",
+                ));
+            }
+        }
+
+        if is_orphaned_handler {
+            if !events.is_empty() || is_timeout_handler || auto_state.is_some() || is_start_handler
+            {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    "#[on_orphaned] handlers cannot also be #[on], #[auto], #[on_start] or #[on_timeout] handlers",
+                ));
+            }
+            if takes_payload {
+                return Err(Error::new_spanned(
+                    &method.sig.inputs,
+                    "#[on_orphaned] handlers take no payload",
+                ));
+            }
+        }
+
+        if let Some(sub) = &submachine {
+            if events.len() > 1 || is_timeout_handler || auto_state.is_some() {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    "#[submachine] handlers cannot also be #[on], #[auto] or #[on_timeout] handlers",
+                ));
+            }
+            if payload_type.is_none() {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    format!(
+                        "#[submachine] handlers take the child's final state, e.g. `outcome: {}`",
+                        sub.child_type("State")
+                            .segments
+                            .last()
+                            .map_or(String::new(), |s| s.ident.to_string())
+                    ),
+                ));
+            }
+        }
+
+        if preempt && events.is_empty() {
+            return Err(Error::new_spanned(
+                &method.sig.ident,
+                "#[preempt] requires an #[on(...)] attribute",
+            ));
+        }
+
+        // Derive: has_payload
+        let has_payload = !events.is_empty() && takes_payload;
+
+        // Derive: is_result / is_fallible, told apart by the `Err` type
+        let (is_result, is_fallible) = match result_err_type(&method.sig.output) {
+            Some(err) if is_transition(err) => (true, false),
+            Some(_) => (false, true),
+            None => (false, false),
+        };
+
+        // Derive: timeout (fail loudly on invalid duration)
+        let timeout = if let Some(ref st) = state_timeout_attr {
+            let duration_str = st.duration.value();
+            let parsed = humantime::parse_duration(&duration_str).map_err(|e| {
+                syn::Error::new_spanned(
+                    &st.duration,
+                    format!("Invalid duration '{}': {}", duration_str, e),
+                )
+            })?;
+            Some(parsed)
+        } else {
+            None
+        };
+
+        // Extract return states from return type
+        let mut return_states = extract_return_states(&method.sig.output)?;
+        let state_enum = format_ident!("{}State", fsm_name);
+        let dynamic = return_states.iter().any(|state| state.name == state_enum);
+        if dynamic {
+            if is_result {
+                return Err(Error::new_spanned(
+                    &method.sig.output,
+                    format!(
+                        "Handlers returning Transition<{}> can't also return a Result of two transitions",
+                        state_enum
+                    ),
+                ));
+            }
+            if let Some(st) = &state_timeout_attr {
+                return Err(Error::new_spanned(
+                    &st.duration,
+                    "#[state_timeout] needs a statically known target state",
+                ));
+            }
+        }
+        let narrowed = targets.is_some();
+        let converts = match &method.sig.output {
+            ReturnType::Type(_, ty) => contains_impl_into(ty),
+            ReturnType::Default => false,
+        };
+        match targets {
+            Some(targets) if dynamic => {
+                return_states = targets.into_iter().map(|name| State { name }).collect();
+            }
+            Some(targets) => {
+                return Err(Error::new_spanned(
+                    &targets[0],
+                    format!(
+                        "#[targets] requires a handler returning Transition<{}>",
+                        state_enum
+                    ),
+                ));
+            }
+            None if dynamic => return_states.clear(),
+            None => {}
+        }
+
+        Ok(Self {
+            method: method.clone(),
+            events,
+            is_timeout_handler,
+            auto_state,
+            is_start_handler,
+            is_orphaned_handler,
+            return_states,
+            dynamic,
+            narrowed,
+            converts,
+            source_states,
+            triggers,
+            has_payload,
+            payload_fields,
+            payload_borrowed,
+            coalesce,
+            preempt,
+            is_result,
+            is_fallible,
+            timeout,
+            submachine,
+        })
+    }
+}
+
+/// The type an event carries for a handler's payload argument, and whether the
+/// handler borrows it. `&Job` with an elided lifetime borrows a `Job` owned by
+/// the run loop; references with a named lifetime, like `&'static str`, are
+/// carried as they are.
+fn payload_arg(ty: &Type) -> (Type, bool) {
+    match ty {
+        Type::Reference(reference)
+            if reference.lifetime.is_none() && reference.mutability.is_none() =>
+        {
+            ((*reference.elem).clone(), true)
+        }
+        _ => (ty.clone(), false),
+    }
+}
+
+/// Name of the payload struct generated for `event` when its handler takes
+/// several payload arguments.
+pub fn payload_struct_ident(fsm_name: &Ident, event: &Ident) -> Ident {
+    format_ident!("{}{}Payload", fsm_name, event)
+}
+
+/// The `Err` type of a handler returning `Result<_, E>`.
+fn result_err_type(output: &ReturnType) -> Option<&Type> {
+    let ReturnType::Type(_, ty) = output else {
+        return None;
+    };
+    let Type::Path(path) = ty.as_ref() else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    args.args
+        .iter()
+        .filter_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        })
+        .nth(1)
+}
+
+fn is_transition(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "Transition"),
+        Type::ImplTrait(impl_trait) => into_target(impl_trait).is_some_and(is_transition),
+        _ => false,
+    }
+}
+
+/// The `T` of an `impl Into<T>` type.
+fn into_target(impl_trait: &syn::TypeImplTrait) -> Option<&Type> {
+    impl_trait.bounds.iter().find_map(|bound| {
+        let syn::TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last()?;
+        if segment.ident != "Into" {
+            return None;
+        }
+        let PathArguments::AngleBracketed(args) = &segment.arguments else {
+            return None;
+        };
+        args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        })
+    })
+}
+
+/// Whether a return type is, or wraps in a `Result`, an `impl Into<_>`.
+fn contains_impl_into(ty: &Type) -> bool {
+    match ty {
+        Type::ImplTrait(impl_trait) => into_target(impl_trait).is_some(),
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+            segment.ident == "Result"
+                && matches!(&segment.arguments, PathArguments::AngleBracketed(args)
+                    if args.args.iter().any(|arg| matches!(arg, GenericArgument::Type(ty) if contains_impl_into(ty))))
+        }),
+        _ => false,
+    }
+}
+
+/// Extract state names from a return type (Transition<State> or
+/// Result<Transition<State>, Transition<State>>).
+fn extract_return_states(output: &ReturnType) -> syn::Result<Vec<State>> {
+    let return_type = match output {
+        ReturnType::Type(_, ty) => ty.as_ref(),
+        ReturnType::Default => return Ok(Vec::new()),
+    };
+
+    let mut states = Vec::new();
+    extract_states_recursive(return_type, &mut states)?;
+    Ok(states)
+}
+
+fn extract_states_recursive(ty: &Type, states: &mut Vec<State>) -> syn::Result<()> {
+    if let Type::ImplTrait(impl_trait) = ty
+        && let Some(target) = into_target(impl_trait)
+    {
+        return extract_states_recursive(target, states);
+    }
+    if let Type::Path(path) = ty
+        && let Some(segment) = path.path.segments.last()
+    {
+        if segment.ident == "Transition" {
+            if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                for arg in &args.args {
+                    if let GenericArgument::Type(Type::Path(inner_path)) = arg
+                        && let Some(state_seg) = inner_path.path.segments.last()
+                    {
+                        states.push(State {
+                            name: state_seg.ident.clone(),
+                        });
+                    }
+                }
+            }
+        } else if segment.ident == "Result"
+            && let PathArguments::AngleBracketed(args) = &segment.arguments
+        {
+            for arg in &args.args {
+                if let GenericArgument::Type(inner_ty) = arg {
+                    extract_states_recursive(inner_ty, states)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
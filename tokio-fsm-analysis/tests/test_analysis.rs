@@ -0,0 +1,224 @@
+use tokio_fsm_analysis::{FsmStructure, parse_source, validation::Trigger};
+
+const V1: &str = r#"
+    #[fsm(initial = Idle)]
+    impl OrderFsm {
+        type Context = ();
+        type Error = std::convert::Infallible;
+
+        #[on(state = Idle, event = Start)]
+        #[state_timeout(duration = "5s")]
+        async fn start(&mut self) -> Transition<Running> {
+            Transition::to(Running)
+        }
+
+        #[on(state = Running, event = Finish)]
+        async fn finish(&mut self) -> Transition<Done> {
+            Transition::to(Done)
+        }
+
+        #[on_timeout]
+        async fn timeout(&mut self) -> Transition<Idle> {
+            Transition::to(Idle)
+        }
+    }
+"#;
+
+const V1_TABLE: &str = r#"
+    fsm_table! {
+        OrderFsm {
+            type Context = ();
+
+            Idle + Start => Running,
+            Running + Finish [finish] => Done,
+        }
+    }
+"#;
+
+fn parse_one(source: &str) -> FsmStructure {
+    let mut machines = parse_source(source).unwrap();
+    assert_eq!(machines.len(), 1);
+    machines.remove(0).unwrap()
+}
+
+#[test]
+fn test_metrics() {
+    let fsm = parse_one(V1);
+    let metrics = fsm.metrics();
+
+    assert_eq!(metrics.states, 3);
+    assert_eq!(metrics.events, 2);
+    // Start, Finish and the Running timeout.
+    assert_eq!(metrics.transitions, 3);
+    assert_eq!(metrics.terminal_states, vec!["Done"]);
+}
+
+#[test]
+fn test_diff_between_versions() {
+    let v2 = V1
+        .replace("event = Finish", "event = Complete")
+        .replace("#[state_timeout(duration = \"5s\")]", "");
+    let (old, new) = (parse_one(V1), parse_one(&v2));
+
+    let diff = old.diff(&new);
+    assert_eq!(diff.added_events, vec!["Complete"]);
+    assert_eq!(diff.removed_events, vec!["Finish"]);
+    assert!(diff.removed_transitions.contains(&(
+        "Running".to_string(),
+        "timeout (5s)".to_string(),
+        "Idle".to_string()
+    )));
+    assert!(diff.added_states.is_empty());
+    assert!(old.diff(&old).is_empty());
+}
+
+#[test]
+fn test_invalid_machines_report_errors() {
+    let source = r#"
+        mod inner {
+            #[fsm(initial = Idle)]
+            impl BrokenFsm {
+                #[on(state = Ghost, event = Boo)]
+                async fn haunt(&mut self) -> Transition<Idle> {
+                    Transition::to(Idle)
+                }
+            }
+        }
+    "#;
+    let machines = parse_source(source).unwrap();
+    let err = machines[0].as_ref().unwrap_err();
+    assert!(err.to_string().contains("unreachable"));
+}
+
+#[test]
+fn test_auto_transitions() {
+    let fsm = parse_one(
+        r#"
+        #[fsm(initial = Idle)]
+        impl AutoFsm {
+            #[on(state = Idle, event = Submit)]
+            async fn submit(&mut self) -> Transition<Checking> {
+                Transition::to(Checking)
+            }
+
+            #[auto(state = Checking)]
+            async fn check(&mut self) -> Transition<Done> {
+                Transition::to(Done)
+            }
+        }
+    "#,
+    );
+    assert!(
+        tokio_fsm_analysis::graph::mermaid(&fsm).contains("Checking --> Done: auto"),
+        "{}",
+        tokio_fsm_analysis::graph::mermaid(&fsm)
+    );
+
+    let looping = r#"
+        #[fsm(initial = Idle)]
+        impl LoopFsm {
+            #[on(state = Idle, event = Start)]
+            async fn start(&mut self) -> Transition<Ping> {
+                Transition::to(Ping)
+            }
+
+            #[auto(state = Ping)]
+            async fn ping(&mut self) -> Transition<Pong> {
+                Transition::to(Pong)
+            }
+
+            #[auto(state = Pong)]
+            async fn pong(&mut self) -> Transition<Ping> {
+                Transition::to(Ping)
+            }
+        }
+    "#;
+    let machines = parse_source(looping).unwrap();
+    let err = machines[0].as_ref().unwrap_err();
+    assert!(err.to_string().contains("cycle"), "{err}");
+}
+
+#[test]
+fn test_transition_tables() {
+    let (attribute, table) = (parse_one(V1), parse_one(V1_TABLE));
+    assert_eq!(table.fsm_name, "OrderFsm");
+    assert_eq!(table.metrics().transitions, 2);
+    // The same machine minus the timeout.
+    let diff = attribute.diff(&table);
+    assert_eq!(diff.removed_transitions.len(), 1);
+    assert!(diff.added_transitions.is_empty());
+
+    let conflicting = r#"
+        tokio_fsm::fsm_table! {
+            ConflictFsm {
+                Idle + Start => Running,
+                Idle + Start => Done,
+            }
+        }
+    "#;
+    let machines = parse_source(conflicting).unwrap();
+    let err = machines[0].as_ref().unwrap_err();
+    assert!(err.to_string().contains("more than one row"), "{err}");
+}
+
+#[test]
+fn test_dot_rendering() {
+    let dot = tokio_fsm_analysis::graph::dot(&parse_one(V1));
+    assert_eq!(
+        dot,
+        concat!(
+            "digraph OrderFsm {\n",
+            "    rankdir=LR;\n",
+            "    __start [shape=point];\n",
+            "    __start -> Idle;\n",
+            "    Idle -> Running [label=\"Start\"];\n",
+            "    Running -> Done [label=\"Finish\"];\n",
+            "    Running -> Idle [label=\"timeout (5s)\", style=dashed];\n",
+            "}\n",
+        )
+    );
+
+    let err = parse_source(&V1.replace("initial = Idle", "initial = Idle, emit_graph = \"svg\""))
+        .unwrap()
+        .remove(0)
+        .unwrap_err();
+    assert!(err.to_string().contains("Unknown graph format"), "{err}");
+}
+
+#[test]
+fn test_dynamic_targets() {
+    let source = r#"
+        #[fsm(initial = Idle)]
+        impl RouterFsm {
+            #[on(state = Idle, event = Route)]
+            #[targets(Fast, Slow)]
+            async fn route(&mut self, size: u64) -> Transition<RouterFsmState> {
+                Transition::to(RouterFsmState::Fast)
+            }
+
+            #[on(state = Fast, event = Reset)]
+            #[on(state = Slow, event = Reset)]
+            async fn reset(&mut self) -> Transition<RouterFsmState> {
+                Transition::to(RouterFsmState::Idle)
+            }
+        }
+    "#;
+    let fsm = parse_one(source);
+    let targets = |from: &str, event: &str| -> Vec<String> {
+        fsm.edges()
+            .into_iter()
+            .filter(|e| e.from == from)
+            .filter(|e| matches!(&e.trigger, Trigger::Event(name) if name == event))
+            .map(|e| e.to.to_string())
+            .collect()
+    };
+    assert_eq!(targets("Idle", "Route"), ["Fast", "Slow"]);
+    assert_eq!(targets("Slow", "Reset"), ["Idle", "Fast", "Slow"]);
+
+    let static_target = source.replace(
+        "-> Transition<RouterFsmState> {\n                Transition::to(RouterFsmState::Fast)",
+        "-> Transition<Fast> {\n                Transition::to(Fast)",
+    );
+    let err = parse_source(&static_target).unwrap().remove(0).unwrap_err();
+    assert!(err.to_string().contains("#[targets] requires"), "{err}");
+}
@@ -1,10 +1,14 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::ItemImpl;
-
-use crate::validation::FsmStructure;
+use syn::{
+    ItemImpl, Type,
+    visit_mut::{self, VisitMut},
+};
+use tokio_fsm_analysis::validation::FsmStructure;
 
+pub mod channel;
 pub mod enums;
+pub mod graph;
 pub mod impls;
 pub mod structs;
 
@@ -18,16 +22,43 @@ pub fn generate(fsm: &FsmStructure, original_impl: &ItemImpl) -> TokenStream {
     // Generate type definitions
     let state_enum = enums::render_state_enum(fsm);
     let event_enum = enums::render_event_enum(fsm);
+    let payload_builders = enums::render_payload_builders(fsm);
+    let payload_conversions = enums::render_payload_conversions(fsm);
+    let external_conversions = enums::render_external_conversions(fsm);
 
     let fsm_struct = structs::render_fsm_struct(fsm);
     let handle_struct = structs::render_handle_struct(fsm);
+    let sender_struct = structs::render_sender_struct(fsm);
+    let builder_struct = structs::render_builder_struct(fsm);
+    let typed_handle_struct = structs::render_typed_handle_struct(fsm);
     let task_struct = structs::render_task_struct(fsm);
+    let core_struct = structs::render_core_struct(fsm);
 
     // Generate implementations
     let spawn_impl = impls::render_spawn(fsm);
     let run_impl = impls::render_run(fsm);
+    let emit_impl = impls::render_emit(fsm);
+    let last_panic_impl = impls::render_last_panic(fsm);
+    let current_event_impl = impls::render_current_event();
+    let event_meta_impl = impls::render_event_meta();
+    let self_handle_impl = impls::render_self_handle(fsm);
+    let is_preempting_impl = impls::render_is_preempting(fsm);
+    let event_index_impl = impls::render_event_index(fsm);
+    let spawn_work_impl = impls::render_spawn_work(fsm);
+    let spawn_child_impl = impls::render_spawn_child(fsm);
+    let coalesce_key_impl = impls::render_coalesce_key(fsm);
+    let mermaid_impl = graph::render_mermaid_fn(fsm);
+    let definition_impl = graph::render_definition_fn(fsm);
+    let transitions_impl = graph::render_transitions_fn(fsm);
     let handle_impl = impls::render_handle_impl(fsm);
+    let event_methods_impl = impls::render_event_methods(fsm);
+    let sender_impl = impls::render_sender_impl(fsm);
+    let builder_impl = impls::render_builder_impl(fsm);
+    let typed_handle_impl = impls::render_typed_handle_impl(fsm);
     let task_impl = impls::render_task_impl(fsm);
+    let submachine_impl = impls::render_submachine_impl(fsm);
+    let core_impl = impls::render_core_impl(fsm);
+    let machine_impl = impls::render_machine_impl(fsm);
 
     // Strip macro attributes from original methods, remove associated types
     let cleaned_items: Vec<syn::ImplItem> = original_methods
@@ -39,7 +70,20 @@ pub fn generate(fsm: &FsmStructure, original_impl: &ItemImpl) -> TokenStream {
                     !attr.path().is_ident("on")
                         && !attr.path().is_ident("state_timeout")
                         && !attr.path().is_ident("on_timeout")
+                        && !attr.path().is_ident("on_start")
+                        && !attr.path().is_ident("on_orphaned")
+                        && !attr.path().is_ident("on_entry")
+                        && !attr.path().is_ident("persist")
+                        && !attr.path().is_ident("on_shutdown")
+                        && !attr.path().is_ident("auto")
+                        && !attr.path().is_ident("preempt")
+                        && !attr.path().is_ident("submachine")
+                        && !attr.path().is_ident("targets")
                 });
+                ResolveSelfTypes { fsm }.visit_impl_item_fn_mut(&mut method);
+                if let syn::ReturnType::Type(_, ty) = &mut method.sig.output {
+                    capture_nothing(ty);
+                }
                 Some(syn::ImplItem::Fn(method))
             }
             syn::ImplItem::Type(_) => None,
@@ -50,19 +94,108 @@ pub fn generate(fsm: &FsmStructure, original_impl: &ItemImpl) -> TokenStream {
     quote! {
         #state_enum
         #event_enum
+        #payload_builders
+        #payload_conversions
+        #external_conversions
 
         #fsm_struct
         #handle_struct
+        #sender_struct
+        #builder_struct
+        #typed_handle_struct
         #task_struct
+        #core_struct
 
         impl #fsm_name {
             #spawn_impl
             #run_impl
+            #emit_impl
+            #last_panic_impl
+            #current_event_impl
+            #event_meta_impl
+            #self_handle_impl
+            #is_preempting_impl
+            #event_index_impl
+            #spawn_work_impl
+            #spawn_child_impl
+            #coalesce_key_impl
+            #mermaid_impl
+            #definition_impl
+            #transitions_impl
 
             #(#cleaned_items)*
         }
 
         #handle_impl
+        #event_methods_impl
+        #sender_impl
+        #typed_handle_impl
+        #builder_impl
         #task_impl
+        #submachine_impl
+        #machine_impl
+        #core_impl
+    }
+}
+
+/// Adds `use<>` to `impl Into<Transition<_>>` return types, which would
+/// otherwise capture the `&mut self` borrow and keep the FSM borrowed until
+/// the transition is committed.
+fn capture_nothing(ty: &mut Type) {
+    match ty {
+        Type::ImplTrait(impl_trait) => {
+            let into = impl_trait.bounds.iter().any(|bound| {
+                matches!(bound, syn::TypeParamBound::Trait(bound)
+                    if bound.path.segments.last().is_some_and(|segment| segment.ident == "Into"))
+            });
+            let captures = impl_trait
+                .bounds
+                .iter()
+                .any(|bound| matches!(bound, syn::TypeParamBound::PreciseCapture(_)));
+            if into && !captures {
+                impl_trait.bounds.push(syn::parse_quote!(use<>));
+            }
+        }
+        Type::Path(path) => {
+            if let Some(segment) = path.path.segments.last_mut()
+                && segment.ident == "Result"
+                && let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments
+            {
+                for arg in &mut args.args {
+                    if let syn::GenericArgument::Type(ty) = arg {
+                        capture_nothing(ty);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces `Self::Context` and `Self::Error` in handlers, e.g. a return type
+/// of `Result<Transition<Done>, Self::Error>`, with the declared types, since
+/// the associated types themselves are removed from the generated impl.
+struct ResolveSelfTypes<'a> {
+    fsm: &'a FsmStructure,
+}
+
+impl VisitMut for ResolveSelfTypes<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty
+            && path.qself.is_none()
+            && let [owner, name] = path.path.segments.iter().collect::<Vec<_>>()[..]
+            && owner.ident == "Self"
+            && name.arguments.is_none()
+        {
+            if name.ident == "Context" {
+                *ty = self.fsm.context_type.clone();
+                return;
+            }
+            if name.ident == "Error" {
+                *ty = self.fsm.error_type.clone();
+                return;
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
     }
 }
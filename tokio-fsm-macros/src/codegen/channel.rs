@@ -0,0 +1,153 @@
+//! Shims over the event channel backend selected with `#[fsm(channel = ...)]`.
+//!
+//! Every backend is adapted to tokio's `SendError`/`TrySendError` so the
+//! handle API is identical regardless of the channel in use.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use tokio_fsm_analysis::validation::{ChannelBackend, FsmStructure};
+
+/// `let (event_tx, event_rx) = ...;`
+pub fn render_create(fsm: &FsmStructure) -> TokenStream {
+    let channel_size = fsm.channel_size;
+    let create = match fsm.channel {
+        ChannelBackend::Tokio => quote! { tokio_fsm::__private::rt::mpsc::channel(#channel_size) },
+        ChannelBackend::Flume => quote! { tokio_fsm::__private::flume::bounded(#channel_size) },
+        ChannelBackend::Kanal => {
+            quote! { tokio_fsm::__private::kanal::bounded_async(#channel_size) }
+        }
+    };
+
+    quote! { let (event_tx, event_rx) = #create; }
+}
+
+pub fn sender_type(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+    match fsm.channel {
+        ChannelBackend::Tokio => {
+            quote! { tokio::sync::mpsc::Sender<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+        ChannelBackend::Flume => {
+            quote! { tokio_fsm::__private::flume::Sender<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+        ChannelBackend::Kanal => {
+            quote! { tokio_fsm::__private::kanal::AsyncSender<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+    }
+}
+
+/// The type of the receiving end of the event queue.
+pub fn receiver_type(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+    match fsm.channel {
+        ChannelBackend::Tokio => {
+            quote! { tokio::sync::mpsc::Receiver<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+        ChannelBackend::Flume => {
+            quote! { tokio_fsm::__private::flume::Receiver<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+        ChannelBackend::Kanal => {
+            quote! { tokio_fsm::__private::kanal::AsyncReceiver<tokio_fsm::Envelope<#event_enum_name>> }
+        }
+    }
+}
+
+/// Body of `Handle::send`, sending `envelope` through `self.event_tx`. On
+/// failure the error carries the bare event.
+pub fn render_send(fsm: &FsmStructure) -> TokenStream {
+    match fsm.channel {
+        ChannelBackend::Tokio => quote! {
+            self.event_tx
+                .send(envelope)
+                .await
+                .map_err(|err| tokio::sync::mpsc::error::SendError(err.0.event))
+        },
+        ChannelBackend::Flume => quote! {
+            self.event_tx
+                .send_async(envelope)
+                .await
+                .map_err(|err| tokio::sync::mpsc::error::SendError(err.into_inner().event))
+        },
+        // kanal drops the value on a failed async send, so the slow path
+        // keeps a clone to hand back if the FSM has stopped.
+        ChannelBackend::Kanal => quote! {
+            let mut slot = Some(envelope);
+            match self.event_tx.try_send_option(&mut slot) {
+                Ok(true) => Ok(()),
+                Ok(false) => {
+                    let envelope = slot.take().expect("kanal keeps unsent values");
+                    let retained = envelope.event.clone();
+                    self.event_tx
+                        .send(envelope)
+                        .await
+                        .map_err(|_| tokio::sync::mpsc::error::SendError(retained))
+                }
+                Err(_) => Err(tokio::sync::mpsc::error::SendError(
+                    slot.take().expect("kanal keeps unsent values").event,
+                )),
+            }
+        },
+    }
+}
+
+/// Body of `Handle::try_send`, like [`render_send`].
+pub fn render_try_send(fsm: &FsmStructure) -> TokenStream {
+    match fsm.channel {
+        ChannelBackend::Tokio => quote! {
+            self.event_tx.try_send(envelope).map_err(|err| match err {
+                tokio::sync::mpsc::error::TrySendError::Full(envelope) => {
+                    tokio::sync::mpsc::error::TrySendError::Full(envelope.event)
+                }
+                tokio::sync::mpsc::error::TrySendError::Closed(envelope) => {
+                    tokio::sync::mpsc::error::TrySendError::Closed(envelope.event)
+                }
+            })
+        },
+        ChannelBackend::Flume => quote! {
+            self.event_tx.try_send(envelope).map_err(|err| match err {
+                tokio_fsm::__private::flume::TrySendError::Full(envelope) => {
+                    tokio::sync::mpsc::error::TrySendError::Full(envelope.event)
+                }
+                tokio_fsm::__private::flume::TrySendError::Disconnected(envelope) => {
+                    tokio::sync::mpsc::error::TrySendError::Closed(envelope.event)
+                }
+            })
+        },
+        ChannelBackend::Kanal => quote! {
+            let mut slot = Some(envelope);
+            match self.event_tx.try_send_option(&mut slot) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(tokio::sync::mpsc::error::TrySendError::Full(
+                    slot.take().expect("kanal keeps unsent values").event,
+                )),
+                Err(_) => Err(tokio::sync::mpsc::error::TrySendError::Closed(
+                    slot.take().expect("kanal keeps unsent values").event,
+                )),
+            }
+        },
+    }
+}
+
+/// Number of events waiting in the queue behind `self.event_tx`.
+pub fn render_len(fsm: &FsmStructure) -> TokenStream {
+    match fsm.channel {
+        ChannelBackend::Tokio => {
+            quote! { self.event_tx.max_capacity() - self.event_tx.capacity() }
+        }
+        ChannelBackend::Flume | ChannelBackend::Kanal => quote! { self.event_tx.len() },
+    }
+}
+
+/// Body of the sender's `reserve`: a slot of the queue, where the backend
+/// can hold one ahead of the send.
+pub fn render_reserve(fsm: &FsmStructure) -> TokenStream {
+    match fsm.channel {
+        ChannelBackend::Tokio => quote! {
+            match self.event_tx.clone().reserve_owned().await {
+                Ok(permit) => tokio_fsm::Reservation::permit(permit),
+                Err(_) => tokio_fsm::Reservation::none(),
+            }
+        },
+        ChannelBackend::Flume | ChannelBackend::Kanal => quote! { tokio_fsm::Reservation::none() },
+    }
+}
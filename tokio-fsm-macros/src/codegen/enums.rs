@@ -1,11 +1,20 @@
 use proc_macro2::TokenStream;
-use quote::quote;
-
-use crate::validation::FsmStructure;
+use quote::{format_ident, quote};
+use syn::Ident;
+use tokio_fsm_analysis::{
+    graph,
+    validation::{self, Edge, FsmStructure, Trigger},
+};
 
 pub fn render_state_enum(fsm: &FsmStructure) -> TokenStream {
     let states: Vec<_> = fsm.states.iter().map(|s| &s.name).collect();
     let state_enum_name = fsm.state_enum_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let edges = fsm.edges();
+    let state_docs: Vec<String> = states
+        .iter()
+        .map(|state| state_doc(fsm, &edges, state))
+        .collect();
 
     let state_structs: Vec<_> = fsm
         .states
@@ -13,6 +22,18 @@ pub fn render_state_enum(fsm: &FsmStructure) -> TokenStream {
         .map(|s| {
             let name = &s.name;
             let enum_name = &state_enum_name;
+            let entry_data = fsm
+                .entry_hooks
+                .iter()
+                .find(|hook| hook.state == *name)
+                .and_then(|hook| hook.data.as_ref())
+                .map(|ty| {
+                    quote! {
+                        impl tokio_fsm::EntryData for #name {
+                            type Data = #ty;
+                        }
+                    }
+                });
             quote! {
                 #[derive(Debug, Clone, Copy)]
                 pub struct #name;
@@ -21,40 +42,545 @@ pub fn render_state_enum(fsm: &FsmStructure) -> TokenStream {
                         #enum_name::#name
                     }
                 }
+                impl tokio_fsm::FsmState for #name {
+                    type Event = #event_enum_name;
+                }
+                #entry_data
             }
         })
         .collect();
 
+    let state_count = states.len();
+
+    // Events with a handler in each state, deduplicated in edge order.
+    let valid_event_arms: Vec<TokenStream> = fsm
+        .states
+        .iter()
+        .map(|state| {
+            let mut names: Vec<String> = Vec::new();
+            for edge in &edges {
+                if let Trigger::Event(event) = &edge.trigger
+                    && edge.from == state.name
+                    && !names.contains(&event.to_string())
+                {
+                    names.push(event.to_string());
+                }
+            }
+            if fsm.forwarded_to(&state.name).is_some() {
+                names.push(state.name.to_string());
+            }
+            let name = &state.name;
+            quote! { Self::#name => &[#(#names),*], }
+        })
+        .collect();
+    let terminal_states: Vec<_> = fsm
+        .states
+        .iter()
+        .map(|s| &s.name)
+        .filter(|state| !edges.iter().any(|e| e.from == **state))
+        .collect();
+    let is_terminal = if terminal_states.is_empty() {
+        quote! { false }
+    } else {
+        quote! { matches!(self, #(Self::#terminal_states)|*) }
+    };
+    // `is_running()` for `Running`, except where it would shadow `is_terminal`.
+    let predicates = states.iter().filter_map(|state| {
+        let method = format_ident!(
+            "is_{}",
+            super::impls::snake_case(state),
+            span = state.span()
+        );
+        if method == "is_terminal" {
+            return None;
+        }
+        let doc = format!(" Returns `true` if this is `{}`.", state);
+        Some(quote! {
+            #[doc = #doc]
+            pub fn #method(&self) -> bool {
+                matches!(self, Self::#state)
+            }
+        })
+    });
+    let serde_derive = render_serde_derive(fsm);
+    let non_exhaustive = render_non_exhaustive(fsm);
+    let (repr, discriminants, repr_conversions) = render_state_repr(fsm);
+
     quote! {
         #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #serde_derive
+        #non_exhaustive
+        #repr
         pub enum #state_enum_name {
-            #(#states,)*
+            #(#[doc = #state_docs] #states #discriminants,)*
+        }
+
+        #repr_conversions
+
+        // Dynamic handlers return a transition to the state enum.
+        impl tokio_fsm::FsmState for #state_enum_name {
+            type Event = #event_enum_name;
         }
 
         #(#state_structs)*
+
+        impl #state_enum_name {
+            /// Every state of the FSM, initial state first.
+            pub const ALL: [Self; #state_count] = [#(Self::#states),*];
+
+            /// Returns the names of the events that have a handler in this state.
+            pub fn valid_events(&self) -> &'static [&'static str] {
+                match self {
+                    #(#valid_event_arms)*
+                }
+            }
+
+            /// Returns `true` for states no transition leaves, which end the
+            /// FSM's run as a `#[submachine]`.
+            pub fn is_terminal(&self) -> bool {
+                #is_terminal
+            }
+
+            #(#predicates)*
+
+            /// Returns the name of the state, e.g. for log fields or metric labels.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    #(Self::#states => stringify!(#states),)*
+                }
+            }
+        }
+
+        impl std::fmt::Display for #state_enum_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for #state_enum_name {
+            type Err = tokio_fsm::ParseNameError;
+
+            fn from_str(name: &str) -> Result<Self, Self::Err> {
+                match name {
+                    #(stringify!(#states) => Ok(Self::#states),)*
+                    _ => Err(tokio_fsm::ParseNameError::new(name, stringify!(#state_enum_name))),
+                }
+            }
+        }
+
+        impl TryFrom<&str> for #state_enum_name {
+            type Error = tokio_fsm::ParseNameError;
+
+            fn try_from(name: &str) -> Result<Self, tokio_fsm::ParseNameError> {
+                name.parse()
+            }
+        }
     }
 }
 
 pub fn render_event_enum(fsm: &FsmStructure) -> TokenStream {
+    let edges = fsm.edges();
     let variants: Vec<TokenStream> = fsm
         .events
         .iter()
         .map(|event| {
             let event_name = &event.name;
+            let doc = match fsm.forwarded_to(event_name) {
+                Some(submachine) => {
+                    let child = &submachine.fsm;
+                    format!(
+                        " Forwarded to the `{}` submachine while in `{}`; in any other state it is ignored.",
+                        quote!(#child).to_string().replace(' ', ""),
+                        submachine.state
+                    )
+                }
+                None => event_doc(&edges, event_name),
+            };
             if let Some(ref payload_type) = event.payload_type {
-                quote! { #event_name(#payload_type), }
+                quote! { #[doc = #doc] #event_name(#payload_type), }
             } else {
-                quote! { #event_name, }
+                quote! { #[doc = #doc] #event_name, }
             }
         })
         .collect();
 
     let event_enum_name = fsm.event_enum_ident();
+    let serde_derive = render_serde_derive(fsm);
+    let non_exhaustive = render_non_exhaustive(fsm);
+    let event_derives = &fsm.event_derives;
+
+    let name_arms: Vec<TokenStream> = fsm
+        .events
+        .iter()
+        .map(|event| {
+            let event_name = &event.name;
+            if event.payload_type.is_some() {
+                quote! { Self::#event_name(..) => stringify!(#event_name), }
+            } else {
+                quote! { Self::#event_name => stringify!(#event_name), }
+            }
+        })
+        .collect();
+
+    let arbitrary_variants: Vec<TokenStream> = fsm
+        .events
+        .iter()
+        .map(|event| {
+            let event_name = &event.name;
+            match event.payload_type {
+                Some(ref payload_type) => quote! { #event_name(#payload_type) },
+                None => quote! { #event_name },
+            }
+        })
+        .collect();
+
+    let event_names: Vec<_> = fsm.events.iter().map(|e| &e.name).collect();
+    let event_count = event_names.len();
+    let unit_events: Vec<_> = fsm
+        .events
+        .iter()
+        .filter(|e| e.payload_type.is_none())
+        .map(|e| &e.name)
+        .collect();
 
     quote! {
-        #[derive(Debug, Clone)]
+        #[derive(Debug, Clone #(, #event_derives)*)]
+        #serde_derive
+        #non_exhaustive
         pub enum #event_enum_name {
             #(#variants)*
         }
+
+        impl #event_enum_name {
+            /// The names of every event variant, in declaration order.
+            pub const NAMES: [&'static str; #event_count] = [#(stringify!(#event_names)),*];
+
+            /// Returns the name of the event variant, without its payload.
+            pub fn as_str(&self) -> &'static str {
+                match *self {
+                    #(#name_arms)*
+                }
+            }
+        }
+
+        impl std::fmt::Display for #event_enum_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        /// Parses payload-less events by name. Events carrying a payload cannot
+        /// be built from a string and are rejected.
+        impl std::str::FromStr for #event_enum_name {
+            type Err = tokio_fsm::ParseNameError;
+
+            fn from_str(name: &str) -> Result<Self, Self::Err> {
+                match name {
+                    #(stringify!(#unit_events) => Ok(Self::#unit_events),)*
+                    _ => Err(tokio_fsm::ParseNameError::new(name, concat!("payload-less ", stringify!(#event_enum_name)))),
+                }
+            }
+        }
+
+        impl TryFrom<&str> for #event_enum_name {
+            type Error = tokio_fsm::ParseNameError;
+
+            fn try_from(name: &str) -> Result<Self, tokio_fsm::ParseNameError> {
+                name.parse()
+            }
+        }
+
+        tokio_fsm::__impl_arbitrary!(#event_enum_name { #(#arbitrary_variants),* });
+    }
+}
+
+/// Documents a state variant with the transitions into and out of it.
+fn state_doc(fsm: &FsmStructure, edges: &[Edge], state: &Ident) -> String {
+    let mut doc = Vec::new();
+    if *state == fsm.initial_state {
+        doc.push(" The initial state.".to_string());
+    }
+
+    let incoming: Vec<String> = edges
+        .iter()
+        .filter(|e| e.to == *state)
+        .map(|e| format!(" * From `{}` {}", e.from, edge_cause(&e.trigger)))
+        .collect();
+    if !incoming.is_empty() {
+        push_section(&mut doc, " Entered:", incoming);
+    }
+
+    let outgoing: Vec<String> = edges
+        .iter()
+        .filter(|e| e.from == *state)
+        .map(|e| format!(" * To `{}` {}", e.to, edge_cause(&e.trigger)))
+        .collect();
+    if outgoing.is_empty() {
+        push_section(
+            &mut doc,
+            " Terminal: no transitions leave this state.",
+            Vec::new(),
+        );
+    } else {
+        push_section(&mut doc, " Left:", outgoing);
+    }
+    doc.join("\n")
+}
+
+/// Documents an event variant with the transitions it triggers.
+fn event_doc(edges: &[Edge], event: &Ident) -> String {
+    let handled: Vec<String> = edges
+        .iter()
+        .filter(|e| matches!(&e.trigger, Trigger::Event(name) if name == event))
+        .map(|e| format!(" * `{}` -> `{}`", e.from, e.to))
+        .collect();
+    let mut doc = Vec::new();
+    push_section(
+        &mut doc,
+        " Transitions it triggers; in any other state it is ignored:",
+        handled,
+    );
+    doc.join("\n")
+}
+
+fn push_section(doc: &mut Vec<String>, heading: &str, items: Vec<String>) {
+    if !doc.is_empty() {
+        doc.push(String::new());
+    }
+    doc.push(heading.to_string());
+    if !items.is_empty() {
+        doc.push(String::new());
+        doc.extend(items);
+    }
+}
+
+fn edge_cause(trigger: &Trigger) -> String {
+    match trigger {
+        Trigger::Event(event) => format!("on `{event}`"),
+        Trigger::Timeout(_) => format!("on {}", graph::trigger_label(trigger)),
+        Trigger::Auto => "automatically".to_string(),
+        Trigger::Start => "on start".to_string(),
+    }
+}
+
+/// Renders, for every event whose handler takes several payload arguments,
+/// the payload struct it carries and a builder for it, started with
+/// `{Fsm}Event::{event}()`.
+pub fn render_payload_builders(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+    let serde_derive = render_serde_derive(fsm);
+    let event_derives = &fsm.event_derives;
+
+    let builders = fsm
+        .events
+        .iter()
+        .filter(|e| !e.fields.is_empty())
+        .map(|event| {
+            let event_name = &event.name;
+            let payload = validation::payload_struct_ident(&fsm.fsm_name, event_name);
+            let builder = format_ident!("{}{}Builder", fsm.fsm_name, event_name);
+            let constructor = super::impls::method_ident(event_name);
+            let names: Vec<_> = event.fields.iter().map(|(name, _)| name).collect();
+            let types: Vec<_> = event.fields.iter().map(|(_, ty)| ty).collect();
+            let payload_doc = format!(" Payload of [`{}::{}`].", event_enum_name, event_name);
+            let builder_doc = format!(
+                " Builds a [`{}::{}`] event; start with [`{}::{}`].",
+                event_enum_name, event_name, event_enum_name, constructor
+            );
+            let constructor_doc = format!(
+                " Starts building a [`{}`](Self::{}) event.",
+                event_name, event_name
+            );
+            let setter_docs = names.iter().map(|name| format!(" Sets `{}`.", name));
+            let missing = names.iter().map(|name| {
+                format!(
+                    "`{}` must be set before building a `{}` event",
+                    name, event_name
+                )
+            });
+
+            quote! {
+                #[doc = #payload_doc]
+                #[derive(Debug, Clone #(, #event_derives)*)]
+                #serde_derive
+                pub struct #payload {
+                    #(pub #names: #types,)*
+                }
+
+                tokio_fsm::__impl_arbitrary_payload!(#payload { #(#names: #types),* });
+
+                #[doc = #builder_doc]
+                #[derive(Debug, Clone, Default)]
+                pub struct #builder {
+                    #(#names: Option<#types>,)*
+                }
+
+                impl #builder {
+                    #(
+                        #[doc = #setter_docs]
+                        pub fn #names(mut self, #names: #types) -> Self {
+                            self.#names = Some(#names);
+                            self
+                        }
+                    )*
+
+                    /// Builds the event.
+                    ///
+                    /// # Panics
+                    ///
+                    /// Panics if a field wasn't set.
+                    pub fn build(self) -> #event_enum_name {
+                        #event_enum_name::#event_name(#payload {
+                            #(#names: self.#names.expect(#missing),)*
+                        })
+                    }
+                }
+
+                impl #event_enum_name {
+                    #[doc = #constructor_doc]
+                    pub fn #constructor() -> #builder {
+                        #builder::default()
+                    }
+                }
+            }
+        });
+
+    quote! { #(#builders)* }
+}
+
+/// Renders `From<Payload>` for every payload type carried by exactly one
+/// event, so `handle.send_into(payload)` can pick the variant.
+///
+/// Payload types shared by several events are ambiguous and skipped, as are
+/// references and `#[external_event]` sources, whose conversions would
+/// overlap the `TryFrom` impls generated for the enum.
+pub fn render_payload_conversions(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+    let payloads: Vec<(&syn::Ident, &syn::Type, String)> = fsm
+        .events
+        .iter()
+        .filter_map(|event| {
+            let ty = event.payload_type.as_ref()?;
+            Some((&event.name, ty, quote!(#ty).to_string()))
+        })
+        .collect();
+    let external_sources: Vec<String> = fsm
+        .external_events
+        .iter()
+        .map(|external| {
+            let source = &external.source;
+            quote!(#source).to_string()
+        })
+        .collect();
+
+    let impls = payloads.iter().filter_map(|(event_name, ty, key)| {
+        let unique = payloads.iter().filter(|(_, _, other)| other == key).count() == 1;
+        if !unique || matches!(ty, syn::Type::Reference(_)) || external_sources.contains(key) {
+            return None;
+        }
+        Some(quote! {
+            impl From<#ty> for #event_enum_name {
+                fn from(payload: #ty) -> Self {
+                    Self::#event_name(payload)
+                }
+            }
+        })
+    });
+
+    quote! { #(#impls)* }
+}
+
+/// Renders a `TryFrom` conversion for every `#[external_event]` enum. Variants
+/// without a mapping are handed back as the error.
+pub fn render_external_conversions(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+
+    let impls = fsm.external_events.iter().map(|external| {
+        let source = &external.source;
+        let arms = external.mappings.iter().map(|(event_name, variant)| {
+            let has_payload = fsm
+                .events
+                .iter()
+                .any(|e| e.name == *event_name && e.payload_type.is_some());
+            if has_payload {
+                quote! { #source::#variant(payload) => Ok(Self::#event_name(payload.into())), }
+            } else {
+                quote! { #source::#variant { .. } => Ok(Self::#event_name), }
+            }
+        });
+
+        quote! {
+            impl TryFrom<#source> for #event_enum_name {
+                type Error = #source;
+
+                fn try_from(message: #source) -> Result<Self, #source> {
+                    match message {
+                        #(#arms)*
+                        #[allow(unreachable_patterns)]
+                        other => Err(other),
+                    }
+                }
+            }
+        }
+    });
+
+    quote! { #(#impls)* }
+}
+
+/// Renders the `#[fsm(serde)]` derives, routed through `tokio-fsm`'s re-export
+/// so user crates don't need a direct `serde` dependency.
+fn render_serde_derive(fsm: &FsmStructure) -> TokenStream {
+    if !fsm.serde {
+        return quote! {};
+    }
+
+    quote! {
+        #[derive(tokio_fsm::__private::serde::Serialize, tokio_fsm::__private::serde::Deserialize)]
+        #[serde(crate = "tokio_fsm::__private::serde")]
+    }
+}
+
+/// Renders `#[fsm(state_repr = u8)]`: the `#[repr]` attribute, an explicit
+/// discriminant per state (its index in `ALL`) and conversions to and from
+/// the integer type.
+fn render_state_repr(fsm: &FsmStructure) -> (TokenStream, Vec<TokenStream>, TokenStream) {
+    let Some(repr) = &fsm.state_repr else {
+        return (quote! {}, vec![quote! {}; fsm.states.len()], quote! {});
+    };
+    let state_enum_name = fsm.state_enum_ident();
+    let states: Vec<_> = fsm.states.iter().map(|s| &s.name).collect();
+    let values: Vec<_> = (0..states.len())
+        .map(|index| syn::LitInt::new(&format!("{index}{repr}"), repr.span()))
+        .collect();
+    let discriminants = values.iter().map(|value| quote! { = #value }).collect();
+
+    let conversions = quote! {
+        impl From<#state_enum_name> for #repr {
+            fn from(state: #state_enum_name) -> Self {
+                state as #repr
+            }
+        }
+
+        impl TryFrom<#repr> for #state_enum_name {
+            type Error = tokio_fsm::InvalidDiscriminant;
+
+            fn try_from(value: #repr) -> Result<Self, tokio_fsm::InvalidDiscriminant> {
+                match value {
+                    #(#values => Ok(Self::#states),)*
+                    _ => Err(tokio_fsm::InvalidDiscriminant::new(value as i128, stringify!(#state_enum_name))),
+                }
+            }
+        }
+    };
+    (quote! { #[repr(#repr)] }, discriminants, conversions)
+}
+
+/// Renders `#[fsm(non_exhaustive)]`, so crates exposing an FSM can add states
+/// and events without breaking downstream matches.
+fn render_non_exhaustive(fsm: &FsmStructure) -> TokenStream {
+    if fsm.non_exhaustive {
+        quote! { #[non_exhaustive] }
+    } else {
+        quote! {}
     }
 }
@@ -0,0 +1,61 @@
+//! Diagram renderings of the FSM graph, computed at expansion time.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use tokio_fsm_analysis::{
+    graph,
+    validation::{FsmStructure, Trigger},
+};
+
+/// Renders `{Fsm}::mermaid()`, returning a precomputed `stateDiagram-v2`.
+pub fn render_mermaid_fn(fsm: &FsmStructure) -> TokenStream {
+    let diagram = graph::mermaid(fsm);
+
+    quote! {
+        /// Returns a Mermaid `stateDiagram-v2` description of this FSM's
+        /// states, events and timeouts.
+        pub fn mermaid() -> &'static str {
+            #diagram
+        }
+    }
+}
+
+/// Renders `{Fsm}::definition()`, returning a precomputed JSON description.
+pub fn render_definition_fn(fsm: &FsmStructure) -> TokenStream {
+    let definition = graph::definition_json(fsm);
+
+    quote! {
+        /// Returns a JSON document describing this FSM's states, events,
+        /// payload types, timeouts and transitions.
+        pub fn definition() -> &'static str {
+            #definition
+        }
+    }
+}
+
+/// Renders `{Fsm}::transitions()`, the static `(from, event, to)` table of
+/// event-driven edges.
+pub fn render_transitions_fn(fsm: &FsmStructure) -> TokenStream {
+    let state_enum = fsm.state_enum_ident();
+    let rows: Vec<TokenStream> = fsm
+        .edges()
+        .into_iter()
+        .filter_map(|edge| match edge.trigger {
+            Trigger::Event(event) => {
+                let (from, to) = (edge.from, edge.to);
+                Some(quote! { (#state_enum::#from, stringify!(#event), #state_enum::#to) })
+            }
+            Trigger::Timeout(_) | Trigger::Auto | Trigger::Start => None,
+        })
+        .collect();
+
+    quote! {
+        /// Returns every event-driven transition as `(from, event name, to)`.
+        ///
+        /// Timeout, `#[auto]` and `#[on_start]` edges are not included; see `definition()` or
+        /// `mermaid()`.
+        pub fn transitions() -> &'static [(#state_enum, &'static str, #state_enum)] {
+            &[#(#rows),*]
+        }
+    }
+}
@@ -1,152 +1,1882 @@
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
+use syn::Ident;
+use tokio_fsm_analysis::validation::{self, Event, FsmStructure, Handler, LastHandlePolicy};
 
-use crate::validation::FsmStructure;
+use super::channel;
 
 pub fn render_spawn(fsm: &FsmStructure) -> TokenStream {
     let fsm_name = &fsm.fsm_name;
     let handle_name = fsm.handle_ident();
+    let sender_name = fsm.sender_ident();
     let task_name = fsm.task_ident();
+    let builder_name = fsm.builder_ident();
+    let core_name = fsm.core_ident();
     let state_enum_name = fsm.state_enum_ident();
     let initial_state = &fsm.initial_state;
     let channel_size = fsm.channel_size;
     let context_type = &fsm.context_type;
+    let create_channel = channel::render_create(fsm);
+
+    // With `type Output`, spawn also hands back the command receiver.
+    let (output_channel, output_field, output_return, output_value) = match &fsm.output_type {
+        Some(output_type) => (
+            quote! { let (output_tx, output_rx) = tokio_fsm::__private::rt::mpsc::channel(#channel_size); },
+            quote! { output: output_tx, },
+            quote! { , tokio::sync::mpsc::Receiver<#output_type> },
+            quote! { , output_rx },
+        ),
+        None => (quote! {}, quote! {}, quote! {}, quote! {}),
+    };
+    let (core_return, core_value) = if fsm.output_type.is_some() {
+        (
+            quote! { (#core_name #output_return) },
+            quote! { (core #output_value) },
+        )
+    } else {
+        (quote! { #core_name }, quote! { core })
+    };
+
+    let last_panic_field = fsm.on_panic.as_ref().map(|_| quote! { last_panic: None, });
+    let detached = fsm.on_last_handle == LastHandlePolicy::Detach;
+    let submachine_fields = fsm.submachines().map(|submachine| {
+        let field = submachine.slot_ident();
+        quote! { #field: None, }
+    });
+
+    // Without `type Context`, spawn takes no argument.
+    let (context_param, context_arg, context_init) = if fsm.has_context {
+        (
+            quote! { context: #context_type },
+            quote! { context },
+            quote! {},
+        )
+    } else {
+        (quote! {}, quote! {}, quote! { let context = (); })
+    };
+
+    // With `#[fsm(runtime = ...)]` the task and its background work go to
+    // that runtime, which is also the clock unless the builder set one, and
+    // there is no Tokio runtime to spawn on.
+    let supervise =
+        quote! { fsm.supervise(event_rx, priority_rx, control_rx, shutdown_rx, state_tx, clock) };
+    let (runtime_clock, spawner) = match &fsm.runtime {
+        Some(runtime) => (
+            quote! {
+                let clock = clock.or_else(|| Some(std::sync::Arc::new(<#runtime as Default>::default())));
+            },
+            quote! { Some(tokio_fsm::spawner::<#runtime>()) },
+        ),
+        None => (quote! {}, quote! { None }),
+    };
+    let (spawn_task, spawn_on) = match &fsm.runtime {
+        Some(runtime) => (
+            quote! {
+                let _ = runtime;
+                let runtime = <#runtime as Default>::default();
+                let handle = tokio_fsm::TaskHandle::spawn(&runtime, #supervise);
+            },
+            quote! {},
+        ),
+        None => (
+            quote! {
+                let handle = tokio_fsm::TaskHandle::spawn_default(runtime, #supervise);
+            },
+            quote! {
+                /// Spawns the FSM onto `runtime` rather than the caller's runtime,
+                /// e.g. a dedicated single-threaded runtime for latency-sensitive
+                /// machines. The runtime needs its time driver enabled.
+                pub fn spawn_on(runtime: &tokio::runtime::Handle, #context_param) -> (#handle_name, #task_name #output_return) {
+                    Self::builder(#context_arg).spawn_on(runtime)
+                }
+            },
+        ),
+    };
+    // Builds `fsm` and the ends of its queues from the builder's settings,
+    // shared by `spawn_in` and `core_in`.
+    let assemble = quote! {
+        #runtime_clock
+        #create_channel
+        let entered = tokio_fsm::__private::now_on(&clock);
+        let (state_tx, state_rx) = tokio_fsm::__private::rt::watch::channel(tokio_fsm::StateChange::initial(state, entered));
+        let stats = tokio_fsm::StatsRecorder::new(stringify!(#fsm_name));
+        let transitions = tokio_fsm::TransitionFeed::default();
+        let (priority_tx, priority_rx) = tokio_fsm::__private::rt::mpsc::unbounded_channel();
+        let sender = #sender_name {
+            event_tx,
+            priority_tx,
+            state_rx: state_rx.clone(),
+            stats: stats.clone(),
+            coalescer: tokio_fsm::Coalescer::default(),
+            watermarks: tokio_fsm::Watermarks::new(watermarks),
+            transitions: transitions.watcher(),
+            clock: clock.clone(),
+            spawner: #spawner,
+            validator,
+            shed_above,
+        };
+        let active_event = tokio_fsm::ActiveEvent::default();
+        #output_channel
+
+        let fsm = #fsm_name {
+            state,
+            context,
+            pending: std::collections::VecDeque::new(),
+            requeued: std::collections::VecDeque::new(),
+            halted: false,
+            preempted: false,
+            detached,
+            work: tokio_fsm::WorkSet::new(#spawner),
+            sender: sender.clone(),
+            watchdog: watchdog.map(|budget| {
+                std::sync::Arc::new(tokio_fsm::Watchdog { budget, event: watchdog_event })
+            }),
+            current_event: None,
+            event_meta: None,
+            sources: tokio_fsm::Sources::new(),
+            active_event: active_event.clone(),
+            stats: stats.clone(),
+            transitions,
+            #output_field
+            #last_panic_field
+            #(#submachine_fields)*
+        };
+    };
 
     quote! {
-        pub fn spawn(context: #context_type) -> (#handle_name, #task_name) {
-            let (event_tx, event_rx) = tokio::sync::mpsc::channel(#channel_size);
-            let (state_tx, state_rx) = tokio::sync::watch::channel(#state_enum_name::#initial_state);
-            let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(None);
+        pub fn spawn(#context_param) -> (#handle_name, #task_name #output_return) {
+            Self::builder(#context_arg).spawn()
+        }
+
+        #spawn_on
+
+        /// Resumes an FSM from a snapshot, starting in `snapshot.state` with
+        /// `snapshot.context`.
+        ///
+        /// State timeouts are not re-armed for the restored state; they apply
+        /// again from the next transition on.
+        pub fn spawn_from(snapshot: tokio_fsm::Snapshot<#state_enum_name, #context_type>) -> (#handle_name, #task_name #output_return) {
+            Self::builder_from(snapshot).spawn()
+        }
 
-            let fsm = #fsm_name {
+        /// Creates the FSM without spawning it, to be driven by the caller
+        #[doc = concat!("through [`", stringify!(#core_name), "`].")]
+        pub fn core(#context_param) -> #core_return {
+            Self::builder(#context_arg).core()
+        }
+
+        /// Starts configuring an FSM that begins in its initial state.
+        pub fn builder(#context_param) -> #builder_name {
+            #context_init
+            #builder_name {
                 state: #state_enum_name::#initial_state,
                 context,
-            };
+                validator: None,
+                shed_above: None,
+                watermarks: None,
+                clock: None,
+                watchdog: None,
+                watchdog_event: None,
+                detached: #detached,
+            }
+        }
+
+        /// Starts configuring an FSM resumed from `snapshot`, like `spawn_from`.
+        pub fn builder_from(snapshot: tokio_fsm::Snapshot<#state_enum_name, #context_type>) -> #builder_name {
+            #builder_name {
+                state: snapshot.state,
+                context: snapshot.context,
+                validator: None,
+                shed_above: None,
+                watermarks: None,
+                clock: None,
+                watchdog: None,
+                watchdog_event: None,
+                detached: #detached,
+            }
+        }
 
+        fn spawn_in(builder: #builder_name, runtime: Option<&tokio::runtime::Handle>) -> (#handle_name, #task_name #output_return) {
+            let #builder_name { state, context, validator, shed_above, watermarks, clock, watchdog, watchdog_event, detached } = builder;
+            #assemble
+            let (shutdown_tx, shutdown_rx) = tokio_fsm::__private::rt::watch::channel(None);
+            let (control_tx, control_rx) = tokio_fsm::__private::rt::mpsc::unbounded_channel();
+
+            let id = tokio_fsm::InstanceId::next();
             let shutdown_tx = std::sync::Arc::new(shutdown_tx);
-            let handle = tokio::spawn(fsm.run(event_rx, shutdown_rx, state_tx));
+            let shutdown = tokio_fsm::ShutdownSignal(std::sync::Arc::downgrade(&shutdown_tx));
+            let task_state_rx = state_rx.clone();
+            #spawn_task
 
             (
                 #handle_name {
-                    event_tx,
+                    id,
+                    sender,
+                    control_tx,
                     state_rx,
                     shutdown_tx,
+                    stats,
+                    sink: tokio_fsm::SinkSlot::new(),
                 },
-                #task_name { handle },
+                #task_name {
+                    id,
+                    shutdown,
+                    handle,
+                    state_rx: task_state_rx,
+                    active_event,
+                }
+                #output_value
             )
         }
+
+        fn core_in(builder: #builder_name) -> #core_return {
+            let #builder_name { state, context, validator, shed_above, watermarks, clock, watchdog, watchdog_event, detached } = builder;
+            #assemble
+
+            let core = #core_name {
+                fsm,
+                events: event_rx,
+                priority: priority_rx,
+                state_tx,
+                timer: tokio_fsm::Timer::manual(clock),
+                started: false,
+            };
+            #core_value
+        }
+    }
+}
+
+/// Renders `last_panic`, which lets handlers of the `on_panic` state see what
+/// went wrong.
+pub fn render_last_panic(fsm: &FsmStructure) -> TokenStream {
+    if fsm.on_panic.is_none() {
+        return quote! {};
+    }
+
+    quote! {
+        /// Returns the message of the latest handler panic, or `None` if no
+        /// handler has panicked yet.
+        #[allow(dead_code)]
+        fn last_panic(&self) -> Option<&str> {
+            self.last_panic.as_deref()
+        }
+    }
+}
+
+/// Renders `emit`, the handler-side entry point of the `type Output` channel.
+pub fn render_emit(fsm: &FsmStructure) -> TokenStream {
+    let Some(output_type) = &fsm.output_type else {
+        return quote! {};
+    };
+
+    quote! {
+        /// Pushes a command onto the outbound channel returned by `spawn`.
+        ///
+        /// Waits for capacity, applying backpressure from the executor.
+        async fn emit(&self, command: #output_type) -> Result<(), tokio::sync::mpsc::error::SendError<#output_type>> {
+            self.output.send(command).await
+        }
+    }
+}
+
+/// Renders `spawn_work`, which runs a future off the event loop and feeds its
+/// result back as an event.
+pub fn render_spawn_work(fsm: &FsmStructure) -> TokenStream {
+    let event_enum = fsm.event_enum_ident();
+
+    quote! {
+        /// Runs `work` on a background task and delivers its result as an
+        /// event: `on_ok(value)` on success, `on_err(error)` on failure, e.g.
+        /// `self.spawn_work(fetch(url), Event::Fetched, Event::FetchFailed)`.
+        ///
+        /// The task is tied to the FSM: it is aborted when the FSM stops.
+        /// Completion events bypass the queue, so they can't be lost to
+        /// backpressure.
+        #[allow(dead_code)]
+        fn spawn_work<T, E, F>(
+            &mut self,
+            work: F,
+            on_ok: impl FnOnce(T) -> #event_enum + Send + 'static,
+            on_err: impl FnOnce(E) -> #event_enum + Send + 'static,
+        ) where
+            F: std::future::Future<Output = Result<T, E>> + Send + 'static,
+            T: Send + 'static,
+            E: Send + 'static,
+        {
+            self.work.spawn(async move {
+                match work.await {
+                    Ok(value) => on_ok(value),
+                    Err(error) => on_err(error),
+                }
+            });
+        }
+    }
+}
+
+/// Renders `spawn_child`, which ties a child FSM's task to this FSM and feeds
+/// its outcome back as an event.
+pub fn render_spawn_child(fsm: &FsmStructure) -> TokenStream {
+    let event_enum = fsm.event_enum_ident();
+
+    quote! {
+        /// Takes ownership of a child FSM's task and delivers its outcome as
+        /// an event, e.g. with `let (child, task) = StepFsm::spawn(ctx);`,
+        /// `self.spawn_child(task, Event::StepDone)`.
+        ///
+        /// The child is aborted if this FSM stops first, so it can't outlive
+        /// its parent. Completion events bypass the queue, like `spawn_work`.
+        #[allow(dead_code)]
+        fn spawn_child<T>(
+            &mut self,
+            child: T,
+            on_done: impl FnOnce(T::Output) -> #event_enum + Send + 'static,
+        ) where
+            T: tokio_fsm::FsmTask,
+            T::Output: Send + 'static,
+        {
+            let child = tokio_fsm::ChildTask(child);
+            self.work.spawn(async move { on_done(child.await) });
+        }
+    }
+}
+
+/// Renders `coalesce_key`, the latest-wins key of events declared with
+/// `coalesce_by`.
+pub fn render_coalesce_key(fsm: &FsmStructure) -> TokenStream {
+    let event_enum = fsm.event_enum_ident();
+    let arms: Vec<TokenStream> = fsm
+        .events
+        .iter()
+        .filter_map(|event| {
+            let field = fsm.coalesce_field(&event.name)?;
+            let name = &event.name;
+            let label = name.to_string();
+            Some(quote! {
+                #event_enum::#name(payload) => Some(tokio_fsm::CoalesceKey::new(#label, payload.#field.clone())),
+            })
+        })
+        .collect();
+
+    quote! {
+        #[allow(unused_variables, unreachable_patterns)]
+        fn coalesce_key(event: &#event_enum) -> Option<tokio_fsm::CoalesceKey> {
+            match event {
+                #(#arms)*
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Renders `is_preempting`, which routes events with a `#[preempt]` handler
+/// onto the priority lane.
+pub fn render_is_preempting(fsm: &FsmStructure) -> TokenStream {
+    let event_enum = fsm.event_enum_ident();
+    let mut patterns: Vec<TokenStream> = Vec::new();
+    let mut seen = Vec::new();
+    for handler in fsm.handlers.iter().filter(|h| h.preempt) {
+        for event in &handler.events {
+            if seen.contains(&&event.name) {
+                continue;
+            }
+            seen.push(&event.name);
+            let name = &event.name;
+            patterns.push(if event.payload_type.is_some() {
+                quote! { #event_enum::#name(..) }
+            } else {
+                quote! { #event_enum::#name }
+            });
+        }
+    }
+    let body = if patterns.is_empty() {
+        quote! { false }
+    } else {
+        quote! { matches!(event, #(#patterns)|*) }
+    };
+
+    quote! {
+        fn is_preempting(#[allow(unused_variables)] event: &#event_enum) -> bool {
+            #body
+        }
+    }
+}
+
+/// Renders `event_index`, the position of an event's variant in `NAMES`.
+pub fn render_event_index(fsm: &FsmStructure) -> TokenStream {
+    let event_enum = fsm.event_enum_ident();
+    let arms = fsm.events.iter().enumerate().map(|(index, event)| {
+        let name = &event.name;
+        if event.payload_type.is_some() {
+            quote! { #event_enum::#name(..) => #index, }
+        } else {
+            quote! { #event_enum::#name => #index, }
+        }
+    });
+
+    quote! {
+        fn event_index(event: &#event_enum) -> usize {
+            match *event {
+                #(#arms)*
+            }
+        }
+    }
+}
+
+pub fn render_current_event() -> TokenStream {
+    quote! {
+        /// Returns the name of the event being handled, or `None` outside
+        /// of an event handler (e.g. in `#[on_timeout]`).
+        ///
+        /// Useful in handlers bound to several events via `event = A | B`.
+        #[allow(dead_code)]
+        fn current_event(&self) -> Option<&'static str> {
+            self.current_event
+        }
+    }
+}
+
+pub fn render_event_meta() -> TokenStream {
+    quote! {
+        /// Returns when the event being handled was sent and taken off the
+        /// queue, e.g. to skip work on a reading that waited too long.
+        ///
+        /// `None` outside of an event handler and for events that skipped
+        /// the queue: `#[preempt]` events, follow-ups, `spawn_work` results
+        /// and events from attached sources.
+        #[allow(dead_code)]
+        fn event_meta(&self) -> Option<tokio_fsm::EventMeta> {
+            self.event_meta
+        }
+    }
+}
+
+pub fn render_run(fsm: &FsmStructure) -> TokenStream {
+    let supervise = render_supervise(fsm);
+    let auto_handlers = build_auto_handlers(fsm);
+    let entry_hooks = build_entry_hooks(fsm);
+    let start_handler = build_start_handler(fsm);
+    let sync_submachines = render_sync_submachines(fsm);
+
+    quote! {
+        #auto_handlers
+
+        #entry_hooks
+
+        #start_handler
+
+        #sync_submachines
+
+        #supervise
+    }
+}
+
+/// Renders the `Machine` impl through which `tokio_fsm`'s shared run loop
+/// drives the FSM: its dispatch table and whichever hooks it declares.
+pub fn render_machine_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let event_enum_name = fsm.event_enum_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let error_type = &fsm.error_type;
+
+    let event_arms = build_event_arms(fsm);
+    // With an `#[on_orphaned]` handler, the FSM lets it decide what losing
+    // its last handle means.
+    let keep_orphaned = if fsm.handlers.iter().any(|h| h.is_orphaned_handler) {
+        quote! { true }
+    } else {
+        quote! { self.detached }
+    };
+    let has_auto = fsm.handlers.iter().any(|h| h.auto_state.is_some());
+    let has_submachines = fsm.submachines().next().is_some();
+    // Entering a state with an `#[auto]` handler runs it before anything else.
+    let step = (has_auto || has_submachines).then(|| {
+        let run_sync = has_submachines.then(|| quote! { self.sync_submachines(); });
+        let run_auto = if has_auto {
+            quote! { self.run_auto(state_tx, timer).await }
+        } else {
+            quote! { Ok(false) }
+        };
+        quote! {
+            async fn step(
+                &mut self,
+                state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+                timer: &mut tokio_fsm::Timer,
+            ) -> Result<bool, #error_type> {
+                #run_sync
+                #run_auto
+            }
+        }
+    });
+    let start = fsm.handlers.iter().any(|h| h.is_start_handler).then(|| {
+        quote! {
+            async fn start(
+                &mut self,
+                state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+                timer: &mut tokio_fsm::Timer,
+            ) -> Result<(), #error_type> {
+                self.run_start(state_tx, timer).await
+            }
+        }
+    });
+    let timeout = fsm.handlers.iter().any(|h| h.is_timeout_handler).then(|| {
+        let timeout_logic = build_timeout_handler(fsm);
+        quote! {
+            async fn timeout(
+                &mut self,
+                state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+                timer: &mut tokio_fsm::Timer,
+            ) -> Result<(), #error_type> {
+                #timeout_logic
+                Ok(())
+            }
+        }
+    });
+    let orphaned = fsm.handlers.iter().any(|h| h.is_orphaned_handler).then(|| {
+        let orphaned_logic = build_orphaned_handler(fsm);
+        quote! {
+            async fn orphaned(
+                &mut self,
+                state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+                timer: &mut tokio_fsm::Timer,
+            ) -> Result<(), #error_type> {
+                #orphaned_logic
+                Ok(())
+            }
+        }
+    });
+    let schedules = (!fsm.schedules.is_empty()).then(|| {
+        let schedules = fsm.schedules.iter().map(|schedule| {
+            let cron = &schedule.cron;
+            let event = &schedule.event;
+            quote! {
+                tokio_fsm::__private::Schedule::cron(#cron, || #event_enum_name::#event)
+            }
+        });
+        quote! {
+            fn schedules(&self) -> Vec<tokio_fsm::__private::Schedule<#event_enum_name>> {
+                vec![#(#schedules),*]
+            }
+        }
+    });
+    let shutdown = fsm.on_shutdown.as_ref().map(|hook| {
+        quote! {
+            async fn shutdown(&mut self, mode: tokio_fsm::ShutdownMode) {
+                self.#hook(mode).await;
+            }
+        }
+    });
+
+    quote! {
+        impl tokio_fsm::__private::Machine for #fsm_name {
+            type State = #state_enum_name;
+            type Event = #event_enum_name;
+            type Error = #error_type;
+
+            const NAME: &'static str = stringify!(#fsm_name);
+
+            fn state(&self) -> #state_enum_name {
+                self.state
+            }
+
+            fn state_name(state: #state_enum_name) -> &'static str {
+                state.as_str()
+            }
+
+            fn is_terminal(state: #state_enum_name) -> bool {
+                state.is_terminal()
+            }
+
+            fn event_name(event: &#event_enum_name) -> &'static str {
+                event.as_str()
+            }
+
+            fn coalesce_key(event: &#event_enum_name) -> Option<tokio_fsm::CoalesceKey> {
+                #fsm_name::coalesce_key(event)
+            }
+
+            fn halted(&self) -> bool {
+                self.halted
+            }
+
+            fn preempted(&self) -> bool {
+                self.preempted
+            }
+
+            fn keeps_orphaned(&self) -> bool {
+                #keep_orphaned
+            }
+
+            fn context(&self) -> &dyn std::any::Any {
+                &self.context
+            }
+
+            fn stats(&self) -> &tokio_fsm::StatsRecorder {
+                &self.stats
+            }
+
+            fn coalescer(&self) -> &tokio_fsm::Coalescer {
+                &self.sender.coalescer
+            }
+
+            fn now(&self) -> tokio_fsm::__private::rt::Instant {
+                tokio_fsm::__private::now_on(&self.sender.clock)
+            }
+
+            fn transitions(&self) -> &tokio_fsm::TransitionFeed<#state_enum_name> {
+                &self.transitions
+            }
+
+            fn watermarks(&self) -> &tokio_fsm::Watermarks {
+                &self.sender.watermarks
+            }
+
+            fn pending(&mut self) -> &mut std::collections::VecDeque<tokio_fsm::Acked<#event_enum_name>> {
+                &mut self.pending
+            }
+
+            fn requeued(&mut self) -> &mut std::collections::VecDeque<tokio_fsm::Envelope<#event_enum_name>> {
+                &mut self.requeued
+            }
+
+            fn background(&mut self) -> (&mut tokio_fsm::WorkSet<#event_enum_name>, &mut tokio_fsm::Sources<#event_enum_name>) {
+                (&mut self.work, &mut self.sources)
+            }
+
+            async fn dispatch(
+                &mut self,
+                event: #event_enum_name,
+                meta: Option<tokio_fsm::EventMeta>,
+                priority: &mut tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::Acked<#event_enum_name>>,
+                state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+                timer: &mut tokio_fsm::Timer,
+            ) -> Result<(), #error_type> {
+                let event_name = event.as_str();
+                self.current_event = Some(event_name);
+                self.preempted = false;
+                self.event_meta = meta;
+                self.active_event.set(Self::event_index(&event));
+                // The budget runs on the FSM's clock, from before the handler
+                // starts.
+                let watchdog = self.watchdog.clone().map(|watchdog| {
+                    let overrun = timer.sleep(watchdog.budget);
+                    (watchdog, self.sender.clone(), overrun)
+                });
+                let state_name = self.state.as_str();
+                let dispatch = async {
+                    match (self.state, event) {
+                        #(#event_arms)*
+                        _ => {
+                            // Event not handled in current state — dropped
+                            tokio_fsm::DropReason::NoHandler.trace(stringify!(#fsm_name), self.state.as_str(), event_name);
+                        }
+                    }
+                    Ok::<(), #error_type>(())
+                };
+                let outcome = match watchdog {
+                    None => dispatch.await,
+                    Some((watchdog, sender, overrun)) => {
+                        tokio::pin!(dispatch);
+                        tokio::select! {
+                            biased;
+
+                            outcome = &mut dispatch => outcome,
+                            () = overrun => {
+                                // A preempting event cancels the overrunning
+                                // handler; any other waits for it to finish.
+                                if let Some(event) = watchdog.fire(stringify!(#fsm_name), state_name, event_name) {
+                                    let _ = sender.try_send(event);
+                                }
+                                dispatch.await
+                            }
+                        }
+                    }
+                };
+                // A failed handler stops the FSM with the event still marked
+                // active, so the task's `FailureSite` names it.
+                outcome?;
+                self.current_event = None;
+                self.event_meta = None;
+                self.active_event.clear();
+                Ok(())
+            }
+
+            #start
+            #step
+            #timeout
+            #orphaned
+            #schedules
+            #shutdown
+        }
+    }
+}
+
+/// Renders `supervise`, the body of the spawned task, which owns the FSM's
+/// channels and runs the event loop on them.
+///
+/// With `#[fsm(restart = on_error)]` a run that fails, by returning an error
+/// or panicking, is retried from `restart_from` with a clone of the context
+/// the FSM was spawned with, until `max_restarts` is used up.
+fn render_supervise(fsm: &FsmStructure) -> TokenStream {
+    let event_enum_name = fsm.event_enum_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let context_type = &fsm.context_type;
+    let error_type = &fsm.error_type;
+    let receiver_type = channel::receiver_type(fsm);
+
+    let body = match &fsm.restart {
+        None => quote! {
+            tokio_fsm::__private::run(&mut self, &mut events, &mut priority, &mut control, &mut shutdown, &state_tx, clock).await?;
+            Ok(self.context)
+        },
+        Some(restart) => {
+            let max_restarts = restart.max_restarts;
+            let from = &restart.from;
+            let record_change = render_record_change();
+            let submachine_resets = fsm.submachines().map(|submachine| {
+                let field = submachine.slot_ident();
+                quote! { self.#field = None; }
+            });
+            let backoff = (!restart.backoff.is_zero()).then(|| {
+                let secs = restart.backoff.as_secs();
+                let nanos = restart.backoff.subsec_nanos();
+                quote! {
+                    let backoff = std::time::Duration::new(#secs, #nanos);
+                    match &clock {
+                        Some(clock) => clock.sleep_until(clock.now() + backoff).await,
+                        None => tokio_fsm::__private::rt::sleep(backoff).await,
+                    }
+                }
+            });
+            quote! {
+                let seed = self.context.clone();
+                let mut restarts = 0u32;
+                loop {
+                    let run = tokio_fsm::__private::run(&mut self, &mut events, &mut priority, &mut control, &mut shutdown, &state_tx, clock.clone());
+                    match tokio_fsm::catch_unwind(run).await {
+                        Ok(Ok(())) => return Ok(self.context),
+                        Ok(Err(error)) if restarts == #max_restarts => return Err(error),
+                        Err(message) if restarts == #max_restarts => {
+                            std::panic::resume_unwind(Box::new(message))
+                        }
+                        Ok(Err(_)) | Err(_) => restarts += 1,
+                    }
+                    #backoff
+
+                    // Work and follow-ups of the failed run are abandoned
+                    // with it; queued events are handled by the next one.
+                    self.state = #state_enum_name::#from;
+                    self.context = seed.clone();
+                    self.pending.clear();
+                    self.work.clear();
+                    self.current_event = None;
+                    self.active_event.clear();
+                    #(#submachine_resets)*
+                    let state = self.state;
+                    let now = tokio_fsm::__private::now_on(&clock);
+                    state_tx.send_modify(|change| {
+                        *change = change.next(state, tokio_fsm::TransitionCause::Restarted, now)
+                    });
+                    #record_change
+                }
+            }
+        }
+    };
+
+    quote! {
+        async fn supervise(
+            mut self,
+            mut events: #receiver_type,
+            mut priority: tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::Acked<#event_enum_name>>,
+            mut control: tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::Control<#event_enum_name>>,
+            mut shutdown: tokio::sync::watch::Receiver<Option<tokio_fsm::ShutdownMode>>,
+            state_tx: tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+            clock: Option<std::sync::Arc<dyn tokio_fsm::Clock>>,
+        ) -> Result<#context_type, #error_type> {
+            #body
+        }
+    }
+}
+
+/// Renders `handle`, the handler-side entry point of the FSM's own sender.
+pub fn render_self_handle(fsm: &FsmStructure) -> TokenStream {
+    let sender_name = fsm.sender_ident();
+
+    quote! {
+        /// Returns a sender feeding this FSM's own queue, e.g. to schedule
+        /// `self.handle().send_after(delay, event)` from a handler.
+        ///
+        /// The sender doesn't keep the FSM alive: once every handle is
+        /// dropped, the FSM shuts down gracefully.
+        #[allow(dead_code)]
+        fn handle(&self) -> #sender_name {
+            self.sender.clone()
+        }
+    }
+}
+
+pub fn render_sender_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let sender_name = fsm.sender_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let send = channel::render_send(fsm);
+    let try_send = channel::render_try_send(fsm);
+    let len = channel::render_len(fsm);
+    let reserve = channel::render_reserve(fsm);
+
+    quote! {
+        impl #sender_name {
+            /// Sends an event to the FSM.
+            ///
+            /// Events with a `#[preempt]` handler skip the queue. Neither the
+            /// builder's `validator` nor its `shed_above` watermark applies,
+            /// so `send` waits for room even under overload; the handle's
+            /// `submit` runs both and hands rejected events back.
+            pub async fn send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.push(event, None, None, tokio_fsm::Reservation::none()).await
+            }
+
+            /// Sends an event and waits until its handler has run, returning
+            /// the state it left the FSM in. Events the current state doesn't
+            /// handle leave it unchanged; follow-ups they schedule run after
+            /// `call` returns.
+            ///
+            /// Events with a `#[preempt]` handler skip the queue, and `call`
+            /// returns once they have been handled like any other. Events the
+            /// builder's `validator` rejects, or that arrive above its
+            /// `shed_above` watermark, are never enqueued and fail with
+            /// `CallError::Discarded` carrying `DropReason::Invalid` or
+            /// `DropReason::Shed`.
+            pub async fn call(&self, event: #event_enum_name) -> Result<#state_enum_name, tokio_fsm::CallError<#event_enum_name>> {
+                self.call_reserved(tokio_fsm::Reservation::none(), event).await
+            }
+
+            /// Waits for room in the queue and holds it for `call_reserved`.
+            async fn reserve(&self) -> tokio_fsm::Reservation<#event_enum_name> {
+                #reserve
+            }
+
+            /// Like `call`, sending into the room `reservation` holds.
+            async fn call_reserved(&self, reservation: tokio_fsm::Reservation<#event_enum_name>, event: #event_enum_name) -> Result<#state_enum_name, tokio_fsm::CallError<#event_enum_name>> {
+                let event = self.admit(event).map_err(|err| {
+                    tokio_fsm::CallError::Discarded(match err {
+                        tokio_fsm::SubmitError::Invalid { .. } => tokio_fsm::DropReason::Invalid,
+                        _ => tokio_fsm::DropReason::Shed,
+                    })
+                })?;
+                let (reply_tx, reply_rx) = tokio_fsm::__private::rt::oneshot::channel();
+                let ack = move |outcome: Result<&dyn std::any::Any, tokio_fsm::DropReason>| {
+                    let outcome = outcome.map(|state| {
+                        *state
+                            .downcast_ref::<#state_enum_name>()
+                            .expect("Envelope::ack called with a foreign state")
+                    });
+                    let _ = reply_tx.send(outcome);
+                };
+                self.push(event, None, Some(Box::new(ack)), reservation)
+                    .await
+                    .map_err(|err| tokio_fsm::CallError::Closed(err.0))?;
+                match reply_rx.await {
+                    Ok(Ok(state)) => Ok(state),
+                    Ok(Err(reason)) => Err(tokio_fsm::CallError::Discarded(reason)),
+                    Err(_) => Err(tokio_fsm::CallError::Dropped),
+                }
+            }
+
+            /// Sends an event that is dropped instead of handled if it is
+            /// still queued once `ttl` has passed, like a control-loop `Tick`
+            /// that would be harmful to act on late.
+            ///
+            /// Expired events are reported with `DropReason::Expired`. Events
+            /// with a `#[preempt]` handler skip the queue and never expire.
+            pub async fn send_with_ttl(&self, event: #event_enum_name, ttl: std::time::Duration) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.push(event, Some(tokio_fsm::__private::now_on(&self.clock) + ttl), None, tokio_fsm::Reservation::none()).await
+            }
+
+            /// Runs the builder's `validator` and `shed_above` checks for the
+            /// sends that can hand a rejected event back: `submit`,
+            /// `try_submit` and `call`.
+            fn admit(&self, event: #event_enum_name) -> Result<#event_enum_name, tokio_fsm::SubmitError<#event_enum_name>> {
+                if let Err(error) = self.validate(&event) {
+                    tokio_fsm::DropReason::Invalid.trace(stringify!(#fsm_name), self.current_state().as_str(), event.as_str());
+                    return Err(tokio_fsm::SubmitError::Invalid { event, error });
+                }
+                self.shed(event)
+            }
+
+            /// Rejects `event` if the queue is at or above the builder's
+            /// `shed_above` watermark. Events with a `#[preempt]` handler are
+            /// never shed.
+            fn shed(&self, event: #event_enum_name) -> Result<#event_enum_name, tokio_fsm::SubmitError<#event_enum_name>> {
+                match self.shed_above {
+                    Some(watermark) if !#fsm_name::is_preempting(&event) && self.queue_len() >= watermark => {
+                        tokio_fsm::DropReason::Shed.trace(stringify!(#fsm_name), self.current_state().as_str(), event.as_str());
+                        Err(tokio_fsm::SubmitError::Shed(event))
+                    }
+                    _ => Ok(event),
+                }
+            }
+
+            /// Runs the validator registered with the builder, if any.
+            fn validate(&self, event: &#event_enum_name) -> Result<(), tokio_fsm::ValidationError> {
+                match self.validator {
+                    Some(validator) => validator(event),
+                    None => Ok(()),
+                }
+            }
+
+            /// Enqueues an event into the room `reservation` holds, if it
+            /// holds any.
+            async fn push(&self, event: #event_enum_name, deadline: Option<tokio_fsm::__private::rt::Instant>, ack: Option<tokio_fsm::Ack>, reservation: tokio_fsm::Reservation<#event_enum_name>) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                let result = if #fsm_name::is_preempting(&event) {
+                    self.priority_tx
+                        .send((event, ack))
+                        .map_err(|err| tokio::sync::mpsc::error::SendError(err.0.0))
+                } else {
+                    // Taken back if the send fails or this future is dropped
+                    // while waiting for capacity.
+                    let coalesced = #fsm_name::coalesce_key(&event).map(|key| self.coalescer.enqueue(key));
+                    let mut envelope = tokio_fsm::Envelope::new(event, deadline, tokio_fsm::__private::now_on(&self.clock));
+                    envelope.ack = ack;
+                    let result = match reservation.into_permit() {
+                        Some(permit) => {
+                            permit.send(envelope);
+                            Ok(())
+                        }
+                        None => { #send }
+                    };
+                    if result.is_ok() {
+                        if let Some(coalesced) = coalesced {
+                            coalesced.commit();
+                        }
+                        if tokio_fsm::__private::INTROSPECT {
+                            self.stats.enqueued();
+                        }
+                        self.watermarks.observe(self.queue_len());
+                    }
+                    result
+                };
+                if tokio_fsm::__private::TRACE_DROPS {
+                    if let Err(tokio::sync::mpsc::error::SendError(event)) = &result {
+                        tokio_fsm::DropReason::ShuttingDown.trace(stringify!(#fsm_name), self.current_state().as_str(), event.as_str());
+                    }
+                }
+                result
+            }
+
+            /// Attempts to send an event without awaiting capacity.
+            ///
+            /// Events arriving above the builder's `shed_above` watermark are
+            /// handed back as `Full`.
+            pub fn try_send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::TrySendError<#event_enum_name>> {
+                match self.shed(event) {
+                    Ok(event) => self.try_push(event),
+                    Err(err) => Err(tokio::sync::mpsc::error::TrySendError::Full(err.into_event())),
+                }
+            }
+
+            /// Attempts to enqueue an event without awaiting capacity.
+            fn try_push(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::TrySendError<#event_enum_name>> {
+                let result = if #fsm_name::is_preempting(&event) {
+                    self.priority_tx
+                        .send((event, None))
+                        .map_err(|err| tokio::sync::mpsc::error::TrySendError::Closed(err.0.0))
+                } else {
+                    let coalesced = #fsm_name::coalesce_key(&event).map(|key| self.coalescer.enqueue(key));
+                    let envelope = tokio_fsm::Envelope::new(event, None, tokio_fsm::__private::now_on(&self.clock));
+                    let result = { #try_send };
+                    if result.is_ok() {
+                        if let Some(coalesced) = coalesced {
+                            coalesced.commit();
+                        }
+                        if tokio_fsm::__private::INTROSPECT {
+                            self.stats.enqueued();
+                        }
+                        self.watermarks.observe(self.queue_len());
+                    }
+                    result
+                };
+                if tokio_fsm::__private::TRACE_DROPS {
+                    if let Err(err) = &result {
+                        let (reason, event) = match err {
+                            tokio::sync::mpsc::error::TrySendError::Full(event) => (tokio_fsm::DropReason::QueueFull, event),
+                            tokio::sync::mpsc::error::TrySendError::Closed(event) => (tokio_fsm::DropReason::ShuttingDown, event),
+                        };
+                        reason.trace(stringify!(#fsm_name), self.current_state().as_str(), event.as_str());
+                    }
+                }
+                result
+            }
+
+            /// Sends anything convertible into an event, like the handle's
+            /// `send_into`.
+            pub async fn send_into<T: Into<#event_enum_name>>(&self, value: T) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.send(value.into()).await
+            }
+
+            /// Sends `event` once `delay` has elapsed on the FSM's clock, from
+            /// a background task on its runtime.
+            ///
+            /// Abort the returned handle to cancel the send. If the FSM has
+            /// stopped by then, the event is dropped.
+            pub fn send_after(&self, delay: std::time::Duration, event: #event_enum_name) -> tokio_fsm::WorkHandle {
+                let sender = self.clone();
+                let sleep = tokio_fsm::__private::sleep_on(&self.clock, delay);
+                tokio_fsm::WorkHandle::spawn(self.spawner, async move {
+                    sleep.await;
+                    let _ = sender.send(event).await;
+                })
+            }
+
+            /// Returns the number of events waiting in the queue. Events with
+            /// a `#[preempt]` handler and follow-ups aren't counted.
+            pub fn queue_len(&self) -> usize {
+                #len
+            }
+
+            /// Returns the current state of the FSM.
+            pub fn current_state(&self) -> #state_enum_name {
+                self.state_rx.borrow().to
+            }
+        }
+
+        impl std::fmt::Debug for #sender_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(stringify!(#sender_name))
+                    .field("state", &self.current_state())
+                    .finish()
+            }
+        }
+    }
+}
+
+pub fn render_handle_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let handle_name = fsm.handle_ident();
+    let sender_name = fsm.sender_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let context_type = &fsm.context_type;
+
+    quote! {
+        impl #handle_name {
+            /// Returns the identity of the FSM instance behind this handle.
+            pub fn id(&self) -> tokio_fsm::InstanceId {
+                self.id
+            }
+
+            /// Sends an event to the FSM.
+            ///
+            /// Events with a `#[preempt]` handler skip the queue.
+            pub async fn send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.sender.send(event).await
+            }
+
+            /// Sends an event and waits until its handler has run, returning
+            /// the state it left the FSM in. See the sender's `call`.
+            pub async fn call(&self, event: #event_enum_name) -> Result<#state_enum_name, tokio_fsm::CallError<#event_enum_name>> {
+                self.sender.call(event).await
+            }
+
+            /// Sends an event that is dropped if still queued after `ttl`.
+            /// See the sender's `send_with_ttl`.
+            pub async fn send_with_ttl(&self, event: #event_enum_name, ttl: std::time::Duration) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.sender.send_with_ttl(event, ttl).await
+            }
+
+            /// Attempts to send an event without awaiting capacity.
+            pub fn try_send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::TrySendError<#event_enum_name>> {
+                self.sender.try_send(event)
+            }
+
+            /// Sends `event` once `delay` has elapsed, like the sender
+            /// returned by `self.handle()` inside handlers.
+            pub fn send_after(&self, delay: std::time::Duration, event: #event_enum_name) -> tokio_fsm::WorkHandle {
+                self.sender.send_after(delay, event)
+            }
+
+            /// Returns a sender that doesn't keep the FSM alive.
+            pub fn sender(&self) -> #sender_name {
+                self.sender.clone()
+            }
+
+            /// Returns the number of events waiting in the queue. Events with
+            /// a `#[preempt]` handler and follow-ups aren't counted.
+            pub fn queue_len(&self) -> usize {
+                self.sender.queue_len()
+            }
+
+            /// Subscribes to crossings of the watermarks set with the
+            /// builder's `watermarks(low, high)`, e.g. to pause a producer
+            /// while the queue is `QueuePressure::High` instead of stalling
+            /// in `send().await`. Always `Normal` without watermarks.
+            pub fn queue_pressure(&self) -> tokio::sync::watch::Receiver<tokio_fsm::QueuePressure> {
+                self.sender.watermarks.subscribe()
+            }
+
+            /// Runs the validator registered with the builder, if any.
+            pub fn validate(&self, event: &#event_enum_name) -> Result<(), tokio_fsm::ValidationError> {
+                self.sender.validate(event)
+            }
+
+            /// Validates an event and sends it to the FSM, waiting for queue
+            /// capacity.
+            ///
+            /// Rejected events are handed back in `SubmitError::Invalid`
+            /// without being enqueued, and events arriving above the
+            /// `shed_above` watermark in `SubmitError::Shed`. `send` and
+            /// `try_send` skip the validator, and `send` waits for room rather
+            /// than being shed.
+            pub async fn submit(&self, event: #event_enum_name) -> Result<(), tokio_fsm::SubmitError<#event_enum_name>> {
+                let event = self.sender.admit(event)?;
+                self.sender.push(event, None, None, tokio_fsm::Reservation::none()).await.map_err(tokio_fsm::SubmitError::from)
+            }
+
+            /// Validates an event and attempts to send it without awaiting
+            /// capacity.
+            pub fn try_submit(&self, event: #event_enum_name) -> Result<(), tokio_fsm::SubmitError<#event_enum_name>> {
+                let event = self.sender.admit(event)?;
+                self.sender.try_push(event).map_err(tokio_fsm::SubmitError::from)
+            }
+
+            /// Sends anything convertible into an event, such as a payload
+            /// type carried by exactly one event.
+            pub async fn send_into<T: Into<#event_enum_name>>(&self, value: T) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.send(value.into()).await
+            }
+
+            /// Converts an `#[external_event]` message and sends it to the FSM.
+            ///
+            /// Messages without a mapped event are dropped, just like events the
+            /// current state has no handler for, and reported with
+            /// `DropReason::NoHandler` under the message's type name.
+            pub async fn send_external<M>(&self, message: M) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>>
+            where
+                #event_enum_name: TryFrom<M>,
+            {
+                match #event_enum_name::try_from(message) {
+                    Ok(event) => self.send(event).await,
+                    Err(_) => {
+                        tokio_fsm::DropReason::NoHandler.trace(stringify!(#fsm_name), self.current_state().as_str(), std::any::type_name::<M>());
+                        Ok(())
+                    }
+                }
+            }
+
+            /// Removes every event still waiting in the queue and returns them
+            /// in arrival order, so they can be re-routed or persisted (e.g.
+            /// right before `shutdown_immediate`). The event currently being
+            /// handled, if any, is not included. Like `purge`, this returns
+            /// expired and superseded events too, and a `call` waiting on any
+            /// of them fails with `CallError::Discarded(DropReason::Purged)`.
+            ///
+            /// Returns an empty `Vec` once the FSM has stopped.
+            pub async fn drain_pending(&self) -> Vec<#event_enum_name> {
+                let (reply_tx, reply_rx) = tokio_fsm::__private::rt::oneshot::channel();
+                if self.control_tx.send(tokio_fsm::Control::Drain(reply_tx)).is_err() {
+                    return Vec::new();
+                }
+                reply_rx.await.unwrap_or_default()
+            }
+
+            /// Removes the queued, not yet processed events matching `predicate`
+            /// and returns them in arrival order; the rest keep their place.
+            ///
+            /// E.g. `handle.purge(|e| matches!(e, OrderFsmEvent::Ship(..)))`
+            /// once an order is cancelled. Matches that have expired or been
+            /// superseded are returned too, and a `call` waiting on any of
+            /// them fails with `CallError::Discarded(DropReason::Purged)`.
+            /// Returns an empty `Vec` once the FSM has stopped.
+            pub async fn purge(&self, predicate: impl FnMut(&#event_enum_name) -> bool + Send + 'static) -> Vec<#event_enum_name> {
+                let (reply_tx, reply_rx) = tokio_fsm::__private::rt::oneshot::channel();
+                let command = tokio_fsm::Control::Purge(Box::new(predicate), reply_tx);
+                if self.control_tx.send(command).is_err() {
+                    return Vec::new();
+                }
+                reply_rx.await.unwrap_or_default()
+            }
+
+            /// Feeds every event `source` yields to the FSM, e.g. a websocket
+            /// or message-bus subscription, without a forwarding task.
+            ///
+            /// The run loop polls attached streams alongside the queue, after
+            /// queued events, and drops each once it ends. Its events skip
+            /// the queue, so `queue_len`, coalescing and TTLs don't apply to
+            /// them. The stream is dropped if the FSM has stopped.
+            pub fn attach_source<S>(&self, source: S)
+            where
+                S: tokio_fsm::__private::futures_core::Stream<Item = #event_enum_name> + Send + 'static,
+            {
+                let _ = self.control_tx.send(tokio_fsm::Control::Attach(Box::pin(source)));
+            }
+
+            /// Round-trips a no-op through the run loop, proving the task is
+            /// alive and returning how long the loop took to get to it.
+            ///
+            /// The ping skips the event queue but waits for the handler in
+            /// progress, so a slow reply points at a stuck handler, e.g. for
+            /// a health check with a deadline.
+            pub async fn ping(&self) -> Result<std::time::Duration, tokio_fsm::PingError> {
+                let started = tokio_fsm::__private::rt::now();
+                let (reply_tx, reply_rx) = tokio_fsm::__private::rt::oneshot::channel();
+                self.control_tx
+                    .send(tokio_fsm::Control::Ping(reply_tx))
+                    .map_err(|_| tokio_fsm::PingError::Stopped)?;
+                reply_rx.await.map_err(|_| tokio_fsm::PingError::Stopped)?;
+                Ok(started.elapsed())
+            }
+
+            /// Runs `f` on the FSM's context and returns its result, e.g. to
+            /// check invariants from a test. Like `ping`, it jumps the event
+            /// queue but waits for the handler in progress.
+            pub async fn inspect<R: Send + 'static>(
+                &self,
+                f: impl FnOnce(&#context_type) -> R + Send + 'static,
+            ) -> Result<R, tokio_fsm::PingError> {
+                let (reply_tx, reply_rx) = tokio_fsm::__private::rt::oneshot::channel();
+                let inspect = move |context: &dyn std::any::Any| {
+                    let context = context
+                        .downcast_ref::<#context_type>()
+                        .expect("Control::Inspect called with a foreign context");
+                    let _ = reply_tx.send(f(context));
+                };
+                self.control_tx
+                    .send(tokio_fsm::Control::Inspect(Box::new(inspect)))
+                    .map_err(|_| tokio_fsm::PingError::Stopped)?;
+                reply_rx.await.map_err(|_| tokio_fsm::PingError::Stopped)
+            }
+
+            /// Returns a snapshot of per-handler latencies, keyed by the
+            /// `(state, event)` that triggered each handler.
+            pub fn stats(&self) -> tokio_fsm::FsmStats {
+                self.stats.snapshot()
+            }
+
+            /// Returns `true` if the FSM currently has a handler for `event`.
+            ///
+            /// This is a cheap, synchronous check against the transition table,
+            /// e.g. to reject a request upfront instead of enqueueing an event
+            /// that would be ignored. The state may still change before a
+            /// subsequently sent event is processed.
+            pub fn accepts(&self, event: &#event_enum_name) -> bool {
+                self.current_state().valid_events().contains(&event.as_str())
+            }
+
+            /// Returns the current state of the FSM.
+            pub fn current_state(&self) -> #state_enum_name {
+                self.state_rx.borrow().to
+            }
+
+            /// Returns the current state together with its transition sequence number.
+            ///
+            /// The sequence starts at `0` and increases by one on every committed
+            /// transition, so round trips such as `A -> B -> A` remain observable.
+            pub fn current_state_versioned(&self) -> (u64, #state_enum_name) {
+                let change = self.state_rx.borrow();
+                (change.seq, change.to)
+            }
+
+            /// Returns the most recent state change, including its cause.
+            pub fn last_change(&self) -> tokio_fsm::StateChange<#state_enum_name> {
+                *self.state_rx.borrow()
+            }
+
+            /// Returns how long the FSM has been in its current state, on the
+            /// FSM's clock. Transitions back into the same state don't reset
+            /// it.
+            pub fn time_in_current_state(&self) -> std::time::Duration {
+                let entered = self.state_rx.borrow().entered;
+                tokio_fsm::__private::now_on(&self.sender.clock).saturating_duration_since(entered)
+            }
+
+            /// Subscribes to state change notifications.
+            ///
+            /// The receiver always holds the latest change; intermediate changes
+            /// may be skipped by slow observers, which the `seq` field reveals.
+            pub fn subscribe(&self) -> tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>> {
+                self.state_rx.clone()
+            }
+
+            /// Subscribes to every state change from now on, for observers
+            /// that must see each transition rather than the latest.
+            ///
+            /// Changes are buffered until received, so drop the receiver once
+            /// done with it. It closes once the FSM has stopped, after the
+            /// final `TransitionCause::Shutdown` change of a clean stop.
+            pub fn subscribe_transitions(&self) -> tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::StateChange<#state_enum_name>> {
+                self.sender.transitions.subscribe()
+            }
+
+            /// Waits for the FSM to reach the specified state.
+            pub async fn wait_for_state(&self, target: #state_enum_name) -> Result<(), tokio::sync::watch::error::RecvError> {
+                self.wait_until(|state| *state == target).await.map(|_| ())
+            }
+
+            /// Waits for the FSM to reach any of the specified states.
+            ///
+            /// Returns whichever state is observed first, e.g. to wait for
+            /// "success or failure" without racing two futures.
+            pub async fn wait_for_any_state(&self, targets: &[#state_enum_name]) -> Result<#state_enum_name, tokio::sync::watch::error::RecvError> {
+                self.wait_until(|state| targets.contains(state)).await
+            }
+
+            /// Waits until the current state satisfies `predicate`, returning that state.
+            ///
+            /// The predicate is checked against the current state first, then
+            /// against the latest state after each change. Like `subscribe`,
+            /// this only sees the latest state, so a state the FSM passes
+            /// through quickly may be missed; use `subscribe_transitions` to
+            /// check every one.
+            pub async fn wait_until(&self, mut predicate: impl FnMut(&#state_enum_name) -> bool) -> Result<#state_enum_name, tokio::sync::watch::error::RecvError> {
+                let mut rx = self.state_rx.clone();
+                loop {
+                    let state = rx.borrow_and_update().to;
+                    if predicate(&state) {
+                        return Ok(state);
+                    }
+                    rx.changed().await?;
+                }
+            }
+
+            /// Initiates a graceful shutdown. Processes remaining events before exiting.
+            pub fn shutdown_graceful(&self) {
+                let _ = self.shutdown_tx.send(Some(tokio_fsm::ShutdownMode::Graceful));
+            }
+
+            /// Initiates an immediate shutdown. Drops unprocessed events.
+            pub fn shutdown_immediate(&self) {
+                let _ = self.shutdown_tx.send(Some(tokio_fsm::ShutdownMode::Immediate));
+            }
+        }
+
+        impl tokio_fsm::FsmHandle for #handle_name {
+            type State = #state_enum_name;
+            type Event = #event_enum_name;
+            type Context = #context_type;
+
+            fn send(&self, event: #event_enum_name) -> impl std::future::Future<Output = Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>>> + Send {
+                #handle_name::send(self, event)
+            }
+
+            fn try_send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::TrySendError<#event_enum_name>> {
+                #handle_name::try_send(self, event)
+            }
+
+            fn current_state(&self) -> #state_enum_name {
+                #handle_name::current_state(self)
+            }
+
+            fn queue_len(&self) -> usize {
+                #handle_name::queue_len(self)
+            }
+
+            fn inspect<R: Send + 'static>(
+                &self,
+                f: impl FnOnce(&#context_type) -> R + Send + 'static,
+            ) -> impl std::future::Future<Output = Result<R, tokio_fsm::PingError>> + Send {
+                #handle_name::inspect(self, f)
+            }
+
+            fn subscribe(&self) -> tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>> {
+                #handle_name::subscribe(self)
+            }
+
+            fn subscribe_transitions(&self) -> tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::StateChange<#state_enum_name>> {
+                #handle_name::subscribe_transitions(self)
+            }
+
+            fn id(&self) -> tokio_fsm::InstanceId {
+                self.id
+            }
+
+            fn inspector(&self) -> tokio_fsm::Inspector {
+                let state_rx = self.state_rx.clone();
+                tokio_fsm::Inspector {
+                    id: self.id,
+                    mermaid: #fsm_name::mermaid(),
+                    stats: self.stats.clone(),
+                    last_change: std::sync::Arc::new(move || {
+                        let change = *state_rx.borrow();
+                        tokio_fsm::TransitionRecord {
+                            seq: change.seq,
+                            from: change.from.as_str(),
+                            to: change.to.as_str(),
+                            cause: change.cause,
+                        }
+                    }),
+                }
+            }
+        }
+
+        impl tokio_fsm::FsmCall for #handle_name {
+            fn call(&self, event: #event_enum_name) -> impl std::future::Future<Output = Result<#state_enum_name, tokio_fsm::CallError<#event_enum_name>>> + Send {
+                #handle_name::call(self, event)
+            }
+
+            fn reserve(&self) -> impl std::future::Future<Output = tokio_fsm::Reservation<#event_enum_name>> + Send {
+                self.sender.reserve()
+            }
+
+            fn call_reserved(&self, reservation: tokio_fsm::Reservation<#event_enum_name>, event: #event_enum_name) -> impl std::future::Future<Output = Result<#state_enum_name, tokio_fsm::CallError<#event_enum_name>>> + Send {
+                self.sender.call_reserved(reservation, event)
+            }
+        }
+
+        impl PartialEq for #handle_name {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
+
+        impl Eq for #handle_name {}
+
+        impl std::fmt::Debug for #handle_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(stringify!(#handle_name))
+                    .field("id", &self.id)
+                    .field("state", &self.current_state())
+                    .finish()
+            }
+        }
+
+        impl std::hash::Hash for #handle_name {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.id.hash(state);
+            }
+        }
+
+        tokio_fsm::__impl_sink!(#handle_name, #event_enum_name);
+    }
+}
+
+/// Renders the typestate API: `handle.typed(State)` and one method per
+/// statically-known transition on `[FsmName]TypedHandle<State>`.
+pub fn render_typed_handle_impl(fsm: &FsmStructure) -> TokenStream {
+    let handle_name = fsm.handle_ident();
+    let typed_handle_name = fsm.typed_handle_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let state_enum_name = fsm.state_enum_ident();
+
+    let state_impls = fsm.states.iter().filter_map(|state| {
+        let from = &state.name;
+        let methods: Vec<TokenStream> = fsm
+            .events
+            .iter()
+            .filter_map(|event| {
+                let to = fsm.static_target(from, &event.name)?;
+                let name = &event.name;
+                let method = method_ident(name);
+                let doc = format!("Sends `{name}`, after which the FSM is expected in `{to}`.");
+                let (params, event_value) = render_send_args(fsm, event);
+                Some(quote! {
+                    #[doc = #doc]
+                    pub async fn #method(self #(, #params)*) -> Result<#typed_handle_name<#to>, tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                        self.handle.send(#event_value).await?;
+                        Ok(#typed_handle_name {
+                            handle: self.handle,
+                            state: std::marker::PhantomData,
+                        })
+                    }
+                })
+            })
+            .collect();
+        if methods.is_empty() {
+            return None;
+        }
+        Some(quote! {
+            impl #typed_handle_name<#from> {
+                #(#methods)*
+            }
+        })
+    });
+
+    quote! {
+        impl #handle_name {
+            /// Returns this handle typed at `state` if the FSM is currently in
+            /// it, or the handle back otherwise.
+            ///
+            /// The typing holds as long as nothing else moves the FSM: another
+            /// handle, a state timeout or an unhandled event leave it stale.
+            pub fn typed<S: Into<#state_enum_name>>(self, state: S) -> Result<#typed_handle_name<S>, Self> {
+                if self.current_state() == state.into() {
+                    Ok(#typed_handle_name {
+                        handle: self,
+                        state: std::marker::PhantomData,
+                    })
+                } else {
+                    Err(self)
+                }
+            }
+        }
+
+        impl<S> #typed_handle_name<S> {
+            /// Returns the untyped handle.
+            pub fn handle(&self) -> &#handle_name {
+                &self.handle
+            }
+
+            /// Drops the typing, e.g. to send an event whose next state is
+            /// only known at runtime.
+            pub fn into_inner(self) -> #handle_name {
+                self.handle
+            }
+        }
+
+        impl<S> std::fmt::Debug for #typed_handle_name<S> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_tuple(stringify!(#typed_handle_name))
+                    .field(&self.handle)
+                    .finish()
+            }
+        }
+
+        #(#state_impls)*
+    }
+}
+
+/// Methods of the handle, its traits and `std`'s blanket impls, which an
+/// event's send method must not shadow.
+const HANDLE_METHODS: &[&str] = &[
+    "accepts",
+    "attach_source",
+    "borrow",
+    "borrow_mut",
+    "call",
+    "clone",
+    "clone_from",
+    "clone_into",
+    "current_state",
+    "current_state_versioned",
+    "drain_pending",
+    "eq",
+    "fmt",
+    "from",
+    "hash",
+    "id",
+    "inspect",
+    "inspector",
+    "into",
+    "last_change",
+    "ne",
+    "ping",
+    "poll_close",
+    "poll_flush",
+    "poll_ready",
+    "purge",
+    "queue_len",
+    "queue_pressure",
+    "send",
+    "send_after",
+    "send_external",
+    "send_into",
+    "send_with_ttl",
+    "sender",
+    "shutdown_graceful",
+    "shutdown_immediate",
+    "start_send",
+    "stats",
+    "submit",
+    "subscribe",
+    "time_in_current_state",
+    "to_owned",
+    "try_from",
+    "try_into",
+    "try_send",
+    "try_submit",
+    "type_id",
+    "typed",
+    "validate",
+    "wait_for_any_state",
+    "wait_for_state",
+    "wait_until",
+];
+
+/// Renders one send method per event on the handle, e.g. `handle.start()`
+/// for `Start` and `handle.process(data)` for `Process(Data)`.
+///
+/// Events whose method would clash with one of the handle's own get none.
+pub fn render_event_methods(fsm: &FsmStructure) -> TokenStream {
+    let handle_name = fsm.handle_ident();
+    let event_enum_name = fsm.event_enum_ident();
+
+    let methods = fsm.events.iter().filter_map(|event| {
+        let name = &event.name;
+        let method = method_ident(name);
+        if HANDLE_METHODS.contains(&method.to_string().trim_start_matches("r#")) {
+            return None;
+        }
+        let doc = format!("Sends `{name}`, waiting for queue capacity like `send`.");
+        let (params, event_value) = render_send_args(fsm, event);
+        Some(quote! {
+            #[doc = #doc]
+            pub async fn #method(&self #(, #params)*) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
+                self.send(#event_value).await
+            }
+        })
+    });
+
+    quote! {
+        impl #handle_name {
+            #(#methods)*
+        }
+    }
+}
+
+/// The parameters of a generated send method for `event` and the event they
+/// build. Events with a generated payload struct take its fields one by one.
+fn render_send_args(fsm: &FsmStructure, event: &Event) -> (Vec<TokenStream>, TokenStream) {
+    let event_enum_name = fsm.event_enum_ident();
+    let name = &event.name;
+    if !event.fields.is_empty() {
+        let payload = validation::payload_struct_ident(&fsm.fsm_name, name);
+        let params = event
+            .fields
+            .iter()
+            .map(|(field, ty)| quote! { #field: #ty })
+            .collect();
+        let fields = event.fields.iter().map(|(field, _)| field);
+        return (
+            params,
+            quote! { #event_enum_name::#name(#payload { #(#fields),* }) },
+        );
+    }
+    match &event.payload_type {
+        Some(ty) => (
+            vec![quote! { payload: #ty }],
+            quote! { #event_enum_name::#name(payload) },
+        ),
+        None => (Vec::new(), quote! { #event_enum_name::#name }),
+    }
+}
+
+/// `FetchFailed` -> `fetch_failed`, escaping keywords as raw identifiers.
+pub fn method_ident(event: &Ident) -> Ident {
+    let name = snake_case(event);
+    match name.as_str() {
+        // Keywords that can't be raw identifiers.
+        "crate" | "self" | "super" => format_ident!("{}_", name, span = event.span()),
+        _ if syn::parse_str::<Ident>(&name).is_err() => Ident::new_raw(&name, event.span()),
+        _ => Ident::new(&name, event.span()),
+    }
+}
+
+/// `FetchFailed` -> `"fetch_failed"`.
+pub fn snake_case(name: &Ident) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+pub fn render_builder_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let builder_name = fsm.builder_ident();
+    let handle_name = fsm.handle_ident();
+    let task_name = fsm.task_ident();
+    let core_name = fsm.core_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let output_return = fsm
+        .output_type
+        .as_ref()
+        .map(|output_type| quote! { , tokio::sync::mpsc::Receiver<#output_type> });
+    let core_return = match &output_return {
+        Some(output_return) => quote! { (#core_name #output_return) },
+        None => quote! { #core_name },
+    };
+    // FSMs on a custom runtime can't be placed on a Tokio one.
+    let spawn_on = fsm.runtime.is_none().then(|| {
+        quote! {
+            /// Spawns the FSM onto `runtime`. Background work started by its
+            /// handlers, such as `spawn_work`, runs there too.
+            pub fn spawn_on(self, runtime: &tokio::runtime::Handle) -> (#handle_name, #task_name #output_return) {
+                #fsm_name::spawn_in(self, Some(runtime))
+            }
+        }
+    });
+
+    quote! {
+        impl #builder_name {
+            /// Checks events passed to the handle's `submit`, `try_submit` and
+            /// `call`, rejecting malformed ones before they are enqueued:
+            /// `submit` and `try_submit` hand them back in
+            /// `SubmitError::Invalid`, `call` fails with
+            /// `CallError::Discarded(DropReason::Invalid)`. `send` and
+            /// `try_send` skip the check.
+            pub fn validator(mut self, validator: fn(&#event_enum_name) -> Result<(), tokio_fsm::ValidationError>) -> Self {
+                self.validator = Some(validator);
+                self
+            }
+
+            /// Rejects events sent while `watermark` or more events are queued,
+            /// trading rejections for bounded latency under overload:
+            /// `submit` and `try_submit` return `SubmitError::Shed`, `try_send`
+            /// returns `Full` and `call` fails with
+            /// `CallError::Discarded(DropReason::Shed)`. `send` can't report a
+            /// rejection, so it waits for room as usual. Preempting events and
+            /// the FSM's own follow-ups are unaffected.
+            pub fn shed_above(mut self, watermark: usize) -> Self {
+                self.shed_above = Some(watermark);
+                self
+            }
+
+            /// Publishes `QueuePressure::High` on the handle's
+            /// `queue_pressure()` once `high` events are queued, and
+            /// `Normal` again once the queue has drained to `low`.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `low` isn't below `high`.
+            pub fn watermarks(mut self, low: usize, high: usize) -> Self {
+                assert!(low < high, "the low watermark must be below the high watermark");
+                self.watermarks = Some((low, high));
+                self
+            }
+
+            /// Measures state timeouts and the `watchdog` budget against
+            /// `clock` instead of tokio's timer, e.g. a
+            /// `tokio_fsm::ManualClock` in tests.
+            pub fn clock(mut self, clock: impl tokio_fsm::Clock) -> Self {
+                self.clock = Some(std::sync::Arc::new(clock));
+                self
+            }
+
+            /// Reports every event handler that runs for longer than `budget`,
+            /// measured on the FSM's `clock`, as a `tracing` warning and a
+            /// `tokio_fsm_watchdog_fired_total` counter (with the `tracing`
+            /// and `metrics` features). The handler itself keeps running
+            /// unless `watchdog_event` preempts it.
+            pub fn watchdog(mut self, budget: std::time::Duration) -> Self {
+                self.watchdog = Some(budget);
+                self
+            }
+
+            /// Sends `event` to the FSM whenever the `watchdog` fires, e.g. a
+            /// `#[preempt]` event that cancels the hung handler and moves to
+            /// an error state. Has no effect without `watchdog`.
+            pub fn watchdog_event(mut self, event: #event_enum_name) -> Self {
+                self.watchdog_event = Some(event);
+                self
+            }
+
+            /// Keeps the FSM running once every handle is dropped, on its
+            /// timeouts, attached sources and `self.handle()` senders, like
+            /// `#[fsm(on_last_handle = detach)]`. It then stops when a
+            /// transition halts it or it reaches a terminal state.
+            pub fn detached(mut self) -> Self {
+                self.detached = true;
+                self
+            }
+
+            /// Spawns the FSM on the current Tokio runtime.
+            pub fn spawn(self) -> (#handle_name, #task_name #output_return) {
+                #fsm_name::spawn_in(self, None)
+            }
+
+            #spawn_on
+
+            /// Creates the FSM without spawning it, to be driven by the
+            /// caller. The validator and `shed_above` have no effect on events
+            /// passed to `process`.
+            pub fn core(self) -> #core_return {
+                #fsm_name::core_in(self)
+            }
+        }
     }
 }
 
-pub fn render_run(fsm: &FsmStructure) -> TokenStream {
-    let event_enum_name = fsm.event_enum_ident();
+/// Renders the `Submachine` impl, which lets other FSMs run this one in a
+/// `#[submachine]` state.
+pub fn render_submachine_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let handle_name = fsm.handle_ident();
+    let task_name = fsm.task_ident();
     let state_enum_name = fsm.state_enum_ident();
     let context_type = &fsm.context_type;
-    let error_type = &fsm.error_type;
 
-    let event_arms = build_event_arms(fsm);
-    let timeout_logic = build_timeout_handler(fsm);
+    let builder = if fsm.has_context {
+        quote! { Self::builder(context) }
+    } else {
+        quote! { { let () = context; Self::builder() } }
+    };
+    // The child's commands have nowhere to go, so its `emit` fails.
+    let output = fsm.output_type.as_ref().map(|_| quote! { , _output });
 
     quote! {
-        async fn run(
-            mut self,
-            mut events: tokio::sync::mpsc::Receiver<#event_enum_name>,
-            mut shutdown: tokio::sync::watch::Receiver<Option<tokio_fsm::ShutdownMode>>,
-            state_tx: tokio::sync::watch::Sender<#state_enum_name>,
-        ) -> Result<#context_type, #error_type> {
-            let sleep = tokio::time::sleep(tokio::time::Duration::from_secs(3153600000));
-            tokio::pin!(sleep);
+        impl tokio_fsm::Submachine for #fsm_name {
+            type Context = #context_type;
+            type Handle = #handle_name;
+            type Task = #task_name;
 
-            loop {
-                tokio::select! {
-                    _ = &mut sleep => {
-                        #timeout_logic
-                        sleep.as_mut().reset(tokio::time::Instant::now() + tokio::time::Duration::from_secs(3153600000));
-                    }
-                    _ = shutdown.changed() => {
-                        let mode = *shutdown.borrow();
-                        if let Some(mode) = mode {
-                            match mode {
-                                tokio_fsm::ShutdownMode::Immediate => return Ok(self.context),
-                                tokio_fsm::ShutdownMode::Graceful => {
-                                    while let Ok(event) = events.try_recv() {
-                                         match (self.state, event) {
-                                            #(#event_arms)*
-                                            _ => {}
-                                        }
-                                    }
-                                    return Ok(self.context);
-                                }
-                            }
-                        }
-                    }
-                    event = events.recv() => {
-                        let Some(event) = event else { break };
-                        match (self.state, event) {
-                            #(#event_arms)*
-                            _ => {
-                                // Event not handled in current state — silently ignored
-                            }
-                        }
-                    }
-                }
+            fn spawn_submachine(context: Self::Context) -> (Self::Handle, Self::Task) {
+                let (handle, task #output) = #builder.spawn();
+                (handle, task)
             }
 
-            Ok(self.context)
+            fn is_terminal(state: &#state_enum_name) -> bool {
+                state.is_terminal()
+            }
         }
     }
 }
 
-pub fn render_handle_impl(fsm: &FsmStructure) -> TokenStream {
-    let handle_name = fsm.handle_ident();
+pub fn render_task_impl(fsm: &FsmStructure) -> TokenStream {
+    let task_name = fsm.task_ident();
     let event_enum_name = fsm.event_enum_ident();
-    let state_enum_name = fsm.state_enum_ident();
+    let context_type = &fsm.context_type;
+    let error_type = &fsm.error_type;
 
     quote! {
-        impl #handle_name {
-            /// Sends an event to the FSM.
-            pub async fn send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::SendError<#event_enum_name>> {
-                self.event_tx.send(event).await
+        impl #task_name {
+            /// Returns where the FSM is in its graph, or was when it stopped:
+            /// after awaiting `&mut task` fails, the state and event it
+            /// failed at.
+            pub fn site(&self) -> tokio_fsm::FailureSite {
+                tokio_fsm::FailureSite {
+                    state: self.state_rx.borrow().to.as_str(),
+                    event: self.active_event.get().map(|index| #event_enum_name::NAMES[index]),
+                }
             }
 
-            /// Attempts to send an event without awaiting capacity.
-            pub fn try_send(&self, event: #event_enum_name) -> Result<(), tokio::sync::mpsc::error::TrySendError<#event_enum_name>> {
-                self.event_tx.try_send(event)
+            /// Stops the FSM at its next await point, dropping unprocessed
+            /// events. Awaiting the task then yields a cancelled `TaskError::Join`
+            /// (`TaskError::Runtime(None)` on a custom runtime).
+            pub fn abort(&self) {
+                self.handle.abort();
             }
 
-            /// Returns the current state of the FSM.
-            pub fn current_state(&self) -> #state_enum_name {
-                *self.state_rx.borrow()
+            /// Returns the identity of the FSM instance, shared with its
+            /// handles.
+            pub fn id(&self) -> tokio_fsm::InstanceId {
+                self.id
             }
+        }
 
-            /// Waits for the FSM to reach the specified state.
-            pub async fn wait_for_state(&self, target: #state_enum_name) -> Result<(), tokio::sync::watch::error::RecvError> {
-                let mut rx = self.state_rx.clone();
-                while *rx.borrow_and_update() != target {
-                    rx.changed().await?;
-                }
-                Ok(())
+        impl tokio_fsm::FsmTask for #task_name {
+            fn abort(&self) {
+                #task_name::abort(self)
             }
 
-            /// Initiates a graceful shutdown. Processes remaining events before exiting.
-            pub fn shutdown_graceful(&self) {
-                let _ = self.shutdown_tx.send(Some(tokio_fsm::ShutdownMode::Graceful));
+            fn id(&self) -> tokio_fsm::InstanceId {
+                #task_name::id(self)
             }
 
-            /// Initiates an immediate shutdown. Drops unprocessed events.
-            pub fn shutdown_immediate(&self) {
-                let _ = self.shutdown_tx.send(Some(tokio_fsm::ShutdownMode::Immediate));
+            fn site(&self) -> tokio_fsm::FailureSite {
+                #task_name::site(self)
             }
-        }
-    }
-}
 
-pub fn render_task_impl(fsm: &FsmStructure) -> TokenStream {
-    let task_name = fsm.task_ident();
-    let context_type = &fsm.context_type;
-    let error_type = &fsm.error_type;
+            fn shutdown_signal(&self) -> tokio_fsm::ShutdownSignal {
+                self.shutdown.clone()
+            }
+        }
 
-    quote! {
         impl std::future::Future for #task_name {
             type Output = Result<#context_type, tokio_fsm::TaskError<#error_type>>;
 
@@ -154,7 +1884,8 @@ pub fn render_task_impl(fsm: &FsmStructure) -> TokenStream {
                 match std::pin::Pin::new(&mut self.handle).poll(cx) {
                     std::task::Poll::Ready(Ok(Ok(res))) => std::task::Poll::Ready(Ok(res)),
                     std::task::Poll::Ready(Ok(Err(e))) => std::task::Poll::Ready(Err(tokio_fsm::TaskError::Fsm(e))),
-                    std::task::Poll::Ready(Err(e)) => std::task::Poll::Ready(Err(tokio_fsm::TaskError::Join(e))),
+                    std::task::Poll::Ready(Err(tokio_fsm::JoinFailure::Tokio(e))) => std::task::Poll::Ready(Err(tokio_fsm::TaskError::Join(e))),
+                    std::task::Poll::Ready(Err(tokio_fsm::JoinFailure::Runtime(message))) => std::task::Poll::Ready(Err(tokio_fsm::TaskError::Runtime(message))),
                     std::task::Poll::Pending => std::task::Poll::Pending,
                 }
             }
@@ -167,83 +1898,700 @@ pub fn render_task_impl(fsm: &FsmStructure) -> TokenStream {
 /// Builds state-gated match arms for the event loop.
 fn build_event_arms(fsm: &FsmStructure) -> Vec<TokenStream> {
     let mut arms = Vec::new();
+    let fsm_name = &fsm.fsm_name;
     let event_enum = fsm.event_enum_ident();
     let state_enum = fsm.state_enum_ident();
 
     for handler in &fsm.handlers {
-        if let Some(ref event) = handler.event {
-            let event_name = &event.name;
-            let method_name = &handler.method.sig.ident;
-
-            // Timeout reset logic
-            let timeout_reset = if let Some(duration) = handler.timeout {
-                let secs = duration.as_secs();
-                let nanos = duration.subsec_nanos();
-                quote! {
-                    sleep.as_mut().reset(tokio::time::Instant::now() + std::time::Duration::new(#secs, #nanos));
-                }
-            } else {
-                quote! {
-                    sleep.as_mut().reset(tokio::time::Instant::now() + std::time::Duration::from_secs(3153600000));
-                }
-            };
+        for (source_state, event_name) in &handler.triggers {
+            let event_label = event_name.to_string();
+            let cause = quote! { tokio_fsm::TransitionCause::Event(#event_label) };
 
             // Payload handling
-            let (payload_pattern, payload_call) = if handler.has_payload {
-                (quote! { (payload) }, quote! { (payload) })
+            // Borrowed arguments point into the event, dropped after the call.
+            let borrow = |borrowed: &bool| {
+                if *borrowed {
+                    quote! { & }
+                } else {
+                    quote! {}
+                }
+            };
+            let (payload_pattern, payload_call) = if !handler.payload_fields.is_empty() {
+                let fields = handler.payload_fields.iter().map(|(field, _)| field);
+                let borrows = handler.payload_borrowed.iter().map(borrow);
+                (
+                    quote! { (payload) },
+                    quote! { (#(#borrows payload.#fields),*) },
+                )
+            } else if handler.has_payload {
+                let borrows = handler.payload_borrowed.iter().map(borrow);
+                (quote! { (payload) }, quote! { (#(#borrows)* payload) })
             } else {
                 (quote! {}, quote! { () })
             };
 
-            // Result vs direct transition
-            let arm_inner = if handler.is_result {
-                quote! {
-                    match self.#method_name #payload_call .await {
-                        Ok(transition) => {
-                            self.state = transition.into_state().into();
-                            let _ = state_tx.send(self.state);
-                            #timeout_reset
-                        }
-                        Err(transition) => {
-                            self.state = transition.into_state().into();
-                            let _ = state_tx.send(self.state);
-                            sleep.as_mut().reset(tokio::time::Instant::now() + std::time::Duration::from_secs(3153600000));
-                        }
+            let arm_inner = render_invocation(
+                fsm,
+                handler,
+                &payload_call,
+                source_state,
+                &event_label,
+                &cause,
+            );
+
+            // One state-gated match arm per (state, event) pair
+            arms.push(quote! {
+                (#state_enum::#source_state, #event_enum::#event_name #payload_pattern) => {
+                    #arm_inner
+                }
+            });
+        }
+    }
+
+    for submachine in fsm.submachines() {
+        let state = &submachine.state;
+        let forward = submachine.forward_event();
+        let field = submachine.slot_ident();
+        arms.push(quote! {
+            (#state_enum::#state, #event_enum::#forward(event)) => {
+                let forwarded = match &self.#field {
+                    Some(child) => child.forward(event).await.is_ok(),
+                    None => false,
+                };
+                if !forwarded {
+                    tokio_fsm::DropReason::NoHandler.trace(stringify!(#fsm_name), self.state.as_str(), event_name);
+                }
+            }
+        });
+    }
+
+    arms
+}
+
+/// Builds the `sync_submachines` method, which starts the child FSM of a
+/// `#[submachine]` state on entry and stops it once the parent has left.
+///
+/// It runs before every event, so the child is started however the state was
+/// entered, and keeps running across self-transitions.
+fn render_sync_submachines(fsm: &FsmStructure) -> TokenStream {
+    let state_enum = fsm.state_enum_ident();
+    let event_enum = fsm.event_enum_ident();
+
+    let syncs: Vec<TokenStream> = fsm
+        .submachines()
+        .map(|submachine| {
+            let state = &submachine.state;
+            let field = submachine.slot_ident();
+            let child = &submachine.fsm;
+            let done = submachine.done_event();
+            quote! {
+                if self.state == #state_enum::#state {
+                    if self.#field.is_none() {
+                        self.#field = Some(tokio_fsm::SubmachineSlot::spawn::<#child, _>(
+                            &mut self.work,
+                            #event_enum::#done,
+                        ));
                     }
+                } else {
+                    self.#field = None;
                 }
-            } else {
-                quote! {
-                    let transition = self.#method_name #payload_call .await;
-                    self.state = transition.into_state().into();
-                    let _ = state_tx.send(self.state);
-                    #timeout_reset
+            }
+        })
+        .collect();
+
+    if syncs.is_empty() {
+        return quote! {};
+    }
+
+    quote! {
+        /// Starts or stops the child FSMs of `#[submachine]` states to match
+        /// the current state.
+        fn sync_submachines(&mut self) {
+            #(#syncs)*
+        }
+    }
+}
+
+/// Builds the `run_auto` method, which runs the `#[auto]` handler of the
+/// current state, if it has one.
+fn build_auto_handlers(fsm: &FsmStructure) -> TokenStream {
+    let state_enum = fsm.state_enum_ident();
+    let error_type = &fsm.error_type;
+    let cause = quote! { tokio_fsm::TransitionCause::Auto };
+
+    let arms: Vec<TokenStream> = fsm
+        .handlers
+        .iter()
+        .filter_map(|handler| {
+            let state = handler.auto_state.as_ref()?;
+            let body = render_invocation(fsm, handler, &quote! { () }, state, "auto", &cause);
+            Some(quote! {
+                #state_enum::#state => {
+                    #body
+                    true
                 }
-            };
+            })
+        })
+        .collect();
+
+    if arms.is_empty() {
+        return quote! {};
+    }
+
+    quote! {
+        /// Runs the `#[auto]` handler of the current state. Returns `false`
+        /// if the state has none.
+        async fn run_auto(
+            &mut self,
+            state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum>>,
+            timer: &mut tokio_fsm::Timer,
+        ) -> Result<bool, #error_type> {
+            Ok(match self.state {
+                #(#arms)*
+                _ => false,
+            })
+        }
+    }
+}
+
+/// Builds the `run_entry` method, which runs the `#[on_entry]` hook of the
+/// state just entered with the value its transition carried.
+fn build_entry_hooks(fsm: &FsmStructure) -> TokenStream {
+    if fsm.entry_hooks.is_empty() {
+        return quote! {};
+    }
+    let state_enum = fsm.state_enum_ident();
 
-            // Generate one match arm per source state (state-gated)
-            for source_state in &handler.source_states {
-                arms.push(quote! {
-                    (#state_enum::#source_state, #event_enum::#event_name #payload_pattern) => {
-                        #arm_inner
+    let arms = fsm.entry_hooks.iter().map(|hook| {
+        let state = &hook.state;
+        let method = &hook.method;
+        match &hook.data {
+            // `EntryData` lets only this type reach the hook.
+            Some(ty) => quote! {
+                #state_enum::#state => {
+                    if let Some(data) = data {
+                        let data = data
+                            .downcast::<#ty>()
+                            .expect("Transition::to_with carries the state's EntryData");
+                        self.#method(*data).await;
                     }
-                });
+                }
+            },
+            None => quote! {
+                #state_enum::#state => self.#method().await,
+            },
+        }
+    });
+
+    quote! {
+        /// Runs the `#[on_entry]` hook of the state just entered. Hooks taking
+        /// a value only run for transitions made with `Transition::to_with`.
+        async fn run_entry(&mut self, data: Option<Box<dyn std::any::Any + Send>>) {
+            match self.state {
+                #(#arms)*
+                _ => {}
             }
         }
     }
+}
 
-    arms
+/// Builds the `run_start` method, which runs the `#[on_start]` handler.
+fn build_start_handler(fsm: &FsmStructure) -> TokenStream {
+    let Some(handler) = fsm.handlers.iter().find(|h| h.is_start_handler) else {
+        return quote! {};
+    };
+    let state_enum = fsm.state_enum_ident();
+    let error_type = &fsm.error_type;
+    let cause = quote! { tokio_fsm::TransitionCause::Start };
+    let body = render_invocation(
+        fsm,
+        handler,
+        &quote! { () },
+        &fsm.initial_state,
+        "start",
+        &cause,
+    );
+
+    quote! {
+        /// Runs the `#[on_start]` handler, before the loop takes any event.
+        async fn run_start(
+            &mut self,
+            state_tx: &tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum>>,
+            timer: &mut tokio_fsm::Timer,
+        ) -> Result<(), #error_type> {
+            #body
+            Ok(())
+        }
+    }
+}
+
+/// Calls `handler` in `state`, records its latency under `(state,
+/// event_label)` and commits the transition it returns.
+///
+/// If `state` has `#[preempt]` handlers, the call is raced against the
+/// priority lane: a preempting event cancels it at its next await point and
+/// is dispatched next, leaving the state unchanged.
+fn render_invocation(
+    fsm: &FsmStructure,
+    handler: &Handler,
+    call_args: &TokenStream,
+    state: &Ident,
+    event_label: &str,
+    cause: &TokenStream,
+) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let method_name = &handler.method.sig.ident;
+    let state_label = state.to_string();
+    let commit_outcome = render_outcome(fsm, handler, cause);
+
+    // `#[auto]` handlers run to completion: the state they were entered for
+    // would immediately re-run them. So does `#[on_start]`, which runs before
+    // the priority lane is read.
+    let preempting = if handler.preempt || handler.auto_state.is_some() || handler.is_start_handler
+    {
+        Vec::new()
+    } else {
+        fsm.preempting_events(state)
+    };
+    let call = guard_panics(fsm, quote! { self.#method_name #call_args });
+    let commit_outcome = commit_or_crash(fsm, &commit_outcome);
+    if preempting.is_empty() {
+        return quote! {
+            let started = tokio_fsm::__private::rt::now();
+            let outcome = #call.await;
+            self.stats.record(#state_label, #event_label, started.elapsed());
+            #commit_outcome
+        };
+    }
+
+    let event_enum = fsm.event_enum_ident();
+    let patterns = preempting.iter().map(|event| {
+        let name = &event.name;
+        if event.payload_type.is_some() {
+            quote! { #event_enum::#name(..) }
+        } else {
+            quote! { #event_enum::#name }
+        }
+    });
+    quote! {
+        let started = tokio_fsm::__private::rt::now();
+        // Priority events that don't preempt this state wait until the
+        // handler is done.
+        let (outcome, deferred) = {
+            let work = #call;
+            tokio::pin!(work);
+            let mut deferred = Vec::new();
+            let outcome = loop {
+                tokio::select! {
+                    biased;
+
+                    Some((event, ack)) = priority.recv() => {
+                        if matches!(event, #(#patterns)|*) {
+                            break Err((event, ack));
+                        }
+                        deferred.push((event, ack));
+                    }
+                    outcome = &mut work => break Ok(outcome),
+                }
+            };
+            (outcome, deferred)
+        };
+        for event in deferred.into_iter().rev() {
+            self.pending.push_front(event);
+        }
+        match outcome {
+            Ok(outcome) => {
+                self.stats.record(#state_label, #event_label, started.elapsed());
+                #commit_outcome
+            }
+            Err(preempting) => {
+                self.preempted = true;
+                tokio_fsm::DropReason::Preempted.trace(stringify!(#fsm_name), #state_label, #event_label);
+                self.pending.push_front(preempting);
+            }
+        }
+    }
+}
+
+/// Commits the `outcome` of calling `handler`: its transition, either side of
+/// a `Result` of two, or the error a fallible handler returned.
+fn render_outcome(fsm: &FsmStructure, handler: &Handler, cause: &TokenStream) -> TokenStream {
+    // Timeout reset logic
+    let timeout_reset = if let Some(duration) = handler.timeout {
+        let secs = duration.as_secs();
+        let nanos = duration.subsec_nanos();
+        quote! {
+            timer.reset_after(std::time::Duration::new(#secs, #nanos));
+        }
+    } else {
+        quote! {
+            timer.disarm();
+        }
+    };
+
+    // Result vs direct transition
+    if handler.is_result {
+        let commit_ok = render_commit(fsm, cause, &timeout_reset);
+        let commit_err = render_commit(
+            fsm,
+            cause,
+            &quote! {
+                timer.disarm();
+            },
+        );
+        let convert_ok = render_conversion(fsm, handler, 0);
+        let convert_err = render_conversion(fsm, handler, 1);
+        quote! {
+            match outcome {
+                Ok(transition) => {
+                    #convert_ok
+                    #commit_ok
+                }
+                Err(transition) => {
+                    #convert_err
+                    #commit_err
+                }
+            }
+        }
+    } else if handler.is_fallible {
+        let commit = render_commit(fsm, cause, &timeout_reset);
+        let convert = render_conversion(fsm, handler, 0);
+        let commit = render_target_check(fsm, handler, commit);
+        quote! {
+            let transition = match outcome {
+                Ok(transition) => transition,
+                Err(error) => return Err(error),
+            };
+            #convert
+            #commit
+        }
+    } else {
+        let commit = render_commit(fsm, cause, &timeout_reset);
+        let convert = render_conversion(fsm, handler, 0);
+        let commit = render_target_check(fsm, handler, commit);
+        quote! {
+            let transition = outcome;
+            #convert
+            #commit
+        }
+    }
+}
+
+/// Converts the `transition` a handler returned as `impl Into<Transition<_>>`
+/// into the transition to its `index`th return state (`Ok` first).
+fn render_conversion(fsm: &FsmStructure, handler: &Handler, index: usize) -> TokenStream {
+    if !handler.converts {
+        return quote! {};
+    }
+    let state = if handler.dynamic {
+        fsm.state_enum_ident()
+    } else {
+        handler.return_states[index].name.clone()
+    };
+    quote! {
+        let transition: tokio_fsm::Transition<#state> = transition.into();
+    }
+}
+
+/// Commits the transition of a dynamic handler narrowed with
+/// `#[targets(...)]` only if it picked one of them, so the graph validation
+/// relied on holds at runtime. Any other transition is refused, along with
+/// its effects and follow-ups, leaving the state unchanged.
+fn render_target_check(fsm: &FsmStructure, handler: &Handler, commit: TokenStream) -> TokenStream {
+    if !(handler.dynamic && handler.narrowed) {
+        return commit;
+    }
+    let fsm_name = &fsm.fsm_name;
+    let state_enum = fsm.state_enum_ident();
+    let targets = handler.return_states.iter().map(|state| &state.name);
+    let handler_name = handler.method.sig.ident.to_string();
+    quote! {
+        if matches!(transition.state(), #(#state_enum::#targets)|*) {
+            #commit
+        } else {
+            tokio_fsm::__private::reject_off_target(
+                stringify!(#fsm_name),
+                self.state.as_str(),
+                #handler_name,
+                transition.state().as_str(),
+            );
+        }
+    }
+}
+
+/// Builds the `#[on_orphaned]` handler block, run once every handle is gone.
+fn build_orphaned_handler(fsm: &FsmStructure) -> TokenStream {
+    let Some(handler) = fsm.handlers.iter().find(|h| h.is_orphaned_handler) else {
+        return quote! {};
+    };
+    let name = &handler.method.sig.ident;
+    let call = guard_panics(fsm, quote! { self.#name() });
+    let commit_outcome = commit_or_crash(
+        fsm,
+        &render_outcome(
+            fsm,
+            handler,
+            &quote! { tokio_fsm::TransitionCause::Orphaned },
+        ),
+    );
+    quote! {
+        let started = tokio_fsm::__private::rt::now();
+        let outcome = #call.await;
+        self.stats.record(self.state.as_str(), "orphaned", started.elapsed());
+        #commit_outcome
+    }
 }
 
 /// Builds the timeout handler block for the run loop.
 fn build_timeout_handler(fsm: &FsmStructure) -> TokenStream {
     if let Some(handler) = fsm.handlers.iter().find(|h| h.is_timeout_handler) {
         let name = &handler.method.sig.ident;
+        let commit = render_commit(
+            fsm,
+            &quote! { tokio_fsm::TransitionCause::Timeout },
+            &quote! {},
+        );
+        let call = guard_panics(fsm, quote! { self.#name() });
+        let transition = if handler.is_fallible {
+            quote! {
+                let transition = match outcome {
+                    Ok(transition) => transition,
+                    Err(error) => return Err(error),
+                };
+            }
+        } else {
+            quote! { let transition = outcome; }
+        };
+        let convert = render_conversion(fsm, handler, 0);
+        let commit = render_target_check(fsm, handler, commit);
+        let commit_outcome = commit_or_crash(
+            fsm,
+            &quote! {
+                #transition
+                #convert
+                #commit
+            },
+        );
         quote! {
-            let transition = self.#name().await;
-            self.state = transition.into_state().into();
-            let _ = state_tx.send(self.state);
+            let started = tokio_fsm::__private::rt::now();
+            let outcome = #call.await;
+            self.stats.record(self.state.as_str(), "timeout", started.elapsed());
+            #commit_outcome
         }
     } else {
         quote! {}
     }
 }
+
+/// Wraps a handler `call` in `catch_unwind` if the FSM has an `on_panic`
+/// state, making its output a `Result` with the panic message as the error.
+fn guard_panics(fsm: &FsmStructure, call: TokenStream) -> TokenStream {
+    if fsm.on_panic.is_some() {
+        quote! { tokio_fsm::catch_unwind(#call) }
+    } else {
+        call
+    }
+}
+
+/// Commits the `outcome` of a call made through [`guard_panics`], diverting
+/// to the `on_panic` state if the handler panicked.
+///
+/// A panicking handler returns no transition, so there are no effects or
+/// follow-ups to run and no timeout to arm.
+fn commit_or_crash(fsm: &FsmStructure, commit_outcome: &TokenStream) -> TokenStream {
+    let Some(on_panic) = &fsm.on_panic else {
+        return commit_outcome.clone();
+    };
+
+    let state_enum = fsm.state_enum_ident();
+    let record_change = render_record_change();
+    quote! {
+        match outcome {
+            Ok(outcome) => {
+                #commit_outcome
+            }
+            Err(message) => {
+                self.last_panic = Some(message);
+                self.state = #state_enum::#on_panic;
+                let state = self.state;
+                let now = timer.now();
+                state_tx.send_modify(|change| {
+                    *change = change.next(state, tokio_fsm::TransitionCause::Panicked, now)
+                });
+                #record_change
+                timer.disarm();
+            }
+        }
+    }
+}
+
+/// Appends the change just published on `state_tx` to the instance's
+/// introspection history.
+fn render_record_change() -> TokenStream {
+    quote! { tokio_fsm::__private::record_change::<Self>(&self, &state_tx); }
+}
+
+/// Commits a handler's `transition`, executes its effects and queues its
+/// follow-up events.
+///
+/// Effects run only after the new state is published, so observers never see
+/// a side effect before the transition that caused it.
+fn render_commit(
+    fsm: &FsmStructure,
+    cause: &TokenStream,
+    timeout_reset: &TokenStream,
+) -> TokenStream {
+    let record_change = render_record_change();
+    let run_entry = if fsm.entry_hooks.is_empty() {
+        quote! {}
+    } else {
+        quote! { self.run_entry(parts.data).await; }
+    };
+
+    let publish = quote! {
+        let state = self.state;
+        let now = timer.now();
+        state_tx.send_modify(|change| *change = change.next(state, #cause, now));
+        #record_change
+        #timeout_reset
+        for effect in parts.effects {
+            effect.execute();
+        }
+        for event in parts.follow_ups {
+            self.pending.push_back((event, None));
+        }
+        self.halted = parts.halt;
+        #run_entry
+    };
+
+    let Some(persist) = &fsm.persist else {
+        return quote! {
+            let parts = transition.into_parts();
+            self.state = parts.state.into();
+            #publish
+        };
+    };
+
+    // The hook sees the new state before anyone else does. If it fails, the
+    // transition's effects and follow-ups are discarded along with it.
+    let hook = &persist.method;
+    let on_error = &persist.on_error;
+    let state_enum = fsm.state_enum_ident();
+    quote! {
+        let parts = transition.into_parts();
+        self.state = parts.state.into();
+        if self.#hook().await.is_ok() {
+            #publish
+        } else {
+            self.state = #state_enum::#on_error;
+            let state = self.state;
+            let now = timer.now();
+            state_tx.send_modify(|change| {
+                *change = change.next(state, tokio_fsm::TransitionCause::PersistFailed, now)
+            });
+            #record_change
+            timer.disarm();
+        }
+    }
+}
+
+/// Renders the methods of the `{Fsm}Core`, which replay the run loop's steps
+/// one event at a time on the caller's task.
+pub fn render_core_impl(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let core_name = fsm.core_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let context_type = &fsm.context_type;
+    let error_type = &fsm.error_type;
+
+    quote! {
+        impl #core_name {
+            /// Returns the current state.
+            pub fn state(&self) -> #state_enum_name {
+                self.fsm.state
+            }
+
+            /// Returns the context.
+            pub fn context(&self) -> &#context_type {
+                &self.fsm.context
+            }
+
+            /// Consumes the core, returning the context.
+            pub fn into_context(self) -> #context_type {
+                self.fsm.context
+            }
+
+            /// Whether a `Transition::halt` has stopped the FSM. A halted
+            /// core drops every further event.
+            pub fn is_halted(&self) -> bool {
+                self.fsm.halted
+            }
+
+            /// Subscribes to state change notifications.
+            pub fn subscribe(&self) -> tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>> {
+                self.state_tx.subscribe()
+            }
+
+            /// When the current state's timeout is due, if one is armed.
+            /// The caller should call `poll_timeout` once it has passed.
+            pub fn timeout_deadline(&self) -> Option<tokio_fsm::__private::rt::Instant> {
+                self.timer.deadline()
+            }
+
+            /// Handles `event`, then any follow-ups and `#[auto]` handlers
+            /// it leads to, and returns the state the FSM settled in.
+            pub async fn process(&mut self, event: #event_enum_name) -> Result<#state_enum_name, #error_type> {
+                self.start().await?;
+                if self.fsm.halted {
+                    tokio_fsm::DropReason::ShuttingDown.trace(stringify!(#fsm_name), self.fsm.state.as_str(), event.as_str());
+                } else {
+                    tokio_fsm::__private::Machine::dispatch(&mut self.fsm, event, None, &mut self.priority, &self.state_tx, &mut self.timer).await?;
+                    self.settle().await?;
+                }
+                Ok(self.fsm.state)
+            }
+
+            /// Fires the state timeout if its deadline has passed on the
+            /// FSM's clock, and returns the state the FSM settled in. Does
+            /// nothing otherwise.
+            pub async fn poll_timeout(&mut self) -> Result<#state_enum_name, #error_type> {
+                self.start().await?;
+                if !self.fsm.halted && self.timer.is_due() {
+                    self.timer.disarm();
+                    tokio_fsm::__private::Machine::timeout(&mut self.fsm, &self.state_tx, &mut self.timer).await?;
+                    self.settle().await?;
+                }
+                Ok(self.fsm.state)
+            }
+
+            /// Runs the `#[on_start]` handler and anything it leads to, once.
+            async fn start(&mut self) -> Result<(), #error_type> {
+                if !self.started {
+                    self.started = true;
+                    tokio_fsm::__private::Machine::start(&mut self.fsm, &self.state_tx, &mut self.timer).await?;
+                    self.settle().await?;
+                }
+                Ok(())
+            }
+
+            /// Runs `#[auto]` handlers and queued follow-ups until the FSM
+            /// waits for an outside event.
+            async fn settle(&mut self) -> Result<(), #error_type> {
+                while !self.fsm.halted {
+                    if tokio_fsm::__private::Machine::step(&mut self.fsm, &self.state_tx, &mut self.timer).await? {
+                        continue;
+                    }
+                    if let Some((event, _)) = self.fsm.pending.pop_front().or_else(|| self.priority.try_recv().ok()) {
+                        tokio_fsm::__private::Machine::dispatch(&mut self.fsm, event, None, &mut self.priority, &self.state_tx, &mut self.timer).await?;
+                    } else if let Some((event, meta)) = tokio_fsm::__private::next_queued(&mut self.fsm, &mut self.events) {
+                        tokio_fsm::__private::Machine::dispatch(&mut self.fsm, event, Some(meta), &mut self.priority, &self.state_tx, &mut self.timer).await?;
+                    } else {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
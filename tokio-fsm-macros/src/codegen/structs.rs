@@ -1,40 +1,188 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-
-use crate::validation::FsmStructure;
+use tokio_fsm_analysis::validation::FsmStructure;
 
 pub fn render_fsm_struct(fsm: &FsmStructure) -> TokenStream {
     let fsm_name = &fsm.fsm_name;
     let state_enum_name = fsm.state_enum_ident();
+    let event_enum_name = fsm.event_enum_ident();
     let context_type = &fsm.context_type;
+    let sender_name = fsm.sender_ident();
+    let output_field = fsm.output_type.as_ref().map(|output_type| {
+        quote! {
+            /// Outbound command channel, fed via `emit`.
+            output: tokio::sync::mpsc::Sender<#output_type>,
+        }
+    });
+
+    let last_panic_field = fsm.on_panic.as_ref().map(|_| {
+        quote! {
+            /// Message of the latest handler panic, read via `last_panic`.
+            last_panic: Option<String>,
+        }
+    });
+
+    let submachine_fields = fsm.submachines().map(|submachine| {
+        let field = submachine.slot_ident();
+        let handle = submachine.child_type("Handle");
+        quote! {
+            /// Child FSM run while in its `#[submachine]` state.
+            #field: Option<tokio_fsm::SubmachineSlot<#handle>>,
+        }
+    });
 
     quote! {
         /// The finite state machine structure.
         pub struct #fsm_name {
             state: #state_enum_name,
             context: #context_type,
+            /// Follow-up events scheduled via `Transition::then`, and
+            /// preempting events put off until the running handler is done.
+            pending: std::collections::VecDeque<tokio_fsm::Acked<#event_enum_name>>,
+            /// Queued events a purge kept, handled before the rest of the queue.
+            requeued: std::collections::VecDeque<tokio_fsm::Envelope<#event_enum_name>>,
+            /// Set by a committed `Transition::halt`, ending the run loop.
+            halted: bool,
+            /// Set when a `#[preempt]` event cancels the handler of the event
+            /// being dispatched.
+            preempted: bool,
+            /// Whether the FSM outlives its handles (`on_last_handle = detach`
+            /// or the builder's `detached`).
+            detached: bool,
+            /// Background tasks started via `spawn_work`, aborted on drop.
+            work: tokio_fsm::WorkSet<#event_enum_name>,
+            /// Name of the event currently being dispatched.
+            current_event: Option<&'static str>,
+            /// Timestamps of the event currently being dispatched, if it was
+            /// queued.
+            event_meta: Option<tokio_fsm::EventMeta>,
+            /// Mirrors `current_event` for the task, surviving a panic.
+            active_event: tokio_fsm::ActiveEvent,
+            /// Event streams attached via the handle's `attach_source`.
+            sources: tokio_fsm::Sources<#event_enum_name>,
+            /// Per-handler latencies, shared with every handle.
+            stats: tokio_fsm::StatsRecorder,
+            /// Receivers of every state change, from the handle's
+            /// `subscribe_transitions`.
+            transitions: tokio_fsm::TransitionFeed<#state_enum_name>,
+            /// The FSM's own sender, handed out by `handle()`.
+            sender: #sender_name,
+            /// Handler budget set with the builder's `watchdog`.
+            watchdog: Option<std::sync::Arc<tokio_fsm::Watchdog<#event_enum_name>>>,
+            #output_field
+            #last_panic_field
+            #(#submachine_fields)*
         }
     }
 }
 
 pub fn render_handle_struct(fsm: &FsmStructure) -> TokenStream {
     let handle_name = fsm.handle_ident();
+    let sender_name = fsm.sender_ident();
     let event_enum_name = fsm.event_enum_ident();
     let state_enum_name = fsm.state_enum_ident();
 
     quote! {
         /// A handle to the running FSM for event submission and state observation.
+        ///
+        /// Handles compare equal and hash identically when they refer to the
+        /// same FSM instance.
         #[derive(Clone)]
         pub struct #handle_name {
-            event_tx: tokio::sync::mpsc::Sender<#event_enum_name>,
-            state_rx: tokio::sync::watch::Receiver<#state_enum_name>,
+            id: tokio_fsm::InstanceId,
+            sender: #sender_name,
+            control_tx: tokio::sync::mpsc::UnboundedSender<tokio_fsm::Control<#event_enum_name>>,
+            state_rx: tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>>,
             shutdown_tx: std::sync::Arc<tokio::sync::watch::Sender<Option<tokio_fsm::ShutdownMode>>>,
+            stats: tokio_fsm::StatsRecorder,
+            /// In-flight send of the `Sink` impl (`sink` feature).
+            sink: tokio_fsm::SinkSlot<#event_enum_name>,
+        }
+    }
+}
+
+pub fn render_sender_struct(fsm: &FsmStructure) -> TokenStream {
+    let sender_name = fsm.sender_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let sender_type = super::channel::sender_type(fsm);
+    let state_enum_name = fsm.state_enum_ident();
+
+    quote! {
+        /// Sends events to an FSM without keeping it alive, as returned by
+        /// `self.handle()` inside handlers.
+        ///
+        /// The FSM stops once every `Handle` is dropped, even while senders
+        /// remain; sends then fail as if it had shut down. FSMs declared with
+        /// `on_last_handle = detach` keep running.
+        #[derive(Clone)]
+        pub struct #sender_name {
+            event_tx: #sender_type,
+            /// Lane for events with a `#[preempt]` handler.
+            priority_tx: tokio::sync::mpsc::UnboundedSender<tokio_fsm::Acked<#event_enum_name>>,
+            state_rx: tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>>,
+            stats: tokio_fsm::StatsRecorder,
+            /// Queued-event counts per `coalesce_by` key, shared with the FSM.
+            coalescer: tokio_fsm::Coalescer,
+            /// Queue pressure tracking, shared with the FSM.
+            watermarks: tokio_fsm::Watermarks,
+            /// Registers receivers of every state change with the FSM.
+            transitions: tokio_fsm::TransitionWatcher<#state_enum_name>,
+            /// The builder's `clock`, which stamps state changes.
+            clock: Option<std::sync::Arc<dyn tokio_fsm::Clock>>,
+            /// Spawns `send_after` timers on the FSM's custom runtime, if any.
+            spawner: Option<tokio_fsm::Spawner>,
+            /// Rejects malformed events before they are enqueued.
+            validator: Option<fn(&#event_enum_name) -> Result<(), tokio_fsm::ValidationError>>,
+            /// Queue length at which events start being shed.
+            shed_above: Option<usize>,
+        }
+    }
+}
+
+pub fn render_typed_handle_struct(fsm: &FsmStructure) -> TokenStream {
+    let handle_name = fsm.handle_ident();
+    let typed_handle_name = fsm.typed_handle_ident();
+
+    quote! {
+        /// A handle typed at the state `S` its FSM is expected to be in.
+        ///
+        /// It has one method per event with a handler in `S` whose next state
+        /// is known statically, and that method returns the handle typed at
+        /// that state. Obtained with `handle.typed(State)`.
+        pub struct #typed_handle_name<S> {
+            handle: #handle_name,
+            state: std::marker::PhantomData<S>,
+        }
+    }
+}
+
+pub fn render_builder_struct(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let builder_name = fsm.builder_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let context_type = &fsm.context_type;
+
+    quote! {
+        /// Configures and spawns a
+        #[doc = concat!("[`", stringify!(#fsm_name), "`].")]
+        pub struct #builder_name {
+            state: #state_enum_name,
+            context: #context_type,
+            validator: Option<fn(&#event_enum_name) -> Result<(), tokio_fsm::ValidationError>>,
+            shed_above: Option<usize>,
+            watermarks: Option<(usize, usize)>,
+            clock: Option<std::sync::Arc<dyn tokio_fsm::Clock>>,
+            watchdog: Option<std::time::Duration>,
+            watchdog_event: Option<#event_enum_name>,
+            detached: bool,
         }
     }
 }
 
 pub fn render_task_struct(fsm: &FsmStructure) -> TokenStream {
     let task_name = fsm.task_ident();
+    let state_enum_name = fsm.state_enum_ident();
     let context_type = &fsm.context_type;
     let error_type = &fsm.error_type;
 
@@ -42,7 +190,43 @@ pub fn render_task_struct(fsm: &FsmStructure) -> TokenStream {
         /// A handle to the background task running the FSM.
         /// Awaiting this will return the final context or an error.
         pub struct #task_name {
-            handle: tokio::task::JoinHandle<Result<#context_type, #error_type>>,
+            id: tokio_fsm::InstanceId,
+            shutdown: tokio_fsm::ShutdownSignal,
+            handle: tokio_fsm::TaskHandle<Result<#context_type, #error_type>>,
+            /// The last published state, reported in `TaskError`.
+            state_rx: tokio::sync::watch::Receiver<tokio_fsm::StateChange<#state_enum_name>>,
+            active_event: tokio_fsm::ActiveEvent,
+        }
+    }
+}
+
+pub fn render_core_struct(fsm: &FsmStructure) -> TokenStream {
+    let fsm_name = &fsm.fsm_name;
+    let core_name = fsm.core_ident();
+    let state_enum_name = fsm.state_enum_ident();
+    let event_enum_name = fsm.event_enum_ident();
+    let receiver_type = super::channel::receiver_type(fsm);
+
+    quote! {
+        /// Runs a
+        #[doc = concat!("[`", stringify!(#fsm_name), "`]")]
+        /// in place, without a task or a handle, for embedding the machine in
+        /// an existing event loop.
+        ///
+        /// Events are passed to `process` and state timeouts fire only when
+        /// `poll_timeout` is called after `timeout_deadline`. Neither needs a
+        /// Tokio runtime unless a handler does, e.g. via `spawn_work`,
+        /// `#[submachine]` states or the builder's `watchdog`, and the FSM
+        /// declares no `runtime` to run them on instead.
+        pub struct #core_name {
+            fsm: #fsm_name,
+            /// Events sent through `self.handle()` in handlers.
+            events: #receiver_type,
+            priority: tokio::sync::mpsc::UnboundedReceiver<tokio_fsm::Acked<#event_enum_name>>,
+            state_tx: tokio::sync::watch::Sender<tokio_fsm::StateChange<#state_enum_name>>,
+            timer: tokio_fsm::Timer,
+            /// Whether the `#[on_start]` handler has run.
+            started: bool,
         }
     }
 }
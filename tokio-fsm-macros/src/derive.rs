@@ -0,0 +1,119 @@
+//! `#[derive(Fsm)]`: lowers an enum and its transition table to the `impl`
+//! block `#[fsm]` expects.
+
+use darling::{FromMeta, ast::NestedMeta};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Fields, Ident, ItemImpl};
+use tokio_fsm_analysis::{attrs, validation};
+
+use crate::codegen;
+
+pub fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "#[derive(Fsm)] can only be used on enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "#[derive(Fsm)] does not support generic enums",
+        ));
+    }
+
+    let mut variants: Vec<&Ident> = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                variant,
+                "#[derive(Fsm)] states must be unit variants",
+            ));
+        }
+        variants.push(&variant.ident);
+    }
+    let Some(&initial) = variants.first() else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "#[derive(Fsm)] requires at least one state",
+        ));
+    };
+
+    let mut transitions = Vec::new();
+    for attr in &input.attrs {
+        if attr.path().is_ident("transition") {
+            let transition: attrs::TransitionAttr = attr.parse_args()?;
+            for state in [&transition.from, &transition.to] {
+                if !variants.contains(&state) {
+                    return Err(Error::new_spanned(
+                        state,
+                        format!("State '{}' is not a variant of '{}'", state, input.ident),
+                    ));
+                }
+            }
+            transitions.push(transition);
+        }
+    }
+    if transitions.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "#[derive(Fsm)] requires at least one #[transition(From -> To on Event)]",
+        ));
+    }
+    for &variant in &variants[1..] {
+        let used = transitions
+            .iter()
+            .any(|t| &t.from == variant || &t.to == variant);
+        if !used {
+            return Err(Error::new_spanned(
+                variant,
+                format!("State '{}' is not used by any #[transition]", variant),
+            ));
+        }
+    }
+
+    let enum_name = &input.ident;
+    let fsm_name = format_ident!("{}Fsm", enum_name);
+    let handlers = transitions.iter().enumerate().map(|(index, t)| {
+        let (from, to, event) = (&t.from, &t.to, &t.event);
+        let method = format_ident!("transition_{}", index, span = event.span());
+        quote! {
+            #[on(state = #from, event = #event)]
+            async fn #method(&mut self) -> tokio_fsm::Transition<#to> {
+                tokio_fsm::Transition::to(#to)
+            }
+        }
+    });
+    let item: ItemImpl = syn::parse_quote! {
+        impl #fsm_name {
+            #(#handlers)*
+        }
+    };
+
+    let args = NestedMeta::parse_meta_list(quote! { initial = #initial })?;
+    let args = attrs::FsmArgs::from_list(&args).map_err(Error::from)?;
+    let fsm = validation::FsmStructure::parse(args, &item)?;
+    let generated = codegen::generate(&fsm, &item);
+
+    let state_enum = fsm.state_enum_ident();
+    Ok(quote! {
+        #generated
+
+        impl From<#state_enum> for #enum_name {
+            fn from(state: #state_enum) -> Self {
+                match state {
+                    #(#state_enum::#variants => Self::#variants,)*
+                }
+            }
+        }
+
+        impl From<#enum_name> for #state_enum {
+            fn from(state: #enum_name) -> Self {
+                match state {
+                    #(#enum_name::#variants => Self::#variants,)*
+                }
+            }
+        }
+    })
+}
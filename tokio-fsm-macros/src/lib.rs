@@ -2,11 +2,11 @@
 
 use darling::FromMeta;
 use proc_macro::TokenStream;
-use syn::{ItemImpl, parse_macro_input};
+use syn::{DeriveInput, ItemImpl, parse_macro_input};
+use tokio_fsm_analysis::{attrs, table, validation};
 
-mod attrs;
 mod codegen;
-mod validation;
+mod derive;
 
 /// Generates an asynchronous Finite State Machine (FSM) from an `impl` block.
 ///
@@ -18,30 +18,174 @@ mod validation;
 /// * `initial = StateName`: (Required) The name of the starting state.
 /// * `channel_size = usize`: (Optional) The capacity of the internal event
 ///   queue (default: 100).
+/// * `channel = tokio | flume | kanal`: (Optional) The MPSC implementation
+///   behind the event queue (default: `tokio`). `flume` and `kanal` require the
+///   feature of the same name on `tokio-fsm`; the handle API is unchanged.
+/// * `runtime = path::To::Runtime`: (Optional) Spawns the FSM on a
+///   `tokio_fsm::Runtime` other than the caller's Tokio runtime, such as
+///   `tokio_fsm::SmolRuntime` or `tokio_fsm::AsyncStdRuntime` (with the `smol`
+///   and `async-std` features). Its clock times state timeouts and
+///   `send_after`, it also runs `spawn_work` tasks, `#[submachine]` watchers
+///   and `send_after` timers, and `spawn_on` isn't generated. Without it, FSMs
+///   built for `wasm32-unknown-unknown` spawn on the browser's event loop.
+/// * `emit_graph = "dot" | "mermaid"`: (Optional) Writes the FSM's graph to
+///   `[FsmName].dot` or `[FsmName].mmd` while the macro expands, so CI can
+///   publish diagrams without running any code. Files go to `OUT_DIR`, which
+///   requires a build script, unless `emit_path = "dir"` names a directory
+///   relative to the crate root.
+/// * `serde`: (Optional) Derives `Serialize`/`Deserialize` on the generated
+///   State and Event enums. Requires the `serde` feature of `tokio-fsm`, and
+///   every event payload must implement the serde traits.
+/// * `non_exhaustive`: (Optional) Marks the generated State and Event enums
+///   `#[non_exhaustive]`, so a library exposing its FSM can add states and
+///   events in a minor release without breaking downstream `match`es.
+/// * `state_repr = u8`: (Optional) Makes the generated State enum `#[repr(u8)]`
+///   with each state's index in `ALL` as its discriminant, and implements
+///   `From<State> for u8` and `TryFrom<u8> for State`. Any primitive integer
+///   type works.
+/// * `on_panic = Crashed`: (Optional) Catches panics in handlers and moves the
+///   FSM to `Crashed` instead of ending its task. The panic message is
+///   available to `Crashed`'s handlers via `self.last_panic()`; state the
+///   handler mutated before panicking is left as it was.
+/// * `restart = on_error`: (Optional) Restarts the event loop when it fails
+///   instead of ending the task, from the initial state (or `restart_from =
+///   State`) with a clone of the spawn-time context, so `Context` must be
+///   `Clone`. `max_restarts = 3` (the default) bounds the restarts before the
+///   failure is returned from the task, and `backoff = "1s"` delays each one.
+/// * `on_last_handle = shutdown | detach`: (Optional) What happens once every
+///   handle is dropped. `shutdown` (the default) stops the FSM gracefully and
+///   resolves the task with the context; `detach` keeps it running on its
+///   timeouts, attached sources and `self.handle()` until it halts or reaches a
+///   terminal state. The builder's `detached()` sets it per instance.
+/// * `event_derive(PartialEq, Eq, Hash)`: (Optional) Extra derives for the
+///   generated Event enum, which always derives `Debug` and `Clone`. Every
+///   event payload must implement the derived traits.
 ///
 /// # Generated Types
 ///
 /// The macro generates several types based on the name of the `impl` block
 /// (e.g., `WorkerFsm`):
 ///
-/// * `WorkerFsmState`: An enum containing all discovered states.
+/// * `WorkerFsmState`: An enum containing all discovered states. Each variant
+///   is documented with the transitions into and out of it, and has an
+///   `is_<state>()` predicate in snake case.
 /// * `WorkerFsmEvent`: An enum containing all discovered events and their data
-///   payloads.
+///   payloads, each documented with the transitions it triggers. Implements
+///   `From<Payload>` for payload types used by exactly one event, so
+///   `handle.send_into(job)` works.
+/// * `WorkerFsm{Event}Payload` and `WorkerFsm{Event}Builder`: For events whose
+///   handler takes several payload arguments, the struct carrying them and its
+///   builder, started with `WorkerFsmEvent::{event}()` (in snake case).
 /// * `WorkerFsmHandle`: A cloneable handle used to interact with the FSM (send
-///   events, query state).
+///   events, query state, read per-handler latencies via `stats()`).
+/// * `WorkerFsmTypedHandle<S>`: A typestate wrapper returned by
+///   `handle.typed(State)`. For every event whose next state is known
+///   statically from `S` (a single-target handler, followed through `#[auto]`
+///   states), it has a snake_case method that sends the event and returns the
+///   handle typed at that state, so sending an event `S` doesn't handle is a
+///   compile error.
+/// * `WorkerFsmBuilder`: Returned by `WorkerFsm::builder(context)`. Use
+///   `.validator(f)` to reject malformed events in the handle's `submit` /
+///   `try_submit` and in `call` before they are enqueued, then `.spawn()`.
 /// * `WorkerFsmTask`: A `Future` that must be awaited to run the FSM. Resolves
 ///   to `Result<Context, TaskError>`.
+/// * `WorkerFsmCore`: Returned by `WorkerFsm::core(context)` (or the builder's
+///   `.core()`). Runs the FSM in place, without a task or channels:
+///   `process(event).await` handles an event and its follow-ups, and
+///   `poll_timeout().await` fires the state timeout once `timeout_deadline()`
+///   has passed.
+///
+/// It also adds introspection helpers to the FSM type itself:
+///
+/// * `WorkerFsm::mermaid()`: A Mermaid `stateDiagram-v2` of the machine,
+///   including timeouts, computed at compile time.
+/// * `WorkerFsm::definition()`: A JSON document describing states, events,
+///   payload types, timeouts and transitions.
+/// * `WorkerFsm::transitions()`: The static `(from, event, to)` table of
+///   event-driven transitions.
+///
+/// # Associated Types
+///
+/// * `type Context = ...;`: (Optional) The data owned by the FSM. Defaults to
+///   `()`, in which case `spawn()` takes no argument.
+/// * `type Error = ...;`: (Optional) The logical error type of the FSM.
+///   Defaults to `std::convert::Infallible`. Handlers returning
+///   `Result<Transition<S>, Self::Error>` stop the FSM on `Err`, resolving its
+///   task to `TaskError::Fsm`.
+/// * `type Output = ...;`: (Optional) A command type handlers can push via
+///   `self.emit(command).await`. When declared, `spawn` returns the matching
+///   `Receiver` as a third tuple element, letting IO executors live outside the
+///   FSM.
 ///
 /// # Handlers & Attributes
 ///
-/// Within the `impl` block, use the following attributes on `async fn` methods:
+/// Within the `impl` block, use the following attributes on `async fn` methods.
+/// Handlers return a `Transition<S>`, or `impl Into<Transition<S>>` to return
+/// the state marker `S` or a helper type converting into the transition.
+/// A payload argument written `&Payload` borrows the payload from the event,
+/// which the run loop keeps ownership of.
 ///
 /// * `#[on(state = S, event = E)]`: Maps a handler to a specific state and
-///   event trigger.
+///   event trigger. Use `event = A | B` to route several events to the same
+///   handler; `self.current_event()` reports which one fired.
+/// * `#[auto(state = S)]`: Runs the handler immediately upon entering `S`,
+///   before any queued event, and commits the transition it returns. Takes no
+///   payload; automatic transitions must not form a cycle.
+/// * `#[on_start]`: Runs the handler inside the FSM task before the first event
+///   is processed, e.g. to move from the initial state straight to `Recovering`
+///   based on the context. Takes no payload, and runs again after a `restart`.
+/// * `#[on(state = S, event = E, coalesce_by = field)]`: Keeps only the most
+///   recently queued `E` per `payload.field`; older ones are skipped when
+///   dequeued.
+/// * `#[preempt]`: Alongside `#[on(state = S, event = E)]`, routes `E` through
+///   a priority lane. If another handler is running in `S` when `E` arrives,
+///   that handler is cancelled at its next await point and `E`'s handler runs
+///   instead.
+/// * `#[submachine(state = S, fsm = ChildFsm)]`: Runs `ChildFsm`, spawned with
+///   a default context, while the FSM is in `S`. The event `S(ChildFsmEvent)`
+///   is forwarded to it, and once it reaches a terminal state the handler runs
+///   with that `ChildFsmState` as the payload of the event `SDone`, mapping the
+///   child's outcome to a transition. The child is aborted if the FSM leaves
+///   `S` first.
+/// * `#[targets(A, B)]`: On a handler returning `Transition<[FsmName]State>`,
+///   whose next state is chosen at runtime, limits it to `A` and `B`. Without
+///   it, validation assumes the handler can move to any state. A transition to
+///   a state outside the list is refused, leaving the FSM where it was.
 /// * `#[state_timeout(duration = "30s")]`: Configures a timeout for the state
 ///   reached *after* this transition.
 /// * `#[on_timeout]`: Marks a method as the handler to call when a state
 ///   timeout occurs.
+/// * `#[on_entry(state = S)]`: Marks an `async fn(&mut self)` hook run after
+///   every transition into `S`, or an `async fn(&mut self, data: T)` hook run
+///   with the value of a `Transition::to_with(S, data)`. At most one per state.
+/// * `#[persist(on_error = S)]`: Marks an `async fn(&mut self) -> Result<(),
+///   E>` hook that runs after every transition, before the new state is
+///   published and before the next event is processed. If it returns `Err`, the
+///   FSM moves to `S` instead and the transition's effects and follow-ups are
+///   discarded.
+/// * `#[on_shutdown]`: Marks an `async fn(&mut self, mode: ShutdownMode)` hook
+///   that runs once when the run loop exits, with the mode it is stopping in
+///   (`Immediate` after `Transition::halt`), so resources held in the context
+///   can be flushed before the task resolves.
+/// * `#[on_orphaned]`: Runs the handler once every handle is dropped, instead
+///   of shutting down. It can halt the FSM or let it run on, detached, until it
+///   halts or reaches a terminal state. Takes no payload.
+///
+/// On the `impl` block itself, below `#[fsm]`:
+///
+/// * `#[external_event(from = Message, map(Start, Stop = Halt))]`: Generates
+///   `TryFrom<Message>` for the event enum. Each entry maps an event to the
+///   external variant of the same name, or the one given after `=`; payload
+///   events expect a single-field tuple variant whose value converts via
+///   `Into`. Unmapped variants are returned as the error, and
+///   `handle.send_external(message)` drops them like any unhandled event.
+/// * `#[schedule(cron = "0 */5 * * * *", event = Reconcile)]`: With the `cron`
+///   feature, the run loop sends the payload-free event `Reconcile` whenever
+///   the cron expression matches, in UTC, with no ticker task. The expression
+///   has a leading seconds field and is checked at compile time. The event is
+///   handled like any other, so states without a handler drop it, and times
+///   missed while a handler runs are skipped. Repeat the attribute for several
+///   schedules. Cores built with `core()` don't run schedules.
 ///
 /// # Example
 ///
@@ -86,10 +230,155 @@ pub fn fsm(args: TokenStream, input: TokenStream) -> TokenStream {
     }
 }
 
+/// Generates an FSM from a transition table, as an alternative to `#[fsm]`
+/// that keeps every transition in one place.
+///
+/// Each row reads `From + Event => To`. An event can carry a payload,
+/// `Event(Type)`, and a row can name an action in brackets, `[action]`,
+/// which is awaited as `action(&mut context)` (or `action(&mut context,
+/// payload)`) before the transition is taken. The first row's source state
+/// is the initial state, and `type Context = ...;` may precede the rows.
+///
+/// The table is validated and generated exactly like the equivalent `#[fsm]`
+/// `impl` block, so it produces the same types and handle API. (It is not
+/// called `fsm!` because a crate can't export an attribute and a
+/// function-like macro under the same name.)
+///
+/// # Example
+///
+/// ```rust
+/// use tokio_fsm::fsm_table;
+///
+/// #[derive(Default)]
+/// pub struct Order {
+///     validated: bool,
+///     paid: u64,
+/// }
+///
+/// async fn validate(order: &mut Order) {
+///     order.validated = true;
+/// }
+///
+/// async fn charge(order: &mut Order, amount: u64) {
+///     order.paid += amount;
+/// }
+///
+/// fsm_table! {
+///     OrderFsm {
+///         type Context = Order;
+///
+///         Created + Validate [validate] => Validated,
+///         Validated + Pay(u64) [charge] => Paid,
+///         Paid + Ship => Shipped,
+///     }
+/// }
+///
+/// # async fn example() {
+/// let (handle, task) = OrderFsm::spawn(Order::default());
+/// handle.send(OrderFsmEvent::Validate).await.unwrap();
+/// handle.send(OrderFsmEvent::Pay(30)).await.unwrap();
+/// handle.wait_for_state(OrderFsmState::Paid).await.unwrap();
+/// # }
+/// ```
+#[proc_macro]
+pub fn fsm_table(input: TokenStream) -> TokenStream {
+    let table = parse_macro_input!(input as table::FsmTable);
+    let item = table.to_impl();
+    let result = table.args().and_then(|args| generate_fsm(args, item));
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Generates an FSM from an enum of states and a transition table, for
+/// machines whose handlers would only return the next state.
+///
+/// Each `#[transition(From -> To on Event)]` on the enum is one row of the
+/// table. The first variant is the initial state, and events carry no
+/// payload. For an enum `Light`, the macro generates the same types as
+/// `#[fsm]` would for `impl LightFsm` (`LightFsm`, `LightFsmState`,
+/// `LightFsmEvent`, `LightFsmHandle`, `LightFsmTask`, ...) with a `()`
+/// context, plus `From` conversions between `Light` and `LightFsmState`.
+///
+/// Anything beyond that (a context, payloads, timeouts, guards) calls for the
+/// full `#[fsm]` form.
+///
+/// # Example
+///
+/// ```rust
+/// use tokio_fsm::Fsm;
+///
+/// #[derive(Fsm)]
+/// #[transition(Off -> On on Toggle)]
+/// #[transition(On -> Off on Toggle)]
+/// enum Light {
+///     Off,
+///     On,
+/// }
+///
+/// # async fn example() {
+/// let (handle, _task) = LightFsm::spawn();
+/// handle.send(LightFsmEvent::Toggle).await.unwrap();
+/// handle.wait_for_state(LightFsmState::On).await.unwrap();
+/// assert!(matches!(Light::from(handle.current_state()), Light::On));
+/// # }
+/// ```
+#[proc_macro_derive(Fsm, attributes(transition))]
+pub fn derive_fsm(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive::expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
 fn generate_fsm(args: attrs::FsmArgs, input: ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
     // 1. Parse + Validate
     let fsm = validation::FsmStructure::parse(args, &input)?;
+    #[cfg(not(feature = "cron"))]
+    if let Some(schedule) = fsm.schedules.first() {
+        return Err(syn::Error::new_spanned(
+            &schedule.cron,
+            "#[schedule] requires the `cron` feature of tokio-fsm",
+        ));
+    }
+
+    // 2. Write requested graph artifacts
+    emit_graph(&fsm)?;
 
-    // 2. Generate code
+    // 3. Generate code
     Ok(codegen::generate(&fsm, &input))
 }
+
+/// Writes the graph requested with `emit_graph`, leaving the file untouched
+/// when it is already up to date.
+fn emit_graph(fsm: &validation::FsmStructure) -> syn::Result<()> {
+    let Some(format) = fsm.emit_graph else {
+        return Ok(());
+    };
+    let error = |message: String| syn::Error::new(proc_macro2::Span::call_site(), message);
+
+    let dir = match &fsm.emit_path {
+        Some(path) => {
+            let root = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+            std::path::Path::new(&root).join(path)
+        }
+        None => std::env::var_os("OUT_DIR").map(Into::into).ok_or_else(|| {
+            error(
+                "emit_graph writes to OUT_DIR, which is only set for crates with a build script; \
+                 add one or set emit_path"
+                    .to_string(),
+            )
+        })?,
+    };
+    let file = dir.join(format!("{}.{}", fsm.fsm_name, format.extension()));
+    let contents = tokio_fsm_analysis::graph::render(fsm, format);
+
+    if std::fs::read_to_string(&file).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&dir)
+        .and_then(|()| std::fs::write(&file, contents))
+        .map_err(|e| error(format!("failed to write {}: {}", file.display(), e)))
+}